@@ -1,8 +1,8 @@
 //! Abstractions to parse and generate distros.json file.
 
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use serde_with::{VecSkipError, serde_as};
 use url::Url;
 
@@ -17,10 +17,14 @@ use url::Url;
 ///
 /// For [Imager::devices], any new board will be appended to the end of the board list. Existing
 /// boards are not added again. Duplicate boards are checked by [Device::name] field. The [Device]
-/// fields are overwritten.
+/// fields are overwritten. If an incoming board has the same name as an existing board but a
+/// different [Flasher], it is treated as a different board, and the flasher is appended to its
+/// name. This prevents a remote config from hijacking the flasher of a local board.
 ///
 /// For [Config::os_list], all non-duplicate [OsListItem] are appended to the end of the list.
 ///
+/// Use [Config::merge] with a [MergeStrategy] for more control over conflict resolution.
+///
 /// [BeagleBoard.org]: https://www.beagleboard.org/
 #[serde_as]
 #[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
@@ -41,6 +45,7 @@ pub struct Config {
 pub struct Imager {
     /// A list of remote config files
     #[serde(default)]
+    #[serde(serialize_with = "sorted_set")]
     pub remote_configs: HashSet<Url>,
     #[serde_as(as = "VecSkipError<_>")]
     #[serde(default)]
@@ -56,6 +61,7 @@ pub struct Device {
     /// Board Name
     pub name: String,
     /// Board tags are used to match OS images with boards
+    #[serde(serialize_with = "sorted_set")]
     pub tags: HashSet<String>,
     /// Board image URL
     pub icon: Option<Url>,
@@ -73,7 +79,7 @@ pub struct Device {
     /// Board Specification. With order preserved
     pub specification: Vec<(String, String)>,
     /// OSHW details for the device.
-    pub oshw: Option<String>
+    pub oshw: Option<String>,
 }
 
 /// Types of customization Initialization formats
@@ -87,6 +93,10 @@ pub enum InitFormat {
     Sysconf,
     /// Armbian base customization
     Armbian,
+    /// cloud-init NoCloud data source in boot partition
+    CloudInit,
+    /// Raspberry Pi OS `custom.toml` in boot partition
+    Raspberry,
 }
 
 /// Os List can contain multiple types of items depending on the situation.
@@ -123,6 +133,18 @@ pub struct OsSubList {
     /// Flasher type for all top level Os Images in the sublist
     #[serde(default)]
     pub flasher: Flasher,
+    /// Devices inherited by all items that do not declare their own. See [OsSubList::inherit].
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    #[serde(serialize_with = "sorted_set")]
+    pub devices: HashSet<String>,
+    /// Tags inherited by all items that do not declare their own. See [OsSubList::inherit].
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    #[serde(serialize_with = "sorted_set")]
+    pub tags: HashSet<String>,
+    /// Minimum imager version required by all items in the sublist. See
+    /// [OsListItem::is_supported].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_imager_version: Option<semver::Version>,
     /// List of items
     #[serde_as(as = "VecSkipError<_>")]
     pub subitems: Vec<OsListItem>,
@@ -140,8 +162,14 @@ pub struct OsRemoteSubList {
     /// Flasher type for all top level Os Images in the sublist
     #[serde(default)]
     pub flasher: Flasher,
-    /// Union of devices the OsImages in the SubList can be used with
+    /// Union of devices the OsImages in the SubList can be used with. Also inherited by the
+    /// downloaded items that do not declare their own. See [OsRemoteSubList::resolve].
+    #[serde(serialize_with = "sorted_set")]
     pub devices: HashSet<String>,
+    /// Tags inherited by the downloaded items that do not declare their own.
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    #[serde(serialize_with = "sorted_set")]
+    pub tags: HashSet<String>,
     /// Url to the Remote list
     pub subitems_url: Url,
 }
@@ -166,10 +194,13 @@ pub struct OsImage {
     pub extract_size: u64,
     /// Os Image release date
     pub release_date: chrono::NaiveDate,
-    /// Devices the Os Image can be used with
+    /// Devices the Os Image can be used with. Can be inherited from parent [OsSubList].
+    #[serde(default)]
+    #[serde(serialize_with = "sorted_set")]
     pub devices: HashSet<String>,
     /// Os Image tags
     #[serde(default)]
+    #[serde(serialize_with = "sorted_set")]
     pub tags: HashSet<String>,
     /// Initialization Format. Currently only used by SD Card Images
     #[serde(default)]
@@ -178,6 +209,15 @@ pub struct OsImage {
     pub bmap: Option<Url>,
     /// Special Instructions for flashing board.
     pub info_text: Option<String>,
+    /// Minimum imager version required to flash the image. See [OsListItem::is_supported].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_imager_version: Option<semver::Version>,
+    /// Changes since the previous release of the Os Image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_notes: Option<String>,
+    /// Link to the full changelog.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changelog_url: Option<Url>,
 }
 
 /// Types of flashers Os Image(s) support
@@ -193,50 +233,336 @@ pub enum Flasher {
     Msp430Usb,
     /// PocketBeagle2 Mspm0 firmware
     Pb2Mspm0,
+    /// Flasher registered by downstream applications, identified by name
+    Custom(CustomFlasher),
 }
 
-impl Extend<Self> for Config {
-    fn extend<T: IntoIterator<Item = Self>>(&mut self, iter: T) {
-        for config in iter.into_iter() {
-            self.imager
-                .remote_configs
-                .extend(config.imager.remote_configs);
-
-            for c_dev in config.imager.devices {
-                // If the board already exists, overwrite fields.
-                // Else, add new board
-                if let Some(my_dev) = self
-                    .imager
-                    .devices
-                    .iter_mut()
-                    .find(|x| x.name == c_dev.name)
-                {
-                    my_dev.tags.extend(c_dev.tags.clone());
-                    my_dev.flasher = c_dev.flasher;
+impl std::fmt::Display for Flasher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SdCard => f.write_str("SD Card"),
+            Self::BeagleConnectFreedom => f.write_str("BeagleConnect Freedom"),
+            Self::Msp430Usb => f.write_str("BeagleConnect Freedom MSP430"),
+            Self::Pb2Mspm0 => f.write_str("PocketBeagle 2 MSPM0"),
+            Self::Custom(x) => f.write_str(x.name()),
+        }
+    }
+}
 
-                    if let Some(doc) = &c_dev.documentation {
-                        my_dev.documentation = Some(doc.clone());
-                    }
+/// Name of a custom [Flasher]. Names are interned, so that [Flasher] can remain [Copy].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub struct CustomFlasher(&'static str);
 
-                    if let Some(icon) = &c_dev.icon {
-                        my_dev.icon = Some(icon.clone());
-                    }
-                } else {
-                    self.imager.devices.push(c_dev);
+impl CustomFlasher {
+    /// Each distinct name is allocated only once for the lifetime of the process, no matter how
+    /// many times configs using it are parsed.
+    pub fn new(name: &str) -> Self {
+        static NAMES: std::sync::OnceLock<std::sync::Mutex<HashSet<&'static str>>> =
+            std::sync::OnceLock::new();
+
+        let mut names = NAMES.get_or_init(Default::default).lock().unwrap();
+        if let Some(x) = names.get(name) {
+            return Self(x);
+        }
+
+        let x: &'static str = Box::leak(name.into());
+        names.insert(x);
+        Self(x)
+    }
+
+    pub const fn name(&self) -> &'static str {
+        self.0
+    }
+}
+
+impl<'de> Deserialize<'de> for CustomFlasher {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(Self::new(&name))
+    }
+}
+
+/// Serialize sets in sorted order, so that the output is reproducible.
+fn sorted_set<T: Serialize + Ord, S: Serializer>(
+    set: &HashSet<T>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    set.iter().collect::<BTreeSet<_>>().serialize(serializer)
+}
+
+/// Sort images by release date (newest first), then name. Images are placed before sublists,
+/// which are sorted by name.
+fn canonical_order(items: &mut [OsListItem]) {
+    for item in items.iter_mut() {
+        if let OsListItem::SubList(list) = item {
+            canonical_order(&mut list.subitems);
+        }
+    }
+
+    items.sort_by(|a, b| match (a, b) {
+        (OsListItem::Image(a), OsListItem::Image(b)) => b
+            .release_date
+            .cmp(&a.release_date)
+            .then_with(|| a.name.cmp(&b.name)),
+        (OsListItem::Image(_), _) => std::cmp::Ordering::Less,
+        (_, OsListItem::Image(_)) => std::cmp::Ordering::Greater,
+        _ => a.name().cmp(b.name()),
+    });
+}
+
+/// Issues found in a [Config] by [Config::validate]. These do not prevent parsing, but usually
+/// point to mistakes in the config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Warning {
+    /// An image (or remote sublist) references a device tag that no [Device] declares. Such an
+    /// image is not visible for any board.
+    UnknownImageTag { image: String, tag: String },
+    /// An image that has no devices, even after inheriting from parent sublists. Such an image
+    /// is not visible for any board.
+    ImageWithoutDevices { image: String },
+    /// A [Device] tag that is not used by any image.
+    UnusedBoardTag { board: String, tag: String },
+    /// Multiple [Device] share the same name.
+    DuplicateBoardName { board: String },
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownImageTag { image, tag } => {
+                write!(
+                    f,
+                    "Image \"{image}\" uses tag \"{tag}\" not declared by any board"
+                )
+            }
+            Self::ImageWithoutDevices { image } => {
+                write!(f, "Image \"{image}\" is not used with any board")
+            }
+            Self::UnusedBoardTag { board, tag } => {
+                write!(
+                    f,
+                    "Board \"{board}\" tag \"{tag}\" is not used by any image"
+                )
+            }
+            Self::DuplicateBoardName { board } => {
+                write!(f, "Multiple boards are named \"{board}\"")
+            }
+        }
+    }
+}
+
+fn device_refs<'a>(items: &'a [OsListItem], refs: &mut Vec<(&'a str, &'a HashSet<String>)>) {
+    for item in items {
+        match item {
+            OsListItem::Image(img) => refs.push((&img.name, &img.devices)),
+            OsListItem::SubList(list) => device_refs(&list.subitems, refs),
+            OsListItem::RemoteSubList(list) => refs.push((&list.name, &list.devices)),
+        }
+    }
+}
+
+fn sorted(tags: &HashSet<String>) -> Vec<&String> {
+    let mut tags: Vec<_> = tags.iter().collect();
+    tags.sort();
+    tags
+}
+
+impl Config {
+    /// Cross-reference device tags between boards and images.
+    ///
+    /// Reports image tags not declared by any board, images without any device, and board tags
+    /// not used by any image. Also reports boards sharing the same name.
+    pub fn validate(&self) -> Vec<Warning> {
+        let mut inherited = self.clone();
+        inherited.inherit();
+
+        let mut refs = Vec::new();
+        device_refs(&inherited.os_list, &mut refs);
+
+        let board_tags: HashSet<&String> =
+            self.imager.devices.iter().flat_map(|x| &x.tags).collect();
+        let image_tags: HashSet<&String> = refs.iter().flat_map(|(_, tags)| *tags).collect();
+
+        let unknown = refs.iter().flat_map(|(image, tags)| {
+            sorted(tags)
+                .into_iter()
+                .filter(|x| !board_tags.contains(x))
+                .map(|tag| Warning::UnknownImageTag {
+                    image: image.to_string(),
+                    tag: tag.clone(),
+                })
+        });
+
+        let no_devices = refs
+            .iter()
+            .filter(|(_, devices)| devices.is_empty())
+            .map(|(image, _)| Warning::ImageWithoutDevices {
+                image: image.to_string(),
+            });
+
+        let unused = self.imager.devices.iter().flat_map(|board| {
+            sorted(&board.tags)
+                .into_iter()
+                .filter(|x| !image_tags.contains(x))
+                .map(|tag| Warning::UnusedBoardTag {
+                    board: board.name.clone(),
+                    tag: tag.clone(),
+                })
+        });
+
+        let mut names = HashSet::new();
+        let duplicate = self
+            .imager
+            .devices
+            .iter()
+            .filter(move |x| !names.insert(&x.name))
+            .map(|x| Warning::DuplicateBoardName {
+                board: x.name.clone(),
+            });
+
+        unknown
+            .chain(no_devices)
+            .chain(unused)
+            .chain(duplicate)
+            .collect()
+    }
+
+    /// Serialize to pretty JSON with stable ordering, so that regenerated configs produce minimal
+    /// diffs. Devices are sorted by name, and images by release date (newest first) then name.
+    /// Sets (like tags) are always serialized in sorted order.
+    pub fn to_canonical_json(&self) -> serde_json::Result<String> {
+        let mut config = self.clone();
+
+        config.imager.devices.sort_by(|a, b| a.name.cmp(&b.name));
+        canonical_order(&mut config.os_list);
+
+        serde_json::to_string_pretty(&config)
+    }
+
+    /// Apply [OsSubList::inherit] to all sublists in the config.
+    pub fn inherit(&mut self) {
+        for item in self.os_list.iter_mut() {
+            if let OsListItem::SubList(list) = item {
+                list.inherit();
+            }
+        }
+    }
+
+    /// Iterate over all [OsImage] in the config, including the ones in [OsSubList]. Images in
+    /// [OsRemoteSubList] are not included since they are not downloaded yet.
+    pub fn images(&self) -> impl Iterator<Item = &OsImage> {
+        self.os_list.iter().flat_map(OsListItem::images)
+    }
+
+    /// Iterate over all [OsImage] released on or after `date`. See [Config::images].
+    pub fn images_since(&self, date: chrono::NaiveDate) -> impl Iterator<Item = &OsImage> {
+        self.images().filter(move |x| x.release_date >= date)
+    }
+}
+
+/// How conflicting [Device] fields are resolved by [Config::merge].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DevicePreference {
+    /// Fields present in the incoming config overwrite the existing ones.
+    #[default]
+    PreferIncoming,
+    /// Existing fields are kept. Only missing fields are filled from the incoming config.
+    PreferExisting,
+}
+
+/// How duplicate [OsListItem] are detected by [Config::merge].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImageDedup {
+    /// Items are duplicates only if they are exactly equal.
+    #[default]
+    Equality,
+    /// Items with the same name are duplicates. Which one is kept depends on
+    /// [DevicePreference].
+    Name,
+}
+
+/// Strategy used by [Config::merge]. Default matches the behaviour of [Extend::extend].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MergeStrategy {
+    pub preference: DevicePreference,
+    pub image_dedup: ImageDedup,
+}
+
+impl Config {
+    /// Merge `config` into `self` using `strategy`. See [MergeStrategy].
+    pub fn merge(&mut self, config: Self, strategy: MergeStrategy) {
+        let prefer_incoming = strategy.preference == DevicePreference::PreferIncoming;
+
+        self.imager
+            .remote_configs
+            .extend(config.imager.remote_configs);
+
+        for mut c_dev in config.imager.devices {
+            if self
+                .imager
+                .devices
+                .iter()
+                .any(|x| x.name == c_dev.name && x.flasher != c_dev.flasher)
+            {
+                // Different board with the same name. Keep both, rather than overwriting the
+                // flasher of existing board.
+                c_dev.name = format!("{} ({})", c_dev.name, c_dev.flasher);
+            }
+
+            // If the board already exists, overwrite fields.
+            // Else, add new board
+            if let Some(my_dev) = self
+                .imager
+                .devices
+                .iter_mut()
+                .find(|x| x.name == c_dev.name)
+            {
+                my_dev.tags.extend(c_dev.tags);
+
+                if c_dev.documentation.is_some()
+                    && (prefer_incoming || my_dev.documentation.is_none())
+                {
+                    my_dev.documentation = c_dev.documentation;
+                }
+
+                if c_dev.icon.is_some() && (prefer_incoming || my_dev.icon.is_none()) {
+                    my_dev.icon = c_dev.icon;
                 }
+            } else {
+                self.imager.devices.push(c_dev);
             }
+        }
 
-            // Only add non_duplicate os_list items
-            self.os_list.reserve(config.os_list.len());
-            for item in config.os_list {
-                if !self.os_list.contains(&item) {
-                    self.os_list.push(item);
+        // Only add non_duplicate os_list items
+        self.os_list.reserve(config.os_list.len());
+        for item in config.os_list {
+            match strategy.image_dedup {
+                ImageDedup::Equality => {
+                    if !self.os_list.contains(&item) {
+                        self.os_list.push(item);
+                    }
+                }
+                ImageDedup::Name => {
+                    match self.os_list.iter_mut().find(|x| x.name() == item.name()) {
+                        Some(x) if prefer_incoming => *x = item,
+                        Some(_) => {}
+                        None => self.os_list.push(item),
+                    }
                 }
             }
         }
     }
 }
 
+impl Extend<Self> for Config {
+    fn extend<T: IntoIterator<Item = Self>>(&mut self, iter: T) {
+        for config in iter.into_iter() {
+            self.merge(config, MergeStrategy::default());
+        }
+    }
+}
+
 impl OsListItem {
     pub fn icon(&self) -> &url::Url {
         match self {
@@ -254,6 +580,31 @@ impl OsListItem {
         }
     }
 
+    /// Minimum imager version required by the item. Versions required by children of
+    /// [OsSubList] are not included.
+    pub fn min_imager_version(&self) -> Option<&semver::Version> {
+        match self {
+            OsListItem::Image(img) => img.min_imager_version.as_ref(),
+            OsListItem::SubList(img) => img.min_imager_version.as_ref(),
+            OsListItem::RemoteSubList(_) => None,
+        }
+    }
+
+    /// Check if the item can be used by imager `version`. Imagers older than
+    /// [min_imager_version](Self::min_imager_version) might lack features required by the item.
+    pub fn is_supported(&self, version: &semver::Version) -> bool {
+        self.min_imager_version().is_none_or(|x| version >= x)
+    }
+
+    /// Iterate over all [OsImage] in the item (and it's children)
+    pub fn images(&self) -> Box<dyn Iterator<Item = &OsImage> + '_> {
+        match self {
+            OsListItem::Image(img) => Box::new(std::iter::once(img)),
+            OsListItem::SubList(item) => Box::new(item.subitems.iter().flat_map(Self::images)),
+            OsListItem::RemoteSubList(_) => Box::new(std::iter::empty()),
+        }
+    }
+
     /// Check if the [OsListItem] (or any of it's children) has an image for a board
     pub fn has_board_image(&self, tags: &HashSet<String>) -> bool {
         match self {
@@ -264,15 +615,583 @@ impl OsListItem {
     }
 }
 
+impl OsSubList {
+    /// Copy [OsSubList::devices] and [OsSubList::tags] to the items which do not declare their
+    /// own. Explicit per-item values always win. Nested sublists inherit too, and pass the result
+    /// further down.
+    pub fn inherit(&mut self) {
+        for item in self.subitems.iter_mut() {
+            match item {
+                OsListItem::Image(img) => {
+                    if img.devices.is_empty() {
+                        img.devices.clone_from(&self.devices);
+                    }
+                    if img.tags.is_empty() {
+                        img.tags.clone_from(&self.tags);
+                    }
+                }
+                OsListItem::SubList(list) => {
+                    if list.devices.is_empty() {
+                        list.devices.clone_from(&self.devices);
+                    }
+                    if list.tags.is_empty() {
+                        list.tags.clone_from(&self.tags);
+                    }
+                    list.inherit();
+                }
+                OsListItem::RemoteSubList(list) => {
+                    if list.devices.is_empty() {
+                        list.devices.clone_from(&self.devices);
+                    }
+                    if list.tags.is_empty() {
+                        list.tags.clone_from(&self.tags);
+                    }
+                }
+            }
+        }
+    }
+}
+
 impl OsRemoteSubList {
-    /// Construct [OsSubList] once subitems have been downloaded.
+    /// Construct [OsSubList] once subitems have been downloaded. [OsRemoteSubList::devices] and
+    /// [OsRemoteSubList::tags] are kept, so that [OsSubList::inherit] passes them down.
     pub fn resolve(self, subitems: Vec<OsListItem>) -> OsSubList {
         OsSubList {
             name: self.name,
             description: self.description,
             icon: self.icon,
             flasher: self.flasher,
+            devices: self.devices,
+            tags: self.tags,
+            min_imager_version: None,
             subitems,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"{
+        "os_list": [
+            {
+                "name": "Old Image",
+                "description": "Old Image",
+                "icon": "https://example.com/icon.png",
+                "url": "https://example.com/old.img.xz",
+                "image_download_sha256": "0000000000000000000000000000000000000000000000000000000000000000",
+                "extract_size": 1024,
+                "release_date": "2023-06-01",
+                "devices": ["beagle"]
+            },
+            {
+                "name": "Testing",
+                "description": "Testing Images",
+                "icon": "https://example.com/icon.png",
+                "subitems": [
+                    {
+                        "name": "New Image",
+                        "description": "New Image",
+                        "icon": "https://example.com/icon.png",
+                        "url": "https://example.com/new.img.xz",
+                        "image_download_sha256": "0000000000000000000000000000000000000000000000000000000000000000",
+                        "extract_size": 1024,
+                        "release_date": "2024-03-15",
+                        "devices": ["beagle"]
+                    }
+                ]
+            },
+            {
+                "name": "Exact Image",
+                "description": "Exact Image",
+                "icon": "https://example.com/icon.png",
+                "url": "https://example.com/exact.img.xz",
+                "image_download_sha256": "0000000000000000000000000000000000000000000000000000000000000000",
+                "extract_size": 1024,
+                "release_date": "2024-01-01",
+                "devices": ["beagle"]
+            }
+        ]
+    }"#;
+
+    const DANGLING_CONFIG: &str = r#"{
+        "imager": {
+            "devices": [
+                {
+                    "name": "BeagleY-AI",
+                    "tags": ["beagley-ai", "beagley-ai-old"],
+                    "icon": null,
+                    "description": "BeagleY-AI",
+                    "flasher": "SdCard",
+                    "documentation": null,
+                    "instructions": null,
+                    "oshw": null
+                }
+            ]
+        },
+        "os_list": [
+            {
+                "name": "BeagleY-AI Image",
+                "description": "BeagleY-AI Image",
+                "icon": "https://example.com/icon.png",
+                "url": "https://example.com/image.img.xz",
+                "image_download_sha256": "0000000000000000000000000000000000000000000000000000000000000000",
+                "extract_size": 1024,
+                "release_date": "2024-03-15",
+                "devices": ["beagley-ai", "beagley-ai-typo"]
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn validate() {
+        let config: Config = serde_json::from_str(CONFIG).unwrap();
+        assert_eq!(
+            config.validate(),
+            [
+                Warning::UnknownImageTag {
+                    image: "Old Image".to_string(),
+                    tag: "beagle".to_string()
+                },
+                Warning::UnknownImageTag {
+                    image: "New Image".to_string(),
+                    tag: "beagle".to_string()
+                },
+                Warning::UnknownImageTag {
+                    image: "Exact Image".to_string(),
+                    tag: "beagle".to_string()
+                }
+            ]
+        );
+
+        let config: Config = serde_json::from_str(DANGLING_CONFIG).unwrap();
+        assert_eq!(
+            config.validate(),
+            [
+                Warning::UnknownImageTag {
+                    image: "BeagleY-AI Image".to_string(),
+                    tag: "beagley-ai-typo".to_string()
+                },
+                Warning::UnusedBoardTag {
+                    board: "BeagleY-AI".to_string(),
+                    tag: "beagley-ai-old".to_string()
+                }
+            ]
+        );
+    }
+
+    fn device(name: &str, flasher: Flasher, documentation: Option<&str>) -> Device {
+        Device {
+            name: name.to_string(),
+            tags: HashSet::from([name.to_lowercase()]),
+            icon: None,
+            description: String::new(),
+            flasher,
+            documentation: documentation.map(|x| Url::parse(x).unwrap()),
+            instructions: None,
+            specification: Vec::new(),
+            oshw: None,
+        }
+    }
+
+    fn image(name: &str, extract_size: u64) -> OsListItem {
+        OsListItem::Image(OsImage {
+            name: name.to_string(),
+            description: String::new(),
+            icon: Url::parse("https://example.com/icon.png").unwrap(),
+            url: Url::parse("https://example.com/image.img.xz").unwrap(),
+            image_download_size: None,
+            image_download_sha256: [0; 32],
+            extract_size,
+            release_date: chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+            devices: HashSet::from(["beagle".to_string()]),
+            tags: HashSet::new(),
+            init_format: InitFormat::Sysconf,
+            bmap: None,
+            info_text: None,
+            min_imager_version: None,
+            release_notes: None,
+            changelog_url: None,
+        })
+    }
+
+    fn overlapping_configs() -> (Config, Config) {
+        let existing = Config {
+            imager: Imager {
+                remote_configs: HashSet::new(),
+                devices: vec![device(
+                    "Beagle",
+                    Flasher::SdCard,
+                    Some("https://example.com/existing"),
+                )],
+            },
+            os_list: vec![image("Image", 1024)],
+        };
+        let incoming = Config {
+            imager: Imager {
+                remote_configs: HashSet::new(),
+                devices: vec![device(
+                    "Beagle",
+                    Flasher::SdCard,
+                    Some("https://example.com/incoming"),
+                )],
+            },
+            os_list: vec![image("Image", 2048)],
+        };
+
+        (existing, incoming)
+    }
+
+    #[test]
+    fn merge_default() {
+        let (mut existing, incoming) = overlapping_configs();
+        let mut extended = existing.clone();
+
+        existing.merge(incoming.clone(), MergeStrategy::default());
+        extended.extend([incoming]);
+
+        assert_eq!(existing, extended);
+        assert_eq!(existing.imager.devices.len(), 1);
+        assert_eq!(
+            existing.imager.devices[0]
+                .documentation
+                .as_ref()
+                .unwrap()
+                .as_str(),
+            "https://example.com/incoming"
+        );
+        assert_eq!(
+            existing.os_list,
+            [image("Image", 1024), image("Image", 2048)]
+        );
+    }
+
+    #[test]
+    fn merge_prefer_existing() {
+        let (mut existing, incoming) = overlapping_configs();
+
+        existing.merge(
+            incoming,
+            MergeStrategy {
+                preference: DevicePreference::PreferExisting,
+                image_dedup: ImageDedup::Name,
+            },
+        );
+
+        let dev = &existing.imager.devices[0];
+        assert_eq!(
+            dev.documentation.as_ref().unwrap().as_str(),
+            "https://example.com/existing"
+        );
+        assert_eq!(existing.os_list, [image("Image", 1024)]);
+    }
+
+    #[test]
+    fn merge_prefer_incoming() {
+        let (mut existing, incoming) = overlapping_configs();
+
+        existing.merge(
+            incoming,
+            MergeStrategy {
+                preference: DevicePreference::PreferIncoming,
+                image_dedup: ImageDedup::Name,
+            },
+        );
+
+        let dev = &existing.imager.devices[0];
+        assert_eq!(
+            dev.documentation.as_ref().unwrap().as_str(),
+            "https://example.com/incoming"
+        );
+        assert_eq!(existing.os_list, [image("Image", 2048)]);
+    }
+
+    #[test]
+    fn merge_duplicate_board_name() {
+        let mut existing = Config::default();
+        existing
+            .imager
+            .devices
+            .push(device("Beagle", Flasher::SdCard, None));
+
+        let mut incoming = Config::default();
+        incoming
+            .imager
+            .devices
+            .push(device("Beagle", Flasher::BeagleConnectFreedom, None));
+
+        // Merging twice should not add the board again
+        existing.extend([incoming.clone(), incoming]);
+
+        let devs: Vec<(&str, Flasher)> = existing
+            .imager
+            .devices
+            .iter()
+            .map(|x| (x.name.as_str(), x.flasher))
+            .collect();
+        assert_eq!(
+            devs,
+            [
+                ("Beagle", Flasher::SdCard),
+                (
+                    "Beagle (BeagleConnect Freedom)",
+                    Flasher::BeagleConnectFreedom
+                )
+            ]
+        );
+        assert!(
+            !existing
+                .validate()
+                .iter()
+                .any(|x| matches!(x, Warning::DuplicateBoardName { .. }))
+        );
+
+        existing
+            .imager
+            .devices
+            .push(device("Beagle", Flasher::Pb2Mspm0, None));
+        assert!(existing.validate().contains(&Warning::DuplicateBoardName {
+            board: "Beagle".to_string()
+        }));
+
+        // Custom flashers use their registered name
+        let mut incoming = Config::default();
+        let custom = Flasher::Custom(CustomFlasher::new("my-flasher"));
+        incoming.imager.devices.push(device("Beagle", custom, None));
+        existing.merge(incoming, Default::default());
+        assert_eq!(
+            existing.imager.devices.last().unwrap().name,
+            "Beagle (my-flasher)"
+        );
+    }
+
+    #[test]
+    fn sublist_inherit() {
+        const INHERIT_CONFIG: &str = r#"{
+            "os_list": [
+                {
+                    "name": "Testing",
+                    "description": "Testing Images",
+                    "icon": "https://example.com/icon.png",
+                    "devices": ["beagle"],
+                    "tags": ["testing"],
+                    "subitems": [
+                        {
+                            "name": "Inherited Image",
+                            "description": "Inherited Image",
+                            "icon": "https://example.com/icon.png",
+                            "url": "https://example.com/inherited.img.xz",
+                            "image_download_sha256": "0000000000000000000000000000000000000000000000000000000000000000",
+                            "extract_size": 1024,
+                            "release_date": "2024-03-15"
+                        },
+                        {
+                            "name": "Explicit Image",
+                            "description": "Explicit Image",
+                            "icon": "https://example.com/icon.png",
+                            "url": "https://example.com/explicit.img.xz",
+                            "image_download_sha256": "0000000000000000000000000000000000000000000000000000000000000000",
+                            "extract_size": 1024,
+                            "release_date": "2024-03-15",
+                            "devices": ["other-beagle"]
+                        }
+                    ]
+                }
+            ]
+        }"#;
+
+        let mut config: Config = serde_json::from_str(INHERIT_CONFIG).unwrap();
+        config.inherit();
+
+        let images: Vec<_> = config.images().collect();
+        assert_eq!(images[0].devices, HashSet::from(["beagle".to_string()]));
+        assert_eq!(images[0].tags, HashSet::from(["testing".to_string()]));
+        assert_eq!(
+            images[1].devices,
+            HashSet::from(["other-beagle".to_string()])
+        );
+        assert_eq!(images[1].tags, HashSet::from(["testing".to_string()]));
+    }
+
+    fn orphan_image(name: &str) -> OsListItem {
+        let mut img = image(name, 1024);
+        if let OsListItem::Image(x) = &mut img {
+            x.devices.clear();
+        }
+        img
+    }
+
+    #[test]
+    fn remote_sublist_resolve() {
+        const REMOTE_CONFIG: &str = r#"{
+            "os_list": [
+                {
+                    "name": "Remote",
+                    "description": "Remote Images",
+                    "icon": "https://example.com/icon.png",
+                    "devices": ["beagle"],
+                    "tags": ["testing"],
+                    "subitems_url": "https://example.com/remote.json"
+                }
+            ]
+        }"#;
+
+        let config: Config = serde_json::from_str(REMOTE_CONFIG).unwrap();
+        let OsListItem::RemoteSubList(remote) = config.os_list[0].clone() else {
+            panic!("Expected remote sublist");
+        };
+
+        let mut list = remote.resolve(vec![orphan_image("Remote Image")]);
+        list.inherit();
+
+        let images: Vec<_> = list.subitems.iter().flat_map(OsListItem::images).collect();
+        assert_eq!(images[0].devices, HashSet::from(["beagle".to_string()]));
+        assert_eq!(images[0].tags, HashSet::from(["testing".to_string()]));
+    }
+
+    #[test]
+    fn validate_image_without_devices() {
+        let mut config: Config = serde_json::from_str(CONFIG).unwrap();
+        config.os_list.push(orphan_image("Orphan Image"));
+
+        assert!(config.validate().contains(&Warning::ImageWithoutDevices {
+            image: "Orphan Image".to_string()
+        }));
+    }
+
+    #[test]
+    fn canonical_json() {
+        let mut a = Config::default();
+        a.imager.devices = vec![
+            device("Beagle", Flasher::SdCard, None),
+            device("Another Beagle", Flasher::SdCard, None),
+        ];
+        a.imager.devices[0]
+            .tags
+            .extend(["b".to_string(), "a".to_string(), "c".to_string()]);
+        a.os_list = vec![image("Image", 1024), image("Another Image", 1024)];
+
+        let mut b = a.clone();
+        b.imager.devices.reverse();
+        b.os_list.reverse();
+
+        // Same tags, but inserted in different order
+        let tags = &mut b.imager.devices[1].tags;
+        tags.clear();
+        tags.extend(["c".to_string(), "a".to_string(), "b".to_string()]);
+        tags.insert("beagle".to_string());
+
+        assert_ne!(a, b);
+        assert_eq!(
+            a.to_canonical_json().unwrap(),
+            b.to_canonical_json().unwrap()
+        );
+
+        // Formatting should be idempotent
+        let data = include_str!("../../config.json");
+        let json = serde_json::from_str::<Config>(data)
+            .unwrap()
+            .to_canonical_json()
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<Config>(&json)
+                .unwrap()
+                .to_canonical_json()
+                .unwrap(),
+            json
+        );
+    }
+
+    #[test]
+    fn images_since() {
+        let config: Config = serde_json::from_str(CONFIG).unwrap();
+        assert_eq!(config.images().count(), 3);
+
+        let since = chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let names: Vec<&str> = config
+            .images_since(since)
+            .map(|x| x.name.as_str())
+            .collect();
+
+        assert_eq!(names, ["New Image", "Exact Image"]);
+    }
+
+    #[test]
+    fn release_notes() {
+        let config: Config = serde_json::from_str(CONFIG).unwrap();
+        let img = config.images().next().unwrap();
+        assert_eq!(img.release_notes, None);
+        assert_eq!(img.changelog_url, None);
+
+        // Optional fields are not serialized when missing
+        let json = serde_json::to_string(img).unwrap();
+        assert!(!json.contains("release_notes"));
+        assert!(!json.contains("changelog_url"));
+
+        let mut img = img.clone();
+        img.release_notes = Some("- Update kernel".to_string());
+        img.changelog_url = Some(Url::parse("https://example.com/changelog").unwrap());
+
+        let json = serde_json::to_value(&img).unwrap();
+        assert_eq!(json["release_notes"], "- Update kernel");
+        assert_eq!(json["changelog_url"], "https://example.com/changelog");
+        assert_eq!(serde_json::from_value::<OsImage>(json).unwrap(), img);
+    }
+
+    #[test]
+    fn custom_flasher() {
+        let flashers: Vec<Flasher> =
+            serde_json::from_str(r#"["SdCard", {"Custom": "Dummy"}, {"Custom": "Dummy"}]"#)
+                .unwrap();
+
+        assert_eq!(flashers[1], Flasher::Custom(CustomFlasher::new("Dummy")));
+        // Names are interned
+        let (Flasher::Custom(a), Flasher::Custom(b)) = (flashers[1], flashers[2]) else {
+            panic!("Expected custom flashers");
+        };
+        assert!(std::ptr::eq(a.name(), b.name()));
+
+        assert_eq!(
+            serde_json::to_string(&flashers[1]).unwrap(),
+            r#"{"Custom":"Dummy"}"#
+        );
+    }
+
+    #[test]
+    fn min_imager_version() {
+        let mut img = image("Image", 1024);
+        let version = semver::Version::new(1, 2, 0);
+        assert!(img.is_supported(&version));
+
+        if let OsListItem::Image(x) = &mut img {
+            x.min_imager_version = Some(semver::Version::new(1, 3, 0));
+        }
+        assert!(!img.is_supported(&version));
+        assert!(img.is_supported(&semver::Version::new(1, 3, 0)));
+        assert!(img.is_supported(&semver::Version::new(2, 0, 0)));
+
+        let json = serde_json::to_value(&img).unwrap();
+        assert_eq!(json["min_imager_version"], "1.3.0");
+        let item: OsListItem = serde_json::from_value(json).unwrap();
+        assert_eq!(item, img);
+    }
+
+    #[test]
+    fn init_format() {
+        let formats: Vec<InitFormat> =
+            serde_json::from_str(r#"["none", "sysconf", "armbian", "cloudinit", "raspberry"]"#)
+                .unwrap();
+
+        assert_eq!(
+            formats,
+            [
+                InitFormat::None,
+                InitFormat::Sysconf,
+                InitFormat::Armbian,
+                InitFormat::CloudInit,
+                InitFormat::Raspberry
+            ]
+        );
+    }
+}
@@ -23,8 +23,20 @@ pub mod config;
 pub const DISTROS_URL: &str =
     "https://raw.githubusercontent.com/beagleboard/distros/refs/heads/main/os_list.json";
 
+/// Environment variable pointing to a local config file. When set, applications should use it
+/// instead of the bundled config, and skip fetching remote configs. Useful while developing
+/// distros.json.
+pub const CONFIG_FILE_ENV: &str = "BB_CONFIG_FILE";
+
 pub use config::Config;
 
+/// Path of the local config override set using [CONFIG_FILE_ENV], if any.
+pub fn config_file_override() -> Option<std::path::PathBuf> {
+    std::env::var_os(CONFIG_FILE_ENV)
+        .filter(|x| !x.is_empty())
+        .map(Into::into)
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -32,4 +44,20 @@ mod tests {
         let data = include_bytes!("../../config.json");
         serde_json::from_slice::<super::Config>(data).unwrap();
     }
+
+    #[test]
+    fn config_file_override() {
+        // SAFETY: No other test reads or writes this variable.
+        unsafe { std::env::set_var(super::CONFIG_FILE_ENV, "/tmp/distros.json") };
+        assert_eq!(
+            super::config_file_override(),
+            Some(std::path::PathBuf::from("/tmp/distros.json"))
+        );
+
+        unsafe { std::env::set_var(super::CONFIG_FILE_ENV, "") };
+        assert_eq!(super::config_file_override(), None);
+
+        unsafe { std::env::remove_var(super::CONFIG_FILE_ENV) };
+        assert_eq!(super::config_file_override(), None);
+    }
 }
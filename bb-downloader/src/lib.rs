@@ -6,8 +6,20 @@
 //! - Async
 //! - Cache downloaded file in a directory in filesystem.
 //! - Check if a file is available in cache.
-//! - Uses SHA256 for verifying cached files.
+//! - Uses SHA256 for verifying cached files. Verified hashes are remembered, so unchanged files
+//!   are not re-hashed.
+//! - Optional BLAKE3 support for faster verification of large files (`blake3` feature).
+//! - Verify files using any RustCrypto digest, such as SHA512.
 //! - Optional support to download files without caching.
+//! - Resume interrupted downloads using HTTP Range requests.
+//! - Revalidate files cached with just URL using `ETag` and `Last-Modified` headers.
+//! - Download large files using concurrent HTTP Range requests.
+//! - Fall back to mirrors when a download fails.
+//! - Copy `file://` URLs from the local filesystem, e.g. for offline mirrors.
+//! - Cancel downloads using a `CancellationToken`.
+//! - List, verify, evict, prune and clear cached files.
+//! - Pluggable storage for cached files using [CacheStore].
+//! - [Fetcher] trait to replace network access with fixtures in tests.
 //!
 //! # Sample Usage
 //!
@@ -20,13 +32,13 @@
 //!     let url = "https://example.com/img.jpg";
 //!
 //!     // Download with just URL
-//!     downloader.download(url, None).await.unwrap();
+//!     downloader.download(url, None, None).await.unwrap();
 //!
 //!     // Check if the file is in cache
 //!     assert!(downloader.check_cache_from_url(url).is_some());
 //!
 //!     // Will fetch directly from cache instead of re-downloading
-//!     downloader.download(url, None).await.unwrap();
+//!     downloader.download(url, None, None).await.unwrap();
 //!
 //!     // Will fetch directly from cache instead of re-downloading
 //!     assert!(!downloader.check_cache_from_sha(sha).await.is_some());
@@ -41,16 +53,31 @@
 use futures::{Stream, StreamExt, channel::mpsc};
 #[cfg(feature = "json")]
 use serde::de::DeserializeOwned;
-use sha2::{Digest as _, Sha256};
+use sha2::{
+    Digest, Sha256,
+    digest::core_api::{AlgorithmName, CoreProxy},
+};
 use std::{
+    collections::{HashSet, VecDeque},
     io,
     path::{Path, PathBuf},
-    time::Duration,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
 };
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
 pub use reqwest::IntoUrl;
 
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(15);
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(500);
+const DEFAULT_MEMORY_LIMIT: u64 = 8 * 1024 * 1024;
+/// Extension of files cached with just URL, when the URL does not have one.
+const DEFAULT_EXTENSION: &str = "bin";
+/// Window over which [DownloadProgress::bytes_per_sec] is measured.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(1);
+
 /// Simple downloader that caches files in the provided directory. Uses SHA256 to determine if the
 /// file is already downloaded.
 ///
@@ -63,58 +90,152 @@ pub use reqwest::IntoUrl;
 /// verficiation on the downloaded file. Additionally, it also adds capability to invalidate cached
 /// file.
 ///
-/// Files downloaded with just URL cannot be invalidated without changing the URL, or deleting the
-/// file manually.
+/// Files downloaded with just URL are revalidated using conditional requests if the server
+/// provided `ETag` or `Last-Modified` headers. Else they cannot be invalidated without changing
+/// the URL, or deleting the file manually.
 ///
 /// # Thread Safety
 ///
 /// You do not have to wrap the Client in an Rc or Arc to reuse it, because it already uses an Arc
 /// internally.
+///
+/// # Retries
+///
+/// Failed requests are retried on connection errors and 429/5xx responses, with exponential
+/// backoff. Partial downloads are resumed when retrying.
+///
+/// # Cancellation
+///
+/// Downloads can be cancelled using [`with_cancel`](Self::with_cancel), in which case they fail
+/// with [`io::ErrorKind::Interrupted`]. Partial downloads are kept, so they can be resumed later.
 #[derive(Debug, Clone)]
 pub struct Downloader {
     client: reqwest::Client,
     cache_dir: PathBuf,
+    max_retries: u32,
+    base_delay: Duration,
+    memory_limit: u64,
+    headers: reqwest::header::HeaderMap,
+    cancel: Option<tokio_util::sync::CancellationToken>,
+    store: Arc<dyn CacheStore>,
 }
 
 impl Downloader {
     /// Create a new downloader that uses a directory for storing cached files.
     pub fn new<P: Into<PathBuf>>(cache_dir: P) -> io::Result<Self> {
-        let cache_dir = cache_dir.into();
+        Self::builder(cache_dir).build()
+    }
 
-        if !cache_dir.exists() {
-            let _ = std::fs::create_dir_all(&cache_dir);
+    /// Create a builder to configure the HTTP client used by the downloader.
+    pub fn builder<P: Into<PathBuf>>(cache_dir: P) -> DownloaderBuilder {
+        DownloaderBuilder {
+            cache_dir: cache_dir.into(),
+            proxy: None,
+            system_proxy: true,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            pool_max_idle_per_host: None,
+            user_agent: env!("CARGO_PKG_NAME").to_string(),
+            store: None,
         }
+    }
 
-        if cache_dir.exists() && !cache_dir.is_dir() {
-            return Err(io::Error::new(
-                io::ErrorKind::NotADirectory,
-                "cache_dir should be a directory",
-            ));
-        }
+    /// Number of times a failed request is retried. Set to 0 to disable retries.
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
 
-        let client = reqwest::Client::builder()
-            .user_agent(env!("CARGO_PKG_NAME"))
-            .connect_timeout(Duration::from_secs(10))
-            .read_timeout(Duration::from_secs(15))
-            .build()
-            .expect("Unsupported OS");
+    /// Delay before the first retry. The delay is doubled for each subsequent retry, with some
+    /// random jitter added.
+    pub fn with_base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// Maximum size of files downloaded to memory using
+    /// [`download_bytes_no_cache`](Self::download_bytes_no_cache). Defaults to 8 MiB.
+    pub fn with_memory_limit(mut self, memory_limit: u64) -> Self {
+        self.memory_limit = memory_limit;
+        self
+    }
+
+    /// Add `headers` to all requests. Useful for files hosted behind authenticated endpoints.
+    /// Since [Downloader] is cheap to clone, a clone can be used to add headers for a single
+    /// download.
+    ///
+    /// Headers containing credentials should be marked as sensitive, so they are not logged.
+    pub fn with_headers(mut self, headers: reqwest::header::HeaderMap) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    /// Add `Authorization: Bearer <token>` header to all requests. See
+    /// [`with_headers`](Self::with_headers).
+    pub fn with_bearer_auth(mut self, token: &str) -> io::Result<Self> {
+        let mut value = reqwest::header::HeaderValue::try_from(format!("Bearer {token}"))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        value.set_sensitive(true);
+
+        self.headers.insert(reqwest::header::AUTHORIZATION, value);
+        Ok(self)
+    }
+
+    fn get<U: reqwest::IntoUrl>(&self, url: U) -> reqwest::RequestBuilder {
+        self.client.get(url).headers(self.headers.clone())
+    }
+
+    fn head<U: reqwest::IntoUrl>(&self, url: U) -> reqwest::RequestBuilder {
+        self.client.head(url).headers(self.headers.clone())
+    }
+
+    /// Cancel all downloads started using this downloader when `cancel` is cancelled. Since
+    /// [Downloader] is cheap to clone, a clone can be used to cancel a single download.
+    pub fn with_cancel(mut self, cancel: tokio_util::sync::CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
 
-        Ok(Self { client, cache_dir })
+    fn check_cancel(&self) -> io::Result<()> {
+        match &self.cancel {
+            Some(x) if x.is_cancelled() => Err(cancelled()),
+            _ => Ok(()),
+        }
     }
 
     /// Check if a downloaded file with a particular SHA256 is already in cache.
     pub async fn check_cache_from_sha(&self, sha256: [u8; 32]) -> Option<PathBuf> {
-        let file_path = self.path_from_sha(sha256);
+        self.check_cache::<Sha256Hasher>(&sha256).await
+    }
+
+    /// Check if a downloaded file with a particular BLAKE3 hash is already in cache.
+    #[cfg(feature = "blake3")]
+    pub async fn check_cache_from_blake3(&self, hash: [u8; 32]) -> Option<PathBuf> {
+        self.check_cache::<blake3::Hasher>(&hash).await
+    }
+
+    /// Check if a downloaded file with a particular hash, using digest `D`, is already in cache.
+    pub async fn check_cache_verified<D>(&self, expected: &[u8]) -> Option<PathBuf>
+    where
+        D: Digest + CoreProxy + Send,
+        D::Core: AlgorithmName,
+    {
+        self.check_cache::<DigestHasher<D>>(expected).await
+    }
+
+    async fn check_cache<D: CacheDigest>(&self, expected: &[u8]) -> Option<PathBuf> {
+        let key = key_from_hash::<D>(expected);
 
-        if file_path.exists() {
-            if let Ok(hash) = sha256_from_path(&file_path).await
-                && hash == sha256
+        if let Some(file_path) = self.store.get(&key) {
+            if let Ok(hash) = cached_digest::<D, _>(&file_path, digest_from_path::<D>).await
+                && hash == expected
             {
                 return Some(file_path);
             }
 
             // Delete old file
-            let _ = tokio::fs::remove_file(&file_path).await;
+            let _ = self.store.remove(&key);
+            let _ = tokio::fs::remove_file(sidecar_path(&file_path)).await;
         }
 
         None
@@ -126,12 +247,7 @@ impl Downloader {
     /// of the file to download is already known.
     pub fn check_cache_from_url<U: reqwest::IntoUrl>(&self, url: U) -> Option<PathBuf> {
         // Use hash of url for file name
-        let file_path = self.path_from_url(&url.into_url().ok()?);
-        if file_path.exists() {
-            Some(file_path)
-        } else {
-            None
-        }
+        self.store.get(&key_from_url(&into_url(url).ok()?))
     }
 
     /// Download a JSON file without caching the contents. Should be used when there is no point in
@@ -142,8 +258,7 @@ impl Downloader {
         T: DeserializeOwned,
         U: reqwest::IntoUrl,
     {
-        self.client
-            .get(url)
+        self.get(url)
             .send()
             .await
             .map_err(io::Error::other)?
@@ -152,28 +267,128 @@ impl Downloader {
             .map_err(io::Error::other)
     }
 
+    /// Download a small file to memory without caching the contents. Should be used for small
+    /// assets like icons, where there is no point in caching the file.
+    ///
+    /// Fails with [`io::ErrorKind::FileTooLarge`] if the file is larger than the memory limit, see
+    /// [`with_memory_limit`](Self::with_memory_limit).
+    pub async fn download_bytes_no_cache<U: reqwest::IntoUrl>(
+        &self,
+        url: U,
+    ) -> io::Result<bytes::Bytes> {
+        self.check_cancel()?;
+
+        let response = cancellable(self.cancel.as_ref(), self.get(url).send())
+            .await?
+            .and_then(|x| x.error_for_status())
+            .map_err(io::Error::other)?;
+
+        let too_large = || {
+            io::Error::new(
+                io::ErrorKind::FileTooLarge,
+                format!("File is larger than {} bytes", self.memory_limit),
+            )
+        };
+        if response
+            .content_length()
+            .is_some_and(|x| x > self.memory_limit)
+        {
+            return Err(too_large());
+        }
+
+        let mut data = bytes::BytesMut::new();
+        let mut response_stream = response.bytes_stream();
+        while let Some(x) = cancellable(self.cancel.as_ref(), response_stream.next()).await? {
+            let x = x.map_err(io::Error::other)?;
+            // Server can send more data than advertised
+            if (data.len() + x.len()) as u64 > self.memory_limit {
+                return Err(too_large());
+            }
+            data.extend_from_slice(&x);
+        }
+
+        Ok(data.freeze())
+    }
+
     /// Checks if the file is present in cache. If the file is present, returns path to it. Else
     /// downloads the file.
     ///
-    /// [`download_with_sha`](Self::download_with_sha) should be prefered when the SHA256 of the
-    /// file is known in advance.
+    /// If `sha256` is provided, this is the same as [`download_with_sha`](Self::download_with_sha).
+    /// Else, the file is cached by URL and not verified.
+    ///
+    /// # Revalidation
+    ///
+    /// If the server provided `ETag` or `Last-Modified` headers when the file was downloaded, a
+    /// conditional request is used to check if the cached file is still up to date. The file is
+    /// downloaded again if it changed. The cached file is used if the server cannot be reached.
     ///
     /// # Progress
     ///
-    /// Download progress can be optionally tracked using a [`futures::channel::mpsc`].
+    /// Download progress can be optionally tracked using a [`futures::channel::mpsc`]. Use
+    /// [`download_tracked`](Self::download_tracked) for download speed and resume events.
     pub async fn download<U: reqwest::IntoUrl>(
         &self,
         url: U,
+        sha256: Option<[u8; 32]>,
         chan: Option<mpsc::Sender<f32>>,
     ) -> io::Result<PathBuf> {
-        let url = url.into_url().map_err(io::Error::other)?;
+        forward_progress(chan, |chan| self.download_tracked(url, sha256, chan)).await
+    }
+
+    /// Same as [`download`](Self::download), but reports all [DownloadEvent]s.
+    pub async fn download_tracked<U: reqwest::IntoUrl>(
+        &self,
+        url: U,
+        sha256: Option<[u8; 32]>,
+        chan: Option<mpsc::Sender<DownloadEvent>>,
+    ) -> io::Result<PathBuf> {
+        if let Some(sha256) = sha256 {
+            return self.download_with_sha_tracked(url, sha256, chan).await;
+        }
+
+        let url = into_url(url)?;
 
         // Check cache
         if let Some(p) = self.check_cache_from_url(url.clone()) {
-            return Ok(p);
+            match self.is_modified(url.clone(), &p).await {
+                Ok(false) => return Ok(p),
+                Ok(true) => {
+                    tracing::info!("{url} changed, downloading again");
+                    // Partial file might belong to the old file
+                    let _ = tokio::fs::remove_file(part_path(&p)).await;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to revalidate {url}: {e}. Using cached file");
+                    return Ok(p);
+                }
+            }
         }
 
-        self.download_no_cache(url, chan).await
+        self.download_no_cache_tracked(url, chan).await
+    }
+
+    /// Check if the file at `url` changed since it was cached at `p`, using a conditional request.
+    /// Files without validators are never considered modified.
+    async fn is_modified(&self, url: reqwest::Url, p: &Path) -> io::Result<bool> {
+        let Some(validators) = Validators::read(p).await else {
+            return Ok(false);
+        };
+
+        // Body of a modified file is not used, so the download can be resumed and retried
+        let response = validators
+            .apply(self.get(url))
+            .send()
+            .await
+            .map_err(io::Error::other)?;
+
+        match response.status() {
+            reqwest::StatusCode::NOT_MODIFIED => Ok(false),
+            x if x.is_success() => Ok(true),
+            _ => response
+                .error_for_status()
+                .map(|_| false)
+                .map_err(io::Error::other),
+        }
     }
 
     /// Downloads the file without checking cache.
@@ -183,7 +398,14 @@ impl Downloader {
     ///
     /// # Progress
     ///
-    /// Download progress can be optionally tracked using a [`futures::channel::mpsc`].
+    /// Download progress can be optionally tracked using a [`futures::channel::mpsc`]. Use
+    /// [`download_no_cache_tracked`](Self::download_no_cache_tracked) for download speed and
+    /// resume events.
+    ///
+    /// # Resume
+    ///
+    /// Partial downloads are kept on failure, and resumed on the next call if the server supports
+    /// it.
     ///
     /// # Differences from [Self::download]
     ///
@@ -192,176 +414,328 @@ impl Downloader {
     pub async fn download_no_cache<U: reqwest::IntoUrl>(
         &self,
         url: U,
-        mut chan: Option<mpsc::Sender<f32>>,
+        chan: Option<mpsc::Sender<f32>>,
     ) -> io::Result<PathBuf> {
-        let url = url.into_url().map_err(io::Error::other)?;
-
-        let file_path = self.path_from_url(&url);
-        chan_send(chan.as_mut(), 0.0);
-
-        let mut cur_pos = 0;
-        let mut file = AsyncTempFile::new()?;
-        {
-            let mut file = tokio::io::BufWriter::new(&mut file.0);
+        forward_progress(chan, |chan| self.download_no_cache_tracked(url, chan)).await
+    }
 
-            let response = self
-                .client
-                .get(url)
-                .send()
-                .await
-                .map_err(io::Error::other)?;
-            let response_size = response.content_length();
-            let mut response_stream = response.bytes_stream();
+    /// Same as [`download_no_cache`](Self::download_no_cache), but reports all [DownloadEvent]s.
+    /// [`DownloadEvent::Resumed`] is sent when a download is resumed.
+    pub async fn download_no_cache_tracked<U: reqwest::IntoUrl>(
+        &self,
+        url: U,
+        mut chan: Option<mpsc::Sender<DownloadEvent>>,
+    ) -> io::Result<PathBuf> {
+        let url = into_url(url)?;
 
-            let response_size = match response_size {
-                Some(x) => x as usize,
-                None => response_stream.size_hint().0,
-            };
+        let key = key_from_url(&url);
+        chan_send(chan.as_mut(), DownloadEvent::Progress(0.0));
 
-            while let Some(x) = response_stream.next().await {
-                let mut data = x.map_err(io::Error::other)?;
-                cur_pos += data.len();
-                file.write_all_buf(&mut data).await?;
-                chan_send(chan.as_mut(), (cur_pos as f32) / (response_size as f32));
-            }
+        let part = part_path(&self.cache_dir.join(&key));
+        let (_, validators) = self
+            .download_part::<Sha256Hasher>(url, &part, false, chan.as_mut())
+            .await?;
 
-            file.flush().await?
-        }
+        let file_path = self.store.put(&key, &part)?;
+        let _ = validators.write(&file_path).await;
 
-        file.persist(&file_path).await?;
         Ok(file_path)
     }
 
     /// Downloads the file and streams the content to pipe. This allows not having to wait for the
     /// download to finish to use the partial file.
     ///
-    /// Uses SHA256 to verify that the file in cache is valid.
+    /// Uses SHA256 to verify that the file in cache is valid. Partial downloads are resumed, in
+    /// which case the already downloaded part is streamed first.
+    ///
+    /// # Cancellation
+    ///
+    /// Once the complete file is downloaded and verified, it is saved to cache even if the
+    /// returned future is dropped. So a flashing process cancelled after the download does not
+    /// need to download the file again.
     pub async fn download_to_stream<U: reqwest::IntoUrl>(
         self,
         url: U,
         sha256: [u8; 32],
-        mut writer: bb_helper::file_stream::WriterFileStream,
+        writer: bb_helper::file_stream::WriterFileStream,
     ) -> io::Result<()> {
-        let url = url.into_url().map_err(io::Error::other)?;
+        let url = into_url(url)?;
         tracing::debug!(
             "Download {:?} with sha256: {:?}",
             url,
             const_hex::encode(sha256)
         );
 
-        let file_path = self.path_from_sha(sha256);
-
-        {
-            let mut file = tokio::io::BufWriter::new(&mut writer);
-
-            let response = self
-                .client
-                .get(url)
-                .send()
-                .await
-                .map_err(io::Error::other)?;
+        // Local files are quick to copy, so there is no need to stream while copying
+        if is_local(&url) {
+            let p = self.download_with_sha(url, sha256, None).await?;
+            let mut writer = writer;
+            tokio::io::copy(&mut tokio::fs::File::open(p).await?, &mut writer).await?;
+            return writer.flush().await;
+        }
 
-            let mut response_stream = response.bytes_stream();
+        let key = key_from_hash::<Sha256Hasher>(&sha256);
+        let mut writer = tokio::io::BufWriter::new(writer);
+        let mut hasher = Sha256Hasher::init();
 
-            let mut hasher = Sha256::new();
+        let part = part_path(&self.cache_dir.join(&key));
 
-            while let Some(x) = response_stream.next().await {
-                tracing::debug!("Got buf");
-                let mut data = x.map_err(io::Error::other)?;
-                hasher.update(&data);
-                file.write_all_buf(&mut data).await?;
+        let mut attempt = 0;
+        let mut download = loop {
+            match self.get_resumable(url.clone(), part.clone(), None).await {
+                Ok(x) => break x,
+                Err(e) if self.backoff(attempt, &e).await => attempt += 1,
+                Err(e) => return Err(e),
             }
+        };
+        download.read_prefix(&mut hasher, &mut writer).await?;
 
-            let hash: [u8; 32] = hasher
-                .finalize()
-                .as_slice()
-                .try_into()
-                .expect("SHA-256 is 32 bytes");
+        loop {
+            match download
+                .write_rest(Some(&mut hasher), &mut writer, None)
+                .await
+            {
+                Ok(()) => break,
+                Err(e) if self.backoff(attempt, &e).await => attempt += 1,
+                Err(e) => return Err(e),
+            }
 
-            if hash != sha256 {
-                tracing::error!(
-                    "Expected SHA256: {}, got {}",
-                    const_hex::encode(sha256),
-                    const_hex::encode(hash)
-                );
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "Invalid SHA256",
+            // Data already streamed cannot be taken back, so only retry if the download resumes
+            // exactly where it stopped.
+            let streamed = tokio::fs::metadata(&part).await?.len();
+            download = self.get_resumable(url.clone(), part.clone(), None).await?;
+            if download.offset != streamed {
+                return Err(io::Error::other(
+                    "Server does not support resuming, cannot retry stream",
                 ));
             }
-            file.flush().await?;
         }
+        verify_part(&part, hasher, &sha256).await?;
 
-        tracing::info!("Saving donwloaded file to disk");
-        writer.persist(&file_path).await
+        tokio::spawn(async move {
+            tracing::info!("Saving donwloaded file to disk");
+
+            writer.flush().await?;
+            // Partial file is complete, and was already verified during download
+            let file_path = self.store.put(&key, &part)?;
+            let _ = write_sidecar::<Sha256Hasher>(&file_path, &sha256).await;
+
+            Ok(())
+        })
+        .await
+        .map_err(io::Error::other)?
     }
 
     /// Checks if the file is present in cache. If the file is present, returns path to it. Else
     /// downloads the file.
     ///
-    /// Uses SHA256 to verify that the file in cache is valid.
+    /// Uses SHA256 to verify that the file in cache is valid. Partial downloads are resumed, see
+    /// [`download_no_cache`](Self::download_no_cache).
     ///
     /// # Progress
     ///
-    /// Download progress can be optionally tracked using a [`futures::channel::mpsc`].
+    /// Download progress can be optionally tracked using a [`futures::channel::mpsc`]. Use
+    /// [`download_with_sha_tracked`](Self::download_with_sha_tracked) for download speed and
+    /// resume events.
     pub async fn download_with_sha<U: reqwest::IntoUrl>(
         &self,
         url: U,
         sha256: [u8; 32],
-        mut chan: Option<mpsc::Sender<f32>>,
+        chan: Option<mpsc::Sender<f32>>,
+    ) -> io::Result<PathBuf> {
+        forward_progress(chan, |chan| {
+            self.download_with_sha_tracked(url, sha256, chan)
+        })
+        .await
+    }
+
+    /// Same as [`download_with_sha`](Self::download_with_sha), but reports all [DownloadEvent]s.
+    pub async fn download_with_sha_tracked<U: reqwest::IntoUrl>(
+        &self,
+        url: U,
+        sha256: [u8; 32],
+        chan: Option<mpsc::Sender<DownloadEvent>>,
+    ) -> io::Result<PathBuf> {
+        self.download_verified::<Sha256, U>(url, &sha256, chan)
+            .await
+    }
+
+    /// Same as [`download_with_sha`](Self::download_with_sha), but uses BLAKE3 to verify the file,
+    /// which is much faster for large files.
+    ///
+    /// Files downloaded with BLAKE3 can only be found in cache using
+    /// [`check_cache_from_blake3`](Self::check_cache_from_blake3).
+    #[cfg(feature = "blake3")]
+    pub async fn download_with_blake3<U: reqwest::IntoUrl>(
+        &self,
+        url: U,
+        hash: [u8; 32],
+        chan: Option<mpsc::Sender<DownloadEvent>>,
+    ) -> io::Result<PathBuf> {
+        self.download_digest::<blake3::Hasher, U>(url, &hash, chan)
+            .await
+    }
+
+    /// Same as [`download_with_sha`](Self::download_with_sha), but uses digest `D` (e.g.
+    /// [`sha2::Sha512`]) to verify the file.
+    ///
+    /// Files are cached separately for each digest, and can only be found in cache using
+    /// [`check_cache_verified`](Self::check_cache_verified) with the same digest.
+    pub async fn download_verified<D, U>(
+        &self,
+        url: U,
+        expected: &[u8],
+        chan: Option<mpsc::Sender<DownloadEvent>>,
+    ) -> io::Result<PathBuf>
+    where
+        D: Digest + CoreProxy + Send,
+        D::Core: AlgorithmName,
+        U: reqwest::IntoUrl,
+    {
+        self.download_digest::<DigestHasher<D>, U>(url, expected, chan)
+            .await
+    }
+
+    async fn download_digest<D: CacheDigest, U: reqwest::IntoUrl>(
+        &self,
+        url: U,
+        expected: &[u8],
+        mut chan: Option<mpsc::Sender<DownloadEvent>>,
     ) -> io::Result<PathBuf> {
-        let url = url.into_url().map_err(io::Error::other)?;
+        let url = into_url(url)?;
         tracing::debug!(
-            "Download {:?} with sha256: {:?}",
+            "Download {:?} with {}: {:?}",
             url,
-            const_hex::encode(sha256)
+            D::name(),
+            const_hex::encode(expected)
         );
 
-        if let Some(p) = self.check_cache_from_sha(sha256).await {
+        if let Some(p) = self.check_cache::<D>(expected).await {
             return Ok(p);
         }
 
-        let file_path = self.path_from_sha(sha256);
-        chan_send(chan.as_mut(), 0.0);
+        let key = key_from_hash::<D>(expected);
+        chan_send(chan.as_mut(), DownloadEvent::Progress(0.0));
 
-        let mut file = AsyncTempFile::new()?;
-        {
-            let mut file = tokio::io::BufWriter::new(&mut file.0);
+        let part = part_path(&self.cache_dir.join(&key));
+        let (hasher, _) = self
+            .download_part::<D>(url, &part, true, chan.as_mut())
+            .await?;
+        verify_part(&part, hasher.expect("Hash requested"), expected).await?;
 
-            let response = self
-                .client
-                .get(url)
-                .send()
-                .await
-                .map_err(io::Error::other)?;
+        let file_path = self.store.put(&key, &part)?;
+        // Hash was already verified during download
+        let _ = write_sidecar::<D>(&file_path, expected).await;
 
-            let mut cur_pos = 0;
-            let response_size = response.content_length();
+        Ok(file_path)
+    }
+
+    /// Checks if the file is present in cache. If the file is present, returns path to it. Else
+    /// downloads the file from the first of `urls` that succeeds.
+    ///
+    /// All mirrors are expected to serve the same file, so a partial download from a failed mirror
+    /// is resumed from the next one. A mirror serving a file with a different SHA256 is treated as
+    /// a failure. Returns the error of the last mirror if all of them fail.
+    ///
+    /// See [`download_with_sha`](Self::download_with_sha) for details.
+    pub async fn download_with_mirrors(
+        &self,
+        urls: &[reqwest::Url],
+        sha256: [u8; 32],
+        chan: Option<mpsc::Sender<DownloadEvent>>,
+    ) -> io::Result<PathBuf> {
+        let mut res = Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "No URL to download from",
+        ));
+
+        for url in urls {
+            res = self
+                .download_with_sha_tracked(url.clone(), sha256, chan.clone())
+                .await;
+            match &res {
+                Ok(_) => break,
+                Err(e) => tracing::warn!("Failed to download from mirror {url}: {e}"),
+            }
+        }
 
-            let mut response_stream = response.bytes_stream();
+        res
+    }
 
-            let response_size = match response_size {
-                Some(x) => x as usize,
-                None => response_stream.size_hint().0,
-            };
+    /// Download all `urls` using [`download`](Self::download), with at most `concurrency`
+    /// downloads in flight. Duplicate URLs are only downloaded once.
+    ///
+    /// Results are yielded as downloads finish, which may not be the order of `urls`.
+    pub fn download_many(
+        &self,
+        urls: impl IntoIterator<Item = reqwest::Url>,
+        concurrency: usize,
+    ) -> impl Stream<Item = (reqwest::Url, io::Result<PathBuf>)> + Send + 'static {
+        let mut seen = HashSet::new();
+        let downloads: Vec<_> = urls
+            .into_iter()
+            .filter(|x| seen.insert(x.clone()))
+            .map(|url| {
+                let downloader = self.clone();
+                async move {
+                    let res = downloader.download(url.clone(), None, None).await;
+                    (url, res)
+                }
+            })
+            .collect();
+
+        futures::stream::iter(downloads).buffer_unordered(concurrency.max(1))
+    }
 
-            let mut hasher = Sha256::new();
+    /// Checks if the file is present in cache. If the file is present, returns path to it. Else
+    /// downloads the file using `parts` concurrent `Range` requests, which is faster for large
+    /// files when a single connection cannot use all the bandwidth.
+    ///
+    /// Falls back to [`download_with_sha`](Self::download_with_sha) if the server does not
+    /// advertise support for `Range` requests or does not provide the file size. Unlike
+    /// [`download_with_sha`](Self::download_with_sha), failed downloads are not resumed.
+    ///
+    /// Uses SHA256 to verify the complete file before saving it to cache.
+    ///
+    /// # Progress
+    ///
+    /// Download progress of all parts is combined, and can be optionally tracked using a
+    /// [`futures::channel::mpsc`].
+    pub async fn download_chunked<U: reqwest::IntoUrl>(
+        &self,
+        url: U,
+        sha256: [u8; 32],
+        parts: usize,
+        mut chan: Option<mpsc::Sender<DownloadEvent>>,
+    ) -> io::Result<PathBuf> {
+        let url = into_url(url)?;
+
+        if let Some(p) = self.check_cache_from_sha(sha256).await {
+            return Ok(p);
+        }
 
-            while let Some(x) = response_stream.next().await {
-                let mut data = x.map_err(io::Error::other)?;
-                cur_pos += data.len();
-                hasher.update(&data);
-                file.write_all_buf(&mut data).await?;
+        if is_local(&url) {
+            return self.download_with_sha_tracked(url, sha256, chan).await;
+        }
 
-                chan_send(chan.as_mut(), (cur_pos as f32) / (response_size as f32));
+        let size = match self.range_size(url.clone()).await? {
+            Some(x) if parts > 1 => x,
+            _ => {
+                tracing::info!("Range requests not supported, downloading as a single stream");
+                return self.download_with_sha_tracked(url, sha256, chan).await;
             }
+        };
+
+        self.check_space(Some(size), 0)?;
+        let key = key_from_hash::<Sha256Hasher>(&sha256);
+        let chunks_path = chunks_path(&self.cache_dir.join(&key));
+        chan_send(chan.as_mut(), DownloadEvent::Progress(0.0));
 
-            let hash: [u8; 32] = hasher
-                .finalize()
-                .as_slice()
-                .try_into()
-                .expect("SHA-256 is 32 bytes");
+        let res = async {
+            self.download_chunks(url, &chunks_path, size, parts, chan)
+                .await?;
 
+            let hash = digest_from_path::<Sha256Hasher>(&chunks_path).await?;
             if hash != sha256 {
                 tracing::error!(
                     "Expected SHA256: {}, got {}",
@@ -373,83 +747,2637 @@ impl Downloader {
                     "Invalid SHA256",
                 ));
             }
-            file.flush().await?;
+
+            self.store.put(&key, &chunks_path)
         }
+        .await;
+
+        let file_path = match res {
+            Ok(x) => x,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&chunks_path).await;
+                return Err(e);
+            }
+        };
+
+        // Hash was already verified after download
+        let _ = write_sidecar::<Sha256Hasher>(&file_path, &sha256).await;
 
-        file.persist(&file_path).await?;
         Ok(file_path)
     }
 
-    fn path_from_url(&self, url: &reqwest::Url) -> PathBuf {
-        let fext = Path::new(url.path()).extension().expect("Invalid URL");
-        let file_name: [u8; 32] = Sha256::new()
-            .chain_update(url.as_str())
-            .finalize()
-            .as_slice()
-            .try_into()
-            .expect("SHA-256 is 32 bytes");
-        self.path_from_sha(file_name).with_extension(fext)
+    /// Size of the file at `url`, if the server supports `Range` requests.
+    async fn range_size(&self, url: reqwest::Url) -> io::Result<Option<u64>> {
+        let response = self.head(url).send().await.map_err(io::Error::other)?;
+        let headers = response.headers();
+
+        if !response.status().is_success()
+            || headers
+                .get(reqwest::header::ACCEPT_RANGES)
+                .is_none_or(|x| x != "bytes")
+        {
+            return Ok(None);
+        }
+
+        // Response::content_length is the size of body, which is always empty for HEAD.
+        let size = headers
+            .get(reqwest::header::CONTENT_LENGTH)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.parse().ok());
+
+        Ok(size.filter(|x| *x > 0))
     }
 
-    fn path_from_sha(&self, sha256: [u8; 32]) -> PathBuf {
-        let file_name = const_hex::encode(sha256);
-        self.cache_dir.join(file_name)
+    /// Download file of `size` bytes to `path` in `parts` chunks concurrently.
+    async fn download_chunks(
+        &self,
+        url: reqwest::Url,
+        path: &Path,
+        size: u64,
+        parts: usize,
+        chan: Option<mpsc::Sender<DownloadEvent>>,
+    ) -> io::Result<()> {
+        let file = tokio::fs::File::create(path).await?;
+        file.set_len(size).await?;
+
+        let chunk_size = size.div_ceil(parts as u64);
+        let meter = Mutex::new(Meter::new(0, Some(size)));
+
+        let chunks = (0..parts as u64)
+            .map(|i| i * chunk_size)
+            .take_while(|x| *x < size)
+            .map(|start| {
+                let progress = ChunkProgress {
+                    meter: &meter,
+                    chan: chan.clone(),
+                };
+                self.download_chunk(
+                    url.clone(),
+                    path,
+                    start..(start + chunk_size).min(size),
+                    progress,
+                )
+            });
+
+        futures::future::try_join_all(chunks).await?;
+        Ok(())
     }
-}
 
-async fn sha256_from_path(p: &Path) -> io::Result<[u8; 32]> {
-    let file = tokio::fs::File::open(p).await?;
-    let mut reader = tokio::io::BufReader::new(file);
-    let mut hasher = Sha256::new();
-    let mut buffer = [0; 512];
+    /// Download `range` of the file, and write it at the same position in file at `path`.
+    async fn download_chunk(
+        &self,
+        url: reqwest::Url,
+        path: &Path,
+        range: std::ops::Range<u64>,
+        mut progress: ChunkProgress<'_>,
+    ) -> io::Result<()> {
+        let response = self
+            .get(url)
+            .header(
+                reqwest::header::RANGE,
+                format!("bytes={}-{}", range.start, range.end - 1),
+            )
+            .send()
+            .await
+            .map_err(io::Error::other)?;
 
-    loop {
-        let count = reader.read(&mut buffer).await?;
-        if count == 0 {
-            break;
+        if response.status() != reqwest::StatusCode::PARTIAL_CONTENT
+            || content_range_start(&response) != Some(range.start)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Server did not honour Range request",
+            ));
+        }
+
+        let mut file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+        file.seek(io::SeekFrom::Start(range.start)).await?;
+        let mut file = tokio::io::BufWriter::new(file);
+
+        let mut pos = range.start;
+        let mut response_stream = response.bytes_stream();
+        while let Some(x) = cancellable(self.cancel.as_ref(), response_stream.next()).await? {
+            let data = x.map_err(io::Error::other)?;
+
+            pos += data.len() as u64;
+            if pos > range.end {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Server sent more data than requested",
+                ));
+            }
+
+            file.write_all(&data).await?;
+            progress.add(data.len() as u64);
+        }
+
+        if pos != range.end {
+            return Err(io::ErrorKind::UnexpectedEof.into());
         }
 
-        hasher.update(&buffer[..count]);
+        file.flush().await
     }
 
-    let hash = hasher
-        .finalize()
-        .as_slice()
-        .try_into()
-        .expect("SHA-256 is 32 bytes");
+    /// Download file to `part`, resuming from the existing partial file if possible. Failed
+    /// attempts are retried. Returns the hash of the complete file if `hash` is set, along with
+    /// the validators of the last response.
+    async fn download_part<D: CacheDigest>(
+        &self,
+        url: reqwest::Url,
+        part: &Path,
+        hash: bool,
+        mut chan: Option<&mut mpsc::Sender<DownloadEvent>>,
+    ) -> io::Result<(Option<D>, Validators)> {
+        if is_local(&url) {
+            let hasher = self.copy_local(&url, part, hash, chan).await?;
+            return Ok((hasher, Validators::default()));
+        }
+
+        let mut attempt = 0;
 
-    Ok(hash)
-}
+        loop {
+            let res = async {
+                let mut download = self
+                    .get_resumable(url.clone(), part.to_path_buf(), chan.as_deref_mut())
+                    .await?;
 
-fn chan_send(chan: Option<&mut mpsc::Sender<f32>>, msg: f32) {
-    if let Some(c) = chan {
-        let _ = c.try_send(msg);
+                let mut hasher = hash.then(D::init);
+                if let Some(h) = hasher.as_mut() {
+                    download.read_prefix(h, &mut tokio::io::sink()).await?;
+                }
+                let validators = download.validators();
+                download
+                    .write_rest(hasher.as_mut(), &mut tokio::io::sink(), chan.as_deref_mut())
+                    .await?;
+
+                Ok((hasher, validators))
+            }
+            .await;
+
+            match res {
+                Err(e) if self.backoff(attempt, &e).await => attempt += 1,
+                res => return res,
+            }
+        }
     }
-}
 
-struct AsyncTempFile(tokio::fs::File);
+    /// Copy the file at `file://` URL to `part`. Local files are never resumed.
+    async fn copy_local<D: CacheDigest>(
+        &self,
+        url: &reqwest::Url,
+        part: &Path,
+        hash: bool,
+        mut chan: Option<&mut mpsc::Sender<DownloadEvent>>,
+    ) -> io::Result<Option<D>> {
+        let src = url.to_file_path().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid file URL {url}"),
+            )
+        })?;
+        let mut reader = tokio::fs::File::open(&src).await?;
+        let mut meter = Meter::new(0, Some(reader.metadata().await?.len()));
+        let mut writer = tokio::io::BufWriter::new(tokio::fs::File::create(part).await?);
+        let mut hasher = hash.then(D::init);
+        let mut buffer = vec![0; 64 * 1024];
+        let mut pos = 0;
+
+        loop {
+            self.check_cancel()?;
+            let count = reader.read(&mut buffer).await?;
+            if count == 0 {
+                break;
+            }
+
+            if let Some(h) = hasher.as_mut() {
+                h.feed(&buffer[..count]);
+            }
+            writer.write_all(&buffer[..count]).await?;
+
+            pos += count as u64;
+            send_progress(chan.as_deref_mut(), meter.update(pos));
+        }
 
-impl AsyncTempFile {
-    fn new() -> io::Result<Self> {
-        let f = tempfile::tempfile()?;
-        Ok(Self(tokio::fs::File::from_std(f)))
+        writer.flush().await?;
+        Ok(hasher)
     }
 
-    async fn persist(&mut self, path: &Path) -> io::Result<()> {
-        let mut f = tokio::fs::File::create(path).await?;
-        self.0.seek(io::SeekFrom::Start(0)).await?;
+    /// Wait before retrying a failed attempt. Returns false if the attempt should not be retried.
+    async fn backoff(&self, attempt: u32, e: &io::Error) -> bool {
+        if attempt >= self.max_retries || !is_retryable(e) {
+            return false;
+        }
+
+        let delay = self.base_delay.saturating_mul(1 << attempt.min(16));
+        let delay = delay + delay.mul_f64(jitter());
+        tracing::warn!(
+            "Download attempt {} failed: {e}. Retrying in {delay:?}",
+            attempt + 1
+        );
+        // Next attempt fails immediately if cancelled
+        let _ = cancellable(self.cancel.as_ref(), tokio::time::sleep(delay)).await;
 
-        tokio::io::copy(&mut self.0, &mut f).await?;
+        true
+    }
 
-        // Causes errors if not present
-        f.flush().await?;
+    /// Request the file, resuming from the partial file at `part` if present. Falls back to
+    /// downloading the complete file if the server does not support resuming.
+    async fn get_resumable(
+        &self,
+        url: reqwest::Url,
+        part: PathBuf,
+        chan: Option<&mut mpsc::Sender<DownloadEvent>>,
+    ) -> io::Result<PartialDownload> {
+        self.check_cancel()?;
 
-        Ok(())
+        let offset = tokio::fs::metadata(&part)
+            .await
+            .map(|x| x.len())
+            .unwrap_or(0);
+
+        let mut req = self.get(url.clone());
+        if offset > 0 {
+            req = req.header(reqwest::header::RANGE, format!("bytes={offset}-"));
+        }
+        let mut response = cancellable(self.cancel.as_ref(), req.send())
+            .await?
+            .map_err(io::Error::other)?;
+        check_status(&response)?;
+
+        if offset > 0 {
+            if response.status() == reqwest::StatusCode::PARTIAL_CONTENT
+                && content_range_start(&response) == Some(offset)
+            {
+                tracing::info!("Resuming download from {offset} bytes");
+                chan_send(chan, DownloadEvent::Resumed { offset });
+                self.check_space(response.content_length(), 0)?;
+
+                let file = tokio::fs::OpenOptions::new()
+                    .append(true)
+                    .open(&part)
+                    .await?;
+                return Ok(PartialDownload::new(
+                    part,
+                    file,
+                    offset,
+                    response,
+                    self.cancel.clone(),
+                ));
+            }
+
+            tracing::warn!(
+                "Server did not resume download (status {}), restarting",
+                response.status()
+            );
+            // Server ignoring range sends the complete file. Else response does not contain the
+            // file.
+            if response.status() != reqwest::StatusCode::OK {
+                response = cancellable(self.cancel.as_ref(), self.get(url).send())
+                    .await?
+                    .map_err(io::Error::other)?;
+                check_status(&response)?;
+            }
+        }
+
+        // Existing partial file is replaced
+        self.check_space(response.content_length(), offset)?;
+        let file = tokio::fs::File::create(&part).await?;
+        Ok(PartialDownload::new(
+            part,
+            file,
+            0,
+            response,
+            self.cancel.clone(),
+        ))
     }
-}
 
-impl From<std::fs::File> for AsyncTempFile {
-    fn from(value: std::fs::File) -> Self {
-        Self(tokio::fs::File::from_std(value))
+    /// Fail early if `needed` bytes do not fit in cache directory, instead of deep into a
+    /// download. `freed` bytes will be freed before downloading.
+    ///
+    /// Downloads of unknown size, and filesystems where free space cannot be queried, are not
+    /// checked.
+    fn check_space(&self, needed: Option<u64>, freed: u64) -> io::Result<()> {
+        let Some(needed) = needed else {
+            return Ok(());
+        };
+        let available = match available_space(&self.cache_dir) {
+            Ok(x) => x.saturating_add(freed),
+            Err(e) => {
+                tracing::warn!("Failed to query free space in {:?}: {e}", self.cache_dir);
+                return Ok(());
+            }
+        };
+
+        if needed > available {
+            let unit = bb_helper::size::SizeUnit::Binary;
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                format!(
+                    "Not enough space in cache directory to download {}: {} available, {} short",
+                    unit.format(needed),
+                    unit.format(available),
+                    unit.format(needed - available)
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// List all files in cache.
+    pub async fn cache_entries(&self) -> io::Result<Vec<CacheEntry>> {
+        let mut entries = Vec::new();
+        let mut dir = tokio::fs::read_dir(&self.cache_dir).await?;
+
+        while let Some(entry) = dir.next_entry().await? {
+            let path = entry.path();
+            if is_sidecar(&path) {
+                continue;
+            }
+
+            let metadata = entry.metadata().await?;
+            if !metadata.is_file() {
+                continue;
+            }
+
+            entries.push(CacheEntry {
+                path,
+                size: metadata.len(),
+                modified: metadata.modified()?,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Remove a file from cache.
+    pub async fn evict(&self, entry: &CacheEntry) -> io::Result<()> {
+        tokio::fs::remove_file(&entry.path).await?;
+        let _ = tokio::fs::remove_file(sidecar_path(&entry.path)).await;
+        let _ = tokio::fs::remove_file(validators_path(&entry.path)).await;
+        Ok(())
+    }
+
+    /// Remove all files in cache which were not modified in `max_age`. Returns the number of
+    /// bytes freed.
+    ///
+    /// Files which cannot be removed are skipped.
+    pub async fn prune_older_than(&self, max_age: Duration) -> io::Result<u64> {
+        let entries = self.cache_entries().await?;
+        Ok(self
+            .evict_all(expired_entries(&entries, SystemTime::now(), max_age))
+            .await)
+    }
+
+    /// Remove all files in cache, including partial downloads. Returns the number of bytes freed.
+    ///
+    /// Files which cannot be removed are skipped.
+    pub async fn clear_cache(&self) -> io::Result<u64> {
+        let entries = self.cache_entries().await?;
+        Ok(self.evict_all(entries.iter()).await)
+    }
+
+    /// Total size of all files in cache, including partial downloads.
+    pub async fn cache_size(&self) -> io::Result<u64> {
+        let entries = self.cache_entries().await?;
+        Ok(entries.iter().map(CacheEntry::size).sum())
+    }
+
+    /// Check if a file in cache matches `sha256`. Returns an error if the file cannot be read.
+    ///
+    /// Unlike [`check_cache_from_sha`](Self::check_cache_from_sha), the file is never deleted on
+    /// mismatch. The file is always hashed again, so corruption after it was downloaded is
+    /// detected.
+    pub async fn verify_cached(&self, path: &Path, sha256: [u8; 32]) -> io::Result<bool> {
+        let hash = digest_from_path::<Sha256Hasher>(path).await?;
+        Ok(hash == sha256)
+    }
+
+    /// Verify all files in cache named by their SHA256, i.e. downloaded using
+    /// [`download_with_sha`](Self::download_with_sha) or similar. Files which cannot be read are
+    /// reported as not matching. Nothing is deleted.
+    ///
+    /// Other files, such as files downloaded with just URL, are skipped, since there is no known
+    /// hash to compare against.
+    pub async fn verify_all(&self) -> io::Result<Vec<(PathBuf, bool)>> {
+        let mut res = Vec::new();
+
+        for entry in self.cache_entries().await? {
+            let Some(sha256) = entry
+                .path
+                .file_name()
+                .and_then(|x| x.to_str())
+                .and_then(|x| const_hex::decode_to_array(x).ok())
+            else {
+                continue;
+            };
+
+            let valid = match self.verify_cached(&entry.path, sha256).await {
+                Ok(x) => x,
+                Err(e) => {
+                    tracing::warn!("Failed to verify {:?}: {e}", entry.path);
+                    false
+                }
+            };
+            res.push((entry.path, valid));
+        }
+
+        Ok(res)
+    }
+
+    async fn evict_all<'a>(&self, entries: impl Iterator<Item = &'a CacheEntry>) -> u64 {
+        let mut freed = 0;
+
+        for entry in entries {
+            match self.evict(entry).await {
+                Ok(_) => freed += entry.size,
+                Err(e) => tracing::warn!("Failed to remove {:?}: {e}", entry.path),
+            }
+        }
+
+        freed
+    }
+
+    #[cfg(test)]
+    fn path_from_url(&self, url: &reqwest::Url) -> PathBuf {
+        self.cache_dir.join(key_from_url(url))
+    }
+
+    #[cfg(test)]
+    fn path_from_hash<D: CacheDigest>(&self, hash: &[u8]) -> PathBuf {
+        self.cache_dir.join(key_from_hash::<D>(hash))
+    }
+}
+
+/// Cache key of a file downloaded with just URL.
+///
+/// URLs without a file extension (e.g. `https://example.com/download?id=123`) use
+/// [`DEFAULT_EXTENSION`]. Cached files are looked up without any request, so the extension cannot
+/// depend on the response.
+fn key_from_url(url: &reqwest::Url) -> String {
+    let fext = Path::new(url.path())
+        .extension()
+        .unwrap_or(DEFAULT_EXTENSION.as_ref())
+        .to_string_lossy();
+    let file_name = Sha256::new().chain_update(url.as_str()).finalize();
+    format!("{}.{fext}", const_hex::encode(file_name))
+}
+
+/// Cache key of a file verified using digest `D`.
+///
+/// Digest name is included in the key, so files verified using different digests never collide.
+/// SHA256 is used as is, for compatibility with existing caches.
+fn key_from_hash<D: CacheDigest>(hash: &[u8]) -> String {
+    let hash = const_hex::encode(hash);
+    let name = D::name();
+
+    if name == Sha256Hasher::name() {
+        hash
+    } else {
+        format!("{name}-{hash}")
+    }
+}
+
+/// Storage for complete files in cache. The default is [DirStore], which keeps files in the cache
+/// directory.
+///
+/// Partial downloads and metadata (such as verified hashes) are always kept in the cache
+/// directory, which acts as scratch space. Complete files are handed over to the store, and must
+/// be available at a local path when requested. Listing and removing cached files using
+/// [`Downloader::cache_entries`] and related functions also only covers the cache directory.
+///
+/// Methods are called from async code, so they should not block for long. Stores backed by remote
+/// storage can keep a local index.
+pub trait CacheStore: std::fmt::Debug + Send + Sync {
+    /// Local path to the file stored as `key`, if present.
+    fn get(&self, key: &str) -> Option<PathBuf>;
+
+    /// Store the complete file at `src` as `key`, replacing any existing file. `src` is in the
+    /// cache directory, and can be moved. Returns the local path to the stored file.
+    fn put(&self, key: &str, src: &Path) -> io::Result<PathBuf>;
+
+    /// Check if a file is stored as `key`.
+    fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Remove the file stored as `key`.
+    fn remove(&self, key: &str) -> io::Result<()>;
+}
+
+/// [CacheStore] keeping files in a directory.
+#[derive(Debug, Clone)]
+pub struct DirStore {
+    dir: PathBuf,
+}
+
+impl DirStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl CacheStore for DirStore {
+    fn get(&self, key: &str) -> Option<PathBuf> {
+        let p = self.dir.join(key);
+        p.exists().then_some(p)
+    }
+
+    fn put(&self, key: &str, src: &Path) -> io::Result<PathBuf> {
+        let p = self.dir.join(key);
+        move_file(src, &p, |a, b| std::fs::rename(a, b))?;
+        Ok(p)
+    }
+
+    fn remove(&self, key: &str) -> io::Result<()> {
+        std::fs::remove_file(self.dir.join(key))
+    }
+}
+
+/// Operations used to fetch remote images. Implemented by [Downloader].
+///
+/// Allows code resolving remote files to be tested without network access, by using a test
+/// double serving fixtures instead.
+pub trait Fetcher: Clone + Send + Sync + 'static {
+    /// See [Downloader::check_cache_from_sha].
+    fn check_cache_from_sha(
+        &self,
+        sha256: [u8; 32],
+    ) -> impl Future<Output = Option<PathBuf>> + Send;
+
+    /// See [Downloader::check_cache_from_url].
+    fn check_cache_from_url(&self, url: reqwest::Url) -> Option<PathBuf>;
+
+    /// See [Downloader::download_tracked].
+    fn download_tracked(
+        &self,
+        url: reqwest::Url,
+        sha256: Option<[u8; 32]>,
+        chan: Option<mpsc::Sender<DownloadEvent>>,
+    ) -> impl Future<Output = io::Result<PathBuf>> + Send;
+
+    /// See [Downloader::download_with_sha_tracked].
+    fn download_with_sha_tracked(
+        &self,
+        url: reqwest::Url,
+        sha256: [u8; 32],
+        chan: Option<mpsc::Sender<DownloadEvent>>,
+    ) -> impl Future<Output = io::Result<PathBuf>> + Send;
+
+    /// See [Downloader::download_to_stream].
+    fn download_to_stream(
+        self,
+        url: reqwest::Url,
+        sha256: [u8; 32],
+        writer: bb_helper::file_stream::WriterFileStream,
+    ) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+impl Fetcher for Downloader {
+    fn check_cache_from_sha(
+        &self,
+        sha256: [u8; 32],
+    ) -> impl Future<Output = Option<PathBuf>> + Send {
+        Downloader::check_cache_from_sha(self, sha256)
+    }
+
+    fn check_cache_from_url(&self, url: reqwest::Url) -> Option<PathBuf> {
+        Downloader::check_cache_from_url(self, url)
+    }
+
+    fn download_tracked(
+        &self,
+        url: reqwest::Url,
+        sha256: Option<[u8; 32]>,
+        chan: Option<mpsc::Sender<DownloadEvent>>,
+    ) -> impl Future<Output = io::Result<PathBuf>> + Send {
+        Downloader::download_tracked(self, url, sha256, chan)
+    }
+
+    fn download_with_sha_tracked(
+        &self,
+        url: reqwest::Url,
+        sha256: [u8; 32],
+        chan: Option<mpsc::Sender<DownloadEvent>>,
+    ) -> impl Future<Output = io::Result<PathBuf>> + Send {
+        Downloader::download_with_sha_tracked(self, url, sha256, chan)
+    }
+
+    fn download_to_stream(
+        self,
+        url: reqwest::Url,
+        sha256: [u8; 32],
+        writer: bb_helper::file_stream::WriterFileStream,
+    ) -> impl Future<Output = io::Result<()>> + Send {
+        Downloader::download_to_stream(self, url, sha256, writer)
+    }
+}
+
+/// Builder for [Downloader].
+///
+/// # Proxy
+///
+/// By default, proxies are taken from `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` environment
+/// variables. On Windows and macOS, the proxy configured in OS settings is also used. Desktop
+/// proxy settings on Linux (e.g. GNOME) are not read.
+#[derive(Debug, Clone)]
+pub struct DownloaderBuilder {
+    cache_dir: PathBuf,
+    proxy: Option<reqwest::Url>,
+    system_proxy: bool,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    pool_max_idle_per_host: Option<usize>,
+    user_agent: String,
+    store: Option<Arc<dyn CacheStore>>,
+}
+
+impl DownloaderBuilder {
+    /// Timeout for establishing a connection. Defaults to 10 seconds.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Timeout for each read from the connection. Defaults to 15 seconds.
+    ///
+    /// This applies to each chunk of data received, not the whole download, so large files can
+    /// take any amount of time as long as data keeps arriving.
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Maximum number of idle connections kept open per host. Unlimited by default.
+    pub fn with_pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// `User-Agent` header sent with all requests. Defaults to the crate name.
+    pub fn with_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    /// Send all requests through `proxy`, except hosts in `NO_PROXY` environment variable.
+    pub fn with_proxy(mut self, proxy: reqwest::Url) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Use the system proxy, see [Proxy](Self#proxy). Enabled by default.
+    ///
+    /// Disabling ignores proxies set in environment variables and OS settings. Useful on captive
+    /// networks, where a stale proxy configuration prevents reaching the login page. The system
+    /// proxy is never used when [`with_proxy`](Self::with_proxy) is set.
+    pub fn with_system_proxy(mut self, system_proxy: bool) -> Self {
+        self.system_proxy = system_proxy;
+        self
+    }
+
+    /// Store complete files in `store` instead of the cache directory. See [CacheStore].
+    pub fn with_store(mut self, store: impl CacheStore + 'static) -> Self {
+        self.store = Some(Arc::new(store));
+        self
+    }
+
+    /// Create the downloader. Fails if cache directory is not a directory or the proxy is invalid.
+    pub fn build(self) -> io::Result<Downloader> {
+        let cache_dir = self.cache_dir;
+
+        if !cache_dir.exists() {
+            let _ = std::fs::create_dir_all(&cache_dir);
+        }
+
+        if cache_dir.exists() && !cache_dir.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                "cache_dir should be a directory",
+            ));
+        }
+
+        let mut client = reqwest::Client::builder()
+            .user_agent(self.user_agent)
+            .connect_timeout(self.connect_timeout)
+            .read_timeout(self.read_timeout);
+
+        if let Some(x) = self.pool_max_idle_per_host {
+            client = client.pool_max_idle_per_host(x);
+        }
+        if !self.system_proxy {
+            client = client.no_proxy();
+        }
+        if let Some(x) = self.proxy {
+            let proxy = reqwest::Proxy::all(x)
+                .map_err(io::Error::other)?
+                .no_proxy(reqwest::NoProxy::from_env());
+            client = client.proxy(proxy);
+        }
+
+        let store = self
+            .store
+            .unwrap_or_else(|| Arc::new(DirStore::new(cache_dir.clone())));
+
+        Ok(Downloader {
+            client: client.build().map_err(io::Error::other)?,
+            store,
+            cache_dir,
+            max_retries: DEFAULT_MAX_RETRIES,
+            base_delay: DEFAULT_BASE_DELAY,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            headers: reqwest::header::HeaderMap::new(),
+            cancel: None,
+        })
+    }
+}
+
+/// Events sent on the progress channel of downloads.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DownloadEvent {
+    /// Fraction of the file downloaded, between 0 and 1.
+    ///
+    /// Only sent if the size of the file is known. Downloads of unknown size should be shown as
+    /// indeterminate, which can be detected using [DownloadEvent::Stats].
+    Progress(f32),
+    /// Detailed progress, including download speed. Sent before each [DownloadEvent::Progress],
+    /// and for downloads of unknown size.
+    Stats(DownloadProgress),
+    /// Download resumed from a partial file left by an earlier attempt.
+    Resumed {
+        /// Bytes already downloaded.
+        offset: u64,
+    },
+}
+
+/// Progress of a download.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DownloadProgress {
+    /// Bytes downloaded, including data downloaded before resuming.
+    pub downloaded: u64,
+    /// Size of the file, if known.
+    pub total: Option<u64>,
+    /// Download speed over the last second.
+    pub bytes_per_sec: f32,
+}
+
+impl DownloadProgress {
+    /// Fraction of the file downloaded, between 0 and 1. [None] if the size is not known.
+    pub fn fraction(&self) -> Option<f32> {
+        self.total
+            .map(|x| (self.downloaded as f32 / x.max(1) as f32).clamp(0.0, 1.0))
+    }
+
+    /// Time to download rest of the file at the current speed. [None] if the size or speed is not
+    /// known.
+    pub fn time_remaining(&self) -> Option<Duration> {
+        let remaining = self.total?.saturating_sub(self.downloaded);
+        if self.bytes_per_sec <= 0.0 {
+            return None;
+        }
+
+        Some(Duration::from_secs_f32(
+            remaining as f32 / self.bytes_per_sec,
+        ))
+    }
+}
+
+/// A file in cache
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+impl CacheEntry {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Size in bytes
+    pub const fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Last time the file was written to cache
+    pub const fn modified(&self) -> SystemTime {
+        self.modified
+    }
+}
+
+/// Entries older than `max_age` at `now`. Entries with modification time in the future are never
+/// selected.
+fn expired_entries(
+    entries: &[CacheEntry],
+    now: SystemTime,
+    max_age: Duration,
+) -> impl Iterator<Item = &CacheEntry> {
+    entries.iter().filter(move |x| {
+        now.duration_since(x.modified)
+            .is_ok_and(|age| age > max_age)
+    })
+}
+
+/// Digest used to verify files in cache.
+trait CacheDigest: Send + Sized {
+    /// Stored in sidecar files and file names, so hashes of different algorithms are never mixed
+    /// up.
+    fn name() -> String;
+
+    fn init() -> Self;
+    fn feed(&mut self, data: &[u8]);
+    fn finish(self) -> Vec<u8>;
+}
+
+/// [CacheDigest] using any [Digest] from RustCrypto.
+struct DigestHasher<D>(D);
+
+type Sha256Hasher = DigestHasher<Sha256>;
+
+impl<D> CacheDigest for DigestHasher<D>
+where
+    D: Digest + CoreProxy + Send,
+    D::Core: AlgorithmName,
+{
+    fn name() -> String {
+        struct Name<D>(std::marker::PhantomData<D>);
+
+        impl<D: AlgorithmName> std::fmt::Display for Name<D> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                D::write_alg_name(f)
+            }
+        }
+
+        Name::<D::Core>(std::marker::PhantomData)
+            .to_string()
+            .to_lowercase()
+    }
+
+    fn init() -> Self {
+        Self(D::new())
+    }
+
+    fn feed(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.0.finalize().to_vec()
+    }
+}
+
+#[cfg(feature = "blake3")]
+impl CacheDigest for blake3::Hasher {
+    fn name() -> String {
+        "blake3".to_string()
+    }
+
+    fn init() -> Self {
+        Self::new()
+    }
+
+    fn feed(&mut self, data: &[u8]) {
+        self.update(data);
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.finalize().as_bytes().to_vec()
+    }
+}
+
+async fn digest_from_path<D: CacheDigest>(p: &Path) -> io::Result<Vec<u8>> {
+    let mut hasher = D::init();
+    hash_copy(p, &mut hasher, &mut tokio::io::sink()).await?;
+
+    Ok(hasher.finish())
+}
+
+/// Sidecar file storing the last verified hash of a cached file.
+fn sidecar_path(p: &Path) -> PathBuf {
+    p.with_extension("verified")
+}
+
+fn is_sidecar(p: &Path) -> bool {
+    p.extension()
+        .is_some_and(|x| x == "verified" || x == "validators")
+}
+
+/// Key used to detect changes in a file. Any change in size or modification time invalidates the
+/// verified hash. Hashes of a different algorithm are ignored.
+async fn sidecar_key<D: CacheDigest>(p: &Path) -> io::Result<String> {
+    let metadata = tokio::fs::metadata(p).await?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(io::Error::other)?
+        .as_nanos();
+
+    Ok(format!("{} {} {}", metadata.len(), mtime, D::name()))
+}
+
+async fn write_sidecar<D: CacheDigest>(p: &Path, hash: &[u8]) -> io::Result<()> {
+    let key = sidecar_key::<D>(p).await?;
+    tokio::fs::write(
+        sidecar_path(p),
+        format!("{} {}", key, const_hex::encode(hash)),
+    )
+    .await
+}
+
+/// Return hash of file, skipping hashing if the file is unchanged since the last verification.
+async fn cached_digest<D, F>(p: &Path, hash_fn: F) -> io::Result<Vec<u8>>
+where
+    D: CacheDigest,
+    F: AsyncFn(&Path) -> io::Result<Vec<u8>>,
+{
+    let key = sidecar_key::<D>(p).await?;
+
+    if let Ok(sidecar) = tokio::fs::read_to_string(sidecar_path(p)).await
+        && let Some((sidecar_key, hash)) = sidecar.rsplit_once(' ')
+        && sidecar_key == key
+        && let Ok(hash) = const_hex::decode(hash)
+    {
+        return Ok(hash);
+    }
+
+    let hash = hash_fn(p).await?;
+    let _ = write_sidecar::<D>(p, &hash).await;
+
+    Ok(hash)
+}
+
+/// Sidecar file storing the validators of a file cached with just URL.
+fn validators_path(p: &Path) -> PathBuf {
+    p.with_extension("validators")
+}
+
+/// Cache validators sent by the server, used to check if a cached file is still up to date.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_response(response: &reqwest::Response) -> Self {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|x: &reqwest::header::HeaderValue| x.to_str().ok())
+                .map(str::to_string)
+        };
+
+        Self {
+            etag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+        }
+    }
+
+    const fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// Read validators of the file cached at `p`.
+    async fn read(p: &Path) -> Option<Self> {
+        let data = tokio::fs::read_to_string(validators_path(p)).await.ok()?;
+        let mut res = Self::default();
+
+        for line in data.lines() {
+            match line.split_once(": ")? {
+                ("ETag", x) => res.etag = Some(x.to_string()),
+                ("Last-Modified", x) => res.last_modified = Some(x.to_string()),
+                _ => {}
+            }
+        }
+
+        (!res.is_empty()).then_some(res)
+    }
+
+    /// Save validators of the file cached at `p`. Old validators are removed if there are none.
+    async fn write(&self, p: &Path) -> io::Result<()> {
+        let path = validators_path(p);
+        if self.is_empty() {
+            return match tokio::fs::remove_file(path).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+
+        let mut data = String::new();
+        if let Some(x) = &self.etag {
+            data.push_str(&format!("ETag: {x}\n"));
+        }
+        if let Some(x) = &self.last_modified {
+            data.push_str(&format!("Last-Modified: {x}\n"));
+        }
+
+        tokio::fs::write(path, data).await
+    }
+
+    /// Make `req` conditional, so the server only sends the file if it changed.
+    fn apply(&self, mut req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if let Some(x) = &self.etag {
+            req = req.header(reqwest::header::IF_NONE_MATCH, x);
+        }
+        if let Some(x) = &self.last_modified {
+            req = req.header(reqwest::header::IF_MODIFIED_SINCE, x);
+        }
+        req
+    }
+}
+
+fn chan_send(chan: Option<&mut mpsc::Sender<DownloadEvent>>, msg: DownloadEvent) {
+    if let Some(c) = chan {
+        let _ = c.try_send(msg);
+    }
+}
+
+/// Run `f` with a [DownloadEvent] channel, forwarding [DownloadEvent::Progress] to `chan`.
+async fn forward_progress<F, Fut>(chan: Option<mpsc::Sender<f32>>, f: F) -> io::Result<PathBuf>
+where
+    F: FnOnce(Option<mpsc::Sender<DownloadEvent>>) -> Fut,
+    Fut: Future<Output = io::Result<PathBuf>>,
+{
+    let Some(mut chan) = chan else {
+        return f(None).await;
+    };
+
+    let (tx, rx) = mpsc::channel(100);
+    let forward = rx.for_each(|x| {
+        if let DownloadEvent::Progress(x) = x {
+            let _ = chan.try_send(x);
+        }
+        std::future::ready(())
+    });
+
+    // Forwarding stops once all senders are dropped at the end of the download
+    let (res, ()) = futures::join!(f(Some(tx)), forward);
+    res
+}
+
+/// Space available to unprivileged users on the filesystem containing `p`.
+#[cfg(unix)]
+fn available_space(p: &Path) -> io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(p.as_os_str().as_bytes()).map_err(io::Error::other)?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+
+    // SAFETY: path is a valid C string, and stat is only read if statvfs succeeds.
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stat = unsafe { stat.assume_init() };
+
+    // Field types differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    Ok((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+/// Space available to the current user on the volume containing `p`.
+#[cfg(windows)]
+fn available_space(p: &Path) -> io::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+
+    let path: Vec<u16> = p.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut available = 0u64;
+
+    unsafe {
+        windows::Win32::Storage::FileSystem::GetDiskFreeSpaceExW(
+            windows::core::PCWSTR(path.as_ptr()),
+            Some(&raw mut available),
+            None,
+            None,
+        )
+    }
+    .map_err(io::Error::other)?;
+
+    Ok(available)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn available_space(_: &Path) -> io::Result<u64> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// Partial download of a file in cache. Kept on failure, so the download can be resumed.
+fn part_path(p: &Path) -> PathBuf {
+    let mut p = p.as_os_str().to_owned();
+    p.push(".part");
+    p.into()
+}
+
+/// Move `src` to `dst` using `rename`. Falls back to copying when they are on different
+/// filesystems (eg. store outside cache directory). The copy is first written next to `dst`, so an
+/// interrupted copy never leaves an incomplete file at `dst`.
+fn move_file(
+    src: &Path,
+    dst: &Path,
+    rename: impl Fn(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    match rename(src, dst) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            let mut temp = dst.as_os_str().to_owned();
+            temp.push(".copy");
+            let temp = PathBuf::from(temp);
+
+            if let Err(e) = std::fs::copy(src, &temp).and_then(|_| std::fs::rename(&temp, dst)) {
+                let _ = std::fs::remove_file(&temp);
+                return Err(e);
+            }
+
+            std::fs::remove_file(src)
+        }
+        res => res,
+    }
+}
+
+/// Download in progress using concurrent `Range` requests. Unlike [part_path], data is not
+/// contiguous, so it cannot be resumed.
+fn chunks_path(p: &Path) -> PathBuf {
+    let mut p = p.as_os_str().to_owned();
+    p.push(".chunks");
+    p.into()
+}
+
+/// Same as [reqwest::IntoUrl::into_url], but also accepts `file://` URLs.
+fn into_url<U: reqwest::IntoUrl>(url: U) -> io::Result<reqwest::Url> {
+    match url.into_url() {
+        Ok(x) => Ok(x),
+        // reqwest rejects URLs without a host, but still reports the parsed URL
+        Err(e) => match e.url() {
+            Some(x) if is_local(x) => Ok(x.clone()),
+            _ => Err(io::Error::other(e)),
+        },
+    }
+}
+
+/// `file://` URLs are copied from the local filesystem, which is useful for offline mirrors and
+/// tests.
+fn is_local(url: &reqwest::Url) -> bool {
+    url.scheme() == "file"
+}
+
+/// Send [DownloadEvent::Stats], followed by [DownloadEvent::Progress] if the size is known.
+fn send_progress(mut chan: Option<&mut mpsc::Sender<DownloadEvent>>, progress: DownloadProgress) {
+    chan_send(chan.as_deref_mut(), DownloadEvent::Stats(progress));
+    if let Some(x) = progress.fraction() {
+        chan_send(chan, DownloadEvent::Progress(x));
+    }
+}
+
+/// Combined progress of all chunks in [Downloader::download_chunked].
+struct ChunkProgress<'a> {
+    meter: &'a Mutex<Meter>,
+    chan: Option<mpsc::Sender<DownloadEvent>>,
+}
+
+impl ChunkProgress<'_> {
+    fn add(&mut self, bytes: u64) {
+        let progress = self.meter.lock().unwrap().add(bytes);
+        send_progress(self.chan.as_mut(), progress);
+    }
+}
+
+/// Measures download throughput over the last [THROUGHPUT_WINDOW].
+struct Meter {
+    total: Option<u64>,
+    /// Bytes downloaded at different times, oldest first.
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl Meter {
+    /// Start measuring with `downloaded` bytes already present, which do not count towards
+    /// throughput.
+    fn new(downloaded: u64, total: Option<u64>) -> Self {
+        Self::new_at(Instant::now(), downloaded, total)
+    }
+
+    fn new_at(now: Instant, downloaded: u64, total: Option<u64>) -> Self {
+        Self {
+            total,
+            samples: VecDeque::from([(now, downloaded)]),
+        }
+    }
+
+    fn add(&mut self, bytes: u64) -> DownloadProgress {
+        let downloaded = self.samples.back().map_or(0, |x| x.1) + bytes;
+        self.update(downloaded)
+    }
+
+    fn update(&mut self, downloaded: u64) -> DownloadProgress {
+        self.update_at(Instant::now(), downloaded)
+    }
+
+    fn update_at(&mut self, now: Instant, downloaded: u64) -> DownloadProgress {
+        self.samples.push_back((now, downloaded));
+        // Keep one sample older than the window, so the complete window is covered
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) >= THROUGHPUT_WINDOW {
+            self.samples.pop_front();
+        }
+
+        let (start, start_downloaded) = self.samples[0];
+        let elapsed = now.duration_since(start).as_secs_f32();
+        let bytes_per_sec = if elapsed > 0.0 {
+            downloaded.saturating_sub(start_downloaded) as f32 / elapsed
+        } else {
+            0.0
+        };
+
+        DownloadProgress {
+            downloaded,
+            total: self.total,
+            bytes_per_sec,
+        }
+    }
+}
+
+/// Start offset of `Content-Range: bytes <start>-<end>/<size>`.
+fn content_range_start(response: &reqwest::Response) -> Option<u64> {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split_once('-')?
+        .0
+        .parse()
+        .ok()
+}
+
+/// Check hash of the complete partial file. Partial file is removed on mismatch, since resuming
+/// it will never succeed.
+async fn verify_part<D: CacheDigest>(part: &Path, hasher: D, expected: &[u8]) -> io::Result<()> {
+    let hash = hasher.finish();
+
+    if hash != expected {
+        tracing::error!(
+            "Expected hash: {}, got {}",
+            const_hex::encode(expected),
+            const_hex::encode(hash)
+        );
+        let _ = tokio::fs::remove_file(part).await;
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid hash"));
+    }
+
+    Ok(())
+}
+
+/// Fail on responses which are worth retrying (429 and 5xx), since they do not contain the file.
+fn check_status(response: &reqwest::Response) -> io::Result<()> {
+    let status = response.status();
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        response.error_for_status_ref().map_err(io::Error::other)?;
+    }
+
+    Ok(())
+}
+
+fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "Download cancelled")
+}
+
+/// Run `fut` to completion, unless `cancel` is cancelled first.
+async fn cancellable<F: Future>(
+    cancel: Option<&tokio_util::sync::CancellationToken>,
+    fut: F,
+) -> io::Result<F::Output> {
+    match cancel {
+        Some(x) => x.run_until_cancelled(fut).await.ok_or_else(cancelled),
+        None => Ok(fut.await),
+    }
+}
+
+/// Connection errors, interrupted responses and 429/5xx responses are considered transient.
+fn is_retryable(e: &io::Error) -> bool {
+    // Truncated response
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        return true;
+    }
+
+    let Some(e) = e.get_ref().and_then(|x| x.downcast_ref::<reqwest::Error>()) else {
+        return false;
+    };
+
+    match e.status() {
+        Some(s) => s == reqwest::StatusCode::TOO_MANY_REQUESTS || s.is_server_error(),
+        // Interrupted responses are reported as decode errors by the body stream
+        None => e.is_connect() || e.is_timeout() || e.is_request() || e.is_body() || e.is_decode(),
+    }
+}
+
+/// Random fraction between 0 and 1, so clients retrying at the same time spread out.
+fn jitter() -> f64 {
+    use std::hash::BuildHasher;
+
+    let x = std::collections::hash_map::RandomState::new().hash_one(SystemTime::now());
+    (x as f64) / (u64::MAX as f64)
+}
+
+/// Feed contents of file to `hasher`, and copy them to `writer`.
+async fn hash_copy<D, W>(p: &Path, hasher: &mut D, writer: &mut W) -> io::Result<()>
+where
+    D: CacheDigest,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let file = tokio::fs::File::open(p).await?;
+    let mut reader = tokio::io::BufReader::new(file);
+    let mut buffer = [0; 512];
+
+    loop {
+        let count = reader.read(&mut buffer).await?;
+        if count == 0 {
+            break;
+        }
+
+        hasher.feed(&buffer[..count]);
+        writer.write_all(&buffer[..count]).await?;
+    }
+
+    Ok(())
+}
+
+/// Download in progress, written to a partial file.
+struct PartialDownload {
+    path: PathBuf,
+    file: tokio::io::BufWriter<tokio::fs::File>,
+    /// Size of the partial file before resuming.
+    offset: u64,
+    response: Option<reqwest::Response>,
+    cancel: Option<tokio_util::sync::CancellationToken>,
+}
+
+impl PartialDownload {
+    fn new(
+        path: PathBuf,
+        file: tokio::fs::File,
+        offset: u64,
+        response: reqwest::Response,
+        cancel: Option<tokio_util::sync::CancellationToken>,
+    ) -> Self {
+        Self {
+            path,
+            file: tokio::io::BufWriter::new(file),
+            offset,
+            response: Some(response),
+            cancel,
+        }
+    }
+
+    fn validators(&self) -> Validators {
+        self.response
+            .as_ref()
+            .map(Validators::from_response)
+            .unwrap_or_default()
+    }
+
+    /// Hash the data downloaded before resuming, and copy it to `writer`.
+    async fn read_prefix<D, W>(&self, hasher: &mut D, writer: &mut W) -> io::Result<()>
+    where
+        D: CacheDigest,
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        if self.offset == 0 {
+            return Ok(());
+        }
+
+        hash_copy(&self.path, hasher, writer).await
+    }
+
+    /// Download rest of the file, copying new data to `writer`. Data received is flushed to the
+    /// partial file even on failure.
+    async fn write_rest<D, W>(
+        &mut self,
+        mut hasher: Option<&mut D>,
+        writer: &mut W,
+        mut chan: Option<&mut mpsc::Sender<DownloadEvent>>,
+    ) -> io::Result<()>
+    where
+        D: CacheDigest,
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        let response = self.response.take().expect("Download already finished");
+        let expected_size = response.content_length().map(|x| x + self.offset);
+        let mut meter = Meter::new(self.offset, expected_size);
+        let mut response_stream = response.bytes_stream();
+
+        let mut cur_pos = self.offset;
+        loop {
+            let next = cancellable(self.cancel.as_ref(), response_stream.next())
+                .await
+                .and_then(|x| x.transpose().map_err(io::Error::other));
+            let data = match next {
+                Ok(Some(x)) => x,
+                Ok(None) => break,
+                Err(e) => {
+                    let _ = self.file.flush().await;
+                    return Err(e);
+                }
+            };
+
+            cur_pos += data.len() as u64;
+            if let Some(h) = hasher.as_deref_mut() {
+                h.feed(&data);
+            }
+            self.file.write_all(&data).await?;
+            writer.write_all(&data).await?;
+
+            send_progress(chan.as_deref_mut(), meter.update(cur_pos));
+        }
+
+        self.file.flush().await?;
+
+        // Truncated responses should never end up in cache, even if the file is not verified
+        match expected_size {
+            Some(x) if cur_pos < x => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Expected {x} bytes, but download ended at {cur_pos} bytes"),
+            )),
+            Some(x) if cur_pos > x => {
+                // Partial file cannot be resumed
+                let _ = tokio::fs::remove_file(&self.path).await;
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Expected {x} bytes, but received {cur_pos} bytes"),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::{Duration, SystemTime},
+    };
+
+    #[tokio::test]
+    async fn checksum_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let p = dir.path().join("img");
+        tokio::fs::write(&p, [1u8; 4096]).await.unwrap();
+
+        let count = AtomicUsize::new(0);
+        let hash_fn = async |p: &std::path::Path| {
+            count.fetch_add(1, Ordering::Relaxed);
+            super::digest_from_path::<super::Sha256Hasher>(p).await
+        };
+
+        let hash1 = super::cached_digest::<super::Sha256Hasher, _>(&p, &hash_fn)
+            .await
+            .unwrap();
+        let hash2 = super::cached_digest::<super::Sha256Hasher, _>(&p, &hash_fn)
+            .await
+            .unwrap();
+        assert_eq!(hash1, hash2);
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+
+        // Invalidate on change
+        tokio::fs::write(&p, [2u8; 2048]).await.unwrap();
+        let hash3 = super::cached_digest::<super::Sha256Hasher, _>(&p, &hash_fn)
+            .await
+            .unwrap();
+        assert_ne!(hash1, hash3);
+        assert_eq!(count.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn throughput() {
+        use std::time::Instant;
+
+        let start = Instant::now();
+        let ms = |x| start + Duration::from_millis(x);
+        // Resumed with 1000 bytes already downloaded
+        let mut meter = super::Meter::new_at(start, 1000, Some(11000));
+
+        let p = meter.update_at(ms(500), 2000);
+        assert_eq!(p.bytes_per_sec, 2000.0);
+        assert_eq!(p.fraction(), Some(2000.0 / 11000.0));
+
+        // Only the last second counts
+        meter.update_at(ms(1000), 3000);
+        meter.update_at(ms(1500), 7000);
+        let p = meter.update_at(ms(2000), 11000);
+        assert_eq!(p.bytes_per_sec, 8000.0);
+        assert_eq!(p.fraction(), Some(1.0));
+        assert_eq!(p.time_remaining(), Some(Duration::ZERO));
+
+        // Unknown size
+        let mut meter = super::Meter::new_at(start, 0, None);
+        let p = meter.update_at(ms(1000), 500);
+        assert_eq!(p.fraction(), None);
+        assert_eq!(p.time_remaining(), None);
+    }
+
+    #[cfg(feature = "blake3")]
+    #[tokio::test]
+    async fn download_with_blake3() {
+        use sha2::Digest;
+
+        let data = vec![0xabu8; 4096];
+        let hash: [u8; 32] = blake3::hash(&data).into();
+        let sha256: [u8; 32] = sha2::Sha256::digest(&data).into();
+        let addr = mock_server(data.clone());
+
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path()).unwrap();
+
+        let p = downloader
+            .download_with_blake3(format!("http://{addr}/img.xz"), hash, None)
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(&p).await.unwrap(), data);
+        assert_eq!(downloader.check_cache_from_blake3(hash).await, Some(p));
+
+        // Sidecar of another algorithm is never trusted
+        let p = downloader.path_from_hash::<super::Sha256Hasher>(&sha256);
+        tokio::fs::write(&p, &data).await.unwrap();
+        super::write_sidecar::<blake3::Hasher>(&p, &sha256)
+            .await
+            .unwrap();
+        assert_eq!(
+            super::cached_digest::<super::Sha256Hasher, _>(
+                &p,
+                super::digest_from_path::<super::Sha256Hasher>
+            )
+            .await
+            .unwrap(),
+            sha256
+        );
+        assert!(downloader.check_cache_from_blake3(sha256).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn download_verified_sha512() {
+        use sha2::Digest;
+
+        let data = vec![0xabu8; 4096];
+        let hash = sha2::Sha512::digest(&data);
+        let sha256: [u8; 32] = sha2::Sha256::digest(&data).into();
+        let addr = mock_server(data.clone());
+
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path()).unwrap();
+
+        let p = downloader
+            .download_verified::<sha2::Sha512, _>(format!("http://{addr}/img.xz"), &hash, None)
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(&p).await.unwrap(), data);
+        assert_eq!(
+            p.file_name().unwrap().to_str().unwrap(),
+            format!("sha512_64-{}", const_hex::encode(hash))
+        );
+        assert_eq!(
+            downloader.check_cache_verified::<sha2::Sha512>(&hash).await,
+            Some(p)
+        );
+
+        // SHA256 files keep plain names
+        assert_eq!(
+            downloader.path_from_hash::<super::Sha256Hasher>(&sha256),
+            dir.path().join(const_hex::encode(sha256))
+        );
+        assert!(downloader.check_cache_from_sha(sha256).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn verify_all() {
+        use sha2::Digest;
+
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path()).unwrap();
+        let file = |data: &[u8]| {
+            let sha256: [u8; 32] = sha2::Sha256::digest(data).into();
+            (dir.path().join(const_hex::encode(sha256)), sha256)
+        };
+
+        let (good, good_sha) = file(&[1; 4096]);
+        tokio::fs::write(&good, [1; 4096]).await.unwrap();
+        let (bad, bad_sha) = file(&[2; 4096]);
+        tokio::fs::write(&bad, [3; 4096]).await.unwrap();
+        // Not named by SHA256
+        let url_file = dir
+            .path()
+            .join(format!("{}.json", const_hex::encode([0; 32])));
+        tokio::fs::write(&url_file, [4; 100]).await.unwrap();
+
+        assert!(downloader.verify_cached(&good, good_sha).await.unwrap());
+        assert!(!downloader.verify_cached(&bad, bad_sha).await.unwrap());
+
+        let mut res = downloader.verify_all().await.unwrap();
+        res.sort();
+        let mut expected = vec![(good, true), (bad.clone(), false)];
+        expected.sort();
+        assert_eq!(res, expected);
+
+        // Nothing is deleted
+        assert!(bad.exists());
+        assert!(url_file.exists());
+    }
+
+    #[test]
+    fn path_from_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path()).unwrap();
+        let path = |url: &str| downloader.path_from_url(&reqwest::Url::parse(url).unwrap());
+
+        let p = path("https://example.com/img.png");
+        assert_eq!(p.extension().unwrap(), "png");
+        assert_eq!(p.parent().unwrap(), dir.path());
+
+        let urls = [
+            "https://example.com/download?id=123",
+            "https://example.com/download?id=124",
+            "https://example.com/",
+            "https://example.com/v1.0/icon",
+        ];
+        let paths: Vec<_> = urls.iter().map(|x| path(x)).collect();
+        for p in &paths {
+            assert_eq!(p.extension().unwrap(), super::DEFAULT_EXTENSION);
+        }
+        assert!(paths.windows(2).all(|x| x[0] != x[1]));
+    }
+
+    #[test]
+    fn expired_entries() {
+        const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+        let now = SystemTime::UNIX_EPOCH + 100 * DAY;
+        let entry = |name: &str, age: Duration| super::CacheEntry {
+            path: PathBuf::from(name),
+            size: 1024,
+            modified: now - age,
+        };
+        let entries = [
+            entry("new", DAY),
+            entry("old", 31 * DAY),
+            entry("boundary", 30 * DAY),
+            entry("ancient", 99 * DAY),
+            super::CacheEntry {
+                path: PathBuf::from("future"),
+                size: 1024,
+                modified: now + DAY,
+            },
+        ];
+
+        let expired: Vec<_> = super::expired_entries(&entries, now, 30 * DAY)
+            .map(|x| x.path())
+            .collect();
+        assert_eq!(expired, [Path::new("old"), Path::new("ancient")]);
+
+        assert_eq!(super::expired_entries(&entries, now, 100 * DAY).count(), 0);
+    }
+
+    fn mock_server(body: Vec<u8>) -> std::net::SocketAddr {
+        serve_once(body, true)
+    }
+
+    /// Serve `body` to a single request. Without `Content-Length`, the end of body is marked by
+    /// closing the connection.
+    fn serve_once(body: Vec<u8>, content_length: bool) -> std::net::SocketAddr {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+
+            write!(stream, "HTTP/1.1 200 OK\r\nConnection: close\r\n").unwrap();
+            if content_length {
+                write!(stream, "Content-Length: {}\r\n", body.len()).unwrap();
+            }
+            write!(stream, "\r\n").unwrap();
+            stream.write_all(&body).unwrap();
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn custom_store() {
+        use sha2::Digest;
+
+        /// Keeps files in a separate directory, like a store backed by remote storage.
+        #[derive(Debug)]
+        struct Store {
+            inner: super::DirStore,
+        }
+
+        impl super::CacheStore for Store {
+            fn get(&self, key: &str) -> Option<PathBuf> {
+                self.inner.get(key)
+            }
+
+            fn put(&self, key: &str, src: &Path) -> std::io::Result<PathBuf> {
+                self.inner.put(key, src)
+            }
+
+            fn remove(&self, key: &str) -> std::io::Result<()> {
+                self.inner.remove(key)
+            }
+        }
+
+        let data = vec![0xabu8; 4096];
+        let sha256: [u8; 32] = sha2::Sha256::digest(&data).into();
+        let addr = mock_server(data.clone());
+
+        let dir = tempfile::tempdir().unwrap();
+        let store_dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::builder(dir.path())
+            .with_store(Store {
+                inner: super::DirStore::new(store_dir.path()),
+            })
+            .build()
+            .unwrap();
+
+        let p = downloader
+            .download_with_sha(format!("http://{addr}/img.xz"), sha256, None)
+            .await
+            .unwrap();
+        assert_eq!(p, store_dir.path().join(const_hex::encode(sha256)));
+        assert_eq!(tokio::fs::read(&p).await.unwrap(), data);
+        assert_eq!(downloader.check_cache_from_sha(sha256).await, Some(p));
+        assert!(
+            !downloader
+                .path_from_hash::<super::Sha256Hasher>(&sha256)
+                .exists()
+        );
+    }
+
+    #[test]
+    fn move_file_across_filesystems() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let dst = dir.path().join("dst");
+        std::fs::write(&src, b"data").unwrap();
+
+        let crosses_devices = |_: &Path, _: &Path| Err(std::io::ErrorKind::CrossesDevices.into());
+        super::move_file(&src, &dst, crosses_devices).unwrap();
+
+        assert_eq!(std::fs::read(&dst).unwrap(), b"data");
+        assert!(!src.exists());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // Other errors are not retried
+        let denied = |_: &Path, _: &Path| Err(std::io::ErrorKind::PermissionDenied.into());
+        std::fs::write(&src, b"data").unwrap();
+        assert!(super::move_file(&src, &dst, denied).is_err());
+        assert!(src.exists());
+    }
+
+    #[tokio::test]
+    async fn download_with_known_sha() {
+        use sha2::Digest;
+
+        let data = vec![0xabu8; 4096];
+        let sha256: [u8; 32] = sha2::Sha256::digest(&data).into();
+
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path()).unwrap();
+
+        let url = format!("http://{}/img.xz", mock_server(data.clone()));
+        assert!(
+            downloader
+                .download(&url, Some([0; 32]), None)
+                .await
+                .is_err()
+        );
+
+        let url = format!("http://{}/img.xz", mock_server(data.clone()));
+        let p = downloader.download(&url, Some(sha256), None).await.unwrap();
+        assert_eq!(downloader.check_cache_from_sha(sha256).await, Some(p));
+        assert!(downloader.check_cache_from_url(&url).is_none());
+    }
+
+    #[tokio::test]
+    async fn file_url() {
+        use sha2::Digest;
+
+        let data: Vec<u8> = (0..(256 * 1024)).map(|x| (x % 251) as u8).collect();
+        let sha256: [u8; 32] = sha2::Sha256::digest(&data).into();
+
+        let mirror = tempfile::tempdir().unwrap();
+        let src = mirror.path().join("img.xz");
+        tokio::fs::write(&src, &data).await.unwrap();
+        let url = reqwest::Url::from_file_path(&src).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path()).unwrap();
+
+        assert!(
+            downloader
+                .download_with_sha(url.clone(), [0; 32], None)
+                .await
+                .is_err()
+        );
+
+        let (tx, rx) = futures::channel::mpsc::channel(100);
+        let p = downloader
+            .download_with_sha(url.clone(), sha256, Some(tx))
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(&p).await.unwrap(), data);
+        assert!(p.starts_with(dir.path()));
+        // Only progress is forwarded
+        let events: Vec<f32> = futures::StreamExt::collect(rx).await;
+        assert_eq!(events.first(), Some(&0.0));
+        assert_eq!(events.last(), Some(&1.0));
+
+        let p = downloader.download(url.clone(), None, None).await.unwrap();
+        assert_eq!(tokio::fs::read(&p).await.unwrap(), data);
+
+        let missing = reqwest::Url::from_file_path(mirror.path().join("missing")).unwrap();
+        let err = downloader.download(missing, None, None).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn check_space() {
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path()).unwrap();
+
+        assert!(super::available_space(dir.path()).unwrap() > 0);
+        downloader.check_space(None, 0).unwrap();
+        downloader.check_space(Some(1024), 0).unwrap();
+
+        let err = downloader.check_space(Some(u64::MAX / 2), 0).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::StorageFull);
+        assert!(err.to_string().contains("short"));
+    }
+
+    #[tokio::test]
+    async fn download_many() {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let (in_flight_clone, max_clone, requests_clone) =
+            (in_flight.clone(), max_in_flight.clone(), requests.clone());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let (in_flight, max, requests) = (
+                    in_flight_clone.clone(),
+                    max_clone.clone(),
+                    requests_clone.clone(),
+                );
+                std::thread::spawn(move || {
+                    let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap() > 2 {
+                        line.clear();
+                    }
+
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    let cur = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max.fetch_max(cur, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(100));
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\nicon"
+                    )
+                    .unwrap();
+                });
+            }
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path()).unwrap();
+        let urls: Vec<reqwest::Url> = ["a", "b", "a", "c", "d", "b"]
+            .iter()
+            .map(|x| format!("http://{addr}/{x}.png").parse().unwrap())
+            .collect();
+
+        let res: Vec<_> = futures::StreamExt::collect(downloader.download_many(urls, 2)).await;
+        assert_eq!(res.len(), 4);
+        for (_, p) in res {
+            assert_eq!(tokio::fs::read(p.unwrap()).await.unwrap(), b"icon");
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+        assert!(max_in_flight.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[tokio::test]
+    async fn unknown_size_progress() {
+        let data = vec![0xabu8; 64 * 1024];
+        let addr = serve_once(data.clone(), false);
+
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path()).unwrap();
+
+        let (tx, rx) = futures::channel::mpsc::channel(1000);
+        let p = downloader
+            .download_no_cache_tracked(format!("http://{addr}/img.xz"), Some(tx))
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(p).await.unwrap(), data);
+
+        let events: Vec<_> = futures::StreamExt::collect(rx).await;
+        let stats: Vec<_> = events
+            .iter()
+            .filter_map(|x| match x {
+                super::DownloadEvent::Stats(x) => Some(x),
+                _ => None,
+            })
+            .collect();
+        assert!(stats.iter().all(|x| x.fraction().is_none()));
+        assert_eq!(stats.last().unwrap().downloaded, data.len() as u64);
+        // Only the initial progress is sent
+        assert_eq!(
+            events
+                .iter()
+                .filter(|x| matches!(x, super::DownloadEvent::Progress(_)))
+                .collect::<Vec<_>>(),
+            [&super::DownloadEvent::Progress(0.0)]
+        );
+    }
+
+    /// Serve `body`, honouring and advertising `Range` requests if `ranges` is set. First
+    /// response is cut after `cut` bytes to simulate an interrupted download. Returns the requests
+    /// received, as method followed by the requested range.
+    fn range_server(
+        body: Vec<u8>,
+        ranges: bool,
+        cut: Option<usize>,
+    ) -> (std::net::SocketAddr, Arc<Mutex<Vec<String>>>) {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let requests_clone = requests.clone();
+
+        std::thread::spawn(move || {
+            for (i, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut request = line.split_whitespace().next().unwrap().to_string();
+                let mut range = None;
+                line.clear();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if let Some(x) = line.to_lowercase().strip_prefix("range: bytes=") {
+                        request = format!("{request} {}", x.trim());
+                        let (start, end) = x.trim().split_once('-').unwrap();
+                        let start: usize = start.parse().unwrap();
+                        let end = end.parse().map_or(body.len(), |x: usize| x + 1);
+                        range = Some(start..end.min(body.len()));
+                    }
+                    line.clear();
+                }
+                let head = request == "HEAD";
+                requests_clone.lock().unwrap().push(request);
+
+                let (status, data) = match range {
+                    Some(r) if ranges => (
+                        format!(
+                            "206 Partial Content\r\nContent-Range: bytes {}-{}/{}",
+                            r.start,
+                            r.end - 1,
+                            body.len()
+                        ),
+                        &body[r],
+                    ),
+                    _ => ("200 OK".to_string(), &body[..]),
+                };
+                let accept_ranges = if ranges {
+                    "Accept-Ranges: bytes\r\n"
+                } else {
+                    ""
+                };
+                let sent = match cut {
+                    _ if head => &[],
+                    Some(x) if i == 0 => &data[..x],
+                    _ => data,
+                };
+
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\n{accept_ranges}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    data.len()
+                )
+                .unwrap();
+                stream.write_all(sent).unwrap();
+            }
+        });
+
+        (addr, requests)
+    }
+
+    async fn interrupted_download(ranges: bool) -> (Vec<super::DownloadEvent>, Vec<String>) {
+        use sha2::Digest;
+
+        let data: Vec<u8> = (0..(64 * 1024)).map(|x| (x % 251) as u8).collect();
+        let sha256: [u8; 32] = sha2::Sha256::digest(&data).into();
+        let (addr, requests) = range_server(data.clone(), ranges, Some(20000));
+        let url = format!("http://{addr}/img.xz");
+
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path())
+            .unwrap()
+            .with_max_retries(0);
+        let part = super::part_path(&downloader.path_from_hash::<super::Sha256Hasher>(&sha256));
+
+        assert!(
+            downloader
+                .download_with_sha(&url, sha256, None)
+                .await
+                .is_err()
+        );
+        assert_eq!(std::fs::metadata(&part).unwrap().len(), 20000);
+
+        let (tx, rx) = futures::channel::mpsc::channel(100);
+        let p = downloader
+            .download_with_sha_tracked(&url, sha256, Some(tx))
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(p).await.unwrap(), data);
+        assert!(!part.exists());
+
+        let requests = requests.lock().unwrap().clone();
+        (futures::StreamExt::collect(rx).await, requests)
+    }
+
+    #[tokio::test]
+    async fn resume_download() {
+        let (events, requests) = interrupted_download(true).await;
+
+        assert_eq!(requests, ["GET", "GET 20000-"]);
+        assert!(events.contains(&super::DownloadEvent::Resumed { offset: 20000 }));
+        assert_eq!(events.last(), Some(&super::DownloadEvent::Progress(1.0)));
+    }
+
+    #[tokio::test]
+    async fn resume_ignored_by_server() {
+        let (events, requests) = interrupted_download(false).await;
+
+        // Restarted with the complete response
+        assert_eq!(requests, ["GET", "GET 20000-"]);
+        assert!(
+            !events
+                .iter()
+                .any(|x| matches!(x, super::DownloadEvent::Resumed { .. }))
+        );
+        assert_eq!(events.last(), Some(&super::DownloadEvent::Progress(1.0)));
+    }
+
+    /// Respond with 503 to the first `failures` requests, and serve `body` afterwards.
+    fn flaky_server(body: Vec<u8>, failures: usize) -> (std::net::SocketAddr, Arc<AtomicUsize>) {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+        let count_clone = count.clone();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+
+                let data: &[u8] = if count_clone.fetch_add(1, Ordering::Relaxed) < failures {
+                    b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                } else {
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .unwrap();
+                    &body
+                };
+                stream.write_all(data).unwrap();
+            }
+        });
+
+        (addr, count)
+    }
+
+    #[tokio::test]
+    async fn retry_server_error() {
+        use sha2::Digest;
+
+        let data = vec![0xabu8; 4096];
+        let sha256: [u8; 32] = sha2::Sha256::digest(&data).into();
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path())
+            .unwrap()
+            .with_base_delay(Duration::from_millis(1));
+
+        let (addr, count) = flaky_server(data.clone(), 2);
+        let p = downloader
+            .download_with_sha(format!("http://{addr}/img.xz"), sha256, None)
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(p).await.unwrap(), data);
+        assert_eq!(count.load(Ordering::Relaxed), 3);
+
+        // Last error is returned once retries are exhausted
+        let (addr, count) = flaky_server(data.clone(), 2);
+        let err = downloader
+            .with_max_retries(1)
+            .download_no_cache(format!("http://{addr}/img.xz"), None)
+            .await
+            .unwrap_err();
+        let err = err.get_ref().unwrap().downcast_ref::<reqwest::Error>();
+        assert_eq!(
+            err.unwrap().status(),
+            Some(reqwest::StatusCode::SERVICE_UNAVAILABLE)
+        );
+        assert_eq!(count.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn retry_resumes() {
+        use sha2::Digest;
+
+        let data: Vec<u8> = (0..(64 * 1024)).map(|x| (x % 251) as u8).collect();
+        let sha256: [u8; 32] = sha2::Sha256::digest(&data).into();
+        let (addr, requests) = range_server(data.clone(), true, Some(20000));
+
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path())
+            .unwrap()
+            .with_base_delay(Duration::from_millis(1));
+
+        let (tx, rx) = futures::channel::mpsc::channel(100);
+        let p = downloader
+            .download_with_sha_tracked(format!("http://{addr}/img.xz"), sha256, Some(tx))
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(p).await.unwrap(), data);
+
+        assert_eq!(*requests.lock().unwrap(), ["GET", "GET 20000-"]);
+        let events: Vec<_> = futures::StreamExt::collect(rx).await;
+        assert!(events.contains(&super::DownloadEvent::Resumed { offset: 20000 }));
+    }
+
+    /// Serve the current body and its ETag, honouring `If-None-Match`. Returns the requests
+    /// received, as method followed by the `If-None-Match` header.
+    fn etag_server(
+        current: Arc<Mutex<(Vec<u8>, String)>>,
+    ) -> (std::net::SocketAddr, Arc<Mutex<Vec<String>>>) {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let requests_clone = requests.clone();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut request = line.split_whitespace().next().unwrap().to_string();
+                let mut if_none_match = None;
+                line.clear();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if let Some(x) = line.to_lowercase().strip_prefix("if-none-match: ") {
+                        request = format!("{request} {}", x.trim());
+                        if_none_match = Some(x.trim().to_string());
+                    }
+                    line.clear();
+                }
+                requests_clone.lock().unwrap().push(request);
+
+                let (body, etag) = current.lock().unwrap().clone();
+                if if_none_match.as_ref() == Some(&etag) {
+                    write!(
+                        stream,
+                        "HTTP/1.1 304 Not Modified\r\nETag: {etag}\r\nConnection: close\r\n\r\n"
+                    )
+                    .unwrap();
+                    continue;
+                }
+
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nETag: {etag}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+
+        (addr, requests)
+    }
+
+    #[tokio::test]
+    async fn revalidate_etag() {
+        let current = Arc::new(Mutex::new((vec![1u8; 4096], "\"v1\"".to_string())));
+        let (addr, requests) = etag_server(current.clone());
+        let url = format!("http://{addr}/distros.json");
+
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path()).unwrap();
+
+        let p = downloader.download(&url, None, None).await.unwrap();
+        assert_eq!(tokio::fs::read(&p).await.unwrap(), [1u8; 4096]);
+
+        // Not modified
+        let p = downloader.download(&url, None, None).await.unwrap();
+        assert_eq!(tokio::fs::read(&p).await.unwrap(), [1u8; 4096]);
+
+        *current.lock().unwrap() = (vec![2u8; 2048], "\"v2\"".to_string());
+        let p = downloader.download(&url, None, None).await.unwrap();
+        assert_eq!(tokio::fs::read(&p).await.unwrap(), [2u8; 2048]);
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            ["GET", "GET \"v1\"", "GET \"v1\"", "GET"]
+        );
+        // Validators are not listed as cache entries
+        assert_eq!(downloader.cache_entries().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn download_with_mirrors() {
+        use sha2::Digest;
+
+        let data = vec![0xabu8; 4096];
+        let sha256: [u8; 32] = sha2::Sha256::digest(&data).into();
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path())
+            .unwrap()
+            .with_max_retries(0);
+
+        // Unreachable, wrong file, and correct file
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_addr = closed.local_addr().unwrap();
+        drop(closed);
+        let urls: Vec<reqwest::Url> = [
+            closed_addr,
+            mock_server(vec![0xcd; 4096]),
+            mock_server(data.clone()),
+        ]
+        .iter()
+        .map(|addr| format!("http://{addr}/img.xz").parse().unwrap())
+        .collect();
+
+        let p = downloader
+            .download_with_mirrors(&urls, sha256, None)
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(p).await.unwrap(), data);
+
+        assert!(
+            downloader
+                .download_with_mirrors(&[], [0; 32], None)
+                .await
+                .is_err()
+        );
+    }
+
+    /// Server which stalls after sending the first 20000 bytes of a 65536 bytes file.
+    fn stalled_server() -> std::net::SocketAddr {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: 65536\r\nConnection: close\r\n\r\n"
+            )
+            .unwrap();
+            stream.write_all(&[0xab; 20000]).unwrap();
+            std::thread::sleep(Duration::from_secs(10));
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn cancel_download() {
+        let addr = stalled_server();
+        let dir = tempfile::tempdir().unwrap();
+        let cancel = tokio_util::sync::CancellationToken::new();
+        let downloader = super::Downloader::new(dir.path())
+            .unwrap()
+            .with_cancel(cancel.clone());
+        let url: reqwest::Url = format!("http://{addr}/img.xz").parse().unwrap();
+        let part = super::part_path(&downloader.path_from_url(&url));
+
+        let (tx, mut rx) = futures::channel::mpsc::channel(100);
+        let task =
+            tokio::spawn(async move { downloader.download_no_cache_tracked(url, Some(tx)).await });
+
+        // Wait for the first data to arrive
+        while futures::StreamExt::next(&mut rx).await
+            != Some(super::DownloadEvent::Progress(20000.0 / 65536.0))
+        {}
+        cancel.cancel();
+
+        let err = tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+        // Partial file is kept for resuming
+        assert_eq!(std::fs::metadata(&part).unwrap().len(), 20000);
+    }
+
+    #[tokio::test]
+    async fn proxy() {
+        let data = vec![0xabu8; 4096];
+        let (addr, requests) = range_server(data.clone(), false, None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::builder(dir.path())
+            .with_proxy(format!("http://{addr}").parse().unwrap())
+            .with_system_proxy(false)
+            .build()
+            .unwrap();
+
+        // Host cannot be resolved, so it can only be reached through the proxy
+        let p = downloader
+            .download_no_cache("http://image.invalid/img.xz", None)
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(p).await.unwrap(), data);
+        assert_eq!(*requests.lock().unwrap(), ["GET"]);
+    }
+
+    #[tokio::test]
+    async fn download_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path())
+            .unwrap()
+            .with_memory_limit(4096);
+
+        let addr = mock_server(vec![0xab; 4096]);
+        let data = downloader
+            .download_bytes_no_cache(format!("http://{addr}/icon.svg"))
+            .await
+            .unwrap();
+        assert_eq!(data, vec![0xab; 4096]);
+        // Not cached
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let addr = mock_server(vec![0xab; 4097]);
+        let err = downloader
+            .download_bytes_no_cache(format!("http://{addr}/icon.svg"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::FileTooLarge);
+    }
+
+    #[tokio::test]
+    async fn custom_headers() {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+            let mut headers = Vec::new();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                headers.push(line.trim().to_lowercase());
+                line.clear();
+            }
+
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}"
+            )
+            .unwrap();
+            headers
+        });
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-custom", reqwest::header::HeaderValue::from_static("1"));
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path())
+            .unwrap()
+            .with_headers(headers)
+            .with_bearer_auth("secret")
+            .unwrap();
+
+        downloader
+            .download_bytes_no_cache(format!("http://{addr}/list.json"))
+            .await
+            .unwrap();
+
+        let headers = handle.join().unwrap();
+        assert!(headers.contains(&"authorization: bearer secret".to_string()));
+        assert!(headers.contains(&"x-custom: 1".to_string()));
+        // Credentials are never logged
+        assert!(!format!("{downloader:?}").contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn read_timeout() {
+        let addr = stalled_server();
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::builder(dir.path())
+            .with_read_timeout(Duration::from_millis(200))
+            .build()
+            .unwrap()
+            .with_max_retries(0);
+
+        let err = tokio::time::timeout(
+            Duration::from_secs(5),
+            downloader.download_no_cache(format!("http://{addr}/img.xz"), None),
+        )
+        .await
+        .unwrap()
+        .unwrap_err();
+        let err = err.get_ref().unwrap().downcast_ref::<reqwest::Error>();
+        assert!(err.unwrap().is_timeout());
+    }
+
+    async fn chunked_download(ranges: bool) -> (Vec<super::DownloadEvent>, Vec<String>) {
+        use sha2::Digest;
+
+        let data: Vec<u8> = (0..(64 * 1024 + 10)).map(|x| (x % 251) as u8).collect();
+        let sha256: [u8; 32] = sha2::Sha256::digest(&data).into();
+        let (addr, requests) = range_server(data.clone(), ranges, None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path()).unwrap();
+
+        let (tx, rx) = futures::channel::mpsc::channel(100);
+        let p = downloader
+            .download_chunked(format!("http://{addr}/img.xz"), sha256, 4, Some(tx))
+            .await
+            .unwrap();
+        assert_eq!(p, downloader.path_from_hash::<super::Sha256Hasher>(&sha256));
+        assert_eq!(tokio::fs::read(p).await.unwrap(), data);
+        assert!(
+            !super::chunks_path(&downloader.path_from_hash::<super::Sha256Hasher>(&sha256))
+                .exists()
+        );
+
+        let mut requests = requests.lock().unwrap().clone();
+        requests.sort();
+        (futures::StreamExt::collect(rx).await, requests)
+    }
+
+    #[tokio::test]
+    async fn download_chunked() {
+        let (events, requests) = chunked_download(true).await;
+
+        assert_eq!(
+            requests,
+            [
+                "GET 0-16386",
+                "GET 16387-32773",
+                "GET 32774-49160",
+                "GET 49161-65545",
+                "HEAD"
+            ]
+        );
+        assert_eq!(events.last(), Some(&super::DownloadEvent::Progress(1.0)));
+    }
+
+    #[tokio::test]
+    async fn download_chunked_fallback() {
+        let (events, requests) = chunked_download(false).await;
+
+        assert_eq!(requests, ["GET", "HEAD"]);
+        assert_eq!(events.last(), Some(&super::DownloadEvent::Progress(1.0)));
+    }
+
+    #[tokio::test]
+    async fn stream_kept_after_cancel() {
+        use sha2::Digest;
+        use std::io::Read;
+
+        let data: Vec<u8> = (0..(256 * 1024)).map(|x| (x % 251) as u8).collect();
+        let sha256: [u8; 32] = sha2::Sha256::digest(&data).into();
+        let addr = mock_server(data.clone());
+
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path()).unwrap();
+        let file_path = downloader.path_from_hash::<super::Sha256Hasher>(&sha256);
+
+        let (tx, mut rx) = bb_helper::file_stream::file_stream().unwrap();
+        let task = tokio::spawn(downloader.clone().download_to_stream(
+            format!("http://{addr}/img.xz"),
+            sha256,
+            tx,
+        ));
+
+        // Flasher reads the complete image, and is cancelled before finishing
+        let len = data.len();
+        let buf = tokio::task::spawn_blocking(move || {
+            let mut buf = vec![0u8; len];
+            rx.read_exact(&mut buf).unwrap();
+            buf
+        })
+        .await
+        .unwrap();
+        assert_eq!(buf, data);
+
+        // Partial file exists from the start, so wait for the file to be moved to cache
+        while !file_path.exists() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        task.abort();
+
+        let res = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Some(p) = downloader.check_cache_from_sha(sha256).await {
+                    return p;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(res, file_path);
+        assert_eq!(tokio::fs::read(res).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn prune_older_than() {
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path()).unwrap();
+
+        let p = dir.path().join("img");
+        tokio::fs::write(&p, [1u8; 4096]).await.unwrap();
+        super::write_sidecar::<super::Sha256Hasher>(&p, &[0; 32])
+            .await
+            .unwrap();
+
+        let entries = downloader.cache_entries().await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].size(), 4096);
+
+        assert_eq!(
+            downloader
+                .prune_older_than(Duration::from_secs(3600))
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            downloader.prune_older_than(Duration::ZERO).await.unwrap(),
+            4096
+        );
+        assert!(!p.exists());
+        assert!(!super::sidecar_path(&p).exists());
+    }
+
+    #[tokio::test]
+    async fn clear_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = super::Downloader::new(dir.path()).unwrap();
+
+        let p = dir.path().join("img");
+        tokio::fs::write(&p, [1u8; 4096]).await.unwrap();
+        super::write_sidecar::<super::Sha256Hasher>(&p, &[0; 32])
+            .await
+            .unwrap();
+        tokio::fs::write(super::part_path(&p), [1u8; 1024])
+            .await
+            .unwrap();
+
+        // Sidecars are not counted
+        assert_eq!(downloader.cache_size().await.unwrap(), 5120);
+        assert_eq!(downloader.clear_cache().await.unwrap(), 5120);
+        assert_eq!(downloader.cache_size().await.unwrap(), 0);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
     }
 }
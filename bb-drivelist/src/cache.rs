@@ -0,0 +1,116 @@
+//! Cache [drive_list] results, and track changes to the device set using a generation counter.
+//!
+//! [drive_list]: crate::drive_list
+
+use std::time::{Duration, Instant};
+
+use crate::DeviceDescriptor;
+
+/// Caches [drive_list] results for some time. Each time the set of devices changes, the
+/// generation is incremented. This allows consumers (like GUIs polling for devices) to cheaply
+/// check if anything changed since the last time they looked.
+///
+/// Only device identity (path, size and serial) is considered when detecting changes, so things
+/// like free space on a mountpoint do not bump the generation.
+///
+/// [drive_list]: crate::drive_list
+#[derive(Debug, Clone)]
+pub struct DriveListCache {
+    ttl: Duration,
+    generation: u64,
+    drives: Vec<DeviceDescriptor>,
+    last_refresh: Option<Instant>,
+}
+
+impl DriveListCache {
+    /// Create a new cache. Drives are enumerated again only after `ttl` has elapsed.
+    pub const fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            generation: 0,
+            drives: Vec::new(),
+            last_refresh: None,
+        }
+    }
+
+    /// Current generation. Starts at 0, before the first refresh.
+    pub const fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Check if the device set has changed since `generation`.
+    pub const fn changed_since(&self, generation: u64) -> bool {
+        self.generation != generation
+    }
+
+    /// Cached drives from the last refresh.
+    pub fn drives(&self) -> &[DeviceDescriptor] {
+        &self.drives
+    }
+
+    /// Enumerate drives again if the cache has expired. Returns the current generation.
+    pub fn refresh(&mut self) -> anyhow::Result<u64> {
+        let expired = self.last_refresh.is_none_or(|x| x.elapsed() >= self.ttl);
+
+        if expired {
+            let drives = crate::drive_list()?;
+            self.update(drives);
+        }
+
+        Ok(self.generation)
+    }
+
+    fn update(&mut self, drives: Vec<DeviceDescriptor>) {
+        // First enumeration always counts as a change
+        if self.last_refresh.is_none() || keys(&drives) != keys(&self.drives) {
+            self.generation += 1;
+        }
+
+        self.last_refresh = Some(Instant::now());
+        self.drives = drives;
+    }
+}
+
+fn keys(drives: &[DeviceDescriptor]) -> Vec<(&str, Option<u64>, Option<&str>)> {
+    let mut keys: Vec<_> = drives
+        .iter()
+        .map(|x| (x.device.as_str(), x.size, x.serial.as_deref()))
+        .collect();
+    keys.sort();
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drive(device: &str) -> DeviceDescriptor {
+        DeviceDescriptor {
+            device: device.to_string(),
+            size: Some(32 * 1000 * 1000 * 1000),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn generation() {
+        let mut cache = DriveListCache::new(Duration::from_secs(1));
+        assert_eq!(cache.generation(), 0);
+
+        cache.update(vec![drive("/dev/sda"), drive("/dev/sdb")]);
+        let generation = cache.generation();
+        assert!(cache.changed_since(0));
+
+        // Same devices in different order, and changed mountpoints
+        let mut sdb = drive("/dev/sdb");
+        sdb.mountpoints.push(crate::MountPoint::new("/media/sdb"));
+        cache.update(vec![sdb, drive("/dev/sda")]);
+        assert!(!cache.changed_since(generation));
+        assert_eq!(cache.drives()[0].mountpoints.len(), 1);
+
+        // Device removed
+        cache.update(vec![drive("/dev/sda")]);
+        assert!(cache.changed_since(generation));
+        assert_eq!(cache.generation(), generation + 1);
+    }
+}
@@ -3,6 +3,10 @@
 pub struct MountPoint {
     pub path: String,
     pub label: Option<String>,
+    /// Filesystem type, as reported by lsblk (eg. `vfat`, `ext4`)
+    pub filesystem: Option<String>,
+    /// Partition label (GPT partition name), which can differ from filesystem label
+    pub partition_label: Option<String>,
     pub total_bytes: Option<u64>,
     pub available_bytes: Option<u64>,
 }
@@ -12,12 +16,78 @@ impl MountPoint {
         Self {
             path: path.to_string(),
             label: None,
+            filesystem: None,
+            partition_label: None,
             total_bytes: None,
             available_bytes: None,
         }
     }
 }
 
+/// Normalize filesystem names reported by different platforms to the names used by lsblk.
+pub(crate) fn normalize_filesystem(fs: &str) -> String {
+    let fs = fs.to_lowercase();
+    match fs.as_str() {
+        "msdos" | "fat" | "fat12" | "fat16" | "fat32" => "vfat".to_string(),
+        _ => fs,
+    }
+}
+
+/// Builds human readable device descriptions from the fields reported by each platform.
+///
+/// Fields are trimmed, internal whitespace is collapsed and fields are separated by a single
+/// space. A field is dropped if the next field repeats it (eg. vendor `SanDisk` followed by
+/// model `SanDisk Ultra`).
+#[derive(Debug, Default, Clone)]
+pub(crate) struct DescriptionBuilder {
+    parts: Vec<String>,
+}
+
+impl DescriptionBuilder {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a field. Missing and blank fields are ignored.
+    pub(crate) fn part(mut self, part: Option<&str>) -> Self {
+        let part = part
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        if part.is_empty() || self.parts.iter().any(|x| x.eq_ignore_ascii_case(&part)) {
+            return self;
+        }
+
+        if let Some(last) = self.parts.last()
+            && starts_with_word(&part, last)
+        {
+            self.parts.pop();
+        }
+
+        self.parts.push(part);
+        self
+    }
+
+    /// Build the description. `fallback` is used when all fields are empty.
+    pub(crate) fn build(self, fallback: &str) -> String {
+        if self.parts.is_empty() {
+            fallback.trim().to_string()
+        } else {
+            self.parts.join(" ")
+        }
+    }
+}
+
+/// Check if `s` starts with the word(s) `prefix`, ignoring ASCII case.
+fn starts_with_word(s: &str, prefix: &str) -> bool {
+    s.len() > prefix.len()
+        && s.is_char_boundary(prefix.len())
+        && s[..prefix.len()].eq_ignore_ascii_case(prefix)
+        && s[prefix.len()..].starts_with(' ')
+}
+
 #[derive(Debug, Clone)]
 /// Device Description
 pub struct DeviceDescriptor {
@@ -35,6 +105,8 @@ pub struct DeviceDescriptor {
     pub logical_block_size: u32,
     pub mountpoints: Vec<MountPoint>,
     pub mountpoint_labels: Vec<String>,
+    /// Stable hardware identifier (serial number or WWN), if reported by the OS
+    pub serial: Option<String>,
     /// Device is read-only
     pub is_readonly: bool,
     /// Device is a system drive
@@ -70,6 +142,7 @@ impl Default for DeviceDescriptor {
             size: Default::default(),
             mountpoints: Default::default(),
             mountpoint_labels: Default::default(),
+            serial: Default::default(),
             is_readonly: Default::default(),
             is_system: Default::default(),
             is_card: Default::default(),
@@ -81,3 +154,75 @@ impl Default for DeviceDescriptor {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{DescriptionBuilder, normalize_filesystem};
+
+    #[test]
+    fn filesystem() {
+        // Linux
+        assert_eq!(normalize_filesystem("vfat"), "vfat");
+        assert_eq!(normalize_filesystem("ext4"), "ext4");
+        // Windows
+        assert_eq!(normalize_filesystem("FAT32"), "vfat");
+        assert_eq!(normalize_filesystem("NTFS"), "ntfs");
+        assert_eq!(normalize_filesystem("exFAT"), "exfat");
+        // MacOS
+        assert_eq!(normalize_filesystem("msdos"), "vfat");
+        assert_eq!(normalize_filesystem("apfs"), "apfs");
+    }
+
+    #[test]
+    fn description() {
+        // Linux: label, vendor and model reported separately, often padded
+        let desc = DescriptionBuilder::new()
+            .part(None)
+            .part(Some("SanDisk "))
+            .part(Some("Ultra"))
+            .build("sdb");
+        assert_eq!(desc, "SanDisk Ultra");
+
+        let desc = DescriptionBuilder::new()
+            .part(Some("BOOT"))
+            .part(Some("Generic "))
+            .part(Some("SD  Card Reader"))
+            .build("sdb");
+        assert_eq!(desc, "BOOT Generic SD Card Reader");
+
+        let desc = DescriptionBuilder::new()
+            .part(Some("ATA     "))
+            .part(Some("ata"))
+            .part(Some("ATA Samsung SSD"))
+            .build("sda");
+        assert_eq!(desc, "ATA Samsung SSD");
+
+        let desc = DescriptionBuilder::new()
+            .part(Some("SanDisk"))
+            .part(Some("SanDiskUltra"))
+            .build("sdb");
+        assert_eq!(desc, "SanDisk SanDiskUltra");
+
+        let desc = DescriptionBuilder::new()
+            .part(None)
+            .part(Some("  "))
+            .part(None)
+            .build("/dev/loop0");
+        assert_eq!(desc, "/dev/loop0");
+
+        // Windows: friendly name
+        let desc = DescriptionBuilder::new()
+            .part(Some("Generic- SD/MMC  USB Device "))
+            .build("\\\\.\\PhysicalDrive1");
+        assert_eq!(desc, "Generic- SD/MMC USB Device");
+
+        // MacOS: media name
+        let desc = DescriptionBuilder::new()
+            .part(Some("APPLE SD Card Reader Media"))
+            .build("disk4");
+        assert_eq!(desc, "APPLE SD Card Reader Media");
+
+        let desc = DescriptionBuilder::new().part(Some("")).build("disk4");
+        assert_eq!(desc, "disk4");
+    }
+}
@@ -4,10 +4,12 @@
 //! - Linux
 //! - Macos
 
+mod cache;
 mod device;
 
 mod pal;
 
+pub use cache::DriveListCache;
 pub use device::{DeviceDescriptor, MountPoint};
 
 /// Get a list of all drives
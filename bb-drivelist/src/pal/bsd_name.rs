@@ -0,0 +1,130 @@
+//! Parsing of MacOS BSD disk names (eg: `disk4`, `disk4s1`, `disk3s1s1`). Kept free of any
+//! platform APIs, so that it can be tested everywhere.
+
+/// Partition type GUID of APFS containers.
+const APFS_CONTAINER_CONTENT: &str = "EF57347C-0000-11AA-AA11-00306543ECAC";
+/// IOKit media class of disks synthesized from APFS containers.
+const APFS_MEDIA_NAME: &str = "AppleAPFSMedia";
+
+/// Parsed BSD name.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct BsdName {
+    /// Number of the whole disk. `4` for `disk4s1`.
+    pub(crate) disk: u32,
+    /// Partition (and APFS volume/snapshot) numbers. `[1, 2]` for `disk3s1s2`. Empty for whole
+    /// disks.
+    pub(crate) slices: Vec<u32>,
+}
+
+impl BsdName {
+    /// Parse BSD name. Raw device names (`rdisk4`) and `/dev/` prefix are also accepted.
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        let name = name.strip_prefix("/dev/").unwrap_or(name);
+        let name = name.strip_prefix('r').unwrap_or(name);
+        let mut parts = name.strip_prefix("disk")?.split('s');
+
+        let disk = parse_num(parts.next()?)?;
+        let slices = parts.map(parse_num).collect::<Option<Vec<_>>>()?;
+
+        Some(Self { disk, slices })
+    }
+
+    pub(crate) const fn is_partition(&self) -> bool {
+        !self.slices.is_empty()
+    }
+
+    /// BSD name of the whole disk. `disk4` for `disk4s1`.
+    pub(crate) fn whole_disk(&self) -> String {
+        format!("disk{}", self.disk)
+    }
+}
+
+/// Only plain decimal numbers. Rejects empty strings, signs and leading zeros.
+fn parse_num(s: &str) -> Option<u32> {
+    if s.is_empty() || !s.bytes().all(|x| x.is_ascii_digit()) || (s.len() > 1 && s.starts_with('0'))
+    {
+        return None;
+    }
+
+    s.parse().ok()
+}
+
+/// Check if a BSD name is a partition (e.g., "disk0s1", "disk3s1s1").
+pub(crate) fn is_partition_name(name: &str) -> bool {
+    BsdName::parse(name).is_some_and(|x| x.is_partition())
+}
+
+/// BSD name of the disk containing a partition (e.g., "disk10s1" -> "disk10"). Names which do
+/// not match the expected pattern are returned as is.
+pub(crate) fn whole_disk_name(name: &str) -> String {
+    BsdName::parse(name)
+        .map(|x| x.whole_disk())
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// APFS synthesizes a disk for each container. These are not physical devices, and should never
+/// be flashed.
+pub(crate) fn is_apfs_synthesized(media_content: Option<&str>, media_name: Option<&str>) -> bool {
+    media_content == Some(APFS_CONTAINER_CONTENT) || media_name == Some(APFS_MEDIA_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bsd_names() {
+        let cases = [
+            ("disk0", Some((0, vec![]))),
+            ("disk10", Some((10, vec![]))),
+            ("disk0s1", Some((0, vec![1]))),
+            ("disk10s12", Some((10, vec![12]))),
+            ("disk3s1s1", Some((3, vec![1, 1]))),
+            ("rdisk4s2", Some((4, vec![2]))),
+            ("/dev/disk4", Some((4, vec![]))),
+            ("/dev/rdisk4", Some((4, vec![]))),
+            ("disk", None),
+            ("disks1", None),
+            ("disk1s", None),
+            ("disk1s1s", None),
+            ("disk01", None),
+            ("disk1x2", None),
+            ("sda1", None),
+            ("", None),
+        ];
+
+        for (name, expected) in cases {
+            let expected = expected.map(|(disk, slices)| BsdName { disk, slices });
+            assert_eq!(BsdName::parse(name), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn partitions() {
+        assert!(!is_partition_name("disk4"));
+        assert!(!is_partition_name("disk10"));
+        assert!(is_partition_name("disk4s1"));
+        assert!(is_partition_name("disk3s1s1"));
+        assert!(!is_partition_name("sda1"));
+
+        assert_eq!(whole_disk_name("disk0s1"), "disk0");
+        assert_eq!(whole_disk_name("disk10s1"), "disk10");
+        assert_eq!(whole_disk_name("disk3s1s1"), "disk3");
+        assert_eq!(whole_disk_name("disk4"), "disk4");
+        assert_eq!(whole_disk_name("unknown"), "unknown");
+    }
+
+    #[test]
+    fn apfs_synthesized() {
+        assert!(is_apfs_synthesized(Some(APFS_CONTAINER_CONTENT), None));
+        assert!(is_apfs_synthesized(
+            Some("GUID_partition_scheme"),
+            Some(APFS_MEDIA_NAME)
+        ));
+        assert!(!is_apfs_synthesized(
+            Some("FDisk_partition_scheme"),
+            Some("SD Card Reader")
+        ));
+        assert!(!is_apfs_synthesized(None, None));
+    }
+}
@@ -1,42 +1,96 @@
-use std::process::Command;
+use std::{process::Command, str::FromStr};
 
-use crate::device::{DeviceDescriptor, MountPoint};
-use serde::Deserialize;
+use crate::device::{DescriptionBuilder, DeviceDescriptor, MountPoint, normalize_filesystem};
+use serde::{Deserialize, Deserializer};
+
+/// Columns used when `--output-all` is not supported. Only includes columns present in older
+/// lsblk versions.
+const MINIMAL_COLUMNS: &str = "NAME,KNAME,SIZE,TRAN,SUBSYSTEMS,RO,RM,HOTPLUG,PHY-SEC,LOG-SEC,\
+PTTYPE,LABEL,VENDOR,MODEL,SERIAL,WWN,MOUNTPOINT,FSTYPE,PARTLABEL";
 
 #[derive(Deserialize, Debug)]
 struct Devices {
     blockdevices: Vec<Device>,
 }
 
+/// Older lsblk versions output all values as strings. Missing columns are also tolerated.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Compat<T> {
+    Value(T),
+    String(String),
+}
+
+fn compat_bool<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    Ok(match Option::<Compat<bool>>::deserialize(deserializer)? {
+        Some(Compat::Value(x)) => x,
+        Some(Compat::String(x)) => x == "1" || x == "true",
+        None => false,
+    })
+}
+
+fn compat_num<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+{
+    Ok(match Option::<Compat<T>>::deserialize(deserializer)? {
+        Some(Compat::Value(x)) => Some(x),
+        Some(Compat::String(x)) => x.parse().ok(),
+        None => None,
+    })
+}
+
 #[derive(Deserialize, Debug)]
 struct Device {
+    #[serde(default, deserialize_with = "compat_num")]
     size: Option<u64>,
     #[serde(default = "Device::name_default")]
     kname: String,
     #[serde(default = "Device::name_default")]
     name: String,
+    #[serde(default)]
     tran: Option<String>,
+    #[serde(default = "Device::subsystems_default")]
     subsystems: String,
+    #[serde(default, deserialize_with = "compat_bool")]
     ro: bool,
-    #[serde(rename = "phy-sec")]
-    phy_sec: u32,
-    #[serde(rename = "log-sec")]
-    log_sec: u32,
+    #[serde(rename = "phy-sec", default, deserialize_with = "compat_num")]
+    phy_sec: Option<u32>,
+    #[serde(rename = "log-sec", default, deserialize_with = "compat_num")]
+    log_sec: Option<u32>,
+    #[serde(default, deserialize_with = "compat_bool")]
     rm: bool,
+    #[serde(default, alias = "pttype")]
     ptype: Option<String>,
     #[serde(default)]
     children: Vec<Child>,
+    #[serde(default)]
     label: Option<String>,
+    #[serde(default)]
     vendor: Option<String>,
+    #[serde(default)]
     model: Option<String>,
+    #[serde(default)]
+    serial: Option<String>,
+    #[serde(default)]
+    wwn: Option<String>,
+    #[serde(default, deserialize_with = "compat_bool")]
     hotplug: bool,
 }
 
 impl Device {
+    const DEFAULT_SECTOR_SIZE: u32 = 512;
+
     fn name_default() -> String {
         "NO_NAME".to_string()
     }
 
+    /// Assume a real block device when subsystems column is missing.
+    fn subsystems_default() -> String {
+        "block".to_string()
+    }
+
     fn is_scsi(&self) -> bool {
         self.subsystems.contains("sata")
             || self.subsystems.contains("scsi")
@@ -46,17 +100,11 @@ impl Device {
     }
 
     fn description(&self) -> String {
-        [
-            self.label.as_deref().unwrap_or_default(),
-            self.vendor.as_deref().unwrap_or_default(),
-            self.model.as_deref().unwrap_or_default(),
-        ]
-        .into_iter()
-        .filter(|x| !x.is_empty())
-        .fold(String::new(), |mut acc, x| {
-            acc.push_str(x);
-            acc
-        })
+        DescriptionBuilder::new()
+            .part(self.label.as_deref())
+            .part(self.vendor.as_deref())
+            .part(self.model.as_deref())
+            .build(&self.name)
     }
 
     fn is_virtual(&self) -> bool {
@@ -70,6 +118,15 @@ impl Device {
     fn is_system(&self) -> bool {
         !(self.is_removable() || self.is_virtual())
     }
+
+    fn serial(&self) -> Option<String> {
+        [self.serial.as_deref(), self.wwn.as_deref()]
+            .into_iter()
+            .flatten()
+            .map(str::trim)
+            .find(|x| !x.is_empty())
+            .map(ToString::to_string)
+    }
 }
 
 impl From<Device> for DeviceDescriptor {
@@ -79,6 +136,7 @@ impl From<Device> for DeviceDescriptor {
         let is_virtual = value.is_virtual();
         let is_removable = value.is_removable();
         let is_system = value.is_system();
+        let serial = value.serial();
 
         Self {
             enumerator: "lsblk:json".to_string(),
@@ -88,43 +146,35 @@ impl From<Device> for DeviceDescriptor {
             is_virtual,
             is_scsi,
             is_usb: value.subsystems.contains("usb"),
+            is_card: value.tran.as_deref() == Some("mmc"),
             is_readonly: value.ro,
             description,
             size: value.size,
-            block_size: value.phy_sec,
-            logical_block_size: value.log_sec,
+            block_size: value.phy_sec.unwrap_or(Device::DEFAULT_SECTOR_SIZE),
+            logical_block_size: value.log_sec.unwrap_or(Device::DEFAULT_SECTOR_SIZE),
             is_removable,
             is_system,
             partition_table_type: value.ptype,
+            serial,
             mountpoints: value.children.into_iter().map(Into::into).collect(),
             ..Default::default()
         }
     }
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(untagged)]
-/// Sometimes fssize and fsavail are strings. So need to handle that.
-enum FsSize {
-    String(String),
-    U64(u64),
-}
-
-impl From<FsSize> for u64 {
-    fn from(value: FsSize) -> Self {
-        match value {
-            FsSize::String(x) => x.parse().unwrap(),
-            FsSize::U64(x) => x,
-        }
-    }
-}
-
 #[derive(Deserialize, Debug)]
 struct Child {
+    #[serde(default)]
     mountpoint: Option<String>,
-    fssize: Option<FsSize>,
-    fsavail: Option<FsSize>,
+    #[serde(default, deserialize_with = "compat_num")]
+    fssize: Option<u64>,
+    #[serde(default, deserialize_with = "compat_num")]
+    fsavail: Option<u64>,
+    #[serde(default)]
+    fstype: Option<String>,
+    #[serde(default)]
     label: Option<String>,
+    #[serde(default)]
     partlabel: Option<String>,
 }
 
@@ -132,27 +182,35 @@ impl From<Child> for MountPoint {
     fn from(value: Child) -> Self {
         Self {
             path: value.mountpoint.unwrap_or_default(),
-            label: if value.label.is_some() {
-                value.label
-            } else {
-                value.partlabel
-            },
-            total_bytes: value.fssize.map(Into::into),
-            available_bytes: value.fsavail.map(Into::into),
+            label: value.label.or_else(|| value.partlabel.clone()),
+            filesystem: value.fstype.as_deref().map(normalize_filesystem),
+            partition_label: value.partlabel,
+            total_bytes: value.fssize,
+            available_bytes: value.fsavail,
         }
     }
 }
 
-pub(crate) fn lsblk() -> anyhow::Result<Vec<DeviceDescriptor>> {
+fn lsblk_json(columns: &[&str]) -> anyhow::Result<Devices> {
     let output = Command::new("lsblk")
-        .args(["--bytes", "--all", "--json", "--paths", "--output-all"])
+        .args(["--bytes", "--all", "--json", "--paths"])
+        .args(columns)
         .output()?;
 
     if !output.status.success() {
-        return Err(anyhow::Error::msg("lsblk fail"));
+        return Err(anyhow::anyhow!(
+            "lsblk fail: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
     }
 
-    let res: Devices = serde_json::from_slice(&output.stdout).unwrap();
+    serde_json::from_slice(&output.stdout).map_err(Into::into)
+}
+
+pub(crate) fn lsblk() -> anyhow::Result<Vec<DeviceDescriptor>> {
+    // Older or minimal lsblk versions do not support `--output-all`
+    let res =
+        lsblk_json(&["--output-all"]).or_else(|_| lsblk_json(&["--output", MINIMAL_COLUMNS]))?;
 
     Ok(res.blockdevices.into_iter().map(Into::into).collect())
 }
@@ -226,4 +284,105 @@ mod tests {
         let res: super::Devices = serde_json::from_str(data).unwrap();
         let _: Vec<DeviceDescriptor> = res.blockdevices.into_iter().map(Into::into).collect();
     }
+
+    #[test]
+    fn sd_card_mountpoints() {
+        let data = r#"
+        {
+            "blockdevices": [
+                {
+                    "name":"/dev/sdb",
+                    "kname":"/dev/sdb",
+                    "fstype":null,
+                    "mountpoint":null,
+                    "label":null,
+                    "partlabel":null,
+                    "ptype":"dos",
+                    "ro":false,
+                    "rm":true,
+                    "hotplug":true,
+                    "model":"SD Card Reader",
+                    "serial":"000000000819",
+                    "size":15931539456,
+                    "phy-sec":512,
+                    "log-sec":512,
+                    "tran":"usb",
+                    "subsystems":"block:scsi:usb:pci",
+                    "vendor":"Generic",
+                    "wwn":null,
+                    "children": [
+                        {
+                            "name":"/dev/sdb1",
+                            "fsavail":"36900864",
+                            "fssize":"133954560",
+                            "fstype":"vfat",
+                            "mountpoint":"/media/user/BOOT",
+                            "label":"BOOT",
+                            "partlabel":"boot"
+                        },
+                        {
+                            "name":"/dev/sdb2",
+                            "fsavail":null,
+                            "fssize":null,
+                            "fstype":"ext4",
+                            "mountpoint":null,
+                            "label":null,
+                            "partlabel":"rootfs"
+                        }
+                    ]
+                }
+            ]
+        }"#;
+
+        let res: super::Devices = serde_json::from_str(data).unwrap();
+        let devs: Vec<DeviceDescriptor> = res.blockdevices.into_iter().map(Into::into).collect();
+        let mps = &devs[0].mountpoints;
+
+        assert_eq!(devs[0].description, "Generic SD Card Reader");
+        assert_eq!(mps.len(), 2);
+
+        assert_eq!(mps[0].path, "/media/user/BOOT");
+        assert_eq!(mps[0].filesystem.as_deref(), Some("vfat"));
+        assert_eq!(mps[0].label.as_deref(), Some("BOOT"));
+        assert_eq!(mps[0].partition_label.as_deref(), Some("boot"));
+        assert_eq!(mps[0].total_bytes, Some(133954560));
+
+        assert_eq!(mps[1].filesystem.as_deref(), Some("ext4"));
+        assert_eq!(mps[1].label.as_deref(), Some("rootfs"));
+        assert_eq!(mps[1].partition_label.as_deref(), Some("rootfs"));
+        assert_eq!(mps[1].total_bytes, None);
+    }
+
+    #[test]
+    fn minimal_columns() {
+        // Older lsblk versions output all values as strings
+        let data = r#"
+        {
+            "blockdevices": [
+                {
+                    "name": "/dev/sdb",
+                    "kname": "/dev/sdb",
+                    "size": "15931539456",
+                    "tran": "usb",
+                    "ro": "0",
+                    "rm": "1",
+                    "model": "SD Card Reader",
+                    "children": [
+                        {"name": "/dev/sdb1", "mountpoint": "/media/BOOT", "fstype": "vfat"}
+                    ]
+                }
+            ]
+        }"#;
+
+        let res: super::Devices = serde_json::from_str(data).unwrap();
+        let devs: Vec<DeviceDescriptor> = res.blockdevices.into_iter().map(Into::into).collect();
+
+        assert_eq!(devs[0].size, Some(15931539456));
+        assert_eq!(devs[0].block_size, 512);
+        assert!(!devs[0].is_readonly);
+        assert!(devs[0].is_removable);
+        assert!(!devs[0].is_virtual);
+        assert_eq!(devs[0].mountpoints[0].path, "/media/BOOT");
+        assert_eq!(devs[0].mountpoints[0].total_bytes, None);
+    }
 }
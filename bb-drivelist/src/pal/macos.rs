@@ -3,8 +3,9 @@ use std::collections::HashMap;
 use std::ffi::{CStr, c_char};
 use std::ptr::NonNull;
 
+use super::bsd_name::{is_apfs_synthesized, is_partition_name, whole_disk_name};
 use crate::MountPoint;
-use crate::device::DeviceDescriptor;
+use crate::device::{DescriptionBuilder, DeviceDescriptor, normalize_filesystem};
 use objc2::runtime::AnyObject;
 use objc2::{rc::Retained, sel};
 use objc2_core_foundation::{
@@ -18,7 +19,7 @@ use objc2_disk_arbitration::{
     kDADiskDescriptionMediaContentKey, kDADiskDescriptionMediaEjectableKey,
     kDADiskDescriptionMediaIconKey, kDADiskDescriptionMediaNameKey,
     kDADiskDescriptionMediaRemovableKey, kDADiskDescriptionMediaSizeKey,
-    kDADiskDescriptionMediaWritableKey,
+    kDADiskDescriptionMediaWritableKey, kDADiskDescriptionVolumeKindKey,
 };
 use objc2_foundation::{
     NSArray, NSFileManager, NSMutableArray, NSNumber, NSString, NSURLVolumeLocalizedNameKey,
@@ -43,25 +44,6 @@ fn scsi_type_matches(s: &NSString) -> bool {
         || s.isEqualToString(ns_string!("PCI"))
 }
 
-/// Check if a BSD name matches the partition pattern (e.g., "disk0s1", "disk1s2").
-/// Replaces NSPredicate regex with pure Rust for better performance.
-fn is_partition_name(name: &NSString) -> bool {
-    let s = name.to_string();
-    let Some(rest) = s.strip_prefix("disk") else {
-        return false;
-    };
-    let Some(s_pos) = rest.find('s') else {
-        return false;
-    };
-    // Must have digits before 's' and digits after 's'
-    let before_s = &rest[..s_pos];
-    let after_s = &rest[s_pos + 1..];
-    !before_s.is_empty()
-        && before_s.chars().all(|c| c.is_ascii_digit())
-        && !after_s.is_empty()
-        && after_s.chars().all(|c| c.is_ascii_digit())
-}
-
 /// Extension trait for CFDictionary to get typed values
 trait CFDictionaryExt {
     fn get_cfdict(&self, key: &CFString) -> Option<CFRetained<CFDictionary>>;
@@ -265,10 +247,12 @@ impl DeviceDescriptorFromDiskDescription for DeviceDescriptor {
 
         let mut device = DeviceDescriptor::default();
 
+        let media_content =
+            disk_description.get_string(unsafe { kDADiskDescriptionMediaContentKey });
+        let media_name = disk_description.get_string(unsafe { kDADiskDescriptionMediaNameKey });
+
         // Determine partition table type
-        if let Some(media_content) =
-            disk_description.get_string(unsafe { kDADiskDescriptionMediaContentKey })
-        {
+        if let Some(media_content) = &media_content {
             if media_content.isEqualToString(ns_string!("GUID_partition_scheme")) {
                 device.partition_table_type = Some("gpt".to_string());
             } else if media_content.isEqualToString(ns_string!("FDisk_partition_scheme")) {
@@ -285,10 +269,9 @@ impl DeviceDescriptorFromDiskDescription for DeviceDescriptor {
             .map(|p| p.to_string());
         device.raw = format!("/dev/r{}", disk_bsd_name);
 
-        device.description = disk_description
-            .get_string(unsafe { kDADiskDescriptionMediaNameKey })
-            .map(|desc| desc.to_string())
-            .unwrap_or_default();
+        device.description = DescriptionBuilder::new()
+            .part(media_name.as_ref().map(|desc| desc.to_string()).as_deref())
+            .build(&disk_bsd_name);
 
         device.error = None;
 
@@ -331,6 +314,16 @@ impl DeviceDescriptorFromDiskDescription for DeviceDescriptor {
             .map(|icon| icon.to_string() == "SD.icns")
             .unwrap_or(false);
 
+        // Disks synthesized from APFS containers share the media properties of the physical
+        // disk, but are never flashable themselves.
+        if is_apfs_synthesized(
+            media_content.map(|x| x.to_string()).as_deref(),
+            media_name.map(|x| x.to_string()).as_deref(),
+        ) {
+            device.is_virtual = true;
+            device.is_card = false;
+        }
+
         // NOTE: Not convinced that these bus types should result
         // in device.is_scsi = true, it is rather "not usb or sd drive" bool
         // But the old implementation was like this so kept it this way
@@ -356,7 +349,7 @@ pub(crate) fn drive_list() -> anyhow::Result<Vec<DeviceDescriptor>> {
 
     for disk_bsd_name in &disk_list.disks {
         // Use Rust string check instead of NSPredicate regex for better performance
-        if is_partition_name(&disk_bsd_name) {
+        if is_partition_name(&disk_bsd_name.to_string()) {
             continue;
         }
 
@@ -409,15 +402,8 @@ pub(crate) fn drive_list() -> anyhow::Result<Vec<DeviceDescriptor>> {
             continue;
         };
 
-        // Safely extract disk name from partition name (e.g., "disk0s1" -> "disk0")
-        // Uses strip_prefix to avoid panics on unexpected BSD names
-        let disk_bsdname = if let Some(rest) = partition_bsdname.strip_prefix("disk") {
-            let disk_num_len = rest.find('s').unwrap_or(rest.len());
-            format!("disk{}", &rest[..disk_num_len])
-        } else {
-            // Fallback: use the whole name if it doesn't match expected pattern
-            partition_bsdname.clone()
-        };
+        // Extract disk name from partition name (e.g., "disk0s1" -> "disk0")
+        let disk_bsdname = whole_disk_name(&partition_bsdname);
 
         let Some(mount_path) = path.path().and_then(|it| it.UTF8String().to_string()) else {
             continue;
@@ -443,10 +429,24 @@ pub(crate) fn drive_list() -> anyhow::Result<Vec<DeviceDescriptor>> {
             continue;
         };
 
+        let description = unsafe { disk.description() };
+        let filesystem = description
+            .as_ref()
+            .and_then(|d| d.get_string(unsafe { kDADiskDescriptionVolumeKindKey }))
+            .map(|x| normalize_filesystem(&x.to_string()));
+        let partition_label = description
+            .as_ref()
+            .and_then(|d| d.get_string(unsafe { kDADiskDescriptionMediaNameKey }))
+            .map(|x| x.to_string())
+            .filter(|x| !x.is_empty());
+
         if let Some(&idx) = device_map.get(&format!("/dev/{}", disk_bsdname)) {
-            device_list[idx]
-                .mountpoints
-                .push(MountPoint::new(mount_path));
+            device_list[idx].mountpoints.push(MountPoint {
+                label: Some(label.clone()),
+                filesystem,
+                partition_label,
+                ..MountPoint::new(mount_path)
+            });
             device_list[idx].mountpoint_labels.push(label);
         }
     }
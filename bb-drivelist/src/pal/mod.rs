@@ -1,3 +1,5 @@
+#[cfg(any(target_os = "macos", test))]
+mod bsd_name;
 #[cfg(target_os = "linux")]
 mod linux;
 #[cfg(target_os = "macos")]
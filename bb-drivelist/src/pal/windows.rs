@@ -17,22 +17,23 @@ use windows::Win32::Storage::FileSystem::{
     BusType1394, BusTypeAta, BusTypeAtapi, BusTypeFibre, BusTypeFileBackedVirtual, BusTypeMmc,
     BusTypeNvme, BusTypeRAID, BusTypeSCM, BusTypeSas, BusTypeSata, BusTypeScsi, BusTypeSd,
     BusTypeSsa, BusTypeUfs, BusTypeUnknown, BusTypeUsb, BusTypeVirtual, BusTypeiScsi,
-    FILE_SHARE_READ, GetDiskFreeSpaceW, GetDriveTypeA, GetLogicalDrives, GetVolumePathNameW,
-    IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS, STORAGE_BUS_TYPE,
+    FILE_SHARE_READ, GetDiskFreeSpaceW, GetDriveTypeA, GetLogicalDrives, GetVolumeInformationW,
+    GetVolumePathNameW, IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS, STORAGE_BUS_TYPE,
 };
 use windows::Win32::System::IO::DeviceIoControl;
 use windows::Win32::System::Ioctl::{
     DISK_GEOMETRY_EX, DRIVE_LAYOUT_INFORMATION_EX, GUID_DEVINTERFACE_DISK,
-    IOCTL_DISK_GET_DRIVE_GEOMETRY_EX, IOCTL_DISK_GET_DRIVE_LAYOUT_EX, IOCTL_DISK_IS_WRITABLE,
-    IOCTL_STORAGE_GET_DEVICE_NUMBER, IOCTL_STORAGE_QUERY_PROPERTY, PARTITION_INFORMATION_EX,
-    PARTITION_STYLE_GPT, PARTITION_STYLE_MBR, PropertyStandardQuery,
-    STORAGE_ACCESS_ALIGNMENT_DESCRIPTOR, STORAGE_ADAPTER_DESCRIPTOR, STORAGE_DEVICE_NUMBER,
-    STORAGE_PROPERTY_QUERY, StorageAccessAlignmentProperty, StorageAdapterProperty,
-    VOLUME_DISK_EXTENTS,
+    IOCTL_DISK_GET_DRIVE_GEOMETRY_EX, IOCTL_DISK_GET_DRIVE_LAYOUT_EX,
+    IOCTL_DISK_GET_PARTITION_INFO_EX, IOCTL_DISK_IS_WRITABLE, IOCTL_STORAGE_GET_DEVICE_NUMBER,
+    IOCTL_STORAGE_QUERY_PROPERTY, PARTITION_INFORMATION_EX, PARTITION_STYLE_GPT,
+    PARTITION_STYLE_MBR, PropertyStandardQuery, STORAGE_ACCESS_ALIGNMENT_DESCRIPTOR,
+    STORAGE_ADAPTER_DESCRIPTOR, STORAGE_DEVICE_NUMBER, STORAGE_PROPERTY_QUERY,
+    StorageAccessAlignmentProperty, StorageAdapterProperty, VOLUME_DISK_EXTENTS,
 };
 use windows::Win32::System::WindowsProgramming::{DRIVE_FIXED, DRIVE_REMOVABLE};
 use windows::core::{PCSTR, PCWSTR};
 
+use crate::device::{DescriptionBuilder, normalize_filesystem};
 use crate::{DeviceDescriptor, MountPoint};
 
 pub(crate) fn drive_list() -> anyhow::Result<Vec<DeviceDescriptor>> {
@@ -60,12 +61,15 @@ pub(crate) fn drive_list() -> anyhow::Result<Vec<DeviceDescriptor>> {
 
             let enumerator_name = get_enumerator_name(h_device_info, &device_info_data);
             let friendly_name = get_friendly_name(h_device_info, &mut device_info_data);
-            if friendly_name.is_empty() {
+            let description = DescriptionBuilder::new()
+                .part(Some(&friendly_name))
+                .build("");
+            if description.is_empty() {
                 continue;
             }
 
             let mut item = DeviceDescriptor {
-                description: friendly_name.clone(),
+                description,
                 enumerator: enumerator_name.clone(),
                 is_usb: is_usb_drive(&enumerator_name),
                 is_removable: is_removable(h_device_info, &mut device_info_data),
@@ -494,6 +498,26 @@ fn get_mount_points(device_number: u32, mount_points: &mut Vec<MountPoint>) -> a
             let bytes_per_cluster = sectors_per_cluster as u64 * bytes_per_sector as u64;
             drive.total_bytes = Some(bytes_per_cluster * total_number_of_clusters as u64);
             drive.available_bytes = Some(bytes_per_cluster * number_of_free_clusters as u64);
+
+            let mut volume_name_buf = [0_u16; 261];
+            let mut fs_name_buf = [0_u16; 261];
+            if unsafe {
+                GetVolumeInformationW(
+                    PCWSTR::from_raw(root_path.as_ptr()),
+                    Some(&mut volume_name_buf),
+                    None,
+                    None,
+                    None,
+                    Some(&mut fs_name_buf),
+                )
+            }
+            .is_ok()
+            {
+                drive.label = from_wide(&volume_name_buf);
+                drive.filesystem = from_wide(&fs_name_buf).as_deref().map(normalize_filesystem);
+            }
+
+            drive.partition_label = get_partition_label(HANDLE(h_logical.as_raw_handle()));
             mount_points.push(drive);
         }
     }
@@ -501,6 +525,40 @@ fn get_mount_points(device_number: u32, mount_points: &mut Vec<MountPoint>) -> a
     Ok(())
 }
 
+/// Only GPT partitions have a name.
+fn get_partition_label(h_device: HANDLE) -> Option<String> {
+    let mut size = 0u32;
+    let mut partition_info = PARTITION_INFORMATION_EX::default();
+
+    unsafe {
+        DeviceIoControl(
+            h_device,
+            IOCTL_DISK_GET_PARTITION_INFO_EX,
+            None,
+            0,
+            Some(&mut partition_info as *mut _ as *mut std::ffi::c_void),
+            size_of::<PARTITION_INFORMATION_EX>() as _,
+            Some(&mut size),
+            None,
+        )
+    }
+    .ok()?;
+
+    if partition_info.PartitionStyle == PARTITION_STYLE_GPT {
+        from_wide(&unsafe { partition_info.Anonymous.Gpt }.Name)
+    } else {
+        None
+    }
+}
+
+/// Convert a NUL terminated wide string buffer. Empty strings are treated as missing.
+fn from_wide(buf: &[u16]) -> Option<String> {
+    let len = buf.iter().position(|&x| x == 0).unwrap_or(buf.len());
+    let res = String::from_utf16_lossy(&buf[..len]);
+
+    if res.is_empty() { None } else { Some(res) }
+}
+
 fn get_device_number(h_device: HANDLE) -> Option<u32> {
     let mut size = 0u32;
     let mut disk_extents = VOLUME_DISK_EXTENTS::default();
@@ -600,3 +658,20 @@ fn to_u16_str(arr: &[u8]) -> &[u16] {
     };
     unsafe { std::slice::from_raw_parts(arr.as_ptr().cast(), len) }
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn from_wide() {
+        let mut buf = [0_u16; 36];
+        assert_eq!(super::from_wide(&buf), None);
+
+        for (i, c) in "BOOT".encode_utf16().enumerate() {
+            buf[i] = c;
+        }
+        assert_eq!(super::from_wide(&buf).as_deref(), Some("BOOT"));
+
+        let full: Vec<u16> = "NTFS".encode_utf16().collect();
+        assert_eq!(super::from_wide(&full).as_deref(), Some("NTFS"));
+    }
+}
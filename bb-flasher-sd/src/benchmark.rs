@@ -0,0 +1,150 @@
+//! Measure write and read throughput of SD Cards without an OS image. Useful to diagnose slow
+//! card readers.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::Result;
+use crate::flashing::BUFFER_SIZE;
+use crate::helpers::{DirectIoBuffer, check_token};
+use crate::verify::{Verify, Written};
+
+const SECTOR_SIZE: u64 = 512;
+
+/// Result of [benchmark].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchmarkReport {
+    /// Bytes written and read back.
+    pub size: u64,
+    /// Time taken to write and flush all data.
+    pub write_time: Duration,
+    /// Time taken to read back and verify all data.
+    pub read_time: Duration,
+}
+
+impl BenchmarkReport {
+    /// Write throughput in bytes/sec.
+    pub fn write_throughput(&self) -> f64 {
+        throughput(self.size, self.write_time)
+    }
+
+    /// Read throughput in bytes/sec.
+    pub fn read_throughput(&self) -> f64 {
+        throughput(self.size, self.read_time)
+    }
+}
+
+fn throughput(size: u64, time: Duration) -> f64 {
+    size as f64 / time.as_secs_f64().max(f64::EPSILON)
+}
+
+/// Random data, which cannot be compressed or deduplicated by the card controller.
+struct Pattern(u64);
+
+impl Pattern {
+    fn new() -> Self {
+        // Xorshift state should never be 0
+        Self(uuid::Uuid::new_v4().as_u64_pair().0 | 1)
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            chunk.copy_from_slice(&self.0.to_le_bytes()[..chunk.len()]);
+        }
+    }
+}
+
+fn run(
+    mut sd: impl Read + Write + Seek,
+    size: u64,
+    cancel: Option<tokio_util::sync::CancellationToken>,
+) -> Result<BenchmarkReport> {
+    let size = size.next_multiple_of(SECTOR_SIZE);
+    let mut buf = Box::new(DirectIoBuffer::<BUFFER_SIZE>::new());
+    let mut pattern = Pattern::new();
+    // CRC32 keeps the read time close to the raw read throughput
+    let mut written = Written::new(Verify::Crc32);
+
+    tracing::info!("Writing {size} bytes");
+    let start = Instant::now();
+    sd.seek(SeekFrom::Start(0))?;
+
+    let mut pos = 0;
+    while pos < size {
+        let count = std::cmp::min(size - pos, BUFFER_SIZE as u64) as usize;
+        let data = &mut buf.as_mut_slice()[..count];
+
+        pattern.fill(data);
+        sd.write_all(data)?;
+        written.record(pos, data);
+
+        pos += count as u64;
+        check_token(cancel.as_ref())?;
+    }
+
+    sd.flush()?;
+    let write_time = start.elapsed();
+
+    tracing::info!("Reading back {size} bytes");
+    let start = Instant::now();
+    written.verify(&mut sd, cancel.as_ref())?;
+    let read_time = start.elapsed();
+
+    Ok(BenchmarkReport {
+        size,
+        write_time,
+        read_time,
+    })
+}
+
+/// Write `size` bytes of random data to `dst`, read it back and verify it. `size` is rounded up
+/// to sector size.
+///
+/// `dst` can be an SD Card or a regular file, which is created if it does not exist.
+///
+/// # Warning
+///
+/// Existing data at the start of `dst` is overwritten, and the SD Card will need to be flashed or
+/// formatted afterwards.
+pub async fn benchmark(
+    dst: &Path,
+    size: u64,
+    cancel: Option<tokio_util::sync::CancellationToken>,
+) -> Result<BenchmarkReport> {
+    if dst.is_file() || !dst.exists() {
+        let f = std::fs::File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dst)?;
+        return tokio::task::spawn_blocking(move || run(f, size, cancel))
+            .await
+            .unwrap();
+    }
+
+    let sd = crate::pal::open(dst).await?;
+    tokio::task::spawn_blocking(move || run(sd, size, cancel))
+        .await
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn loopback_file() {
+        let mut f = tempfile::tempfile().unwrap();
+
+        let report = super::run(&mut f, 1024 * 1024 + 100, None).unwrap();
+
+        assert_eq!(report.size, 1024 * 1024 + 512);
+        assert_eq!(f.metadata().unwrap().len(), report.size);
+        for x in [report.write_throughput(), report.read_throughput()] {
+            assert!(x.is_finite() && x > 0.0);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Generate [bmap] for raw OS images, and check images against the checksums in a bmap.
+//!
+//! [bmap]: https://github.com/yoctoproject/bmaptool
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::Read;
+use std::ops::Range;
+
+use sha2::{Digest, Sha256};
+
+use crate::{Error, Result};
+
+const BLOCK_SIZE: usize = 4096;
+const CHECKSUM_PLACEHOLDER: &str =
+    "0000000000000000000000000000000000000000000000000000000000000000";
+
+struct BlockRange {
+    start: u64,
+    end: u64,
+    hasher: Sha256,
+}
+
+impl BlockRange {
+    fn new(start: u64, data: &[u8]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+
+        Self {
+            start,
+            end: start,
+            hasher,
+        }
+    }
+}
+
+/// Checks SHA256 checksum of each mapped range in bmap, while image data is written. Catches
+/// corrupt images, or a bmap for a different image, even if the whole-file checksum is not known.
+pub(crate) struct RangeChecksums {
+    ranges: VecDeque<(Range<u64>, bb_bmap_parser::HashValue)>,
+    hasher: Sha256,
+}
+
+impl RangeChecksums {
+    /// Ranges starting before `start` are skipped, since their data is never written.
+    pub(crate) fn new(bmap: &bb_bmap_parser::Bmap, start: u64) -> Self {
+        let size = bmap.image_size();
+        let ranges = bmap
+            .block_map()
+            .filter(|b| b.offset() >= start)
+            .map(|b| {
+                // Last block can extend past the end of image
+                let end = std::cmp::min(b.offset() + b.length(), size);
+                (b.offset()..end, b.checksum())
+            })
+            .collect();
+
+        Self {
+            ranges,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Hash image `data` at offset `pos`. Data should be passed in order, without gaps in the
+    /// mapped ranges.
+    pub(crate) fn update(&mut self, pos: u64, data: &[u8]) -> Result<()> {
+        let end = pos + data.len() as u64;
+
+        while let Some((r, checksum)) = self.ranges.front() {
+            if r.start >= end {
+                break;
+            }
+
+            let overlap = r.start.max(pos)..r.end.min(end);
+            if !overlap.is_empty() {
+                self.hasher.update(
+                    &data[((overlap.start - pos) as usize)..((overlap.end - pos) as usize)],
+                );
+            }
+
+            if r.end > end {
+                break;
+            }
+
+            if self.hasher.finalize_reset().as_slice() != checksum.as_slice() {
+                tracing::error!("Checksum mismatch in bmap range {r:?}");
+                return Err(Error::InvalidBmap);
+            }
+            self.ranges.pop_front();
+        }
+
+        Ok(())
+    }
+
+    /// Fails if image ended before all mapped ranges.
+    pub(crate) fn finish(&self) -> Result<()> {
+        match self.ranges.front() {
+            Some((r, _)) => {
+                tracing::error!("Image ended before bmap range {r:?}");
+                Err(Error::InvalidBmap)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Generate bmap for a raw (uncompressed) OS image.
+///
+/// Since filesystems are not inspected, a zero-run heuristic is used: blocks containing only
+/// zeros are treated as unmapped. Each range of mapped blocks contains a SHA256 checksum.
+pub fn generate_bmap(mut img: impl Read) -> Result<String> {
+    let mut buf = Vec::with_capacity(BLOCK_SIZE);
+    let mut ranges: Vec<BlockRange> = Vec::new();
+    let mut image_size = 0u64;
+    let mut blocks = 0u64;
+
+    loop {
+        // Only the actual image bytes count towards size and checksums. Last block can be short.
+        buf.clear();
+        let count = img.by_ref().take(BLOCK_SIZE as u64).read_to_end(&mut buf)?;
+        if count == 0 {
+            break;
+        }
+
+        let data = buf.as_slice();
+        image_size += count as u64;
+
+        if data.iter().any(|x| *x != 0) {
+            match ranges.last_mut() {
+                Some(r) if r.end + 1 == blocks => {
+                    r.end = blocks;
+                    r.hasher.update(data);
+                }
+                _ => ranges.push(BlockRange::new(blocks, data)),
+            }
+        }
+
+        blocks += 1;
+    }
+
+    let mapped_blocks: u64 = ranges.iter().map(|r| r.end - r.start + 1).sum();
+
+    let mut res = String::new();
+    res.push_str("<?xml version=\"1.0\" ?>\n");
+    res.push_str("<bmap version=\"2.0\">\n");
+    let _ = writeln!(res, "    <ImageSize> {image_size} </ImageSize>");
+    let _ = writeln!(res, "    <BlockSize> {BLOCK_SIZE} </BlockSize>");
+    let _ = writeln!(res, "    <BlocksCount> {blocks} </BlocksCount>");
+    let _ = writeln!(
+        res,
+        "    <MappedBlocksCount> {mapped_blocks} </MappedBlocksCount>"
+    );
+    res.push_str("    <ChecksumType> sha256 </ChecksumType>\n");
+    let _ = writeln!(
+        res,
+        "    <BmapFileChecksum> {CHECKSUM_PLACEHOLDER} </BmapFileChecksum>"
+    );
+    res.push_str("    <BlockMap>\n");
+    for r in ranges {
+        let chksum = const_hex::encode(r.hasher.finalize());
+        if r.start == r.end {
+            let _ = writeln!(
+                res,
+                "        <Range chksum=\"{chksum}\"> {} </Range>",
+                r.start
+            );
+        } else {
+            let _ = writeln!(
+                res,
+                "        <Range chksum=\"{chksum}\"> {}-{} </Range>",
+                r.start, r.end
+            );
+        }
+    }
+    res.push_str("    </BlockMap>\n");
+    res.push_str("</bmap>\n");
+
+    // The file checksum is calculated with the checksum field set to all zeros.
+    let file_chksum = const_hex::encode(Sha256::digest(res.as_bytes()));
+
+    Ok(res.replace(CHECKSUM_PLACEHOLDER, &file_chksum))
+}
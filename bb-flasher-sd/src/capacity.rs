@@ -0,0 +1,288 @@
+//! Detect counterfeit SD Cards, which report a larger size than they can actually store. Writes
+//! past the real capacity usually wrap around and silently overwrite data at the start of the
+//! card.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::Result;
+use crate::helpers::{DirectIoBuffer, check_token};
+
+const BLOCK_SIZE: usize = 4096;
+/// Approximate number of positions tested across the reported size.
+const SAMPLES: u64 = 64;
+const MAGIC: &[u8; 16] = b"BB-IMAGER-CAPTST";
+
+/// Result of [check_capacity].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityReport {
+    /// Size reported by SD Card.
+    pub reported: u64,
+    /// Size up to which all tested positions stored data correctly. This is an estimate, the
+    /// real capacity lies between this and the next tested position.
+    pub verified: u64,
+}
+
+impl CapacityReport {
+    /// Returns false if SD Card cannot store data up to the reported size.
+    pub const fn is_genuine(&self) -> bool {
+        self.verified == self.reported
+    }
+}
+
+/// Unique data for each tested position, so data read from a different position is detected.
+fn sentinel(buf: &mut [u8], run: u128, pos: u64) {
+    buf.fill(0);
+    buf[..16].copy_from_slice(MAGIC);
+    buf[16..32].copy_from_slice(&run.to_le_bytes());
+    for (i, chunk) in buf[32..].chunks_mut(8).enumerate() {
+        chunk.copy_from_slice(&(pos ^ i as u64).to_le_bytes()[..chunk.len()]);
+    }
+}
+
+/// Positions across `size`, including the last block. Positions are a power of two apart, so
+/// writes wrapping around on counterfeit SD Cards land on other tested positions.
+fn positions(size: u64) -> Vec<u64> {
+    let stride = size
+        .div_ceil(SAMPLES)
+        .next_power_of_two()
+        .max(BLOCK_SIZE as u64);
+    let last = (size / BLOCK_SIZE as u64).saturating_sub(1) * BLOCK_SIZE as u64;
+
+    let mut res: Vec<u64> = (0..)
+        .map(|i| i * stride)
+        .take_while(|x| *x < last)
+        .collect();
+    res.push(last);
+    res
+}
+
+/// Write sentinel blocks at `positions`, and read them back. Returns the first position which
+/// did not store data correctly, or `reported` if all did.
+fn write_sentinels(
+    mut sd: impl Read + Write + Seek,
+    positions: &[u64],
+    reported: u64,
+    cancel: Option<&tokio_util::sync::CancellationToken>,
+) -> Result<u64> {
+    let run = uuid::Uuid::new_v4().as_u128();
+    let mut buf = Box::new(DirectIoBuffer::<BLOCK_SIZE>::new());
+
+    // Highest positions are written first. On a card which wraps around, writes to positions
+    // within the real capacity come last, so only positions past it fail.
+    tracing::info!("Writing sentinel blocks");
+    for p in positions.iter().rev() {
+        sentinel(buf.as_mut_slice(), run, *p);
+        sd.seek(SeekFrom::Start(*p))?;
+        sd.write_all(buf.as_slice())?;
+        check_token(cancel)?;
+    }
+    sd.flush()?;
+
+    tracing::info!("Reading back sentinel blocks");
+    let mut expected = vec![0u8; BLOCK_SIZE];
+    for p in positions {
+        sentinel(&mut expected, run, *p);
+        sd.seek(SeekFrom::Start(*p))?;
+        sd.read_exact(buf.as_mut_slice())?;
+
+        if buf.as_slice() != expected.as_slice() {
+            tracing::warn!("Data written at {p} was not stored");
+            return Ok(*p);
+        }
+    }
+
+    Ok(reported)
+}
+
+fn check(
+    mut sd: impl Read + Write + Seek,
+    cancel: Option<&tokio_util::sync::CancellationToken>,
+) -> Result<CapacityReport> {
+    let reported = sd.seek(SeekFrom::End(0))?;
+    let positions = positions(reported);
+
+    // Saved blocks are written back as is, so they need to be aligned for direct IO.
+    tracing::info!("Saving {} blocks", positions.len());
+    let mut original = Vec::with_capacity(positions.len());
+    for p in &positions {
+        let mut buf = Box::new(DirectIoBuffer::<BLOCK_SIZE>::new());
+        sd.seek(SeekFrom::Start(*p))?;
+        sd.read_exact(buf.as_mut_slice())?;
+        original.push(buf);
+        check_token(cancel)?;
+    }
+
+    let res = write_sentinels(&mut sd, &positions, reported, cancel);
+
+    // Blocks are restored even if writing sentinels failed or was cancelled.
+    tracing::info!("Restoring blocks");
+    for (p, data) in positions.iter().zip(original).rev() {
+        sd.seek(SeekFrom::Start(*p))?;
+        sd.write_all(data.as_slice())?;
+    }
+    sd.flush()?;
+
+    Ok(CapacityReport {
+        reported,
+        verified: res?,
+    })
+}
+
+/// Check if SD Card can store data up to its reported size, by writing and reading back blocks
+/// at positions spread across the SD Card.
+///
+/// The tested blocks are restored afterwards. However, this is not possible on counterfeit SD
+/// Cards, which will need to be flashed or formatted afterwards.
+pub async fn check_capacity(
+    dst: &Path,
+    cancel: Option<tokio_util::sync::CancellationToken>,
+) -> Result<CapacityReport> {
+    let sd = crate::pal::open(dst).await?;
+    tokio::task::spawn_blocking(move || check(sd, cancel.as_ref()))
+        .await
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Seek, SeekFrom, Write};
+
+    use super::{CapacityReport, check};
+
+    const MIB: u64 = 1024 * 1024;
+
+    /// Counterfeit SD Card, which reports `reported` bytes, but wraps around after `data.len()`.
+    struct FakeCard {
+        data: Vec<u8>,
+        reported: u64,
+        pos: u64,
+    }
+
+    impl FakeCard {
+        fn real_pos(&self) -> usize {
+            (self.pos % self.data.len() as u64) as usize
+        }
+    }
+
+    impl Read for FakeCard {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let pos = self.real_pos();
+            let count = buf.len().min(self.data.len() - pos);
+            buf[..count].copy_from_slice(&self.data[pos..(pos + count)]);
+            self.pos += count as u64;
+            Ok(count)
+        }
+    }
+
+    impl Write for FakeCard {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let pos = self.real_pos();
+            let count = buf.len().min(self.data.len() - pos);
+            self.data[pos..(pos + count)].copy_from_slice(&buf[..count]);
+            self.pos += count as u64;
+            Ok(count)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for FakeCard {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.pos = match pos {
+                SeekFrom::Start(x) => x,
+                SeekFrom::End(x) => self.reported.checked_add_signed(x).unwrap(),
+                SeekFrom::Current(x) => self.pos.checked_add_signed(x).unwrap(),
+            };
+            Ok(self.pos)
+        }
+    }
+
+    #[test]
+    fn genuine_card() {
+        let data: Vec<u8> = (0..(8 * MIB)).map(|x| (x % 251) as u8).collect();
+        let mut sd = io::Cursor::new(data.clone());
+
+        let report = check(&mut sd, None).unwrap();
+        assert_eq!(
+            report,
+            CapacityReport {
+                reported: 8 * MIB,
+                verified: 8 * MIB
+            }
+        );
+        assert!(report.is_genuine());
+        // Tested blocks are restored
+        assert_eq!(sd.into_inner(), data);
+    }
+
+    #[test]
+    fn wrap_around_card() {
+        let mut sd = FakeCard {
+            data: vec![0; MIB as usize],
+            reported: 8 * MIB,
+            pos: 0,
+        };
+
+        let report = check(&mut sd, None).unwrap();
+        assert_eq!(
+            report,
+            CapacityReport {
+                reported: 8 * MIB,
+                verified: MIB
+            }
+        );
+        assert!(!report.is_genuine());
+    }
+
+    /// Cancels after `remaining` writes.
+    struct CancelAfter {
+        inner: io::Cursor<Vec<u8>>,
+        remaining: usize,
+        cancel: tokio_util::sync::CancellationToken,
+    }
+
+    impl Read for CancelAfter {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl Write for CancelAfter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.remaining = self.remaining.saturating_sub(1);
+            if self.remaining == 0 {
+                self.cancel.cancel();
+            }
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for CancelAfter {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn restore_on_cancel() {
+        let data: Vec<u8> = (0..(8 * MIB)).map(|x| (x % 251) as u8).collect();
+        let cancel = tokio_util::sync::CancellationToken::new();
+        let mut sd = CancelAfter {
+            inner: io::Cursor::new(data.clone()),
+            remaining: 10,
+            cancel: cancel.clone(),
+        };
+
+        let res = check(&mut sd, Some(&cancel));
+        assert!(matches!(res, Err(crate::Error::Aborted)));
+        // Sentinel blocks were written, but are restored
+        assert_eq!(sd.inner.into_inner(), data);
+    }
+}
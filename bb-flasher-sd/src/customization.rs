@@ -1,22 +1,40 @@
 use std::io::{Read, Seek, SeekFrom, Write};
 
+use crate::partition::Partition;
 use crate::{Error, Result};
 
+/// Volume label of the boot partition in BeagleBoard images.
+const BOOT_LABEL: &str = "BOOT";
+/// File system name in exFAT boot sector, at the offset of OEM name in FAT.
+const EXFAT_SIGNATURE: &[u8; 8] = b"EXFAT   ";
+/// Offset of magic number in ext2/3/4 superblock, which starts at 1024.
+const EXT_MAGIC_OFFSET: usize = 1024 + 56;
+const EXT_MAGIC: [u8; 2] = 0xEF53u16.to_le_bytes();
+
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub enum Customization {
     Sysconf(SysconfCustomization),
+    Raspberry(RaspberryCustomization),
+    CloudInit(CloudInitCustomization),
+    Armbian(ArmbianCustomization),
 }
 
 impl Customization {
     pub(crate) fn customize(&self, dst: impl Write + Seek + Read + std::fmt::Debug) -> Result<()> {
         match self {
             Self::Sysconf(x) => x.customize(dst),
+            Self::Raspberry(x) => x.customize(dst),
+            Self::CloudInit(x) => x.customize(dst),
+            Self::Armbian(x) => x.customize(dst),
         }
     }
 
     pub(crate) fn validate(&self) -> bool {
         match self {
             Self::Sysconf(x) => x.validate(),
+            Self::Raspberry(x) => x.validate(),
+            Self::CloudInit(x) => x.validate(),
+            Self::Armbian(x) => x.validate(),
         }
     }
 }
@@ -29,8 +47,14 @@ pub struct SysconfCustomization {
     pub keymap: Option<Box<str>>,
     pub user: Option<(Box<str>, Box<str>)>,
     pub wifi: Option<(Box<str>, Box<str>)>,
+    /// Wi-Fi network does not broadcast its SSID.
+    pub wifi_hidden: bool,
+    /// ISO 3166-1 alpha-2 country code for Wi-Fi regulatory domain (e.g. "IN").
+    pub wifi_country: Option<Box<str>>,
     pub ssh: Option<Box<str>>,
     pub usb_enable_dhcp: Option<bool>,
+    /// Expand root filesystem to fill the SD Card on first boot.
+    pub expand_rootfs: Option<bool>,
 }
 
 impl SysconfCustomization {
@@ -42,15 +66,7 @@ impl SysconfCustomization {
             return Ok(());
         }
 
-        let boot_partition = {
-            let (start_off, end_off) = customization_partition(&mut dst)?;
-            let slice = fscommon::StreamSlice::new(dst, start_off, end_off)
-                .map_err(|_| Error::InvalidPartitionTable)?;
-            let boot_stream = fscommon::BufStream::new(slice);
-            fatfs::FileSystem::new(boot_stream, fatfs::FsOptions::new())
-                .map_err(|_| Error::InvalidBootPartition)?
-        };
-
+        let boot_partition = boot_partition(&mut dst)?;
         let boot_root = boot_partition.root_dir();
 
         let mut conf = boot_root
@@ -59,6 +75,34 @@ impl SysconfCustomization {
         conf.seek(SeekFrom::End(0))
             .expect("Failed to seek to end of sysconf.txt");
 
+        self.write_sysconf(&mut conf)?;
+
+        if let Some((ssid, psk)) = &self.wifi {
+            let mut wifi_file = boot_root
+                .create_file(format!("services/{}", iwd_file_name(ssid)).as_str())
+                .map_err(|e| Error::WifiSetupFail { source: e })?;
+
+            wifi_file
+                .write_all(self.iwd_psk(psk).as_bytes())
+                .map_err(|e| Error::WifiSetupFail { source: e })?;
+        }
+
+        Ok(())
+    }
+
+    /// IWD network configuration.
+    fn iwd_psk(&self, psk: &str) -> String {
+        let mut conf = format!(
+            "[Security]\nPassphrase={}\n\n[Settings]\nAutoConnect=true",
+            iwd_str(psk)
+        );
+        if self.wifi_hidden {
+            conf.push_str("\nHidden=true");
+        }
+        conf
+    }
+
+    fn write_sysconf(&self, mut conf: impl Write) -> Result<()> {
         if let Some(h) = &self.hostname {
             sysconf_w(&mut conf, "hostname", h)?;
         }
@@ -84,19 +128,16 @@ impl SysconfCustomization {
             sysconf_w(&mut conf, "usb_enable_dhcp", "yes")?;
         }
 
-        if let Some((ssid, psk)) = &self.wifi {
-            let mut wifi_file = boot_root
-                .create_file(format!("services/{ssid}.psk").as_str())
-                .map_err(|e| Error::WifiSetupFail { source: e })?;
+        if Some(true) == self.expand_rootfs {
+            sysconf_w(&mut conf, "expand_rootfs", "yes")?;
+        }
 
-            wifi_file
-                .write_all(
-                    format!("[Security]\nPassphrase={psk}\n\n[Settings]\nAutoConnect=true")
-                        .as_bytes(),
-                )
-                .map_err(|e| Error::WifiSetupFail { source: e })?;
+        if let Some((ssid, _)) = &self.wifi {
+            sysconf_w(&mut conf, "iwd_psk_file", &iwd_file_name(ssid))?;
+        }
 
-            sysconf_w(&mut conf, "iwd_psk_file", &format!("{ssid}.psk"))?;
+        if let Some(c) = &self.wifi_country {
+            sysconf_w(&mut conf, "wifi_country", c)?;
         }
 
         Ok(())
@@ -108,8 +149,309 @@ impl SysconfCustomization {
             || self.keymap.is_some()
             || self.user.is_some()
             || self.wifi.is_some()
+            || self.wifi_country.is_some()
             || self.ssh.is_some()
             || self.usb_enable_dhcp == Some(true)
+            || self.expand_rootfs == Some(true)
+    }
+
+    pub(crate) fn validate(&self) -> bool {
+        if let Some((x, _)) = &self.user {
+            x.as_ref() != "root"
+        } else {
+            true
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
+/// Post install customization options for Raspberry Pi OS images. Written to `custom.toml` in
+/// the boot partition, which is applied by `init_config` on first boot.
+pub struct RaspberryCustomization {
+    pub hostname: Option<Box<str>>,
+    pub timezone: Option<Box<str>>,
+    pub keymap: Option<Box<str>>,
+    pub user: Option<(Box<str>, Box<str>)>,
+    pub wlan: Option<(Box<str>, Box<str>)>,
+    /// ISO 3166-1 alpha-2 country code for Wi-Fi regulatory domain (e.g. "IN").
+    pub wlan_country: Option<Box<str>>,
+    pub ssh: Option<Box<str>>,
+}
+
+impl RaspberryCustomization {
+    pub(crate) fn customize(
+        &self,
+        mut dst: impl Write + Seek + Read + std::fmt::Debug,
+    ) -> Result<()> {
+        if !self.has_customization() {
+            return Ok(());
+        }
+
+        let boot_partition = boot_partition(&mut dst)?;
+        let mut conf = boot_partition
+            .root_dir()
+            .create_file("custom.toml")
+            .map_err(|source| Error::CustomTomlWriteFail { source })?;
+
+        conf.truncate()
+            .and_then(|_| conf.write_all(self.custom_toml().as_bytes()))
+            .map_err(|source| Error::CustomTomlWriteFail { source })
+    }
+
+    fn custom_toml(&self) -> String {
+        let mut conf = String::from("config_version = 1\n");
+
+        if let Some(h) = &self.hostname {
+            conf.push_str(&format!("\n[system]\nhostname = {}\n", toml_str(h)));
+        }
+
+        if let Some((u, p)) = &self.user {
+            conf.push_str(&format!(
+                "\n[user]\nname = {}\npassword = {}\npassword_encrypted = false\n",
+                toml_str(u),
+                toml_str(p)
+            ));
+        }
+
+        if let Some(k) = &self.ssh {
+            conf.push_str(&format!(
+                "\n[ssh]\nenabled = true\npassword_authentication = false\nauthorized_keys = [{}]\n",
+                toml_str(k)
+            ));
+        }
+
+        if let Some((ssid, psk)) = &self.wlan {
+            conf.push_str(&format!(
+                "\n[wlan]\nssid = {}\npassword = {}\npassword_encrypted = false\nhidden = false\n",
+                toml_str(ssid),
+                toml_str(psk)
+            ));
+            if let Some(c) = &self.wlan_country {
+                conf.push_str(&format!("country = {}\n", toml_str(c)));
+            }
+        }
+
+        if self.keymap.is_some() || self.timezone.is_some() {
+            conf.push_str("\n[locale]\n");
+            if let Some(k) = &self.keymap {
+                conf.push_str(&format!("keymap = {}\n", toml_str(k)));
+            }
+            if let Some(tz) = &self.timezone {
+                conf.push_str(&format!("timezone = {}\n", toml_str(tz)));
+            }
+        }
+
+        conf
+    }
+
+    pub(crate) fn has_customization(&self) -> bool {
+        self.hostname.is_some()
+            || self.timezone.is_some()
+            || self.keymap.is_some()
+            || self.user.is_some()
+            || self.wlan.is_some()
+            || self.ssh.is_some()
+    }
+
+    pub(crate) fn validate(&self) -> bool {
+        let user = match &self.user {
+            Some((x, _)) => x.as_ref() != "root",
+            None => true,
+        };
+
+        // Regulatory domain is only meaningful with Wi-Fi
+        user && (self.wlan_country.is_none() || self.wlan.is_some())
+    }
+}
+
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
+/// Post install customization options for cloud-init based images. Written to `user-data`,
+/// `meta-data` and `network-config` in the boot partition, which is used as [NoCloud] data source
+/// on first boot.
+///
+/// [NoCloud]: https://cloudinit.readthedocs.io/en/latest/reference/datasources/nocloud.html
+pub struct CloudInitCustomization {
+    pub hostname: Option<Box<str>>,
+    pub timezone: Option<Box<str>>,
+    pub keymap: Option<Box<str>>,
+    pub user: Option<(Box<str>, Box<str>)>,
+    pub wifi: Option<(Box<str>, Box<str>)>,
+    pub ssh: Option<Box<str>>,
+}
+
+impl CloudInitCustomization {
+    pub(crate) fn customize(
+        &self,
+        mut dst: impl Write + Seek + Read + std::fmt::Debug,
+    ) -> Result<()> {
+        if !self.has_customization() {
+            return Ok(());
+        }
+
+        let boot_partition = boot_partition(&mut dst)?;
+        let boot_root = boot_partition.root_dir();
+
+        // New instance ID makes cloud-init run again, even if the image was booted before.
+        let instance_id = format!("bb-imager-{}", uuid::Uuid::new_v4().hyphenated());
+
+        cloud_init_w(&boot_root, "user-data", &self.user_data())?;
+        cloud_init_w(&boot_root, "meta-data", &self.meta_data(&instance_id))?;
+        if let Some(x) = self.network_config() {
+            cloud_init_w(&boot_root, "network-config", &x)?;
+        }
+
+        Ok(())
+    }
+
+    fn user_data(&self) -> String {
+        let mut conf = String::from("#cloud-config\n");
+
+        if let Some(h) = &self.hostname {
+            conf.push_str(&format!("hostname: {}\n", yaml_str(h)));
+        }
+
+        if let Some(tz) = &self.timezone {
+            conf.push_str(&format!("timezone: {}\n", yaml_str(tz)));
+        }
+
+        if let Some(k) = &self.keymap {
+            conf.push_str(&format!("keyboard:\n  layout: {}\n", yaml_str(k)));
+        }
+
+        match (&self.user, &self.ssh) {
+            (Some((u, p)), ssh) => {
+                conf.push_str(&format!(
+                    "ssh_pwauth: {}\nusers:\n  - name: {}\n    plain_text_passwd: {}\n    lock_passwd: false\n    groups: [adm, sudo]\n    shell: /bin/bash\n",
+                    ssh.is_none(),
+                    yaml_str(u),
+                    yaml_str(p)
+                ));
+                if let Some(k) = ssh {
+                    conf.push_str(&format!(
+                        "    ssh_authorized_keys:\n      - {}\n",
+                        yaml_str(k)
+                    ));
+                }
+            }
+            // Key is added to the default user of the image
+            (None, Some(k)) => {
+                conf.push_str(&format!("ssh_authorized_keys:\n  - {}\n", yaml_str(k)));
+            }
+            (None, None) => {}
+        }
+
+        conf
+    }
+
+    fn meta_data(&self, instance_id: &str) -> String {
+        let mut conf = format!("instance-id: {}\n", yaml_str(instance_id));
+
+        if let Some(h) = &self.hostname {
+            conf.push_str(&format!("local-hostname: {}\n", yaml_str(h)));
+        }
+
+        conf
+    }
+
+    fn network_config(&self) -> Option<String> {
+        let (ssid, psk) = self.wifi.as_ref()?;
+
+        Some(format!(
+            "version: 2\nwifis:\n  wlan0:\n    dhcp4: true\n    optional: true\n    access-points:\n      {}:\n        password: {}\n",
+            yaml_str(ssid),
+            yaml_str(psk)
+        ))
+    }
+
+    pub(crate) fn has_customization(&self) -> bool {
+        self.hostname.is_some()
+            || self.timezone.is_some()
+            || self.keymap.is_some()
+            || self.user.is_some()
+            || self.wifi.is_some()
+            || self.ssh.is_some()
+    }
+
+    pub(crate) fn validate(&self) -> bool {
+        if let Some((x, _)) = &self.user {
+            x.as_ref() != "root"
+        } else {
+            true
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
+/// Post install customization options for Armbian images. Written to `armbian_first_run.txt` in
+/// the boot partition, which is sourced as a shell script on first boot.
+pub struct ArmbianCustomization {
+    pub hostname: Option<Box<str>>,
+    pub timezone: Option<Box<str>>,
+    pub keymap: Option<Box<str>>,
+    pub user: Option<(Box<str>, Box<str>)>,
+    pub wifi: Option<(Box<str>, Box<str>)>,
+}
+
+impl ArmbianCustomization {
+    pub(crate) fn customize(
+        &self,
+        mut dst: impl Write + Seek + Read + std::fmt::Debug,
+    ) -> Result<()> {
+        if !self.has_customization() {
+            return Ok(());
+        }
+
+        let boot_partition = boot_partition(&mut dst)?;
+        let mut conf = boot_partition
+            .root_dir()
+            .create_file("armbian_first_run.txt")
+            .map_err(|source| Error::ArmbianWriteFail { source })?;
+
+        conf.truncate()
+            .and_then(|_| conf.write_all(self.first_run().as_bytes()))
+            .map_err(|source| Error::ArmbianWriteFail { source })
+    }
+
+    fn first_run(&self) -> String {
+        let mut conf = String::from("FR_general_delete_this_file_after_completion=1\n");
+
+        if let Some(h) = &self.hostname {
+            conf.push_str(&format!("PRESET_HOSTNAME={}\n", shell_str(h)));
+        }
+
+        if let Some(tz) = &self.timezone {
+            conf.push_str(&format!("PRESET_TIMEZONE={}\n", shell_str(tz)));
+        }
+
+        if let Some(k) = &self.keymap {
+            conf.push_str(&format!("PRESET_KEYMAP={}\n", shell_str(k)));
+        }
+
+        if let Some((u, p)) = &self.user {
+            conf.push_str(&format!(
+                "PRESET_USER_NAME={}\nPRESET_USER_PASSWORD={}\n",
+                shell_str(u),
+                shell_str(p)
+            ));
+        }
+
+        if let Some((ssid, psk)) = &self.wifi {
+            conf.push_str(&format!(
+                "FR_net_change_defaults=1\nFR_net_wifi_enabled=1\nFR_net_wifi_ssid={}\nFR_net_wifi_key={}\n",
+                shell_str(ssid),
+                shell_str(psk)
+            ));
+        }
+
+        conf
+    }
+
+    pub(crate) fn has_customization(&self) -> bool {
+        self.hostname.is_some()
+            || self.timezone.is_some()
+            || self.keymap.is_some()
+            || self.user.is_some()
+            || self.wifi.is_some()
     }
 
     pub(crate) fn validate(&self) -> bool {
@@ -121,6 +463,102 @@ impl SysconfCustomization {
     }
 }
 
+fn cloud_init_w<T: fatfs::ReadWriteSeek>(
+    dir: &fatfs::Dir<'_, T>,
+    file: &'static str,
+    data: &str,
+) -> Result<()> {
+    let mut f = dir
+        .create_file(file)
+        .map_err(|source| Error::CloudInitWriteFail { source, file })?;
+
+    f.truncate()
+        .and_then(|_| f.write_all(data.as_bytes()))
+        .map_err(|source| Error::CloudInitWriteFail { source, file })
+}
+
+/// Basic TOML string, with quotes, backslashes and control characters escaped.
+fn toml_str(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            '\r' => res.push_str("\\r"),
+            c if c.is_control() => res.push_str(&format!("\\u{:04X}", c as u32)),
+            c => res.push(c),
+        }
+    }
+
+    res.push('"');
+    res
+}
+
+/// YAML double-quoted scalar. Uses the same escapes as TOML basic strings.
+fn yaml_str(s: &str) -> String {
+    toml_str(s)
+}
+
+/// IWD network file name. SSIDs with characters other than ASCII alphanumerics, space, `-` and
+/// `_` are hex encoded and prefixed with `=`, same as IWD.
+fn iwd_file_name(ssid: &str) -> String {
+    let safe = !ssid.is_empty()
+        && ssid
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_'));
+
+    if safe {
+        format!("{ssid}.psk")
+    } else {
+        format!("={}.psk", const_hex::encode(ssid))
+    }
+}
+
+/// IWD settings value. Uses the escapes understood by IWD, so values cannot add extra keys or
+/// lose leading whitespace.
+fn iwd_str(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+
+    for (i, c) in s.chars().enumerate() {
+        match c {
+            ' ' if i == 0 => res.push_str("\\s"),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            '\r' => res.push_str("\\r"),
+            c => res.push(c),
+        }
+    }
+
+    res
+}
+
+/// Single-quoted shell string. Single quotes are closed, escaped and reopened.
+fn shell_str(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+pub(crate) fn boot_partition<D: Write + Seek + Read + std::fmt::Debug>(
+    mut dst: D,
+) -> Result<fatfs::FileSystem<fscommon::BufStream<fscommon::StreamSlice<D>>>> {
+    let (start_off, end_off) = customization_partition(&mut dst)?;
+    let mut slice = fscommon::StreamSlice::new(dst, start_off, end_off)
+        .map_err(|_| Error::InvalidPartitionTable)?;
+
+    if let Some(fs) = unsupported_fs(&mut slice) {
+        return Err(Error::UnsupportedBootPartition(fs));
+    }
+    slice.seek(SeekFrom::Start(0))?;
+
+    let boot_stream = fscommon::BufStream::new(slice);
+    fatfs::FileSystem::new(boot_stream, fatfs::FsOptions::new())
+        .map_err(|_| Error::InvalidBootPartition)
+}
+
 fn sysconf_w(mut sysconf: impl Write, key: &'static str, value: &str) -> Result<()> {
     sysconf
         .write_all(format!("{key}={value}\n").as_bytes())
@@ -130,29 +568,396 @@ fn sysconf_w(mut sysconf: impl Write, key: &'static str, value: &str) -> Result<
         })
 }
 
+/// Find the FAT partition labelled `BOOT`. Falls back to the first FAT partition, for images
+/// without a labelled boot partition, and then to the first exFAT or ext4 partition. These are
+/// not supported, but are reported as such by [boot_partition].
 fn customization_partition(
     mut dst: impl Write + Seek + Read + std::fmt::Debug,
 ) -> Result<(u64, u64)> {
-    // First try GPT partition table. If that fails, try MBR
-    if let Ok(disk) = gpt::GptConfig::new()
-        .writable(false)
-        .open_from_device(&mut dst)
-    {
-        // FIXME: Add better partition lookup
-        let partition_2 = disk.partitions().get(&2).unwrap();
-
-        let start_offset: u64 = partition_2.first_lba * gpt::disk::DEFAULT_SECTOR_SIZE.as_u64();
-        let end_offset: u64 = partition_2.last_lba * gpt::disk::DEFAULT_SECTOR_SIZE.as_u64();
-
-        Ok((start_offset, end_offset))
+    let mut fallback = None;
+    let mut unsupported = None;
+
+    for p in crate::partition::partitions(&mut dst)? {
+        let Some(label) = fat_label(&mut dst, &p) else {
+            if unsupported.is_none()
+                && let Ok(slice) = fscommon::StreamSlice::new(&mut dst, p.start, p.end())
+                && unsupported_fs(slice).is_some()
+            {
+                unsupported = Some((p.start, p.end()));
+            }
+            continue;
+        };
+
+        if label.eq_ignore_ascii_case(BOOT_LABEL) {
+            return Ok((p.start, p.end()));
+        }
+        fallback.get_or_insert((p.start, p.end()));
+    }
+
+    fallback.or(unsupported).ok_or(Error::InvalidBootPartition)
+}
+
+/// Name of file system in partition, if it is one that is detected but cannot be customized.
+fn unsupported_fs(mut part: impl Read + Seek) -> Option<&'static str> {
+    let mut start = [0u8; EXT_MAGIC_OFFSET + 2];
+    part.seek(SeekFrom::Start(0)).ok()?;
+    part.read_exact(&mut start).ok()?;
+
+    if &start[3..11] == EXFAT_SIGNATURE {
+        Some("exFAT")
+    } else if start[EXT_MAGIC_OFFSET..] == EXT_MAGIC && start[510..512] != [0x55, 0xaa] {
+        // FAT boot sector signature is checked since the magic offset can lie in the FAT
+        Some("ext4")
     } else {
-        let mbr =
-            mbrman::MBRHeader::read_from(&mut dst).map_err(|_| Error::InvalidPartitionTable)?;
+        None
+    }
+}
+
+/// Volume label of FAT filesystem in partition `p`. [None] if `p` does not contain a FAT
+/// filesystem.
+fn fat_label<D: Write + Seek + Read>(dst: &mut D, p: &Partition) -> Option<String> {
+    let slice = fscommon::StreamSlice::new(dst, p.start, p.end()).ok()?;
+    let fs =
+        fatfs::FileSystem::new(fscommon::BufStream::new(slice), fatfs::FsOptions::new()).ok()?;
+
+    // Some tools only set the label in root directory
+    match fs.read_volume_label_from_root_dir() {
+        Ok(Some(x)) => Some(x),
+        _ => Some(fs.volume_label()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{
+        ArmbianCustomization, CloudInitCustomization, RaspberryCustomization, SysconfCustomization,
+        customization_partition, iwd_file_name, iwd_str, toml_str,
+    };
+    use crate::partition::partitions;
+
+    const MIB: u64 = 1024 * 1024;
+
+    /// GPT image with 3 partitions. Partitions with a label are formatted as FAT.
+    fn gpt_image(labels: [Option<&[u8; 11]>; 3]) -> Cursor<Vec<u8>> {
+        gpt_image_with_lb(labels, gpt::disk::LogicalBlockSize::Lb512)
+    }
+
+    fn gpt_image_with_lb(
+        labels: [Option<&[u8; 11]>; 3],
+        lb_size: gpt::disk::LogicalBlockSize,
+    ) -> Cursor<Vec<u8>> {
+        let sectors = 16 * MIB / u64::from(lb_size);
+        let mut disk = Cursor::new(vec![0u8; 16 * MIB as usize]);
+        gpt::mbr::ProtectiveMBR::with_lb_size(u32::try_from(sectors - 1).unwrap())
+            .overwrite_lba0(&mut disk)
+            .unwrap();
+
+        let mut gpt = gpt::GptConfig::new()
+            .writable(true)
+            .logical_block_size(lb_size)
+            .create_from_device(&mut disk, None)
+            .unwrap();
+        for i in 1..=3 {
+            gpt.add_partition(
+                &format!("part{i}"),
+                4 * MIB,
+                gpt::partition_types::BASIC,
+                0,
+                None,
+            )
+            .unwrap();
+        }
+        gpt.write_inplace().unwrap();
+
+        for p in partitions(&mut disk).unwrap() {
+            if let Some(label) = labels[p.number as usize - 1] {
+                let slice = fscommon::StreamSlice::new(&mut disk, p.start, p.end()).unwrap();
+                fatfs::format_volume(
+                    slice,
+                    fatfs::FormatVolumeOptions::new().volume_label(*label),
+                )
+                .unwrap();
+            }
+        }
+
+        disk
+    }
+
+    #[test]
+    fn boot_partition_lookup() {
+        let find = |labels| {
+            let mut disk = gpt_image(labels);
+            let parts = partitions(&mut disk).unwrap();
+            let (start, end) = customization_partition(&mut disk).ok()?;
+            parts
+                .iter()
+                .find(|p| p.start == start && p.end() == end)
+                .map(|p| p.number)
+        };
+
+        assert_eq!(
+            find([Some(b"BOOT       "), None, Some(b"DATA       ")]),
+            Some(1)
+        );
+        assert_eq!(
+            find([Some(b"DATA       "), None, Some(b"boot       ")]),
+            Some(3)
+        );
+        // Images without a labelled boot partition use the first FAT partition
+        assert_eq!(
+            find([None, Some(b"DATA       "), Some(b"BOOT2      ")]),
+            Some(2)
+        );
+        assert_eq!(find([None, None, None]), None);
+    }
+
+    #[test]
+    fn boot_partition_4k_sectors() {
+        let mut disk = gpt_image_with_lb(
+            [Some(b"DATA       "), Some(b"BOOT       "), None],
+            gpt::disk::LogicalBlockSize::Lb4096,
+        );
+
+        let p = partitions(&mut disk).unwrap();
+        assert_eq!(p.len(), 3);
+        assert_eq!(p[1].start % 4096, 0);
+        assert_eq!(p[1].size, 4 * MIB);
+        assert_eq!(
+            customization_partition(&mut disk).unwrap(),
+            (p[1].start, p[1].end())
+        );
+    }
+
+    #[test]
+    fn unsupported_boot_partition() {
+        for (offset, magic, fs) in [
+            (3, b"EXFAT   ".as_slice(), "exFAT"),
+            (1080, [0x53, 0xef].as_slice(), "ext4"),
+        ] {
+            let mut disk = gpt_image([None, None, None]);
+            let p = partitions(&mut disk).unwrap()[1];
+            // Only the file system signature is checked
+            disk.set_position(p.start + offset);
+            std::io::Write::write_all(&mut disk, magic).unwrap();
+
+            assert_eq!(
+                customization_partition(&mut disk).unwrap(),
+                (p.start, p.end())
+            );
+            assert!(matches!(
+                super::boot_partition(&mut disk),
+                Err(crate::Error::UnsupportedBootPartition(x)) if x == fs
+            ));
+        }
 
-        let boot_part = mbr.get(1).ok_or(Error::InvalidPartitionTable)?;
-        let start_offset: u64 = (boot_part.starting_lba * 512).into();
-        let end_offset: u64 = start_offset + u64::from(boot_part.sectors) * 512;
+        // FAT partition is preferred
+        let mut disk = gpt_image([None, None, Some(b"BOOT       ")]);
+        let p = partitions(&mut disk).unwrap();
+        disk.set_position(p[0].start + 1080);
+        std::io::Write::write_all(&mut disk, &[0x53, 0xef]).unwrap();
+        assert_eq!(
+            customization_partition(&mut disk).unwrap(),
+            (p[2].start, p[2].end())
+        );
+    }
+
+    #[test]
+    fn toml_escape() {
+        assert_eq!(toml_str("beagle"), r#""beagle""#);
+        assert_eq!(toml_str(r#"my "wifi"\"#), r#""my \"wifi\"\\""#);
+        assert_eq!(toml_str("a\nb\u{7}"), r#""a\nb\u0007""#);
+    }
+
+    #[test]
+    fn sysconf() {
+        let conf = SysconfCustomization {
+            hostname: Some("beagle".into()),
+            wifi: Some(("Home".into(), "secret".into())),
+            expand_rootfs: Some(true),
+            ..Default::default()
+        };
+
+        let mut data = Vec::new();
+        conf.write_sysconf(&mut data).unwrap();
+        assert_eq!(
+            String::from_utf8(data).unwrap(),
+            "hostname=beagle\nexpand_rootfs=yes\niwd_psk_file=Home.psk\n"
+        );
+        assert!(conf.has_customization());
+
+        let conf = SysconfCustomization {
+            expand_rootfs: Some(false),
+            ..Default::default()
+        };
+        let mut data = Vec::new();
+        conf.write_sysconf(&mut data).unwrap();
+        assert!(data.is_empty());
+        assert!(!conf.has_customization());
+    }
+
+    #[test]
+    fn iwd_escape() {
+        assert_eq!(iwd_file_name("My Home_2-4"), "My Home_2-4.psk");
+        assert_eq!(iwd_file_name("Home/5G"), "=486f6d652f3547.psk");
+        assert_eq!(iwd_file_name("Café"), "=436166c3a9.psk");
 
-        Ok((start_offset, end_offset))
+        assert_eq!(iwd_str("secret"), "secret");
+        assert_eq!(iwd_str(" a\\b\nHidden=true"), r"\sa\\b\nHidden=true");
+
+        let conf = SysconfCustomization {
+            wifi: Some(("Home/5G".into(), "secret".into())),
+            ..Default::default()
+        };
+        let mut data = Vec::new();
+        conf.write_sysconf(&mut data).unwrap();
+        assert_eq!(
+            String::from_utf8(data).unwrap(),
+            "iwd_psk_file==486f6d652f3547.psk\n"
+        );
+    }
+
+    #[test]
+    fn sysconf_hidden_wifi() {
+        let conf = SysconfCustomization {
+            wifi: Some(("Home".into(), "secret".into())),
+            wifi_hidden: true,
+            wifi_country: Some("IN".into()),
+            ..Default::default()
+        };
+
+        let mut data = Vec::new();
+        conf.write_sysconf(&mut data).unwrap();
+        assert_eq!(
+            String::from_utf8(data).unwrap(),
+            "iwd_psk_file=Home.psk\nwifi_country=IN\n"
+        );
+        assert_eq!(
+            conf.iwd_psk("secret"),
+            "[Security]\nPassphrase=secret\n\n[Settings]\nAutoConnect=true\nHidden=true"
+        );
+    }
+
+    #[test]
+    fn custom_toml() {
+        let conf = RaspberryCustomization {
+            hostname: Some("pi".into()),
+            timezone: Some("Asia/Kolkata".into()),
+            user: Some(("beagle".into(), "temppwd".into())),
+            wlan: Some(("Home".into(), "secret".into())),
+            wlan_country: Some("IN".into()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            conf.custom_toml(),
+            r#"config_version = 1
+
+[system]
+hostname = "pi"
+
+[user]
+name = "beagle"
+password = "temppwd"
+password_encrypted = false
+
+[wlan]
+ssid = "Home"
+password = "secret"
+password_encrypted = false
+hidden = false
+country = "IN"
+
+[locale]
+timezone = "Asia/Kolkata"
+"#
+        );
+
+        assert!(conf.validate());
+        assert!(
+            !RaspberryCustomization {
+                wlan_country: Some("IN".into()),
+                ..Default::default()
+            }
+            .validate()
+        );
+    }
+
+    #[test]
+    fn armbian_first_run() {
+        let conf = ArmbianCustomization {
+            hostname: Some("beagle".into()),
+            user: Some(("debian".into(), "it's".into())),
+            wifi: Some(("Home".into(), "secret".into())),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            conf.first_run(),
+            r#"FR_general_delete_this_file_after_completion=1
+PRESET_HOSTNAME='beagle'
+PRESET_USER_NAME='debian'
+PRESET_USER_PASSWORD='it'\''s'
+FR_net_change_defaults=1
+FR_net_wifi_enabled=1
+FR_net_wifi_ssid='Home'
+FR_net_wifi_key='secret'
+"#
+        );
+        assert!(conf.validate());
+        assert!(!ArmbianCustomization::default().has_customization());
+    }
+
+    #[test]
+    fn cloud_init() {
+        let conf = CloudInitCustomization {
+            hostname: Some("beagle".into()),
+            timezone: Some("Asia/Kolkata".into()),
+            keymap: Some("us".into()),
+            user: Some(("debian".into(), "temp: \"pwd\"".into())),
+            wifi: Some(("My #Wifi".into(), "secret\\".into())),
+            ssh: Some("ssh-ed25519 AAAA user@host".into()),
+        };
+
+        let user_data = conf.user_data();
+        assert!(user_data.starts_with("#cloud-config\n"));
+        let user_data: serde_yaml::Value = serde_yaml::from_str(&user_data).unwrap();
+        assert_eq!(user_data["hostname"], "beagle");
+        assert_eq!(user_data["timezone"], "Asia/Kolkata");
+        assert_eq!(user_data["keyboard"]["layout"], "us");
+        assert_eq!(user_data["ssh_pwauth"], false);
+        assert_eq!(user_data["users"][0]["name"], "debian");
+        assert_eq!(user_data["users"][0]["plain_text_passwd"], "temp: \"pwd\"");
+        assert_eq!(
+            user_data["users"][0]["ssh_authorized_keys"][0],
+            "ssh-ed25519 AAAA user@host"
+        );
+
+        let meta_data: serde_yaml::Value =
+            serde_yaml::from_str(&conf.meta_data("bb-imager-1")).unwrap();
+        assert_eq!(meta_data["instance-id"], "bb-imager-1");
+        assert_eq!(meta_data["local-hostname"], "beagle");
+
+        let network: serde_yaml::Value =
+            serde_yaml::from_str(&conf.network_config().unwrap()).unwrap();
+        assert_eq!(network["version"], 2);
+        assert_eq!(
+            network["wifis"]["wlan0"]["access-points"]["My #Wifi"]["password"],
+            "secret\\"
+        );
+
+        let conf = CloudInitCustomization {
+            ssh: Some("ssh-ed25519 AAAA user@host".into()),
+            ..Default::default()
+        };
+        let user_data: serde_yaml::Value = serde_yaml::from_str(&conf.user_data()).unwrap();
+        assert_eq!(
+            user_data["ssh_authorized_keys"][0],
+            "ssh-ed25519 AAAA user@host"
+        );
+        assert!(user_data.get("users").is_none());
+        assert!(conf.network_config().is_none());
+        assert!(!CloudInitCustomization::default().has_customization());
     }
 }
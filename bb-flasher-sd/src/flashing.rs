@@ -1,18 +1,62 @@
-use std::io::{Read, Seek, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use tokio::sync::mpsc;
 
-use crate::Result;
+use crate::bmap::RangeChecksums;
 use crate::customization::Customization;
 use crate::helpers::{DirectIoBuffer, Eject, chan_send, check_token, progress};
+use crate::marker::ProvisioningMarker;
+use crate::verify::{Compared, Verify, Written, compare};
+use crate::{Result, Status};
 
 // Stack overflow occurs during debug since box moves data from stack to heap in debug builds
 #[cfg(not(debug_assertions))]
-const BUFFER_SIZE: usize = 1 * 1024 * 1024;
+pub(crate) const BUFFER_SIZE: usize = 1 * 1024 * 1024;
 #[cfg(debug_assertions)]
-const BUFFER_SIZE: usize = 8 * 1024;
+pub(crate) const BUFFER_SIZE: usize = 8 * 1024;
+
+/// Size of the start of image used to find partitions. Should be enough for MBR and GPT.
+const IMG_HEADER_LEN: usize = 1024 * 1024;
+
+/// Outcome of successfully flashing SD Card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Flashed {
+    /// Image was written to SD Card.
+    Written,
+    /// SD Card already contained the image, so nothing was written.
+    UpToDate,
+}
+
+/// Optional steps of [flash]. Everything is disabled by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlashOptions {
+    /// Read back and verify SD Card contents using this hash. See [flash] for details.
+    pub verify: Option<Verify>,
+    /// Marker written to the boot partition after customization.
+    pub marker: Option<ProvisioningMarker>,
+    /// Compare SD Card with image, and only write the data that differs.
+    pub skip_identical: bool,
+    /// Clear the partition table before writing.
+    pub format: bool,
+    /// Eject SD Card once flashing succeeds.
+    pub eject: bool,
+}
+
+/// Statistics of successfully flashing SD Card, returned by [flash].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashStats {
+    /// Whether image was written, or SD Card was already up to date.
+    pub flashed: Flashed,
+    /// Bytes of image written to SD Card. Does not include customization and provisioning marker.
+    pub bytes_written: u64,
+    /// Time taken to write, verify and customize SD Card.
+    pub duration: Duration,
+    /// Whether SD Card contents were read back and found to match the image. Always true for an
+    /// up to date SD Card, since it was compared with the image.
+    pub verified: bool,
+}
 
 fn reader_task(
     mut img: impl Read,
@@ -40,18 +84,26 @@ fn reader_task(
 /// - All writes should be aligned to block size (4K).
 ///
 /// Thus, we will be writing some data that is not strictly present in the bmap.
+///
+/// Checksum of each mapped range is checked as it is written. On mismatch,
+/// [Error::InvalidBmap](crate::Error::InvalidBmap) is returned, although data has already been
+/// written to SD Card.
+#[allow(clippy::too_many_arguments)]
 fn writer_task_bmap(
     bmap: bb_bmap_parser::Bmap,
+    start: u64,
     mut sd: impl Write + Seek,
-    mut chan: Option<&mut mpsc::Sender<f32>>,
+    mut chan: Option<&mut mpsc::Sender<Status>>,
+    mut written: Option<&mut Written>,
     buf_rx: std::sync::mpsc::Receiver<(Box<DirectIoBuffer<BUFFER_SIZE>>, usize)>,
     buf_tx: std::sync::mpsc::SyncSender<Box<DirectIoBuffer<BUFFER_SIZE>>>,
     cancel: Option<tokio_util::sync::CancellationToken>,
-) -> Result<()> {
-    let mut pos = 0;
+) -> Result<u64> {
+    let mut pos = start;
     let (mut buf, mut count) = buf_rx.recv().unwrap();
     let img_size = bmap.total_mapped_size();
     let mut bytes_written = 0u64;
+    let mut checksums = RangeChecksums::new(&bmap, start);
 
     for b in bmap.block_map() {
         let end_offset = b.offset() + b.length();
@@ -62,16 +114,21 @@ fn writer_task_bmap(
                 sd.seek(std::io::SeekFrom::Start(pos))?;
                 sd.write_all(&buf.as_slice()[..count])?;
                 bytes_written += count as u64;
+
+                if let Some(w) = written.as_mut() {
+                    w.record(pos, &buf.as_slice()[..count]);
+                }
             } else if pos >= end_offset {
                 break;
             }
 
+            checksums.update(pos, &buf.as_slice()[..count])?;
             pos += count as u64;
             // Clippy warning is simply wrong here
             #[allow(clippy::option_map_or_none)]
             chan_send(
                 chan.as_mut().map_or(None, |p| Some(p)),
-                progress(bytes_written, img_size),
+                Status::Flashing(progress(bytes_written, img_size)),
             );
             check_token(cancel.as_ref())?;
 
@@ -86,40 +143,53 @@ fn writer_task_bmap(
         }
     }
 
-    sd.flush().map_err(Into::into)
+    checksums.finish()?;
+    sd.flush()?;
+
+    Ok(bytes_written)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn writer_task(
     img_size: u64,
+    start: u64,
     mut sd: impl Write + Seek,
-    mut chan: Option<&mut mpsc::Sender<f32>>,
+    mut chan: Option<&mut mpsc::Sender<Status>>,
+    mut written: Option<&mut Written>,
     buf_rx: std::sync::mpsc::Receiver<(Box<DirectIoBuffer<BUFFER_SIZE>>, usize)>,
     buf_tx: std::sync::mpsc::SyncSender<Box<DirectIoBuffer<BUFFER_SIZE>>>,
     cancel: Option<tokio_util::sync::CancellationToken>,
-) -> Result<()> {
-    let mut pos = 0u64;
+) -> Result<u64> {
+    let mut pos = start;
+    sd.seek(SeekFrom::Start(start))?;
 
     while let Ok((buf, count)) = buf_rx.recv() {
         sd.write_all(&buf.as_slice()[..count])?;
 
+        if let Some(w) = written.as_mut() {
+            w.record(pos, &buf.as_slice()[..count]);
+        }
+
         pos += count as u64;
         // Clippy warning is simply wrong here
         #[allow(clippy::option_map_or_none)]
         chan_send(
             chan.as_mut().map_or(None, |p| Some(p)),
-            progress(pos, img_size),
+            Status::Flashing(progress(pos, img_size)),
         );
 
         let _ = buf_tx.send(buf);
         check_token(cancel.as_ref())?;
     }
 
-    sd.flush().map_err(Into::into)
+    sd.flush()?;
+
+    Ok(pos - start)
 }
 
 /// A lot of reads from compressed files are not aligned. Since reading even from compressed files
 /// is significantly faster than writing to SD Card, better to do multiple reads.
-fn read_aligned(mut img: impl Read, buf: &mut [u8]) -> Result<usize> {
+pub(crate) fn read_aligned(mut img: impl Read, buf: &mut [u8]) -> Result<usize> {
     const ALIGNMENT: usize = 512;
 
     let mut pos = 0;
@@ -140,14 +210,19 @@ fn read_aligned(mut img: impl Read, buf: &mut [u8]) -> Result<usize> {
     Ok(pos)
 }
 
-fn write_sd(
+/// Write image to SD Card. `img` should start at offset `start` of the image, which allows
+/// skipping data already present on SD Card. Returns the number of bytes written.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn write_sd(
     img: impl Read + Send,
     img_size: u64,
+    start: u64,
     bmap: Option<bb_bmap_parser::Bmap>,
     sd: impl Write + Seek,
-    chan: Option<&mut mpsc::Sender<f32>>,
+    chan: Option<&mut mpsc::Sender<Status>>,
+    written: Option<&mut Written>,
     cancel: Option<tokio_util::sync::CancellationToken>,
-) -> Result<()> {
+) -> Result<u64> {
     const NUM_BUFFERS: usize = 4;
 
     let (tx1, rx1) = std::sync::mpsc::sync_channel(NUM_BUFFERS);
@@ -163,13 +238,14 @@ fn write_sd(
         let cancle_clone = cancel.clone();
         let handle = s.spawn(move || reader_task(img, rx1, tx2, cancle_clone));
 
-        match bmap {
-            Some(x) => writer_task_bmap(x, sd, chan, rx2, tx1, cancel),
-            None => writer_task(img_size, sd, chan, rx2, tx1, cancel),
+        let bytes_written = match bmap {
+            Some(x) => writer_task_bmap(x, start, sd, chan, written, rx2, tx1, cancel),
+            None => writer_task(img_size, start, sd, chan, written, rx2, tx1, cancel),
         }?;
         tracing::info!("Total Time taken: {:?}", global_start.elapsed());
 
-        handle.join().unwrap()
+        handle.join().unwrap()?;
+        Ok(bytes_written)
     })
 }
 
@@ -191,13 +267,53 @@ fn write_sd(
 ///
 /// # Progress
 ///
-/// Progress lies between 0 and 1.
+/// [Status::Preparing] is sent first, followed by [Status::Flashing] with progress between 0 and 1.
+/// [Status::Verifying] is sent once writing is done, if [FlashOptions::verify] is set.
+///
+/// # Verification
+///
+/// If [FlashOptions::verify] is set, all data written is read back from SD Card and compared
+/// using the given hash. Verification happens before customization, since customization modifies the SD Card.
+///
+/// With a bmap, the checksum of each mapped range is also checked while writing, and
+/// [Error::InvalidBmap](crate::Error::InvalidBmap) is returned if the image does not match.
+///
+/// # Provisioning Marker
+///
+/// If [FlashOptions::marker] is set, it is written to the boot partition after customization. See
+/// [ProvisioningMarker].
+///
+/// # Skipping Identical Data
+///
+/// If [FlashOptions::skip_identical] is set, SD Card is read back and compared with the image
+/// before writing.
+/// Only the data after the first mismatch is written, and nothing at all if SD Card already
+/// contains the image, in which case [`Flashed::UpToDate`] is returned in [FlashStats]. With a
+/// bmap, only the mapped ranges are compared.
+///
+/// Customization and provisioning marker are still applied to an up to date SD Card. Since they
+/// modify the boot partition, a customized SD Card will never be completely up to date.
+///
+/// # Formatting
+///
+/// If [FlashOptions::format] is set, the partition table (including the backup GPT at the end) is
+/// cleared before writing. Partitions from previous contents which are not overwritten by the image, for
+/// example when using bmap, are no longer detected.
+///
+/// # Ejecting
+///
+/// If [FlashOptions::eject] is set, SD Card is ejected once flashing, verification and
+/// customization succeed.
+/// Failure to eject is logged as a warning, and does not fail flashing.
 ///
 /// # Aborting
 ///
 /// The process can be aborted by dropping all strong references to the [`Arc`] that owns the
 /// [`Weak`] passed as `cancel`.
 ///
+/// Tasks spawned while resolving the image, such as downloads, are still awaited after aborting.
+/// So an image which was already downloaded is kept in cache.
+///
 /// [`Arc`]: std::sync::Arc
 /// [`Weak`]: std::sync::Weak
 /// [BeagleBoard.org]: https://www.beagleboard.org/
@@ -205,10 +321,11 @@ pub async fn flash<R: Read + Send + 'static>(
     img: impl bb_helper::resolvable::Resolvable<ResolvedType = (R, u64)>,
     bmap: Option<impl bb_helper::resolvable::Resolvable<ResolvedType = Box<str>>>,
     dst: Box<Path>,
-    chan: Option<mpsc::Sender<f32>>,
+    chan: Option<mpsc::Sender<Status>>,
     customization: Option<Customization>,
+    options: FlashOptions,
     cancel: Option<tokio_util::sync::CancellationToken>,
-) -> Result<()> {
+) -> Result<FlashStats> {
     if let Some(x) = &customization
         && !x.validate()
     {
@@ -218,6 +335,8 @@ pub async fn flash<R: Read + Send + 'static>(
     tracing::info!("Opening Destination");
     let dst_clone = dst.to_path_buf();
     let sd = crate::pal::open(&dst_clone).await?;
+    #[cfg(feature = "simulate_slow_device")]
+    let sd = crate::throttle::Throttled::from_env(sd);
 
     let mut tasks = tokio::task::JoinSet::new();
 
@@ -233,7 +352,16 @@ pub async fn flash<R: Read + Send + 'static>(
 
     let cancel_child = cancel.as_ref().map(|x| x.child_token());
     let res = tokio::task::spawn_blocking(move || {
-        flash_internal(img, img_size, bmap, sd, chan, customization, cancel_child)
+        flash_internal(
+            img,
+            img_size,
+            bmap,
+            sd,
+            chan,
+            customization,
+            options,
+            cancel_child,
+        )
     })
     .await
     .unwrap();
@@ -251,23 +379,261 @@ pub async fn flash<R: Read + Send + 'static>(
     res
 }
 
+/// Only writes the selected partitions from image to the partitions with the same number on SD
+/// Card. The partition table and all other partitions on SD Card are not touched.
+fn write_partitions(
+    mut img: impl Read,
+    mut sd: impl Read + Write + Seek + std::fmt::Debug,
+    partitions: &[u32],
+    mut chan: Option<&mut mpsc::Sender<Status>>,
+    cancel: Option<tokio_util::sync::CancellationToken>,
+) -> Result<()> {
+    let mut header = vec![0u8; IMG_HEADER_LEN];
+    let count = read_aligned(&mut img, &mut header)?;
+    header.truncate(count);
+
+    let src_parts = crate::partition::partitions(std::io::Cursor::new(header.as_mut_slice()))?;
+    let dst_parts = crate::partition::partitions(&mut sd)?;
+
+    let mut selected = partitions
+        .iter()
+        .map(|n| {
+            let src = src_parts
+                .iter()
+                .find(|p| p.number == *n)
+                .ok_or(crate::Error::PartitionNotFound(*n))?;
+            let dst = dst_parts
+                .iter()
+                .find(|p| p.number == *n)
+                .ok_or(crate::Error::PartitionNotFound(*n))?;
+
+            if src.size > dst.size {
+                return Err(crate::Error::PartitionTooSmall(*n));
+            }
+
+            Ok((*src, *dst))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // Image is a stream, so partitions need to be written in order.
+    selected.sort_by_key(|(src, _)| src.start);
+
+    let total: u64 = selected.iter().map(|(src, _)| src.size).sum();
+    let mut img = std::io::Cursor::new(header).chain(img);
+    let mut buf = Box::new(DirectIoBuffer::<BUFFER_SIZE>::new());
+    let mut pos = 0;
+    let mut bytes_written = 0;
+
+    for (src, dst) in selected {
+        let skip = src
+            .start
+            .checked_sub(pos)
+            .ok_or(crate::Error::InvalidPartitionTable)?;
+        std::io::copy(&mut (&mut img).take(skip), &mut std::io::sink())?;
+
+        sd.seek(SeekFrom::Start(dst.start))?;
+
+        let mut part = (&mut img).take(src.size);
+        loop {
+            let count = read_aligned(&mut part, buf.as_mut_slice())?;
+            if count == 0 {
+                break;
+            }
+
+            sd.write_all(&buf.as_slice()[..count])?;
+
+            bytes_written += count as u64;
+            chan_send(
+                chan.as_deref_mut(),
+                Status::Flashing(progress(bytes_written, total)),
+            );
+            check_token(cancel.as_ref())?;
+        }
+
+        pos = src.end();
+    }
+
+    sd.flush().map_err(Into::into)
+}
+
+/// Flash only some partitions of OS image to SD card. Useful to reflash boot or rootfs without
+/// touching any data partitions.
+///
+/// Partitions are matched by their number, and the partition on SD Card should be at least as
+/// large as the one in image. The partition table on SD Card is not modified.
+///
+/// See [flash] for details regarding other arguments.
+pub async fn flash_partitions<R: Read + Send + 'static>(
+    img: impl bb_helper::resolvable::Resolvable<ResolvedType = (R, u64)>,
+    dst: Box<Path>,
+    partitions: Box<[u32]>,
+    chan: Option<mpsc::Sender<Status>>,
+    customization: Option<Customization>,
+    eject: bool,
+    cancel: Option<tokio_util::sync::CancellationToken>,
+) -> Result<()> {
+    if let Some(x) = &customization
+        && !x.validate()
+    {
+        return Err(crate::Error::InvalidCustomizaton);
+    }
+
+    tracing::info!("Opening Destination");
+    let dst_clone = dst.to_path_buf();
+    let sd = crate::pal::open(&dst_clone).await?;
+    #[cfg(feature = "simulate_slow_device")]
+    let sd = crate::throttle::Throttled::from_env(sd);
+
+    let mut tasks = tokio::task::JoinSet::new();
+
+    tracing::info!("Resolving Image");
+    let (img, _) = img.resolve(&mut tasks).await?;
+
+    let cancel_child = cancel.as_ref().map(|x| x.child_token());
+    let res = tokio::task::spawn_blocking(move || {
+        flash_partitions_internal(
+            img,
+            sd,
+            &partitions,
+            chan,
+            customization,
+            eject,
+            cancel_child,
+        )
+    })
+    .await
+    .unwrap();
+
+    // Cancel all tasks on drop
+    let _drop_guard = cancel.map(|x| x.drop_guard());
+
+    while let Some(t) = tasks.join_next().await {
+        if let Err(e) = t.unwrap() {
+            tasks.abort_all();
+            return Err(e.into());
+        }
+    }
+
+    res
+}
+
+fn flash_partitions_internal(
+    img: impl Read,
+    mut sd: impl Read + Write + Seek + Eject + std::fmt::Debug,
+    partitions: &[u32],
+    mut chan: Option<mpsc::Sender<Status>>,
+    customization: Option<Customization>,
+    eject: bool,
+    cancel: Option<tokio_util::sync::CancellationToken>,
+) -> Result<()> {
+    chan_send(chan.as_mut(), Status::Preparing);
+
+    tracing::info!("Writing partitions {:?} to SD Card", partitions);
+    write_partitions(img, &mut sd, partitions, chan.as_mut(), cancel.clone())?;
+
+    check_token(cancel.as_ref())?;
+
+    tracing::info!("Applying customization");
+    if let Some(c) = customization {
+        let temp = crate::helpers::DeviceWrapper::new(&mut sd).unwrap();
+        c.customize(temp)?;
+    }
+
+    if eject {
+        tracing::info!("Ejecting SD Card");
+        if let Err(e) = sd.eject() {
+            tracing::warn!("Failed to eject SD Card: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Zero the start and end of SD Card, which contain MBR, GPT and backup GPT.
+fn clear_partition_table(mut sd: impl Write + Seek) -> Result<()> {
+    let buf = Box::new(DirectIoBuffer::<BUFFER_SIZE>::new());
+    let len = sd.seek(SeekFrom::End(0))?;
+    let region = IMG_HEADER_LEN as u64;
+
+    // Clear whole SD Card if the regions overlap
+    let ranges = if len <= 2 * region {
+        [0..len, len..len]
+    } else {
+        [0..region, (len - region)..len]
+    };
+
+    for r in ranges {
+        sd.seek(SeekFrom::Start(r.start))?;
+
+        let mut pos = r.start;
+        while pos < r.end {
+            let count = std::cmp::min(r.end - pos, BUFFER_SIZE as u64) as usize;
+            sd.write_all(&buf.as_slice()[..count])?;
+            pos += count as u64;
+        }
+    }
+
+    sd.flush().map_err(Into::into)
+}
+
+#[allow(clippy::too_many_arguments)]
 fn flash_internal(
     img: impl Read + Send,
     img_size: u64,
     bmap: Option<bb_bmap_parser::Bmap>,
-    sd: impl Read + Write + Seek + Eject + std::fmt::Debug,
-    mut chan: Option<mpsc::Sender<f32>>,
+    mut sd: impl Read + Write + Seek + Eject + std::fmt::Debug,
+    mut chan: Option<mpsc::Sender<Status>>,
     customization: Option<Customization>,
+    options: FlashOptions,
     cancel: Option<tokio_util::sync::CancellationToken>,
-) -> Result<()> {
-    chan_send(chan.as_mut(), 0.0);
+) -> Result<FlashStats> {
+    let FlashOptions {
+        verify,
+        marker,
+        skip_identical,
+        format,
+        eject,
+    } = options;
+    let start = Instant::now();
+    chan_send(chan.as_mut(), Status::Preparing);
+
+    // Size of some devices cannot be found by seeking, in which case writing fails later
+    let device = sd.seek(SeekFrom::End(0)).unwrap_or(0);
+    let image = required_size(img_size, bmap.as_ref());
+    if device != 0 && image > device {
+        return Err(crate::Error::ImageTooLarge { image, device });
+    }
+    sd.seek(SeekFrom::Start(0))?;
 
-    let mut sd = crate::helpers::SdCardWrapper::new(sd);
+    let mut sd = crate::helpers::SdCardWrapper::new(sd)?;
 
-    tracing::info!("Writing to SD Card");
-    write_sd(img, img_size, bmap, &mut sd, chan.as_mut(), cancel.clone())?;
+    if format {
+        tracing::info!("Clearing partition table");
+        clear_partition_table(&mut sd)?;
+    }
 
-    check_token(cancel.as_ref())?;
+    let (flashed, bytes_written, written) = write_changed(
+        img,
+        img_size,
+        bmap,
+        &mut sd,
+        chan.as_mut(),
+        verify,
+        skip_identical,
+        cancel.as_ref(),
+    )?;
+    let verified = flashed == Flashed::UpToDate || verify.is_some();
+
+    // First block needs to be on SD Card to be read back. SD Card is wrapped again, so that it
+    // is written last once more after customization.
+    if let Some(w) = written {
+        let mut raw = sd.into_inner()?;
+
+        tracing::info!("Verifying SD Card");
+        chan_send(chan.as_mut(), Status::Verifying);
+        w.verify(&mut raw, cancel.as_ref())?;
+
+        sd = crate::helpers::SdCardWrapper::new(raw)?;
+    }
 
     tracing::info!("Applying customization");
     if let Some(c) = customization {
@@ -275,10 +641,96 @@ fn flash_internal(
         c.customize(temp)?;
     }
 
-    tracing::info!("Ejecting SD Card");
-    let _ = sd.eject();
+    if let Some(m) = marker {
+        tracing::info!("Writing provisioning marker {}", m.id());
+        let temp = crate::helpers::DeviceWrapper::new(&mut sd).unwrap();
+        m.write(temp)?;
+    }
 
-    Ok(())
+    let sd = sd.into_inner()?;
+
+    if eject {
+        tracing::info!("Ejecting SD Card");
+        if let Err(e) = sd.eject() {
+            tracing::warn!("Failed to eject SD Card: {e}");
+        }
+    }
+
+    Ok(FlashStats {
+        flashed,
+        bytes_written,
+        duration: start.elapsed(),
+        verified,
+    })
+}
+
+/// Bytes of SD Card written by image. With a bmap, data after the last mapped range is not
+/// written.
+fn required_size(img_size: u64, bmap: Option<&bb_bmap_parser::Bmap>) -> u64 {
+    bmap.and_then(|x| x.block_map().map(|b| b.offset() + b.length()).max())
+        .map_or(img_size, |x| x.min(img_size))
+}
+
+/// Write image. If `skip_identical` is set, data already present on SD Card is not written again.
+/// Returns the outcome along with the number of bytes written, and the data to verify if `verify`
+/// is set.
+#[allow(clippy::too_many_arguments)]
+fn write_changed(
+    mut img: impl Read + Send,
+    img_size: u64,
+    bmap: Option<bb_bmap_parser::Bmap>,
+    mut sd: impl Read + Write + Seek,
+    mut chan: Option<&mut mpsc::Sender<Status>>,
+    verify: Option<Verify>,
+    skip_identical: bool,
+    cancel: Option<&tokio_util::sync::CancellationToken>,
+) -> Result<(Flashed, u64, Option<Written>)> {
+    let (pending, start) = if skip_identical {
+        let mapped = bmap.as_ref().map(|x| {
+            x.block_map()
+                .map(|b| b.offset()..(b.offset() + b.length()))
+                .collect::<Vec<_>>()
+        });
+
+        tracing::info!("Comparing SD Card with image");
+        match compare(
+            &mut img,
+            &mut sd,
+            img_size,
+            mapped.as_deref(),
+            chan.as_deref_mut(),
+            cancel,
+        )? {
+            Compared::Identical => {
+                tracing::info!("SD Card already up to date");
+                return Ok((Flashed::UpToDate, 0, None));
+            }
+            Compared::Differs { offset, pending } => {
+                tracing::info!("SD Card differs from image at offset {offset}");
+                (pending, offset)
+            }
+        }
+    } else {
+        (Vec::new(), 0)
+    };
+
+    let mut written = verify.map(Written::new);
+
+    tracing::info!("Writing to SD Card");
+    let bytes_written = write_sd(
+        std::io::Cursor::new(pending).chain(img),
+        img_size,
+        start,
+        bmap,
+        &mut sd,
+        chan,
+        written.as_mut(),
+        cancel.cloned(),
+    )?;
+
+    check_token(cancel)?;
+
+    Ok((Flashed::Written, bytes_written, written))
 }
 
 #[cfg(test)]
@@ -305,10 +757,12 @@ mod tests {
         write_sd(
             dummy_file.clone(),
             FILE_LEN as u64,
+            0,
             None,
             &mut sd,
             None,
             None,
+            None,
         )
         .unwrap();
 
@@ -333,10 +787,14 @@ mod tests {
             .checksum_type(bb_bmap_parser::HashType::Sha256);
 
         for i in MAPPED_BLOCKS {
+            let start = (i * BLOCK_LEN) as usize;
+            let data = &dummy_file.get_ref()[start..(start + BLOCK_LEN as usize)];
             bmap.add_block_range(
                 *i,
                 *i,
-                bb_bmap_parser::HashValue::Sha256(Default::default()),
+                bb_bmap_parser::HashValue::Sha256(
+                    <sha2::Sha256 as sha2::Digest>::digest(data).into(),
+                ),
             );
         }
 
@@ -345,10 +803,12 @@ mod tests {
         write_sd(
             dummy_file.clone(),
             FILE_LEN as u64,
+            0,
             Some(bmap.clone()),
             &mut sd,
             None,
             None,
+            None,
         )
         .unwrap();
 
@@ -369,6 +829,514 @@ mod tests {
         }
     }
 
+    #[test]
+    fn sd_write_generated_bmap() {
+        const FILE_LEN: usize = 64 * 1024;
+        const BLOCK_LEN: usize = 4096;
+        const EMPTY_BLOCKS: &[usize] = &[1, 2, 5, 15];
+
+        let mut data = test_file(FILE_LEN).into_inner();
+        for i in EMPTY_BLOCKS {
+            data[(i * BLOCK_LEN)..((i + 1) * BLOCK_LEN)].fill(0);
+        }
+        let dummy_file = std::io::Cursor::new(data);
+
+        let xml = crate::generate_bmap(dummy_file.clone()).unwrap();
+        let bmap = bb_bmap_parser::Bmap::from_xml(&xml).unwrap();
+
+        assert_eq!(
+            bmap.total_mapped_size(),
+            ((FILE_LEN / BLOCK_LEN - EMPTY_BLOCKS.len()) * BLOCK_LEN) as u64
+        );
+
+        let mut sd = std::io::Cursor::new(vec![0u8; FILE_LEN]);
+        write_sd(
+            dummy_file.clone(),
+            FILE_LEN as u64,
+            0,
+            Some(bmap),
+            &mut sd,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(sd.get_ref().as_slice(), dummy_file.get_ref().as_ref());
+
+        // Corruption in a mapped range
+        let mut data = dummy_file.into_inner();
+        data[10 * BLOCK_LEN + 7] ^= 0x01;
+        let res = write_sd(
+            std::io::Cursor::new(data),
+            FILE_LEN as u64,
+            0,
+            Some(bb_bmap_parser::Bmap::from_xml(&xml).unwrap()),
+            std::io::Cursor::new(vec![0u8; FILE_LEN]),
+            None,
+            None,
+            None,
+        );
+        assert!(matches!(res, Err(crate::Error::InvalidBmap)));
+    }
+
+    #[test]
+    fn sd_write_generated_bmap_unaligned() {
+        const FILE_LEN: usize = 16 * 1024 + 1000;
+
+        let dummy_file = test_file(FILE_LEN);
+
+        let xml = crate::generate_bmap(dummy_file.clone()).unwrap();
+        let bmap = bb_bmap_parser::Bmap::from_xml(&xml).unwrap();
+        assert_eq!(bmap.image_size(), FILE_LEN as u64);
+        assert_eq!(bmap.blocks(), 5);
+
+        let mut sd = std::io::Cursor::new(vec![0u8; 20 * 1024]);
+        write_sd(
+            dummy_file.clone(),
+            FILE_LEN as u64,
+            0,
+            Some(bmap),
+            &mut sd,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(&sd.get_ref()[..FILE_LEN], dummy_file.get_ref().as_ref());
+    }
+
+    /// SD Card which counts the bytes written to it.
+    #[derive(Debug)]
+    struct CountingSd {
+        inner: std::io::Cursor<Vec<u8>>,
+        written: usize,
+    }
+
+    impl std::io::Read for CountingSd {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl std::io::Write for CountingSd {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let count = self.inner.write(buf)?;
+            self.written += count;
+            Ok(count)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    impl std::io::Seek for CountingSd {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    impl crate::helpers::Eject for &mut CountingSd {
+        fn eject(self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn skip_identical() {
+        const FILE_LEN: usize = 4 * BUFFER_SIZE;
+        const CHANGED: usize = 2 * BUFFER_SIZE + 10;
+
+        let img = test_file(FILE_LEN);
+
+        let mut sd = CountingSd {
+            inner: std::io::Cursor::new(img.get_ref().to_vec()),
+            written: 0,
+        };
+        let res = super::write_changed(
+            img.clone(),
+            FILE_LEN as u64,
+            None,
+            &mut sd,
+            None,
+            Some(crate::Verify::Crc32),
+            true,
+            None,
+        )
+        .unwrap();
+        assert_eq!((res.0, res.1), (super::Flashed::UpToDate, 0));
+        assert!(res.2.is_none());
+        assert_eq!(sd.written, 0);
+
+        let mut data = img.get_ref().to_vec();
+        data[CHANGED] ^= 0x01;
+        let mut sd = CountingSd {
+            inner: std::io::Cursor::new(data),
+            written: 0,
+        };
+        let res = super::write_changed(
+            img.clone(),
+            FILE_LEN as u64,
+            None,
+            &mut sd,
+            None,
+            Some(crate::Verify::Crc32),
+            true,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            (res.0, res.1),
+            (super::Flashed::Written, (FILE_LEN - 2 * BUFFER_SIZE) as u64)
+        );
+        assert_eq!(sd.written, FILE_LEN - 2 * BUFFER_SIZE);
+        assert_eq!(sd.inner.get_ref().as_slice(), img.get_ref().as_ref());
+    }
+
+    #[test]
+    fn skip_identical_flash() {
+        const FILE_LEN: usize = 4 * BUFFER_SIZE;
+
+        // Test file starts with non-zero data, like a partition table
+        let img = test_file(FILE_LEN);
+        assert!(img.get_ref()[1..255].iter().all(|x| *x != 0));
+
+        let mut sd = CountingSd {
+            inner: std::io::Cursor::new(img.get_ref().to_vec()),
+            written: 0,
+        };
+        let res = super::flash_internal(
+            img.clone(),
+            FILE_LEN as u64,
+            None,
+            &mut sd,
+            None,
+            None,
+            super::FlashOptions {
+                verify: Some(crate::Verify::Crc32),
+                skip_identical: true,
+                ..Default::default()
+            },
+            None,
+        )
+        .unwrap();
+        assert_eq!(res.flashed, super::Flashed::UpToDate);
+        assert_eq!(res.bytes_written, 0);
+        assert_eq!(sd.written, 0);
+    }
+
+    #[test]
+    fn verify_status() {
+        const FILE_LEN: usize = 4 * BUFFER_SIZE;
+
+        let img = test_file(FILE_LEN);
+        let mut sd = std::io::Cursor::new(vec![0u8; FILE_LEN]);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+
+        super::flash_internal(
+            img.clone(),
+            FILE_LEN as u64,
+            None,
+            &mut sd,
+            Some(tx),
+            None,
+            super::FlashOptions {
+                verify: Some(crate::Verify::Sha256),
+                ..Default::default()
+            },
+            None,
+        )
+        .unwrap();
+
+        let mut status = Vec::new();
+        while let Ok(x) = rx.try_recv() {
+            status.push(x);
+        }
+        assert_eq!(status.last(), Some(&crate::Status::Verifying));
+        assert_eq!(status[status.len() - 2], crate::Status::Flashing(1.0));
+    }
+
+    /// SD Card which silently drops all writes to the first sector.
+    #[derive(Debug)]
+    struct BadFirstSector(std::io::Cursor<Vec<u8>>);
+
+    impl std::io::Read for BadFirstSector {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl std::io::Write for BadFirstSector {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let pos = self.0.position() as usize;
+            let count = self.0.write(buf)?;
+            if pos < 512 {
+                let end = std::cmp::min(pos + count, 512);
+                self.0.get_mut()[pos..end].fill(0);
+            }
+            Ok(count)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl std::io::Seek for BadFirstSector {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    impl crate::helpers::Eject for BadFirstSector {
+        fn eject(self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn verify_first_block() {
+        const FILE_LEN: usize = 4 * BUFFER_SIZE;
+
+        let img = test_file(FILE_LEN);
+        let res = super::flash_internal(
+            img,
+            FILE_LEN as u64,
+            None,
+            BadFirstSector(std::io::Cursor::new(vec![0u8; FILE_LEN])),
+            None,
+            None,
+            super::FlashOptions {
+                verify: Some(crate::Verify::Crc32),
+                ..Default::default()
+            },
+            None,
+        );
+        assert!(matches!(res, Err(crate::Error::VerificationFailed)));
+    }
+
+    fn mbr(parts: &[(u32, u32)]) -> [u8; 512] {
+        let mut mbr = [0u8; 512];
+
+        for (i, (start, sectors)) in parts.iter().enumerate() {
+            let entry = &mut mbr[(446 + i * 16)..(446 + (i + 1) * 16)];
+            // Linux partition
+            entry[4] = 0x83;
+            entry[8..12].copy_from_slice(&start.to_le_bytes());
+            entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+        }
+
+        mbr[510] = 0x55;
+        mbr[511] = 0xaa;
+        mbr
+    }
+
+    #[test]
+    fn sd_write_partitions() {
+        const PARTS: &[(u32, u32)] = &[(8, 16), (24, 16)];
+        const FILE_LEN: usize = 40 * 512;
+        const PART_1: std::ops::Range<usize> = (8 * 512)..(24 * 512);
+        const PART_2: std::ops::Range<usize> = (24 * 512)..(40 * 512);
+
+        let mut img = test_file(FILE_LEN).into_inner();
+        img[..512].copy_from_slice(&mbr(PARTS));
+
+        let mut sd = vec![0u8; FILE_LEN];
+        sd[..512].copy_from_slice(&mbr(PARTS));
+        sd[PART_2].fill(0xaa);
+        let mut sd = std::io::Cursor::new(sd);
+
+        super::write_partitions(img.as_ref(), &mut sd, &[1], None, None).unwrap();
+
+        let sd = sd.into_inner();
+        assert_eq!(sd[..512], img[..512]);
+        assert!(sd[512..PART_1.start].iter().all(|x| *x == 0));
+        assert_eq!(sd[PART_1], img[PART_1]);
+        assert!(sd[PART_2].iter().all(|x| *x == 0xaa));
+    }
+
+    thread_local! {
+        static EJECTED: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    impl crate::helpers::Eject for &mut std::io::Cursor<Vec<u8>> {
+        fn eject(self) -> std::io::Result<()> {
+            EJECTED.set(EJECTED.get() + 1);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn format_before_flash() {
+        const FILE_LEN: usize = 12 * 1024;
+        const SD_LEN: usize = 4 * super::IMG_HEADER_LEN;
+
+        let img = test_file(FILE_LEN);
+
+        let mut sd = vec![0xaau8; SD_LEN];
+        sd[..512].copy_from_slice(&mbr(&[(2048, 4096)]));
+        // Backup GPT header
+        sd[(SD_LEN - 512)..(SD_LEN - 504)].copy_from_slice(b"EFI PART");
+        let mut sd = std::io::Cursor::new(sd);
+
+        let res = super::flash_internal(
+            img.clone(),
+            FILE_LEN as u64,
+            None,
+            &mut sd,
+            None,
+            None,
+            super::FlashOptions {
+                format: true,
+                ..Default::default()
+            },
+            None,
+        )
+        .unwrap();
+        assert_eq!(res.flashed, super::Flashed::Written);
+        assert_eq!(res.bytes_written, FILE_LEN as u64);
+        assert!(!res.verified);
+
+        let sd = sd.into_inner();
+        assert_eq!(sd[..FILE_LEN], img.get_ref()[..]);
+        assert!(sd[FILE_LEN..super::IMG_HEADER_LEN].iter().all(|x| *x == 0));
+        // Only partition table regions are cleared
+        assert!(
+            sd[super::IMG_HEADER_LEN..(SD_LEN - super::IMG_HEADER_LEN)]
+                .iter()
+                .all(|x| *x == 0xaa)
+        );
+        assert!(
+            sd[(SD_LEN - super::IMG_HEADER_LEN)..]
+                .iter()
+                .all(|x| *x == 0)
+        );
+    }
+
+    #[test]
+    fn eject_after_flash() {
+        const FILE_LEN: usize = 12 * 1024;
+
+        let img = test_file(FILE_LEN);
+        let mut sd = std::io::Cursor::new(vec![0u8; FILE_LEN]);
+        let mut flash = |eject: bool, cancel: Option<tokio_util::sync::CancellationToken>| {
+            super::flash_internal(
+                img.clone(),
+                FILE_LEN as u64,
+                None,
+                &mut sd,
+                None,
+                None,
+                super::FlashOptions {
+                    eject,
+                    ..Default::default()
+                },
+                cancel,
+            )
+        };
+
+        EJECTED.set(0);
+        flash(false, None).unwrap();
+        assert_eq!(EJECTED.get(), 0);
+
+        flash(true, None).unwrap();
+        assert_eq!(EJECTED.get(), 1);
+
+        // Not ejected if flashing fails
+        let cancel = tokio_util::sync::CancellationToken::new();
+        cancel.cancel();
+        assert!(flash(true, Some(cancel)).is_err());
+        assert_eq!(EJECTED.get(), 1);
+    }
+
+    #[test]
+    fn image_too_large() {
+        const FILE_LEN: usize = 12 * 1024;
+
+        let img = test_file(FILE_LEN);
+        let mut sd = std::io::Cursor::new(vec![0xaau8; FILE_LEN / 2]);
+
+        let res = super::flash_internal(
+            img,
+            FILE_LEN as u64,
+            None,
+            &mut sd,
+            None,
+            None,
+            super::FlashOptions {
+                format: true,
+                ..Default::default()
+            },
+            None,
+        );
+        assert!(matches!(
+            res,
+            Err(crate::Error::ImageTooLarge {
+                image: 12288,
+                device: 6144
+            })
+        ));
+        // Nothing is written, not even when formatting
+        assert!(sd.get_ref().iter().all(|x| *x == 0xaa));
+    }
+
+    #[derive(Debug)]
+    struct EjectFails(std::io::Cursor<Vec<u8>>);
+
+    impl std::io::Read for EjectFails {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl std::io::Write for EjectFails {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl std::io::Seek for EjectFails {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    impl crate::helpers::Eject for EjectFails {
+        fn eject(self) -> std::io::Result<()> {
+            Err(std::io::Error::other("Device busy"))
+        }
+    }
+
+    #[test]
+    fn eject_failure_is_not_fatal() {
+        const FILE_LEN: usize = 12 * 1024;
+
+        let img = test_file(FILE_LEN);
+        let res = super::flash_internal(
+            img,
+            FILE_LEN as u64,
+            None,
+            EjectFails(std::io::Cursor::new(vec![0u8; FILE_LEN])),
+            None,
+            None,
+            super::FlashOptions {
+                verify: Some(crate::Verify::Crc32),
+                eject: true,
+                ..Default::default()
+            },
+            None,
+        );
+        let res = res.unwrap();
+        assert_eq!(res.flashed, super::Flashed::Written);
+        assert!(res.verified);
+    }
+
     struct UnalignedReader(std::io::Cursor<Box<[u8]>>);
 
     impl UnalignedReader {
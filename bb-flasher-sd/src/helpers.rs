@@ -2,9 +2,9 @@ use std::io;
 
 use tokio::sync::mpsc;
 
-use crate::Result;
+use crate::{Result, Status};
 
-pub(crate) fn chan_send(chan: Option<&mut mpsc::Sender<f32>>, msg: f32) {
+pub(crate) fn chan_send(chan: Option<&mut mpsc::Sender<Status>>, msg: Status) {
     if let Some(c) = chan {
         let _ = c.try_send(msg);
     }
@@ -169,36 +169,59 @@ pub(crate) struct SdCardWrapper<W> {
     inner: W,
     buf: Box<DirectIoBuffer<BLOCK_SIZE>>,
     pos: u64,
+    /// First block was written to, and needs to be written to SD Card.
+    dirty: bool,
 }
 
 impl<W> SdCardWrapper<W>
 where
     W: io::Read + io::Write + io::Seek,
 {
-    pub(crate) fn new(inner: W) -> Self {
-        Self {
+    /// The first block is read from SD Card, so that reading it back before writing returns the
+    /// actual contents of SD Card.
+    pub(crate) fn new(mut inner: W) -> io::Result<Self> {
+        let mut buf = Box::new(DirectIoBuffer::new());
+
+        inner.seek(io::SeekFrom::Start(0))?;
+        // SD Card can be smaller than a block. Rest of the buffer stays zeroed.
+        let mut pos = 0;
+        while pos < buf.len() {
+            match inner.read(&mut buf.as_mut_slice()[pos..]) {
+                Ok(0) => break,
+                Ok(count) => pos += count,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        inner.seek(io::SeekFrom::Start(0))?;
+
+        Ok(Self {
             inner,
-            buf: Box::new(DirectIoBuffer::new()),
+            buf,
             pos: 0,
-        }
+            dirty: false,
+        })
     }
 
     fn finish(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
         self.inner.seek(io::SeekFrom::Start(0))?;
         self.inner.write_all(self.buf.as_slice())?;
         self.pos = u64::try_from(self.buf.len()).unwrap();
 
         Ok(())
     }
-}
 
-impl<W> Eject for SdCardWrapper<W>
-where
-    W: io::Read + io::Write + io::Seek + Eject,
-{
-    fn eject(mut self) -> io::Result<()> {
+    /// Write the first block and return the inner device. Should always be called once writing
+    /// is done, else SD Card is left without a partition table.
+    pub(crate) fn into_inner(mut self) -> io::Result<W> {
         self.finish()?;
-        self.inner.eject()
+        self.inner.flush()?;
+
+        Ok(self.inner)
     }
 }
 
@@ -236,6 +259,7 @@ where
             self.inner
                 .seek(io::SeekFrom::Current(i64::try_from(count).unwrap()))?;
             self.buf.as_mut_slice()[pos..(pos + count)].copy_from_slice(&buf[..count]);
+            self.dirty = true;
             count
         } else {
             self.inner.write(buf)?
@@ -331,7 +355,7 @@ mod tests {
     fn sd_card_wrapper() {
         let mut test_data = test_data();
         let mut temp_buf = vec![0; FILE_LEN].into_boxed_slice();
-        let mut sd = SdCardWrapper::new(std::io::Cursor::new(temp_buf.clone()));
+        let mut sd = SdCardWrapper::new(std::io::Cursor::new(temp_buf.clone())).unwrap();
 
         std::io::copy(&mut test_data, &mut sd).unwrap();
 
@@ -352,4 +376,19 @@ mod tests {
         sd.finish().unwrap();
         assert_eq!(test_data.get_ref(), sd.inner.get_ref());
     }
+
+    #[test]
+    fn sd_card_wrapper_first_block() {
+        let mut sd = SdCardWrapper::new(test_data()).unwrap();
+        let mut buf = [0u8; BLOCK_SIZE];
+
+        // First block is read from SD Card, not left zeroed
+        sd.read_exact(&mut buf).unwrap();
+        assert_eq!(buf.as_slice(), &test_data().get_ref()[..BLOCK_SIZE]);
+
+        // SD Card smaller than a block
+        let sd = SdCardWrapper::new(std::io::Cursor::new(vec![1u8; 512])).unwrap();
+        assert!(sd.buf.as_slice()[..512].iter().all(|x| *x == 1));
+        assert!(sd.buf.as_slice()[512..].iter().all(|x| *x == 0));
+    }
 }
@@ -1,7 +1,8 @@
 //! Library to flash SD cards with OS images. Powers sd card flashing in [BeagleBoard Imager].
 //!
-//! Also allows optional extra [Customization] for BeagleBoard images. Currently only supports
-//! sysconf based post-install configuration.
+//! Also allows optional extra [Customization] for BeagleBoard images. Supports sysconf based
+//! post-install configuration for BeagleBoard images, `custom.toml` for Raspberry Pi OS images and
+//! `armbian_first_run.txt` for Armbian images.
 //!
 //! # Platform Support
 //!
@@ -13,26 +14,37 @@
 //!
 //! - `udev`: Dynamic permissions on Linux. Mostly useful for GUI and flatpaks
 //! - `macos_authopen`: Dynamic permissions on MacOS.
+//! - `macos_eject`: Unmount and eject using DiskArbitration instead of `diskutil` on MacOS.
+//! - `simulate_slow_device`: Limit write speed to `BB_SIMULATE_SLOW_DEVICE` bytes/sec. Only
+//!   meant for testing progress reporting in applications.
 //!
 //! # Usage
 //!
 //! ```no_run
 //! use std::path::PathBuf;
-//! use std::fs::File;
 //!
-//! #[tokio::main]
-//! async fn main() {
+//! use bb_helper::resolvable::{LocalFile, LocalStringFile};
+//!
+//! async fn flash() {
 //!     let dst = PathBuf::from("/tmp/dummy").into();
-//!     let img = bb_helper::resolvable::LocalFile::new(PathBuf::from("/tmp/image").into());
+//!     let img = LocalFile::new(PathBuf::from("/tmp/image").into());
 //!     let (tx, mut rx) = tokio::sync::mpsc::channel(20);
+//!     let options = bb_flasher_sd::FlashOptions {
+//!         verify: Some(bb_flasher_sd::Verify::Sha256),
+//!         eject: true,
+//!         ..Default::default()
+//!     };
 //!
-//!     let flash_thread = tokio::spawn(async move { bb_flasher_sd::flash(img, None::<bb_helper::resolvable::LocalStringFile>, dst, Some(tx), None, None).await });
+//!     let flash_thread = tokio::spawn(async move {
+//!         bb_flasher_sd::flash(img, None::<LocalStringFile>, dst, Some(tx), None, options, None)
+//!             .await
+//!     });
 //!
 //!     while let Some(m) = rx.recv().await {
 //!         println!("{:?}", m);
 //!     }
 //!
-//!     flash_thread.await.unwrap().unwrap()
+//!     flash_thread.await.unwrap().unwrap();
 //! }
 //! ```
 //!
@@ -42,13 +54,34 @@ use std::{io, path::PathBuf};
 
 use thiserror::Error;
 
+mod benchmark;
+mod bmap;
+mod capacity;
 pub(crate) mod customization;
 mod flashing;
 mod helpers;
+mod marker;
 pub(crate) mod pal;
+mod partition;
+mod rpi_imager;
+#[cfg(feature = "simulate_slow_device")]
+mod throttle;
+mod verify;
+mod vm_disk;
 
-pub use customization::{Customization, SysconfCustomization};
-pub use flashing::flash;
+pub use benchmark::{BenchmarkReport, benchmark};
+pub use bmap::generate_bmap;
+pub use capacity::{CapacityReport, check_capacity};
+pub use customization::{
+    ArmbianCustomization, CloudInitCustomization, Customization, RaspberryCustomization,
+    SysconfCustomization,
+};
+pub use flashing::{FlashOptions, FlashStats, Flashed, flash, flash_partitions};
+pub use marker::{MARKER_FILE, ProvisioningMarker};
+pub use partition::{Partition, partitions, partitions_with_sector_size};
+pub use rpi_imager::RpiImagerSettings;
+pub use verify::Verify;
+pub use vm_disk::flash_vm_disk;
 
 pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -63,6 +96,9 @@ pub enum Error {
     InvalidPartitionTable,
     #[error("Only FAT BOOT partitions are supported.")]
     InvalidBootPartition,
+    /// BOOT partition uses a file system other than FAT, which cannot be customized yet.
+    #[error("BOOT partition uses {0}, only FAT is supported for customization.")]
+    UnsupportedBootPartition(&'static str),
     #[error("Failed to create sysconf.txt")]
     SysconfCreateFail {
         #[source]
@@ -74,6 +110,27 @@ pub enum Error {
         source: io::Error,
         field: &'static str,
     },
+    #[error("Failed to write custom.toml.")]
+    CustomTomlWriteFail {
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to write cloud-init {file}.")]
+    CloudInitWriteFail {
+        #[source]
+        source: io::Error,
+        file: &'static str,
+    },
+    #[error("Failed to write armbian_first_run.txt.")]
+    ArmbianWriteFail {
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to write provisioning marker.")]
+    MarkerWriteFail {
+        #[source]
+        source: io::Error,
+    },
     #[error("Failed to setup WiFi.")]
     WifiSetupFail {
         #[source]
@@ -101,41 +158,185 @@ pub enum Error {
     },
     #[error("Invalid bmap for the image.")]
     InvalidBmap,
+    /// SD Card is too small for the image. With a bmap, `image` is the end of the last mapped
+    /// range.
+    #[error(
+        "Image ({}) is larger than SD Card ({}).",
+        bb_helper::size::SizeUnit::Binary.format(*.image),
+        bb_helper::size::SizeUnit::Binary.format(*.device)
+    )]
+    ImageTooLarge { image: u64, device: u64 },
     #[error("Writer thread has been closed.")]
     WriterClosed,
+    /// SD Card contents do not match the image after flashing.
+    #[error("Verification failed. SD Card contents do not match the image.")]
+    VerificationFailed,
+    /// Requested partition is not present in image or SD Card.
+    #[error("Partition {0} not found in image or SD Card.")]
+    PartitionNotFound(u32),
+    /// Partition on SD Card cannot hold the partition from image.
+    #[error("Partition {0} on SD Card is smaller than in image.")]
+    PartitionTooSmall(u32),
+    /// Card reader or platform does not allow toggling write protection.
+    #[error("Write protection is not supported for this device.")]
+    WriteProtectUnsupported,
 
     #[cfg(windows)]
     #[error("Failed to clear SD Card.")]
     WindowsCleanError(std::process::Output),
+    /// Volume on SD Card cannot be locked since it is being used by other applications.
+    #[cfg(windows)]
+    #[error("SD Card is in use by {}. Close them and try again.", .0.join(", "))]
+    VolumeInUse(Vec<String>),
+    /// Partition on SD Card could not be unmounted before writing, usually since it is busy.
+    #[cfg(target_os = "linux")]
+    #[error(
+        "Failed to unmount {}. Close any applications using it and try again.",
+        .mountpoint.display()
+    )]
+    UnmountFailed {
+        mountpoint: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+/// Flashing status
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Preparing,
+    Flashing(f32),
+    Verifying,
+}
+
+/// Filter applied when enumerating SD Cards
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Filter {
+    /// List all drives
+    None,
+    /// List removable, non-virtual, non-system drives
+    Removable,
+    /// List actual SD/MMC cards, which are also [Filter::Removable]. Falls back to
+    /// [Filter::Removable] if no card is found.
+    #[default]
+    PreferCard,
+}
+
+/// `true` maps to the default filter, [Filter::PreferCard].
+impl From<bool> for Filter {
+    fn from(value: bool) -> Self {
+        if value { Self::default() } else { Self::None }
+    }
+}
+
+fn filter_drives(
+    drives: Vec<bb_drivelist::DeviceDescriptor>,
+    filter: Filter,
+) -> Vec<bb_drivelist::DeviceDescriptor> {
+    let removable =
+        |x: &bb_drivelist::DeviceDescriptor| x.is_removable && !x.is_virtual && !x.is_system;
+    // Boards booting from eMMC or SD Card report their system disk as card too
+    let card = |x: &bb_drivelist::DeviceDescriptor| x.is_card && removable(x);
+
+    match filter {
+        Filter::None => drives,
+        Filter::Removable => drives.into_iter().filter(removable).collect(),
+        Filter::PreferCard => {
+            if drives.iter().any(card) {
+                drives.into_iter().filter(card).collect()
+            } else {
+                drives.into_iter().filter(removable).collect()
+            }
+        }
+    }
+}
+
+/// Drive enumeration is shared by [devices_generation] and [devices_filtered], so that checking
+/// for changes and listing devices right after only enumerates once.
+static DRIVES: std::sync::Mutex<bb_drivelist::DriveListCache> = std::sync::Mutex::new(
+    bb_drivelist::DriveListCache::new(std::time::Duration::from_millis(500)),
+);
+
+/// Refresh the drive list if it has expired. If enumeration fails, the last list is kept.
+///
+/// This blocks while enumerating, so async callers should use a blocking thread.
+fn drives() -> std::sync::MutexGuard<'static, bb_drivelist::DriveListCache> {
+    let mut drives = DRIVES.lock().unwrap();
+    if let Err(e) = drives.refresh() {
+        tracing::error!("Failed to enumerate drives: {e}");
+    }
+    drives
+}
+
+/// Generation of the set of drives in system. Changes only when drives are added or removed, so
+/// [devices_filtered] needs to be called again only when this changes.
+pub fn devices_generation() -> u64 {
+    drives().generation()
 }
 
 /// Enumerate all SD Cards in system
 pub fn devices(filter: bool) -> std::collections::HashSet<Device> {
-    bb_drivelist::drive_list()
-        .expect("Unsupported OS for Sd Card")
+    devices_filtered(filter.into())
+}
+
+/// Enumerate all SD Cards in system using the provided [Filter]
+pub fn devices_filtered(filter: Filter) -> std::collections::HashSet<Device> {
+    let drives = drives().drives().to_vec();
+
+    filter_drives(drives, filter)
         .into_iter()
-        .filter(|x| {
-            if filter {
-                x.is_removable && !x.is_virtual
-            } else {
-                true
-            }
+        .map(|x| {
+            Device::new(
+                x.description,
+                x.raw.into(),
+                x.size.unwrap_or_default(),
+                x.serial,
+            )
         })
-        .map(|x| Device::new(x.description, x.raw.into(), x.size.unwrap_or_default()))
         .collect()
 }
 
-#[derive(Hash, Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, Clone)]
 /// SD Card
+///
+/// Equality and hashing consider the path, serial and size of the card. Name can jitter between
+/// enumerations of the same card, and is ignored. Size is included, since a different card inserted
+/// in the same reader keeps the path and serial of the reader.
 pub struct Device {
     pub name: String,
     pub path: PathBuf,
     pub size: u64,
+    /// Serial number or WWN. Unlike path, it does not change across re-enumerations.
+    pub serial: Option<String>,
 }
 
 impl Device {
-    const fn new(name: String, path: PathBuf, size: u64) -> Self {
-        Self { name, path, size }
+    const fn new(name: String, path: PathBuf, size: u64, serial: Option<String>) -> Self {
+        Self {
+            name,
+            path,
+            size,
+            serial,
+        }
+    }
+
+    /// Serial alone is not unique, since identical card readers can report the same serial.
+    fn identity(&self) -> (&PathBuf, Option<&str>, u64) {
+        (&self.path, self.serial.as_deref(), self.size)
+    }
+}
+
+impl PartialEq for Device {
+    fn eq(&self, other: &Self) -> bool {
+        self.identity() == other.identity()
+    }
+}
+
+impl Eq for Device {}
+
+impl std::hash::Hash for Device {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.identity().hash(state);
     }
 }
 
@@ -143,3 +344,121 @@ impl Device {
 pub async fn format(dst: &std::path::Path) -> Result<()> {
     crate::pal::format(dst).await
 }
+
+/// Write protect SD card to prevent accidental rewrites.
+///
+/// This is best-effort. Returns [Error::WriteProtectUnsupported] if the platform or card reader
+/// does not support it.
+pub fn lock(dst: &std::path::Path) -> Result<()> {
+    crate::pal::set_write_protect(dst, true)
+}
+
+/// Remove write protection set using [lock].
+pub fn unlock(dst: &std::path::Path) -> Result<()> {
+    crate::pal::set_write_protect(dst, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use bb_drivelist::DeviceDescriptor;
+
+    use super::{Device, Filter, filter_drives};
+
+    fn drives() -> Vec<DeviceDescriptor> {
+        let ssd = DeviceDescriptor {
+            raw: "/dev/nvme0n1".to_string(),
+            ..Default::default()
+        };
+        let usb = DeviceDescriptor {
+            raw: "/dev/sda".to_string(),
+            is_usb: true,
+            is_removable: true,
+            ..Default::default()
+        };
+        let card = DeviceDescriptor {
+            raw: "/dev/mmcblk0".to_string(),
+            is_card: true,
+            is_removable: true,
+            ..Default::default()
+        };
+
+        let emmc = DeviceDescriptor {
+            raw: "/dev/mmcblk1".to_string(),
+            is_card: true,
+            is_system: true,
+            ..Default::default()
+        };
+
+        vec![ssd, usb, card, emmc]
+    }
+
+    fn raw(drives: Vec<DeviceDescriptor>) -> Vec<String> {
+        drives.into_iter().map(|x| x.raw).collect()
+    }
+
+    #[test]
+    fn filter() {
+        assert_eq!(raw(filter_drives(drives(), Filter::None)).len(), 4);
+        assert_eq!(
+            raw(filter_drives(drives(), Filter::Removable)),
+            ["/dev/sda", "/dev/mmcblk0"]
+        );
+        assert_eq!(
+            raw(filter_drives(drives(), Filter::PreferCard)),
+            ["/dev/mmcblk0"]
+        );
+
+        // Fallback to removable drives when no card is present. System eMMC is never listed.
+        let no_card: Vec<_> = drives()
+            .into_iter()
+            .filter(|x| x.raw != "/dev/mmcblk0")
+            .collect();
+        assert_eq!(
+            raw(filter_drives(no_card, Filter::PreferCard)),
+            ["/dev/sda"]
+        );
+    }
+
+    #[test]
+    fn filter_from_bool() {
+        assert_eq!(Filter::from(true), Filter::default());
+        assert_eq!(Filter::from(false), Filter::None);
+    }
+
+    #[test]
+    fn device_identity() {
+        let serial = Some("000000000819".to_string());
+        let a = Device::new(
+            "Generic SD Card Reader".to_string(),
+            "/dev/sdb".into(),
+            15931539456,
+            serial.clone(),
+        );
+        let b = Device::new(
+            "Generic- SD/MMC".to_string(),
+            "/dev/sdb".into(),
+            15931539456,
+            serial.clone(),
+        );
+        let c = Device::new(
+            "Generic SD Card Reader".to_string(),
+            "/dev/sdc".into(),
+            15931539456,
+            serial.clone(),
+        );
+        // Different card in the same reader
+        let d = Device::new(
+            "Generic SD Card Reader".to_string(),
+            "/dev/sdb".into(),
+            31914983424,
+            serial,
+        );
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_ne!(a, d);
+
+        let devs = std::collections::HashSet::from([a, b, c, d]);
+        assert_eq!(devs.len(), 3);
+    }
+}
@@ -0,0 +1,110 @@
+//! Provisioning marker written to the boot partition after flashing. Allows provisioning stations
+//! to track each flashed SD Card.
+
+use std::io::{Read, Seek, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::customization::boot_partition;
+use crate::{Error, Result};
+
+/// Name of the marker file in boot partition. Not used by any first-boot service.
+pub const MARKER_FILE: &str = "bb-imager-marker.json";
+
+/// Unique ID and flashing details written to `bb-imager-marker.json` in boot partition.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct ProvisioningMarker {
+    id: uuid::Uuid,
+    timestamp: u64,
+    image_sha256: Option<[u8; 32]>,
+}
+
+impl ProvisioningMarker {
+    /// Create marker with a new random ID. `image_sha256` should be the SHA256 of the image, as
+    /// published in the config.
+    pub fn new(image_sha256: Option<[u8; 32]>) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_secs())
+                .unwrap_or_default(),
+            image_sha256,
+        }
+    }
+
+    /// Unique ID of the SD Card
+    pub const fn id(&self) -> uuid::Uuid {
+        self.id
+    }
+
+    /// Marker file contents
+    pub fn to_json(&self) -> String {
+        let sha = match &self.image_sha256 {
+            Some(x) => format!("\"{}\"", const_hex::encode(x)),
+            None => "null".to_string(),
+        };
+
+        format!(
+            "{{\n  \"id\": \"{}\",\n  \"timestamp\": {},\n  \"image_sha256\": {}\n}}\n",
+            self.id.hyphenated(),
+            self.timestamp,
+            sha
+        )
+    }
+
+    pub(crate) fn write(&self, mut dst: impl Write + Seek + Read + std::fmt::Debug) -> Result<()> {
+        let boot_partition = boot_partition(&mut dst)?;
+        self.write_to(&boot_partition.root_dir())
+    }
+
+    fn write_to<T: fatfs::ReadWriteSeek>(&self, dir: &fatfs::Dir<'_, T>) -> Result<()> {
+        let mut f = dir
+            .create_file(MARKER_FILE)
+            .map_err(|source| Error::MarkerWriteFail { source })?;
+
+        f.truncate()
+            .and_then(|_| f.write_all(self.to_json().as_bytes()))
+            .map_err(|source| Error::MarkerWriteFail { source })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::{MARKER_FILE, ProvisioningMarker};
+
+    #[test]
+    fn marker() {
+        const LEN: usize = 8 * 1024 * 1024;
+
+        let mut disk = Cursor::new(vec![0u8; LEN]);
+        fatfs::format_volume(&mut disk, fatfs::FormatVolumeOptions::new()).unwrap();
+
+        let marker = ProvisioningMarker::new(Some([0xab; 32]));
+        disk.set_position(0);
+        {
+            let fs = fatfs::FileSystem::new(&mut disk, fatfs::FsOptions::new()).unwrap();
+            marker.write_to(&fs.root_dir()).unwrap();
+        }
+
+        disk.set_position(0);
+        let fs = fatfs::FileSystem::new(&mut disk, fatfs::FsOptions::new()).unwrap();
+        let mut data = String::new();
+        fs.root_dir()
+            .open_file(MARKER_FILE)
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&data).unwrap();
+        assert_eq!(json["id"], marker.id().to_string());
+        assert!(json["timestamp"].as_u64().unwrap() > 0);
+        assert_eq!(json["image_sha256"], "ab".repeat(32));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&ProvisioningMarker::new(None).to_json()).unwrap();
+        assert!(json["image_sha256"].is_null());
+        assert_ne!(json["id"], marker.id().to_string());
+    }
+}
@@ -2,6 +2,7 @@ use crate::{Error, Result, helpers::Eject};
 
 use std::{
     io,
+    os::fd::AsRawFd,
     path::{Path, PathBuf},
 };
 
@@ -91,6 +92,8 @@ pub(crate) async fn open(dst: &Path) -> Result<LinuxDrive> {
         })
     }
 
+    unmount_all(dst).await?;
+
     open_inner(dst)
         .await
         .map_err(|e| Error::FailedToOpenDestination { source: e })
@@ -98,6 +101,8 @@ pub(crate) async fn open(dst: &Path) -> Result<LinuxDrive> {
 
 #[cfg(not(feature = "udev"))]
 pub(crate) async fn open(dst: &Path) -> Result<LinuxDrive> {
+    unmount_all(dst).await?;
+
     let file = tokio::fs::OpenOptions::new()
         .read(true)
         .write(true)
@@ -134,6 +139,170 @@ pub(crate) async fn format(dst: &Path) -> Result<()> {
         .map_err(|source| Error::FailedToFormat { source })
 }
 
+/// Unmount all file systems on `dst` and its partitions. Writing to a device with mounted
+/// partitions can fail, or corrupt the mounted file systems.
+async fn unmount_all(dst: &Path) -> Result<()> {
+    let devices = device_and_partitions(dst);
+    let mut prev = None;
+
+    loop {
+        let mounts = std::fs::read_to_string("/proc/self/mounts")?;
+        // Nested mountpoints are unmounted first
+        let Some((src, mountpoint)) = mountpoints(&mounts, &devices)
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1))
+        else {
+            return Ok(());
+        };
+
+        let res = if prev.as_ref() == Some(&mountpoint) {
+            Err(io::Error::other("Still mounted after unmount"))
+        } else {
+            unmount(&src, &mountpoint).await
+        };
+        res.map_err(|source| Error::UnmountFailed {
+            mountpoint: mountpoint.clone(),
+            source,
+        })?;
+
+        tracing::info!("Unmounted {} from {}", src.display(), mountpoint.display());
+        prev = Some(mountpoint);
+    }
+}
+
+#[cfg(feature = "udev")]
+async fn unmount(src: &Path, _mountpoint: &Path) -> io::Result<()> {
+    let dbus_client = udisks2::Client::new().await.map_err(io::Error::other)?;
+
+    let devs = dbus_client
+        .manager()
+        .resolve_device(
+            HashMap::from([("path", src.to_str().unwrap().into())]),
+            HashMap::new(),
+        )
+        .await
+        .map_err(io::Error::other)?;
+
+    let obj_path = devs
+        .first()
+        .ok_or(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Block device not found",
+        ))?
+        .to_owned();
+
+    dbus_client
+        .object(obj_path)
+        .expect("Unexpected error")
+        .filesystem()
+        .await
+        .map_err(io::Error::other)?
+        .unmount(HashMap::new())
+        .await
+        .map_err(io::Error::other)
+}
+
+#[cfg(not(feature = "udev"))]
+async fn unmount(_src: &Path, mountpoint: &Path) -> io::Result<()> {
+    let output = tokio::process::Command::new("umount")
+        .arg(mountpoint)
+        .output()
+        .await?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+/// Returns `dst` along with all its partitions (e.g. `/dev/sdb1`, `/dev/mmcblk0p1`), as listed in
+/// sysfs. Symlinks are resolved, since mounts always use the real device.
+fn device_and_partitions(dst: &Path) -> Vec<PathBuf> {
+    let dst = std::fs::canonicalize(dst).unwrap_or_else(|_| dst.to_path_buf());
+    let Some(name) = dst.file_name() else {
+        return vec![dst];
+    };
+
+    let mut res = match std::fs::read_dir(Path::new("/sys/class/block").join(name)) {
+        Ok(entries) => entries
+            .flatten()
+            .filter(|x| x.path().join("partition").exists())
+            .map(|x| Path::new("/dev").join(x.file_name()))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    res.push(dst);
+    res
+}
+
+/// Returns `(source, mountpoint)` for all entries in `mounts` (in `/proc/self/mounts` format)
+/// mounted from one of `devices`.
+fn mountpoints(mounts: &str, devices: &[PathBuf]) -> Vec<(PathBuf, PathBuf)> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let src = unescape_mount(fields.next()?);
+            let mountpoint = unescape_mount(fields.next()?);
+
+            // Virtual file systems use names like `tmpfs` as source
+            if !src.starts_with('/') {
+                return None;
+            }
+            let src = std::fs::canonicalize(&src).unwrap_or_else(|_| PathBuf::from(src));
+
+            devices
+                .contains(&src)
+                .then(|| (src, PathBuf::from(mountpoint)))
+        })
+        .collect()
+}
+
+/// Spaces, tabs, newlines and backslashes are octal escaped (e.g. `\040`) in `/proc/self/mounts`.
+fn unescape_mount(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && let Some(x) = s
+                .get(i + 1..i + 4)
+                .and_then(|x| u8::from_str_radix(x, 8).ok())
+        {
+            res.push(x);
+            i += 4;
+        } else {
+            res.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+pub(crate) fn set_write_protect(dst: &Path, lock: bool) -> Result<()> {
+    // _IO(0x12, 93) from linux/fs.h
+    const BLKROSET: libc::Ioctl = 0x125d;
+
+    let file = std::fs::File::open(dst)?;
+    let val = libc::c_int::from(lock);
+
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), BLKROSET, &val as *const libc::c_int) };
+    if ret == 0 {
+        return Ok(());
+    }
+
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::ENOTTY) | Some(libc::EINVAL) => Err(Error::WriteProtectUnsupported),
+        _ => Err(err.into()),
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct LinuxDrive {
     file: std::fs::File,
@@ -204,15 +373,28 @@ impl Eject for LinuxDrive {
         let drive = self.drive.clone();
         std::mem::drop(self);
 
-        let output = std::process::Command::new("eject").arg(drive).output()?;
+        // `eject` is not installed on all distributions, so fall back to udisks.
+        run_eject(std::process::Command::new("eject").arg(&drive)).or_else(|e| {
+            tracing::debug!("eject failed: {e}");
+            run_eject(
+                std::process::Command::new("udisksctl")
+                    .args(["power-off", "--no-user-interaction", "-b"])
+                    .arg(&drive),
+            )
+        })
+    }
+}
+
+#[cfg(not(feature = "udev"))]
+fn run_eject(cmd: &mut std::process::Command) -> io::Result<()> {
+    let output = cmd.output()?;
 
-        if output.status.success() {
-            Ok(())
-        } else {
-            Err(std::io::Error::other(
-                String::from_utf8(output.stderr).unwrap(),
-            ))
-        }
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
     }
 }
 
@@ -237,3 +419,38 @@ impl io::Write for LinuxDrive {
         self.file.flush()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn write_protect_unsupported() {
+        let p = std::env::temp_dir().join("bb-flasher-sd-write-protect");
+        std::fs::write(&p, [0u8; 512]).unwrap();
+
+        let res = super::set_write_protect(&p, true);
+        std::fs::remove_file(&p).unwrap();
+
+        assert!(matches!(res, Err(crate::Error::WriteProtectUnsupported)));
+    }
+
+    #[test]
+    fn mountpoints() {
+        const MOUNTS: &str = "\
+sysfs /sys sysfs rw,nosuid,nodev,noexec,relatime 0 0
+/dev/sda2 / ext4 rw,relatime 0 0
+/dev/sdb1 /media/user/BOOT vfat rw,nosuid,nodev 0 0
+/dev/sdb2 /media/user/root\\040fs ext4 rw,nosuid,nodev 0 0
+/dev/sdb10 /mnt/other ext4 rw 0 0
+/dev/sdbb1 /mnt/similar ext4 rw 0 0
+";
+
+        let devices = ["/dev/sdb", "/dev/sdb1", "/dev/sdb2"].map(std::path::PathBuf::from);
+        assert_eq!(
+            super::mountpoints(MOUNTS, &devices),
+            [
+                ("/dev/sdb1".into(), "/media/user/BOOT".into()),
+                ("/dev/sdb2".into(), "/media/user/root fs".into()),
+            ]
+        );
+    }
+}
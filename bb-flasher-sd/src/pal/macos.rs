@@ -49,12 +49,47 @@ fn unmount_disk(path: &str) -> std::io::Result<()> {
         .map(|_| ())
 }
 
+#[cfg(not(feature = "macos_eject"))]
+fn eject_disk(path: &str) -> std::io::Result<()> {
+    let output = std::process::Command::new("diskutil")
+        .args(["eject", path])
+        .output()?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
 impl crate::helpers::Eject for MacOSFile {
+    #[cfg(not(feature = "macos_eject"))]
     fn eject(self) -> std::io::Result<()> {
         self.inner.sync_all()?;
-        let _ = unmount_disk(&self.path.to_string_lossy());
-        Ok(())
+
+        // Disk cannot be ejected while it is still open.
+        let Self { inner, path } = self;
+        drop(inner);
+
+        eject_disk(&path.to_string_lossy())
     }
+
+    #[cfg(feature = "macos_eject")]
+    fn eject(self) -> std::io::Result<()> {
+        self.inner.sync_all()?;
+
+        // Disk cannot be ejected while it is still open.
+        let Self { inner, path } = self;
+        drop(inner);
+
+        super::macos_eject::eject(&path.to_string_lossy())
+    }
+}
+
+pub(crate) fn set_write_protect(_: &Path, _: bool) -> Result<()> {
+    Err(Error::WriteProtectUnsupported)
 }
 
 pub(crate) async fn format(dst: &Path) -> Result<()> {
@@ -0,0 +1,164 @@
+//! Unmount and eject SD Card using DiskArbitration instead of `diskutil`.
+
+use std::{
+    cell::Cell,
+    ffi::{CString, c_void},
+    io,
+    ptr::NonNull,
+    time::{Duration, Instant},
+};
+
+use objc2_core_foundation::{CFRunLoop, kCFAllocatorDefault, kCFRunLoopDefaultMode};
+use objc2_disk_arbitration::{
+    DADisk, DADiskEject, DADiskUnmount, DADissenter, DADissenterGetStatus, DASession,
+    kDADiskEjectOptionDefault, kDADiskUnmountOptionWhole,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// DAReturn codes for dissenters. See DiskArbitration/DADissenter.h
+const DA_RETURN_BUSY: i32 = 0xF8DA0002_u32 as i32;
+const DA_RETURN_EXCLUSIVE_ACCESS: i32 = 0xF8DA0003_u32 as i32;
+const DA_RETURN_NOT_PRIVILEGED: i32 = 0xF8DA0006_u32 as i32;
+const DA_RETURN_NOT_PERMITTED: i32 = 0xF8DA0009_u32 as i32;
+
+/// Result of an asynchronous DiskArbitration request. Any process with an approval callback
+/// registered can dissent, in which case the status of the dissenter is recorded.
+#[derive(Default)]
+struct Completion {
+    done: Cell<bool>,
+    status: Cell<Option<i32>>,
+}
+
+impl Completion {
+    fn complete(&self, status: Option<i32>) {
+        self.status.set(status);
+        self.done.set(true);
+    }
+
+    fn result(&self, op: &str) -> io::Result<()> {
+        if !self.done.get() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{op} timed out"),
+            ));
+        }
+
+        match self.status.get() {
+            None => Ok(()),
+            Some(status) => Err(dissent_error(op, status)),
+        }
+    }
+
+    fn wait(&self) {
+        let deadline = Instant::now() + TIMEOUT;
+        while !self.done.get() && Instant::now() < deadline {
+            CFRunLoop::run_in_mode(unsafe { kCFRunLoopDefaultMode }, 0.1, true);
+        }
+    }
+
+    fn as_context(&self) -> *mut c_void {
+        self as *const Self as *mut c_void
+    }
+}
+
+fn dissent_error(op: &str, status: i32) -> io::Error {
+    let kind = match status {
+        DA_RETURN_BUSY | DA_RETURN_EXCLUSIVE_ACCESS => io::ErrorKind::ResourceBusy,
+        DA_RETURN_NOT_PRIVILEGED | DA_RETURN_NOT_PERMITTED => io::ErrorKind::PermissionDenied,
+        _ => io::ErrorKind::Other,
+    };
+
+    io::Error::new(kind, format!("{op} dissented with status {status:#x}"))
+}
+
+unsafe extern "C-unwind" fn on_complete(
+    _: NonNull<DADisk>,
+    dissenter: *const DADissenter,
+    context: *mut c_void,
+) {
+    let completion = unsafe { &*(context as *const Completion) };
+    let status = unsafe { dissenter.as_ref() }.map(|d| unsafe { DADissenterGetStatus(d) } as i32);
+
+    completion.complete(status);
+}
+
+/// Unmount all volumes of a disk and eject it. `path` can be any of `/dev/diskN`,
+/// `/dev/rdiskN` or `diskN`.
+pub(crate) fn eject(path: &str) -> io::Result<()> {
+    let bsd_name = path.strip_prefix("/dev/").unwrap_or(path);
+    let bsd_name = bsd_name.strip_prefix('r').unwrap_or(bsd_name);
+    let bsd_name = CString::new(bsd_name).map_err(io::Error::other)?;
+
+    let session = unsafe { DASession::new(kCFAllocatorDefault) }
+        .ok_or_else(|| io::Error::other("Failed to create DiskArbitration session"))?;
+    let disk = NonNull::new(bsd_name.as_ptr() as *mut _)
+        .and_then(|ptr| unsafe { DADisk::from_bsd_name(kCFAllocatorDefault, &session, ptr) })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Disk not found"))?;
+
+    let run_loop =
+        CFRunLoop::current().ok_or_else(|| io::Error::other("Failed to get current run loop"))?;
+    let mode = unsafe { kCFRunLoopDefaultMode }
+        .ok_or_else(|| io::Error::other("Failed to get default run loop mode"))?;
+
+    unsafe { DASession::schedule_with_run_loop(&session, &run_loop, mode) };
+
+    let res = (|| {
+        let unmount = Completion::default();
+        unsafe {
+            DADiskUnmount(
+                &disk,
+                kDADiskUnmountOptionWhole as _,
+                Some(on_complete),
+                unmount.as_context(),
+            )
+        };
+        unmount.wait();
+        unmount.result("Unmount")?;
+
+        let eject = Completion::default();
+        unsafe {
+            DADiskEject(
+                &disk,
+                kDADiskEjectOptionDefault as _,
+                Some(on_complete),
+                eject.as_context(),
+            )
+        };
+        eject.wait();
+        eject.result("Eject")
+    })();
+
+    unsafe { DASession::unschedule_from_run_loop(&session, &run_loop, mode) };
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::ErrorKind, ptr::NonNull};
+
+    use super::{Completion, DA_RETURN_BUSY, DA_RETURN_NOT_PERMITTED, on_complete};
+
+    #[test]
+    fn completion() {
+        let c = Completion::default();
+        assert_eq!(c.result("Unmount").unwrap_err().kind(), ErrorKind::TimedOut);
+
+        // Approved (no dissenter)
+        unsafe { on_complete(NonNull::dangling(), std::ptr::null(), c.as_context()) };
+        assert!(c.result("Unmount").is_ok());
+
+        c.complete(Some(DA_RETURN_BUSY));
+        assert_eq!(
+            c.result("Unmount").unwrap_err().kind(),
+            ErrorKind::ResourceBusy
+        );
+
+        c.complete(Some(DA_RETURN_NOT_PERMITTED));
+        assert_eq!(
+            c.result("Eject").unwrap_err().kind(),
+            ErrorKind::PermissionDenied
+        );
+    }
+}
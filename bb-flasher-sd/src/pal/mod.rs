@@ -2,12 +2,14 @@
 mod linux;
 #[cfg(target_os = "macos")]
 mod macos;
+#[cfg(all(target_os = "macos", feature = "macos_eject"))]
+mod macos_eject;
 #[cfg(windows)]
 mod windows;
 
 #[cfg(target_os = "linux")]
-pub(crate) use linux::{open, format};
+pub(crate) use linux::{open, format, set_write_protect};
 #[cfg(target_os = "macos")]
-pub(crate) use macos::{open, format};
+pub(crate) use macos::{open, format, set_write_protect};
 #[cfg(windows)]
-pub(crate) use windows::{open, format};
+pub(crate) use windows::{open, format, set_write_protect};
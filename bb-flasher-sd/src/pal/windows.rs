@@ -1,16 +1,27 @@
 use std::{
     fs::{File, OpenOptions},
     io::{self, Read, Seek, SeekFrom, Write},
-    os::windows::io::AsRawHandle,
-    path::Path,
+    os::windows::{ffi::OsStrExt, io::AsRawHandle},
+    path::{Path, PathBuf},
     process::Stdio,
 };
 use tokio::io::AsyncWriteExt;
 use windows::Win32::{
-    Foundation::HANDLE,
+    Foundation::{
+        ERROR_INVALID_FUNCTION, ERROR_MORE_DATA, ERROR_NOT_SUPPORTED, ERROR_SUCCESS, HANDLE,
+    },
     System::IO::DeviceIoControl,
-    System::Ioctl::{FSCTL_ALLOW_EXTENDED_DASD_IO, FSCTL_LOCK_VOLUME, FSCTL_UNLOCK_VOLUME},
+    System::Ioctl::{
+        DISK_ATTRIBUTE_READ_ONLY, FSCTL_ALLOW_EXTENDED_DASD_IO, FSCTL_DISMOUNT_VOLUME,
+        FSCTL_LOCK_VOLUME, FSCTL_UNLOCK_VOLUME, IOCTL_DISK_SET_DISK_ATTRIBUTES,
+        IOCTL_STORAGE_EJECT_MEDIA, SET_DISK_ATTRIBUTES,
+    },
+    System::RestartManager::{
+        CCH_RM_SESSION_KEY, RM_PROCESS_INFO, RmEndSession, RmGetList, RmRegisterResources,
+        RmStartSession,
+    },
 };
+use windows::core::{PCWSTR, PWSTR};
 
 use crate::{Error, Result};
 
@@ -22,6 +33,8 @@ pub(crate) struct WinDrive {
 
 const FILE_FLAG_WRITE_THROUGH: u32 = 0x80000000;
 const FILE_FLAG_NO_BUFFERING: u32 = 0x20000000;
+/// Upper limit on files checked when looking for processes holding the volume.
+const MAX_HOLDER_FILES: usize = 1024;
 
 impl WinDrive {
     pub(crate) async fn open(path: &Path) -> anyhow::Result<Self> {
@@ -89,7 +102,9 @@ fn open_and_lock_volume(path: &str) -> anyhow::Result<File> {
             None,
             None,
         )?;
+    }
 
+    if let Err(e) = unsafe {
         DeviceIoControl(
             HANDLE(volume.as_raw_handle()),
             FSCTL_LOCK_VOLUME,
@@ -99,12 +114,132 @@ fn open_and_lock_volume(path: &str) -> anyhow::Result<File> {
             0,
             None,
             None,
-        )?;
+        )
+    } {
+        let holders = volume_holders(path);
+        if holders.is_empty() {
+            return Err(e.into());
+        }
+
+        tracing::warn!("Volume {path} is in use by {holders:?}");
+        return Err(Error::VolumeInUse(holders).into());
     }
 
     Ok(volume)
 }
 
+/// Best-effort lookup of processes holding files open on a volume (eg: `\\.\E:`) using
+/// Restart Manager.
+fn volume_holders(volume: &str) -> Vec<String> {
+    let Some(drive) = volume.strip_prefix(r"\\.\") else {
+        return Vec::new();
+    };
+
+    let files: Vec<Vec<u16>> = volume_files(Path::new(&format!("{drive}\\")))
+        .into_iter()
+        .map(|x| x.as_os_str().encode_wide().chain(Some(0)).collect())
+        .collect();
+    if files.is_empty() {
+        return Vec::new();
+    }
+    let files: Vec<PCWSTR> = files.iter().map(|x| PCWSTR::from_raw(x.as_ptr())).collect();
+
+    let mut session = 0;
+    let mut session_key = [0u16; CCH_RM_SESSION_KEY as usize + 1];
+    if unsafe {
+        RmStartSession(
+            &mut session,
+            None,
+            PWSTR::from_raw(session_key.as_mut_ptr()),
+        )
+    } != ERROR_SUCCESS
+    {
+        return Vec::new();
+    }
+
+    let res = session_holders(session, &files);
+    let _ = unsafe { RmEndSession(session) };
+
+    process_names(&res)
+}
+
+fn session_holders(session: u32, files: &[PCWSTR]) -> Vec<RM_PROCESS_INFO> {
+    if unsafe { RmRegisterResources(session, Some(files), None, None) } != ERROR_SUCCESS {
+        return Vec::new();
+    }
+
+    let mut infos = Vec::new();
+    // The list can grow between calls, so retry a few times.
+    for _ in 0..3 {
+        let mut needed = 0;
+        let mut count = infos.len() as u32;
+        let mut reasons = 0;
+
+        let ptr = if infos.is_empty() {
+            None
+        } else {
+            Some(infos.as_mut_ptr())
+        };
+
+        match unsafe { RmGetList(session, &mut needed, &mut count, ptr, &mut reasons) } {
+            ERROR_SUCCESS => {
+                infos.truncate(count as usize);
+                return infos;
+            }
+            ERROR_MORE_DATA => infos.resize(needed as usize, RM_PROCESS_INFO::default()),
+            _ => break,
+        }
+    }
+
+    Vec::new()
+}
+
+/// Collect files on the volume, stopping at [MAX_HOLDER_FILES].
+fn volume_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => dirs.push(entry.path()),
+                Ok(t) if t.is_file() => files.push(entry.path()),
+                _ => {}
+            }
+
+            if files.len() >= MAX_HOLDER_FILES {
+                return files;
+            }
+        }
+    }
+
+    files
+}
+
+/// Unique, sorted application names from Restart Manager process list.
+fn process_names(infos: &[RM_PROCESS_INFO]) -> Vec<String> {
+    let mut names: Vec<String> = infos
+        .iter()
+        .map(|x| {
+            let len = x
+                .strAppName
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(x.strAppName.len());
+            String::from_utf16_lossy(&x.strAppName[..len])
+        })
+        .filter(|x| !x.is_empty())
+        .collect();
+
+    names.sort();
+    names.dedup();
+    names
+}
+
 fn physical_drive_to_volume(drive: &Path) -> anyhow::Result<Option<String>> {
     let desc = bb_drivelist::drive_list()
         .expect("Unexpected error")
@@ -210,11 +345,68 @@ impl Seek for WinDrive {
     }
 }
 
-/// TODO: Implement real eject
+/// Dismount the locked volume, so Windows does not keep stale file system data, and eject the
+/// media. Volume is unlocked on drop.
 impl crate::helpers::Eject for WinDrive {
     fn eject(self) -> io::Result<()> {
-        let _ = self.drive.sync_all();
-        Ok(())
+        self.drive.sync_all()?;
+
+        if let Some(volume) = &self.volume {
+            device_control(volume, FSCTL_DISMOUNT_VOLUME)?;
+        }
+        device_control(&self.drive, IOCTL_STORAGE_EJECT_MEDIA)
+    }
+}
+
+/// [DeviceIoControl] without input or output buffers.
+fn device_control(f: &File, code: u32) -> io::Result<()> {
+    unsafe {
+        DeviceIoControl(
+            HANDLE(f.as_raw_handle()),
+            code,
+            None,
+            0,
+            None,
+            0,
+            None,
+            None,
+        )
+    }
+    .map_err(Into::into)
+}
+
+pub(crate) fn set_write_protect(dst: &Path, lock: bool) -> Result<()> {
+    let drive = OpenOptions::new().read(true).write(true).open(dst)?;
+
+    let attrs = SET_DISK_ATTRIBUTES {
+        Version: std::mem::size_of::<SET_DISK_ATTRIBUTES>() as u32,
+        Attributes: if lock { DISK_ATTRIBUTE_READ_ONLY } else { 0 },
+        AttributesMask: DISK_ATTRIBUTE_READ_ONLY,
+        ..Default::default()
+    };
+
+    let res = unsafe {
+        DeviceIoControl(
+            HANDLE(drive.as_raw_handle()),
+            IOCTL_DISK_SET_DISK_ATTRIBUTES,
+            Some(&attrs as *const SET_DISK_ATTRIBUTES as *const _),
+            std::mem::size_of::<SET_DISK_ATTRIBUTES>() as u32,
+            None,
+            0,
+            None,
+            None,
+        )
+    };
+
+    match res {
+        Ok(()) => Ok(()),
+        Err(e)
+            if e.code() == ERROR_INVALID_FUNCTION.to_hresult()
+                || e.code() == ERROR_NOT_SUPPORTED.to_hresult() =>
+        {
+            Err(Error::WriteProtectUnsupported)
+        }
+        Err(e) => Err(io::Error::from(e).into()),
     }
 }
 
@@ -227,5 +419,37 @@ pub(crate) async fn format(dst: &Path) -> Result<()> {
 pub(crate) async fn open(dst: &Path) -> Result<WinDrive> {
     WinDrive::open(dst)
         .await
-        .map_err(|e| Error::FailedToOpenDestination { source: e })
+        .map_err(|e| match e.downcast::<Error>() {
+            Ok(e) => e,
+            Err(source) => Error::FailedToOpenDestination { source },
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::System::RestartManager::RM_PROCESS_INFO;
+
+    fn info(name: &str) -> RM_PROCESS_INFO {
+        let mut info = RM_PROCESS_INFO::default();
+        for (i, c) in name.encode_utf16().enumerate() {
+            info.strAppName[i] = c;
+        }
+        info
+    }
+
+    #[test]
+    fn process_names() {
+        let infos = [
+            info("Windows Explorer"),
+            info(""),
+            info("Notepad"),
+            info("Windows Explorer"),
+        ];
+
+        assert_eq!(
+            super::process_names(&infos),
+            ["Notepad", "Windows Explorer"]
+        );
+        assert!(super::process_names(&[]).is_empty());
+    }
 }
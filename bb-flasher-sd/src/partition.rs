@@ -0,0 +1,118 @@
+//! Inspect partitions in OS images and SD Cards.
+
+use std::io::{Read, Seek, Write};
+
+use crate::{Error, Result};
+
+use gpt::disk::LogicalBlockSize;
+
+const DEFAULT_SECTOR_SIZE: u64 = 512;
+/// Logical sector sizes tried when looking for a GPT header.
+const GPT_SECTOR_SIZES: [LogicalBlockSize; 2] = [LogicalBlockSize::Lb512, LogicalBlockSize::Lb4096];
+
+/// A partition in an OS image or SD Card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Partition {
+    /// Partition number, starting from 1.
+    pub number: u32,
+    /// Start offset in bytes.
+    pub start: u64,
+    /// Size in bytes.
+    pub size: u64,
+}
+
+impl Partition {
+    pub(crate) const fn end(&self) -> u64 {
+        self.start + self.size
+    }
+}
+
+/// List all used partitions. GPT is tried first, then MBR.
+///
+/// Only the primary GPT header is required, so it can also be used on the first few MiB of an
+/// image. GPT is detected with both 512 and 4096 byte sectors, while MBR assumes 512 byte
+/// sectors. See [partitions_with_sector_size] for 4Kn media with MBR.
+pub fn partitions(dev: impl Read + Write + Seek + std::fmt::Debug) -> Result<Vec<Partition>> {
+    partitions_with_sector_size(dev, None)
+}
+
+/// Same as [partitions], with a hint for the logical sector size of `dev`. The hint is tried first
+/// for GPT. MBR does not record the sector size, so the hint is always used for MBR.
+pub fn partitions_with_sector_size(
+    mut dev: impl Read + Write + Seek + std::fmt::Debug,
+    sector_size: Option<u64>,
+) -> Result<Vec<Partition>> {
+    let hint = sector_size.and_then(|x| LogicalBlockSize::try_from(x).ok());
+
+    for lb_size in hint.into_iter().chain(GPT_SECTOR_SIZES) {
+        if let Ok(disk) = gpt::GptConfig::new()
+            .writable(false)
+            .only_valid_headers(false)
+            .logical_block_size(lb_size)
+            .open_from_device(&mut dev)
+        {
+            return disk
+                .partitions()
+                .iter()
+                .filter(|(_, p)| p.is_used())
+                .map(|(number, p)| {
+                    Ok(Partition {
+                        number: *number,
+                        start: p.bytes_start(lb_size)?,
+                        size: p.bytes_len(lb_size)?,
+                    })
+                })
+                .collect();
+        }
+    }
+
+    let sector_size = sector_size.unwrap_or(DEFAULT_SECTOR_SIZE);
+    let mbr = mbrman::MBRHeader::read_from(&mut dev).map_err(|_| Error::InvalidPartitionTable)?;
+
+    Ok((1..=4)
+        .filter_map(|number| {
+            let p = mbr.get(number)?;
+            if !p.is_used() {
+                return None;
+            }
+
+            Some(Partition {
+                number: u32::try_from(number).unwrap(),
+                start: u64::from(p.starting_lba) * sector_size,
+                size: u64::from(p.sectors) * sector_size,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Partition, partitions, partitions_with_sector_size};
+
+    #[test]
+    fn mbr_sector_size() {
+        let mut mbr = vec![0u8; 4096];
+        let entry = &mut mbr[446..462];
+        // FAT32 LBA partition
+        entry[4] = 0x0c;
+        entry[8..12].copy_from_slice(&256u32.to_le_bytes());
+        entry[12..16].copy_from_slice(&1024u32.to_le_bytes());
+        mbr[510] = 0x55;
+        mbr[511] = 0xaa;
+
+        let part = |start, size| Partition {
+            number: 1,
+            start,
+            size,
+        };
+
+        assert_eq!(
+            partitions(std::io::Cursor::new(mbr.clone())).unwrap(),
+            [part(256 * 512, 1024 * 512)]
+        );
+        assert_eq!(
+            partitions_with_sector_size(std::io::Cursor::new(mbr), Some(4096)).unwrap(),
+            [part(256 * 4096, 1024 * 4096)]
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! Import customization from [Raspberry Pi Imager] settings, so users migrating from it do not
+//! need to enter everything again.
+//!
+//! Raspberry Pi Imager stores customization in the `imagecustomization` group of its settings
+//! file (`~/.config/Raspberry Pi/Imager.conf` on Linux).
+//!
+//! [Raspberry Pi Imager]: https://github.com/raspberrypi/rpi-imager
+
+use crate::RaspberryCustomization;
+
+const GROUP: &str = "imagecustomization";
+
+/// Customization options found in Raspberry Pi Imager settings.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RpiImagerSettings {
+    pub hostname: Option<Box<str>>,
+    pub timezone: Option<Box<str>>,
+    pub keymap: Option<Box<str>>,
+    /// Password is only stored hashed by Raspberry Pi Imager, so it cannot be imported.
+    pub user_name: Option<Box<str>>,
+    pub wifi_ssid: Option<Box<str>>,
+    /// Only imported if stored as a passphrase. Newer versions store the derived PSK instead.
+    pub wifi_password: Option<Box<str>>,
+    pub wifi_country: Option<Box<str>>,
+    pub ssh: Option<Box<str>>,
+    /// Settings that could not be imported, in the order they were found.
+    pub ignored: Vec<Box<str>>,
+}
+
+impl RpiImagerSettings {
+    /// Parse Raspberry Pi Imager settings file (INI format). Groups other than
+    /// `imagecustomization` are skipped.
+    pub fn parse(conf: &str) -> Self {
+        let mut res = Self::default();
+        let mut in_group = false;
+        let mut ssh_enabled = true;
+
+        for line in conf.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(group) = line.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+                in_group = group.eq_ignore_ascii_case(GROUP);
+                continue;
+            }
+
+            if !in_group {
+                continue;
+            }
+
+            let Some((key, val)) = line.split_once('=') else {
+                continue;
+            };
+            let key = key.trim();
+            let val = unquote(val.trim());
+            let opt = || (!val.is_empty()).then(|| Box::from(val.as_str()));
+
+            match key {
+                "hostname" => res.hostname = opt(),
+                "timezone" => res.timezone = opt(),
+                "keyboardLayout" => res.keymap = opt(),
+                "sshUserName" => res.user_name = opt(),
+                "sshEnabled" => ssh_enabled = opt().is_some_and(|x| x.as_ref() == "true"),
+                "sshAuthorizedKeys" => {
+                    let keys = opt().unwrap_or_default();
+                    let mut keys = keys.lines().map(str::trim).filter(|x| !x.is_empty());
+
+                    res.ssh = keys.next().map(Into::into);
+                    if keys.next().is_some() {
+                        res.ignored
+                            .push("sshAuthorizedKeys (only the first key)".into());
+                    }
+                }
+                "wifiSSID" => res.wifi_ssid = opt(),
+                "wifiPassword" => match opt() {
+                    Some(x) if is_psk(&x) => res.ignored.push("wifiPassword (hashed)".into()),
+                    x => res.wifi_password = x,
+                },
+                "wifiCountry" => res.wifi_country = opt(),
+                "sshUserPassword" => res.ignored.push("sshUserPassword (hashed)".into()),
+                "wifiSSIDHidden" | "sshPasswordAuth"
+                    if opt().is_none_or(|x| x.as_ref() == "false") => {}
+                _ => res.ignored.push(key.into()),
+            }
+        }
+
+        if !ssh_enabled {
+            res.ssh = None;
+        }
+
+        res
+    }
+
+    /// Customization for Raspberry Pi OS images. User is not imported since the password is not
+    /// known, and Wi-Fi only if the passphrase is known.
+    pub fn raspberry(&self) -> RaspberryCustomization {
+        let wlan = self.wifi_ssid.clone().zip(self.wifi_password.clone());
+
+        RaspberryCustomization {
+            hostname: self.hostname.clone(),
+            timezone: self.timezone.clone(),
+            keymap: self.keymap.clone(),
+            user: None,
+            wlan_country: wlan.as_ref().and(self.wifi_country.clone()),
+            wlan,
+            ssh: self.ssh.clone(),
+        }
+    }
+}
+
+/// QSettings quotes values with special characters, and escapes them like C strings.
+fn unquote(val: &str) -> String {
+    let Some(val) = val.strip_prefix('"').and_then(|x| x.strip_suffix('"')) else {
+        return val.to_string();
+    };
+
+    let mut res = String::with_capacity(val.len());
+    let mut chars = val.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => res.push('\n'),
+            Some('t') => res.push('\t'),
+            Some('r') => res.push('\r'),
+            Some(x) => res.push(x),
+            None => {}
+        }
+    }
+
+    res
+}
+
+/// WPA PSK derived from the passphrase. Always 64 hex characters.
+fn is_psk(val: &str) -> bool {
+    val.len() == 64 && val.chars().all(|x| x.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RpiImagerSettings;
+    use crate::RaspberryCustomization;
+
+    const CONF: &str = r#"
+[General]
+hostname=ignored
+
+[imagecustomization]
+beep=false
+hostname=mypi
+keyboardLayout=gb
+sshAuthorizedKeys="ssh-ed25519 AAAAC3Nza user@host\nssh-rsa AAAAB3Nza user@other"
+sshEnabled=true
+sshPasswordAuth=false
+sshUserName=pi
+sshUserPassword=$5$salt$hash
+telemetry=true
+timezone=Europe/London
+wifiCountry=GB
+wifiPassword="pass, word"
+wifiSSID=Home
+wifiSSIDHidden=false
+"#;
+
+    #[test]
+    fn parse() {
+        let settings = RpiImagerSettings::parse(CONF);
+
+        assert_eq!(
+            settings,
+            RpiImagerSettings {
+                hostname: Some("mypi".into()),
+                timezone: Some("Europe/London".into()),
+                keymap: Some("gb".into()),
+                user_name: Some("pi".into()),
+                wifi_ssid: Some("Home".into()),
+                wifi_password: Some("pass, word".into()),
+                wifi_country: Some("GB".into()),
+                ssh: Some("ssh-ed25519 AAAAC3Nza user@host".into()),
+                ignored: vec![
+                    "beep".into(),
+                    "sshAuthorizedKeys (only the first key)".into(),
+                    "sshUserPassword (hashed)".into(),
+                    "telemetry".into(),
+                ],
+            }
+        );
+
+        assert_eq!(
+            settings.raspberry(),
+            RaspberryCustomization {
+                hostname: Some("mypi".into()),
+                timezone: Some("Europe/London".into()),
+                keymap: Some("gb".into()),
+                user: None,
+                wlan: Some(("Home".into(), "pass, word".into())),
+                wlan_country: Some("GB".into()),
+                ssh: Some("ssh-ed25519 AAAAC3Nza user@host".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn hashed_wifi_password() {
+        let conf = format!(
+            "[imagecustomization]\nsshEnabled=false\nsshAuthorizedKeys=ssh-ed25519 AAAA\nwifiSSID=Home\nwifiPassword={}\n",
+            "ab".repeat(32)
+        );
+        let settings = RpiImagerSettings::parse(&conf);
+
+        assert_eq!(settings.ssh, None);
+        assert_eq!(settings.wifi_password, None);
+        assert_eq!(settings.ignored, [Box::from("wifiPassword (hashed)")]);
+    }
+}
@@ -0,0 +1,115 @@
+//! Simulate slow SD Cards for testing progress reporting in applications. Only available with
+//! `simulate_slow_device` feature.
+
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use crate::helpers::Eject;
+
+/// Environment variable to set write speed (in bytes/sec) of simulated slow device.
+pub(crate) const SIMULATE_SLOW_DEVICE_ENV: &str = "BB_SIMULATE_SLOW_DEVICE";
+
+/// Writer that limits write throughput to `rate` bytes/sec.
+#[derive(Debug)]
+pub(crate) struct Throttled<W> {
+    inner: W,
+    rate: Option<u64>,
+    start: Option<Instant>,
+    written: u64,
+}
+
+impl<W> Throttled<W> {
+    pub(crate) const fn new(inner: W, rate: Option<u64>) -> Self {
+        Self {
+            inner,
+            rate,
+            start: None,
+            written: 0,
+        }
+    }
+
+    /// Read rate from [SIMULATE_SLOW_DEVICE_ENV]. Writes are not throttled if it is not set.
+    pub(crate) fn from_env(inner: W) -> Self {
+        let rate = std::env::var(SIMULATE_SLOW_DEVICE_ENV)
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .filter(|&x| x > 0);
+
+        if let Some(r) = rate {
+            tracing::warn!("Simulating slow device with {r} bytes/sec");
+        }
+
+        Self::new(inner, rate)
+    }
+
+    fn throttle(&mut self, count: usize) {
+        let Some(rate) = self.rate else {
+            return;
+        };
+
+        let start = *self.start.get_or_insert_with(Instant::now);
+        self.written += count as u64;
+
+        let expected = Duration::from_secs_f64(self.written as f64 / rate as f64);
+        if let Some(d) = expected.checked_sub(start.elapsed()) {
+            std::thread::sleep(d);
+        }
+    }
+}
+
+impl<W: io::Read> io::Read for Throttled<W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<W: io::Write> io::Write for Throttled<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.throttle(count);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: io::Seek> io::Seek for Throttled<W> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<W: Eject> Eject for Throttled<W> {
+    fn eject(self) -> io::Result<()> {
+        self.inner.eject()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    use super::Throttled;
+
+    #[test]
+    fn rate() {
+        const RATE: u64 = 1024 * 1024;
+        const LEN: usize = 256 * 1024;
+
+        let mut sd = Throttled::new(Vec::new(), Some(RATE));
+        let start = Instant::now();
+        for chunk in vec![0u8; LEN].chunks(4096) {
+            sd.write_all(chunk).unwrap();
+        }
+        let elapsed = start.elapsed();
+
+        assert_eq!(sd.inner.len(), LEN);
+        assert!(elapsed >= Duration::from_millis(240), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(1), "{elapsed:?}");
+    }
+}
@@ -0,0 +1,262 @@
+//! Read-back verification of SD Card after flashing.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+
+use sha2::Digest;
+use tokio::sync::mpsc;
+
+use crate::flashing::{BUFFER_SIZE, read_aligned};
+use crate::helpers::{DirectIoBuffer, chan_send, check_token, progress};
+use crate::{Result, Status};
+
+/// Hash used to verify SD Card contents after flashing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Verify {
+    /// Slow, but also catches deliberate tampering.
+    #[default]
+    Sha256,
+    /// Much faster non-cryptographic hash. Only catches accidental corruption.
+    Crc32,
+}
+
+enum Hasher {
+    Sha256(sha2::Sha256),
+    Crc32(crc32fast::Hasher),
+}
+
+impl Hasher {
+    fn new(verify: Verify) -> Self {
+        match verify {
+            Verify::Sha256 => Self::Sha256(sha2::Sha256::new()),
+            Verify::Crc32 => Self::Crc32(crc32fast::Hasher::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(h) => h.update(data),
+            Self::Crc32(h) => h.update(data),
+        }
+    }
+
+    fn finalize(self) -> Box<[u8]> {
+        match self {
+            Self::Sha256(h) => h.finalize().to_vec().into(),
+            Self::Crc32(h) => h.finalize().to_be_bytes().into(),
+        }
+    }
+}
+
+/// Keeps track of all data written to SD Card, so that it can be read back later.
+pub(crate) struct Written {
+    verify: Verify,
+    ranges: Vec<Range<u64>>,
+    hasher: Hasher,
+}
+
+impl Written {
+    pub(crate) fn new(verify: Verify) -> Self {
+        Self {
+            verify,
+            ranges: Vec::new(),
+            hasher: Hasher::new(verify),
+        }
+    }
+
+    /// Record `data` written at offset `pos`.
+    pub(crate) fn record(&mut self, pos: u64, data: &[u8]) {
+        let end = pos + data.len() as u64;
+
+        match self.ranges.last_mut() {
+            Some(r) if r.end == pos => r.end = end,
+            _ => self.ranges.push(pos..end),
+        }
+
+        self.hasher.update(data);
+    }
+
+    /// Read back all recorded ranges from SD Card and compare with the written data.
+    pub(crate) fn verify(
+        self,
+        mut sd: impl Read + Seek,
+        cancel: Option<&tokio_util::sync::CancellationToken>,
+    ) -> Result<()> {
+        let expected = self.hasher.finalize();
+        let mut hasher = Hasher::new(self.verify);
+        let mut buf = Box::new(DirectIoBuffer::<BUFFER_SIZE>::new());
+
+        for r in self.ranges {
+            sd.seek(SeekFrom::Start(r.start))?;
+
+            let mut pos = r.start;
+            while pos < r.end {
+                let count = std::cmp::min(r.end - pos, BUFFER_SIZE as u64) as usize;
+                let data = &mut buf.as_mut_slice()[..count];
+
+                sd.read_exact(data)?;
+                hasher.update(data);
+
+                pos += count as u64;
+                check_token(cancel)?;
+            }
+        }
+
+        if hasher.finalize() == expected {
+            Ok(())
+        } else {
+            Err(crate::Error::VerificationFailed)
+        }
+    }
+}
+
+/// Result of comparing image with SD Card contents before flashing.
+pub(crate) enum Compared {
+    /// SD Card already contains the whole image.
+    Identical,
+    /// SD Card matches image up to `offset`. `pending` is the image data starting at `offset`,
+    /// which has already been read from image.
+    Differs { offset: u64, pending: Vec<u8> },
+}
+
+/// Read back SD Card and compare it with image, stopping at the first mismatch. If `mapped` is
+/// set, only those ranges are compared. Everything else is considered a match.
+pub(crate) fn compare(
+    mut img: impl Read,
+    mut sd: impl Read + Seek,
+    img_size: u64,
+    mapped: Option<&[Range<u64>]>,
+    mut chan: Option<&mut mpsc::Sender<Status>>,
+    cancel: Option<&tokio_util::sync::CancellationToken>,
+) -> Result<Compared> {
+    let mut img_buf = Box::new(DirectIoBuffer::<BUFFER_SIZE>::new());
+    let mut sd_buf = Box::new(DirectIoBuffer::<BUFFER_SIZE>::new());
+    let mut pos = 0;
+
+    loop {
+        let count = read_aligned(&mut img, img_buf.as_mut_slice())?;
+        if count == 0 {
+            return Ok(Compared::Identical);
+        }
+
+        let data = &img_buf.as_slice()[..count];
+        let end = pos + count as u64;
+
+        if mapped.is_none_or(|m| m.iter().any(|r| r.start < end && pos < r.end)) {
+            let sd_data = &mut sd_buf.as_mut_slice()[..count];
+
+            sd.seek(SeekFrom::Start(pos))?;
+            let matches = match sd.read_exact(sd_data) {
+                Ok(()) => sd_data == data,
+                // SD Card smaller than image
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+                Err(e) => return Err(e.into()),
+            };
+
+            if !matches {
+                return Ok(Compared::Differs {
+                    offset: pos,
+                    pending: data.to_vec(),
+                });
+            }
+        }
+
+        pos = end;
+        chan_send(
+            chan.as_deref_mut(),
+            Status::Flashing(progress(pos, img_size)),
+        );
+        check_token(cancel)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Compared, Verify, Written, compare};
+
+    fn verify(mode: Verify, corrupt: Option<usize>) -> crate::Result<()> {
+        const FILE_LEN: usize = 16 * 1024;
+        const OFFSET: u64 = 4096;
+
+        let data: Vec<u8> = (0..FILE_LEN)
+            .map(|x| u8::try_from(x % 255).unwrap())
+            .collect();
+
+        let mut written = Written::new(mode);
+        written.record(0, &data[..(OFFSET as usize)]);
+        written.record(OFFSET, &data[(OFFSET as usize)..]);
+
+        let mut sd = data.clone();
+        if let Some(i) = corrupt {
+            sd[i] ^= 0x01;
+        }
+
+        written.verify(std::io::Cursor::new(sd), None)
+    }
+
+    #[test]
+    fn verify_corruption() {
+        for mode in [Verify::Sha256, Verify::Crc32] {
+            assert!(verify(mode, None).is_ok());
+            assert!(matches!(
+                verify(mode, Some(5000)),
+                Err(crate::Error::VerificationFailed)
+            ));
+        }
+    }
+
+    #[test]
+    fn compare_mapped() {
+        const FILE_LEN: usize = 4 * super::BUFFER_SIZE;
+        const CHANGED: usize = 2 * super::BUFFER_SIZE + 10;
+
+        let img: Vec<u8> = (0..FILE_LEN)
+            .map(|x| u8::try_from(x % 251).unwrap())
+            .collect();
+        let mut sd = img.clone();
+        sd[CHANGED] ^= 0x01;
+
+        let res = compare(
+            img.as_slice(),
+            std::io::Cursor::new(&sd),
+            FILE_LEN as u64,
+            None,
+            None,
+            None,
+        );
+        assert!(matches!(
+            res,
+            Ok(Compared::Differs { offset, pending })
+                if offset == 2 * super::BUFFER_SIZE as u64
+                    && pending == img[(2 * super::BUFFER_SIZE)..(3 * super::BUFFER_SIZE)]
+        ));
+
+        // Changes outside mapped ranges are ignored
+        let mapped = [
+            0..(super::BUFFER_SIZE as u64),
+            (3 * super::BUFFER_SIZE as u64)..(FILE_LEN as u64),
+        ];
+        let res = compare(
+            img.as_slice(),
+            std::io::Cursor::new(&sd),
+            FILE_LEN as u64,
+            Some(&mapped),
+            None,
+            None,
+        );
+        assert!(matches!(res, Ok(Compared::Identical)));
+
+        // SD Card smaller than image
+        let res = compare(
+            img.as_slice(),
+            std::io::Cursor::new(&img[..super::BUFFER_SIZE]),
+            FILE_LEN as u64,
+            None,
+            None,
+            None,
+        );
+        assert!(
+            matches!(res, Ok(Compared::Differs { offset, .. }) if offset == super::BUFFER_SIZE as u64)
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! Write OS images to a sparse raw disk file, which can be used directly by QEMU
+//! (`-drive file=disk.img,format=raw`). Useful for testing images and customization in
+//! emulation.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use tokio::sync::mpsc;
+
+use crate::customization::Customization;
+use crate::flashing::write_sd;
+use crate::helpers::{DeviceWrapper, chan_send, check_token};
+use crate::marker::ProvisioningMarker;
+use crate::{Result, Status};
+
+const BLOCK_SIZE: usize = 4096;
+const SECTOR_SIZE: u64 = 512;
+
+/// Skips writing zeroed blocks, leaving holes in the file instead. Only valid for a newly
+/// created file, since existing data is not overwritten with zeros.
+struct Sparse<'a>(&'a mut File);
+
+impl Write for Sparse<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for chunk in buf.chunks(BLOCK_SIZE) {
+            if chunk.iter().all(|x| *x == 0) {
+                self.0.seek(SeekFrom::Current(chunk.len() as i64))?;
+            } else {
+                self.0.write_all(chunk)?;
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Seek for Sparse<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+/// Create disk of `size` bytes, rounded up to sector size. The disk is never smaller than the
+/// image.
+fn create_disk(dst: &Path, img_size: u64, size: Option<u64>) -> Result<File> {
+    let size = size
+        .unwrap_or(img_size)
+        .max(img_size)
+        .next_multiple_of(SECTOR_SIZE);
+
+    let f = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(dst)?;
+    f.set_len(size)?;
+
+    Ok(f)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_disk(
+    img: impl Read + Send,
+    img_size: u64,
+    bmap: Option<bb_bmap_parser::Bmap>,
+    mut disk: File,
+    mut chan: Option<mpsc::Sender<Status>>,
+    customization: Option<Customization>,
+    marker: Option<ProvisioningMarker>,
+    cancel: Option<tokio_util::sync::CancellationToken>,
+) -> Result<()> {
+    chan_send(chan.as_mut(), Status::Preparing);
+
+    tracing::info!("Writing to disk");
+    write_sd(
+        img,
+        img_size,
+        0,
+        bmap,
+        Sparse(&mut disk),
+        chan.as_mut(),
+        None,
+        cancel.clone(),
+    )?;
+
+    check_token(cancel.as_ref())?;
+
+    // Customization can overwrite data with zeros, so it should not skip zeroed blocks.
+    tracing::info!("Applying customization");
+    if let Some(c) = customization {
+        let temp = DeviceWrapper::new(&mut disk).unwrap();
+        c.customize(temp)?;
+    }
+
+    if let Some(m) = marker {
+        tracing::info!("Writing provisioning marker {}", m.id());
+        let temp = DeviceWrapper::new(&mut disk).unwrap();
+        m.write(temp)?;
+    }
+
+    disk.sync_all().map_err(Into::into)
+}
+
+/// Write OS image to a sparse raw disk file at `dst`, which can be used as a QEMU drive. Any
+/// existing file is overwritten.
+///
+/// The disk is `size` bytes, or the image size if not provided. Extra space is left unallocated,
+/// like a larger SD Card.
+///
+/// Verification, skipping identical data and formatting are not supported, since the disk is
+/// always newly created. See [flash](crate::flash) for details regarding other arguments.
+#[allow(clippy::too_many_arguments)]
+pub async fn flash_vm_disk<R: Read + Send + 'static>(
+    img: impl bb_helper::resolvable::Resolvable<ResolvedType = (R, u64)>,
+    bmap: Option<impl bb_helper::resolvable::Resolvable<ResolvedType = Box<str>>>,
+    dst: Box<Path>,
+    size: Option<u64>,
+    chan: Option<mpsc::Sender<Status>>,
+    customization: Option<Customization>,
+    marker: Option<ProvisioningMarker>,
+    cancel: Option<tokio_util::sync::CancellationToken>,
+) -> Result<()> {
+    if let Some(x) = &customization
+        && !x.validate()
+    {
+        return Err(crate::Error::InvalidCustomizaton);
+    }
+
+    let mut tasks = tokio::task::JoinSet::new();
+
+    tracing::info!("Resolving Image");
+    let bmap = match bmap {
+        Some(x) => Some(
+            bb_bmap_parser::Bmap::from_xml(&x.resolve(&mut tasks).await?)
+                .map_err(|_| crate::Error::InvalidBmap)?,
+        ),
+        None => None,
+    };
+    let (img, img_size) = img.resolve(&mut tasks).await?;
+
+    let cancel_child = cancel.as_ref().map(|x| x.child_token());
+    let res = tokio::task::spawn_blocking(move || {
+        tracing::info!("Creating disk {:?}", dst);
+        let disk = create_disk(&dst, img_size, size)?;
+        write_disk(
+            img,
+            img_size,
+            bmap,
+            disk,
+            chan,
+            customization,
+            marker,
+            cancel_child,
+        )
+    })
+    .await
+    .unwrap();
+
+    // Cancel all tasks on drop
+    let _drop_guard = cancel.map(|x| x.drop_guard());
+
+    while let Some(t) = tasks.join_next().await {
+        if let Err(e) = t.unwrap() {
+            tasks.abort_all();
+            return Err(e.into());
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{create_disk, write_disk};
+
+    #[test]
+    fn sparse_raw_disk() {
+        const MIB: usize = 1024 * 1024;
+
+        let mut img = vec![0u8; 3 * MIB + 100];
+        img[..MIB].fill(0xab);
+        img[3 * MIB..].fill(0xcd);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("disk.img");
+
+        let disk = create_disk(&path, img.len() as u64, Some(16 * MIB as u64)).unwrap();
+        write_disk(
+            Cursor::new(img.clone()),
+            img.len() as u64,
+            None,
+            disk,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(data.len(), 16 * MIB);
+        assert_eq!(&data[..img.len()], img.as_slice());
+        assert!(data[img.len()..].iter().all(|x| *x == 0));
+
+        // Zeroed blocks should not be allocated
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            let allocated = std::fs::metadata(&path).unwrap().blocks() * 512;
+            assert!(allocated < 3 * MIB as u64);
+        }
+
+        // Disk is never smaller than image
+        let disk = create_disk(&path, img.len() as u64, Some(MIB as u64)).unwrap();
+        assert_eq!(
+            disk.metadata().unwrap().len(),
+            img.len().next_multiple_of(512) as u64
+        );
+    }
+}
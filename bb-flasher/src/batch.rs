@@ -0,0 +1,118 @@
+//! Flash the same image to multiple targets, one after another. Useful for assembly lines.
+
+/// Behaviour when flashing one of the targets fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BatchPolicy {
+    /// Do not flash any more targets. Remaining targets are [skipped](BatchOutcome::Skipped).
+    #[default]
+    StopOnError,
+    /// Flash the remaining targets anyway.
+    ContinueOnError,
+}
+
+/// Result of flashing a single target in a batch.
+#[derive(Debug)]
+pub enum BatchOutcome {
+    Success,
+    Failed(anyhow::Error),
+    /// Not flashed due to an earlier failure.
+    Skipped,
+}
+
+impl BatchOutcome {
+    pub const fn is_success(&self) -> bool {
+        matches!(self, Self::Success)
+    }
+}
+
+impl std::fmt::Display for BatchOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Success => write!(f, "Success"),
+            Self::Failed(e) => write!(f, "Failed: {e:#}"),
+            Self::Skipped => write!(f, "Skipped"),
+        }
+    }
+}
+
+/// Flash each target using `flash`, in order. Returns the outcome of every target, in the same
+/// order as `targets`.
+pub async fn flash_many<T, I, F, Fut>(
+    targets: I,
+    policy: BatchPolicy,
+    mut flash: F,
+) -> Vec<(T, BatchOutcome)>
+where
+    T: Clone,
+    I: IntoIterator<Item = T>,
+    F: FnMut(T) -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    let mut failed = false;
+    let mut report = Vec::new();
+
+    for target in targets {
+        if failed && policy == BatchPolicy::StopOnError {
+            report.push((target, BatchOutcome::Skipped));
+            continue;
+        }
+
+        let outcome = match flash(target.clone()).await {
+            Ok(()) => BatchOutcome::Success,
+            Err(e) => {
+                tracing::error!("Flashing failed: {e:?}");
+                failed = true;
+                BatchOutcome::Failed(e)
+            }
+        };
+
+        report.push((target, outcome));
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn flash(target: u32) -> anyhow::Result<()> {
+        anyhow::ensure!(target != 2, "Injected failure");
+        Ok(())
+    }
+
+    fn summary(report: &[(u32, BatchOutcome)]) -> Vec<(u32, String)> {
+        report.iter().map(|(t, o)| (*t, o.to_string())).collect()
+    }
+
+    #[tokio::test]
+    async fn continue_on_error() {
+        let report = flash_many(1..=4, BatchPolicy::ContinueOnError, flash).await;
+
+        assert_eq!(
+            summary(&report),
+            [
+                (1, "Success".to_string()),
+                (2, "Failed: Injected failure".to_string()),
+                (3, "Success".to_string()),
+                (4, "Success".to_string()),
+            ]
+        );
+        assert_eq!(report.iter().filter(|(_, o)| o.is_success()).count(), 3);
+    }
+
+    #[tokio::test]
+    async fn stop_on_error() {
+        let report = flash_many(1..=4, BatchPolicy::StopOnError, flash).await;
+
+        assert_eq!(
+            summary(&report),
+            [
+                (1, "Success".to_string()),
+                (2, "Failed: Injected failure".to_string()),
+                (3, "Skipped".to_string()),
+                (4, "Skipped".to_string()),
+            ]
+        );
+    }
+}
@@ -1,6 +1,6 @@
 //! Stuff common to all the flashers
 
-use std::{borrow::Cow, collections::HashSet};
+use std::{borrow::Cow, collections::HashSet, time::Duration};
 
 use futures::channel::mpsc;
 #[cfg(any(feature = "bcf", feature = "bcf_msp430", feature = "pb2_mspm0"))]
@@ -20,12 +20,31 @@ pub(crate) enum FlasherError {
 ///
 /// The progress is denoted by [f32] between 0 and 1
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "stage", content = "progress", rename_all = "snake_case")
+)]
 pub enum DownloadFlashingStatus {
     Preparing,
     DownloadingProgress(f32),
     FlashingProgress(f32),
     Verifying,
     Customizing,
+    /// Destination already contains the image, so nothing was written.
+    UpToDate,
+}
+
+/// Summary of successful flashing, returned by [BBFlasher::flash_with_stats].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct FlashStats {
+    /// Bytes of image written to destination. 0 if the flasher does not track it, or destination
+    /// already contained the image.
+    pub bytes_written: u64,
+    /// Time taken for flashing, including any image downloading.
+    pub duration: Duration,
+    /// Destination contents were read back and found to match the image.
+    pub verified: bool,
 }
 
 /// A trait for modeling flashers. Also provides optional live status using channels.
@@ -38,6 +57,44 @@ pub trait BBFlasher {
         self,
         chan: Option<mpsc::Sender<DownloadFlashingStatus>>,
     ) -> impl Future<Output = anyhow::Result<()>>;
+
+    /// Same as [flash](Self::flash), but returns [FlashStats] on success, so applications do not
+    /// need to compute them from status updates.
+    ///
+    /// The default implementation only measures the duration. Flashers which track written data
+    /// should override it.
+    fn flash_with_stats(
+        self,
+        chan: Option<mpsc::Sender<DownloadFlashingStatus>>,
+    ) -> impl Future<Output = anyhow::Result<FlashStats>>
+    where
+        Self: Sized,
+    {
+        async move {
+            let start = std::time::Instant::now();
+            self.flash(chan).await?;
+
+            Ok(FlashStats {
+                bytes_written: 0,
+                duration: start.elapsed(),
+                verified: false,
+            })
+        }
+    }
+}
+
+/// Features supported by a flasher target. Allows applications to adapt their flow without
+/// special casing each flasher.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Capabilities {
+    /// User can choose between multiple destinations
+    pub selectable_destination: bool,
+    /// Flasher can verify the written image
+    pub supports_verify: bool,
+    /// Flasher accepts some customization options
+    pub supports_customization: bool,
+    /// File types (extensions) supported by the flasher
+    pub file_types: &'static [&'static str],
 }
 
 /// A trait for modeling flasher targets.
@@ -51,6 +108,18 @@ where
     /// applications
     const FILE_TYPES: &[&str];
     const IS_DESTINATION_SELECTABLE: bool = true;
+    const SUPPORTS_VERIFY: bool = false;
+    const SUPPORTS_CUSTOMIZATION: bool = false;
+
+    /// Features supported by the flasher target
+    fn capabilities() -> Capabilities {
+        Capabilities {
+            selectable_destination: Self::IS_DESTINATION_SELECTABLE,
+            supports_verify: Self::SUPPORTS_VERIFY,
+            supports_customization: Self::SUPPORTS_CUSTOMIZATION,
+            file_types: Self::FILE_TYPES,
+        }
+    }
 
     /// A list of possible flasher targets
     fn destinations(filter: bool) -> impl Future<Output = HashSet<Self>>;
@@ -58,3 +127,131 @@ where
     /// A sort of device ID (mostly a Path).
     fn identifier<'a>(&'a self) -> Cow<'a, str>;
 }
+
+/// Sort destinations in a canonical order (by name, then by identifier). [HashSet] returned by
+/// [BBFlasherTarget::destinations] has no stable order, so applications should use this to
+/// present destinations consistently.
+pub fn sort_destinations<T>(dsts: impl IntoIterator<Item = T>) -> Vec<T>
+where
+    T: BBFlasherTarget + std::fmt::Display,
+{
+    let mut dsts: Vec<T> = dsts.into_iter().collect();
+    dsts.sort_by_cached_key(|x| (x.to_string(), x.identifier().into_owned()));
+    dsts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Eq, Hash)]
+    struct DummyTarget(&'static str, &'static str);
+
+    impl std::fmt::Display for DummyTarget {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    impl BBFlasherTarget for DummyTarget {
+        const FILE_TYPES: &[&str] = &[];
+
+        async fn destinations(_: bool) -> HashSet<Self> {
+            HashSet::from([
+                Self("USB Stick", "/dev/sdb"),
+                Self("SD Card", "/dev/sdc"),
+                Self("SD Card", "/dev/mmcblk0"),
+                Self("Backup Drive", "/dev/sda"),
+            ])
+        }
+
+        fn identifier(&self) -> Cow<'_, str> {
+            Cow::Borrowed(self.1)
+        }
+    }
+
+    #[tokio::test]
+    async fn destination_order() {
+        let expected = [
+            DummyTarget("Backup Drive", "/dev/sda"),
+            DummyTarget("SD Card", "/dev/mmcblk0"),
+            DummyTarget("SD Card", "/dev/sdc"),
+            DummyTarget("USB Stick", "/dev/sdb"),
+        ];
+
+        for _ in 0..8 {
+            let dsts = sort_destinations(DummyTarget::destinations(true).await);
+            assert_eq!(dsts, expected);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "sd")]
+    fn sd_capabilities() {
+        assert_eq!(
+            crate::sd::Target::capabilities(),
+            Capabilities {
+                selectable_destination: true,
+                supports_verify: true,
+                supports_customization: true,
+                file_types: &["img", "xz"],
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "bcf")]
+    fn bcf_cc1352p7_capabilities() {
+        assert_eq!(
+            crate::bcf::cc1352p7::Target::capabilities(),
+            Capabilities {
+                selectable_destination: true,
+                supports_verify: true,
+                supports_customization: false,
+                file_types: &["bin", "hex", "txt", "xz"],
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "bcf_msp430")]
+    fn bcf_msp430_capabilities() {
+        assert_eq!(
+            crate::bcf::msp430::Target::capabilities(),
+            Capabilities {
+                selectable_destination: true,
+                supports_verify: false,
+                supports_customization: false,
+                file_types: &["hex", "txt", "xz"],
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(any(feature = "pb2_mspm0", feature = "pb2_mspm0_dbus"))]
+    fn pb2_mspm0_capabilities() {
+        assert_eq!(
+            crate::pb2::mspm0::Target::capabilities(),
+            Capabilities {
+                selectable_destination: false,
+                supports_verify: false,
+                supports_customization: true,
+                file_types: &["hex", "txt", "xz"],
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "dfu")]
+    fn dfu_capabilities() {
+        assert_eq!(
+            crate::dfu::Target::capabilities(),
+            Capabilities {
+                selectable_destination: true,
+                supports_verify: false,
+                supports_customization: false,
+                file_types: &[],
+            }
+        );
+    }
+}
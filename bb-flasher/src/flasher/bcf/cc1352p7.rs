@@ -25,6 +25,7 @@ impl From<String> for Target {
 
 impl BBFlasherTarget for Target {
     const FILE_TYPES: &[&str] = &["bin", "hex", "txt", "xz"];
+    const SUPPORTS_VERIFY: bool = true;
 
     fn destinations(filter: bool) -> impl Future<Output = std::collections::HashSet<Self>> {
         let temp = bb_flasher_bcf::cc1352p7::ports(filter)
@@ -33,6 +33,7 @@ pub struct Target {
 impl BBFlasherTarget for Target {
     const FILE_TYPES: &[&str] = &["hex", "txt", "xz"];
     const IS_DESTINATION_SELECTABLE: bool = false;
+    const SUPPORTS_CUSTOMIZATION: bool = true;
 
     // Since only a single destination is possible, no need for filters
     async fn destinations(_: bool) -> HashSet<Self> {
@@ -8,18 +8,54 @@ use std::{borrow::Cow, fmt::Display, path::PathBuf};
 
 use crate::{BBFlasher, BBFlasherTarget, DownloadFlashingStatus, Resolvable};
 
+pub use bb_flasher_sd::{
+    BenchmarkReport, CapacityReport, Filter, ProvisioningMarker, RpiImagerSettings, Verify,
+};
+
+/// Default safe-mode limit (256 GB). Anything larger is almost certainly not an SD Card.
+pub const DEFAULT_MAX_SIZE: u64 = 256 * 1000 * 1000 * 1000;
+
+/// Error returned when a device exceeds the safe-mode size limit.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error(
+    "Refusing to flash device of size {size} bytes, which is above the safe-mode limit of {max_size} bytes."
+)]
+pub struct SizeLimitError {
+    pub size: u64,
+    pub max_size: u64,
+}
+
+const fn check_size(size: u64, max_size: Option<u64>) -> Result<(), SizeLimitError> {
+    match max_size {
+        Some(max_size) if size > max_size => Err(SizeLimitError { size, max_size }),
+        _ => Ok(()),
+    }
+}
+
 /// SD Card
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
 pub struct Target(bb_flasher_sd::Device);
 
 impl Target {
-    fn destinations_internal(filter: bool) -> std::collections::HashSet<Self> {
-        bb_flasher_sd::devices(filter)
+    fn destinations_internal(filter: Filter) -> std::collections::HashSet<Self> {
+        bb_flasher_sd::devices_filtered(filter)
             .into_iter()
             .map(Self)
             .collect()
     }
 
+    /// List SD Cards using a specific [Filter]. [BBFlasherTarget::destinations] uses
+    /// [Filter::PreferCard] when filtering is enabled.
+    pub fn destinations_filtered(filter: Filter) -> std::collections::HashSet<Self> {
+        Self::destinations_internal(filter)
+    }
+
+    /// Generation of the set of drives in system. Destinations need to be listed again only when
+    /// this changes.
+    pub fn generation() -> u64 {
+        bb_flasher_sd::devices_generation()
+    }
+
     /// SD Card size in bytes
     pub const fn size(&self) -> u64 {
         self.0.size
@@ -28,6 +64,38 @@ impl Target {
     pub fn path(&self) -> &std::path::Path {
         &self.0.path
     }
+
+    /// Serial number or WWN of the SD Card, if available.
+    pub fn serial(&self) -> Option<&str> {
+        self.0.serial.as_deref()
+    }
+
+    /// Check if both targets refer to the same physical device. Device paths can change between
+    /// enumerations, so the serial is preferred. Falls back to path when serial is unavailable.
+    pub fn is_same_device(&self, other: &Self) -> bool {
+        match (self.serial(), other.serial()) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.path() == other.path(),
+        }
+    }
+
+    /// Write protect the SD Card to prevent accidental rewrites after provisioning. Best-effort,
+    /// fails with [bb_flasher_sd::Error::WriteProtectUnsupported] if the card reader does not
+    /// allow it.
+    pub fn lock(&self) -> Result<(), bb_flasher_sd::Error> {
+        bb_flasher_sd::lock(&self.0.path)
+    }
+
+    /// Remove write protection set using [Self::lock].
+    pub fn unlock(&self) -> Result<(), bb_flasher_sd::Error> {
+        bb_flasher_sd::unlock(&self.0.path)
+    }
+
+    /// Safe-mode check to prevent flashing large drives (like backup drives) by mistake. Fails if
+    /// the SD Card is larger than `max_size`. Passing [None] disables the check.
+    pub const fn check_size(&self, max_size: Option<u64>) -> Result<(), SizeLimitError> {
+        check_size(self.size(), max_size)
+    }
 }
 
 impl Display for Target {
@@ -40,7 +108,7 @@ impl TryFrom<PathBuf> for Target {
     type Error = std::io::Error;
 
     fn try_from(value: PathBuf) -> Result<Self, Self::Error> {
-        Self::destinations_internal(false)
+        Self::destinations_internal(Filter::None)
             .into_iter()
             .find(|x| x.0.path == value)
             .ok_or(std::io::Error::new(
@@ -52,9 +120,19 @@ impl TryFrom<PathBuf> for Target {
 
 impl BBFlasherTarget for Target {
     const FILE_TYPES: &[&str] = &["img", "xz"];
+    const SUPPORTS_VERIFY: bool = true;
+    const SUPPORTS_CUSTOMIZATION: bool = true;
 
     async fn destinations(filter: bool) -> std::collections::HashSet<Self> {
-        Self::destinations_internal(filter)
+        let filter = if filter {
+            Filter::PreferCard
+        } else {
+            Filter::None
+        };
+
+        tokio::task::spawn_blocking(move || Self::destinations_internal(filter))
+            .await
+            .unwrap()
     }
 
     fn identifier(&self) -> Cow<'_, str> {
@@ -62,10 +140,45 @@ impl BBFlasherTarget for Target {
     }
 }
 
-/// Linux Image post-install customization options. Only work on BeagleBoard.org images.
+/// Generate bmap for a raw OS image. Only zeroed blocks are considered unmapped, so the generated
+/// bmap might not be as efficient as one generated with filesystem information.
+pub fn generate_bmap(img: impl std::io::Read) -> Result<String, bb_flasher_sd::Error> {
+    bb_flasher_sd::generate_bmap(img)
+}
+
+/// Write `size` bytes of random data to an SD Card or regular file, read it back and measure
+/// throughput. Existing data at the start of `dst` is overwritten.
+pub async fn benchmark(
+    dst: &std::path::Path,
+    size: u64,
+) -> Result<BenchmarkReport, bb_flasher_sd::Error> {
+    bb_flasher_sd::benchmark(dst, size, None).await
+}
+
+/// Check if SD Card can store data up to its reported size. Counterfeit SD Cards report a larger
+/// size than they can actually store.
+///
+/// See [bb_flasher_sd::check_capacity] for details.
+pub async fn check_capacity(dst: &std::path::Path) -> Result<CapacityReport, bb_flasher_sd::Error> {
+    bb_flasher_sd::check_capacity(dst, None).await
+}
+
+impl From<bb_flasher_sd::Status> for DownloadFlashingStatus {
+    fn from(value: bb_flasher_sd::Status) -> Self {
+        match value {
+            bb_flasher_sd::Status::Preparing => Self::Preparing,
+            bb_flasher_sd::Status::Flashing(x) => Self::FlashingProgress(x),
+            bb_flasher_sd::Status::Verifying => Self::Verifying,
+        }
+    }
+}
+
+/// Linux Image post-install customization options. Sysconf only works on BeagleBoard.org images,
+/// raspberry only works on Raspberry Pi OS images and armbian only works on Armbian images.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct FlashingSdLinuxConfig {
     customization: Option<bb_flasher_sd::Customization>,
+    marker: Option<ProvisioningMarker>,
 }
 
 impl FlashingSdLinuxConfig {
@@ -86,18 +199,126 @@ impl FlashingSdLinuxConfig {
                     keymap,
                     user,
                     wifi,
+                    wifi_hidden: false,
+                    wifi_country: None,
                     ssh,
                     usb_enable_dhcp,
+                    expand_rootfs: None,
+                },
+            )),
+            marker: None,
+        }
+    }
+
+    pub const fn raspberry(
+        hostname: Option<Box<str>>,
+        timezone: Option<Box<str>>,
+        keymap: Option<Box<str>>,
+        user: Option<(Box<str>, Box<str>)>,
+        wlan: Option<(Box<str>, Box<str>)>,
+        wlan_country: Option<Box<str>>,
+        ssh: Option<Box<str>>,
+    ) -> Self {
+        Self {
+            customization: Some(bb_flasher_sd::Customization::Raspberry(
+                bb_flasher_sd::RaspberryCustomization {
+                    hostname,
+                    timezone,
+                    keymap,
+                    user,
+                    wlan,
+                    wlan_country,
+                    ssh,
+                },
+            )),
+            marker: None,
+        }
+    }
+
+    pub const fn cloud_init(
+        hostname: Option<Box<str>>,
+        timezone: Option<Box<str>>,
+        keymap: Option<Box<str>>,
+        user: Option<(Box<str>, Box<str>)>,
+        wifi: Option<(Box<str>, Box<str>)>,
+        ssh: Option<Box<str>>,
+    ) -> Self {
+        Self {
+            customization: Some(bb_flasher_sd::Customization::CloudInit(
+                bb_flasher_sd::CloudInitCustomization {
+                    hostname,
+                    timezone,
+                    keymap,
+                    user,
+                    wifi,
+                    ssh,
+                },
+            )),
+            marker: None,
+        }
+    }
+
+    pub const fn armbian(
+        hostname: Option<Box<str>>,
+        timezone: Option<Box<str>>,
+        keymap: Option<Box<str>>,
+        user: Option<(Box<str>, Box<str>)>,
+        wifi: Option<(Box<str>, Box<str>)>,
+    ) -> Self {
+        Self {
+            customization: Some(bb_flasher_sd::Customization::Armbian(
+                bb_flasher_sd::ArmbianCustomization {
+                    hostname,
+                    timezone,
+                    keymap,
+                    user,
+                    wifi,
                 },
             )),
+            marker: None,
         }
     }
 
     pub const fn none() -> Self {
         Self {
             customization: None,
+            marker: None,
         }
     }
+
+    /// Expand root filesystem to fill the SD Card on first boot. Only supported by sysconf
+    /// customization.
+    pub fn with_expand_rootfs(mut self, t: Option<bool>) -> Self {
+        if let Some(bb_flasher_sd::Customization::Sysconf(x)) = &mut self.customization {
+            x.expand_rootfs = t;
+        }
+        self
+    }
+
+    /// Mark Wi-Fi network as hidden, i.e. it does not broadcast its SSID. Only supported by
+    /// sysconf customization.
+    pub fn with_wifi_hidden(mut self, t: bool) -> Self {
+        if let Some(bb_flasher_sd::Customization::Sysconf(x)) = &mut self.customization {
+            x.wifi_hidden = t;
+        }
+        self
+    }
+
+    /// Set Wi-Fi regulatory country code (e.g. "IN"). Only supported by sysconf customization,
+    /// use [raspberry](Self::raspberry) to set it for Raspberry Pi OS.
+    pub fn with_wifi_country(mut self, t: Option<Box<str>>) -> Self {
+        if let Some(bb_flasher_sd::Customization::Sysconf(x)) = &mut self.customization {
+            x.wifi_country = t;
+        }
+        self
+    }
+
+    /// Write a [ProvisioningMarker] to boot partition after flashing. Only supported by
+    /// [Flasher], and ignored by [PartitionFlasher].
+    pub fn with_marker(mut self, marker: Option<ProvisioningMarker>) -> Self {
+        self.marker = marker;
+        self
+    }
 }
 
 /// Flasher to format SD Cards
@@ -132,6 +353,7 @@ pub struct Flasher<I: Resolvable, B: Resolvable> {
     bmap: Option<B>,
     dst: PathBuf,
     customization: FlashingSdLinuxConfig,
+    options: bb_flasher_sd::FlashOptions,
     cancel: Option<tokio_util::sync::CancellationToken>,
 }
 
@@ -140,11 +362,13 @@ where
     I: Resolvable,
     B: Resolvable,
 {
+    /// Pass `verify` to read back and compare SD Card contents after writing.
     pub fn new(
         img: I,
         bmap: Option<B>,
         dst: Target,
         customization: FlashingSdLinuxConfig,
+        verify: Option<Verify>,
         cancel: Option<tokio_util::sync::CancellationToken>,
     ) -> Self {
         Self {
@@ -152,9 +376,35 @@ where
             bmap,
             dst: dst.0.path,
             customization,
+            options: bb_flasher_sd::FlashOptions {
+                verify,
+                eject: true,
+                ..Default::default()
+            },
             cancel,
         }
     }
+
+    /// Compare SD Card with image before writing, and only write the data that differs. Sends
+    /// [DownloadFlashingStatus::UpToDate] if SD Card already contains the image.
+    pub fn with_skip_identical(mut self, skip_identical: bool) -> Self {
+        self.options.skip_identical = skip_identical;
+        self
+    }
+
+    /// Clear the partition table on SD Card before writing, so no partitions from previous
+    /// contents remain. Same as using [FormatFlasher] before flashing, but as a single operation.
+    pub fn with_format(mut self, format: bool) -> Self {
+        self.options.format = format;
+        self
+    }
+
+    /// Eject SD Card once flashing, verification and customization succeed. Failure to eject is
+    /// only logged as a warning. Enabled by default.
+    pub fn with_eject_on_success(mut self, eject: bool) -> Self {
+        self.options.eject = eject;
+        self
+    }
 }
 
 impl<I, B> BBFlasher for Flasher<I, B>
@@ -166,21 +416,32 @@ where
         self,
         chan: Option<futures::channel::mpsc::Sender<DownloadFlashingStatus>>,
     ) -> anyhow::Result<()> {
-        let customization = self.customization.customization;
+        self.flash_with_stats(chan).await.map(|_| ())
+    }
+
+    async fn flash_with_stats(
+        self,
+        chan: Option<futures::channel::mpsc::Sender<DownloadFlashingStatus>>,
+    ) -> anyhow::Result<crate::FlashStats> {
+        let FlashingSdLinuxConfig {
+            customization,
+            marker,
+        } = self.customization;
+        let options = bb_flasher_sd::FlashOptions {
+            marker,
+            ..self.options
+        };
         let dst = self.dst;
+        let start = std::time::Instant::now();
 
-        if let Some(mut chan) = chan {
-            let (tx, mut rx) = tokio::sync::mpsc::channel(2);
+        let res = if let Some(mut chan) = chan.clone() {
+            let (tx, mut rx) = tokio::sync::mpsc::channel::<bb_flasher_sd::Status>(2);
 
             let t = tokio::spawn(async move {
                 // Should run until tx is dropped, i.e. flasher task is done.
                 // If it is aborted, then cancel should be dropped, thereby signaling the flasher task to abort
                 while let Some(x) = rx.recv().await {
-                    let _ = chan.try_send(if x == 0.0 {
-                        DownloadFlashingStatus::Preparing
-                    } else {
-                        DownloadFlashingStatus::FlashingProgress(x)
-                    });
+                    let _ = chan.try_send(x.into());
                 }
             });
 
@@ -190,6 +451,7 @@ where
                 dst.into(),
                 Some(tx),
                 customization,
+                options,
                 self.cancel,
             )
             .await;
@@ -204,6 +466,197 @@ where
                 dst.into(),
                 None,
                 customization,
+                options,
+                self.cancel,
+            )
+            .await
+        }?;
+
+        if res.flashed == bb_flasher_sd::Flashed::UpToDate
+            && let Some(mut chan) = chan
+        {
+            let _ = chan.try_send(DownloadFlashingStatus::UpToDate);
+        }
+
+        Ok(crate::FlashStats {
+            bytes_written: res.bytes_written,
+            duration: start.elapsed(),
+            verified: res.verified,
+        })
+    }
+}
+
+/// Flasher to write Os Images to a sparse raw disk file, which can be used as a QEMU drive
+/// (`-drive file=disk.img,format=raw`). Customization is applied the same way as for SD Cards.
+///
+/// # Supported Images
+///
+/// - img: Raw images
+/// - xz: Xz compressed raw images
+#[derive(Debug, Clone)]
+pub struct VmDiskFlasher<I: Resolvable, B: Resolvable> {
+    img: I,
+    bmap: Option<B>,
+    dst: PathBuf,
+    size: Option<u64>,
+    customization: FlashingSdLinuxConfig,
+    cancel: Option<tokio_util::sync::CancellationToken>,
+}
+
+impl<I, B> VmDiskFlasher<I, B>
+where
+    I: Resolvable,
+    B: Resolvable,
+{
+    /// Any existing file at `dst` is overwritten. The disk is `size` bytes (image size if not
+    /// provided), which leaves space for the root filesystem to grow.
+    pub fn new(
+        img: I,
+        bmap: Option<B>,
+        dst: PathBuf,
+        size: Option<u64>,
+        customization: FlashingSdLinuxConfig,
+        cancel: Option<tokio_util::sync::CancellationToken>,
+    ) -> Self {
+        Self {
+            img,
+            bmap,
+            dst,
+            size,
+            customization,
+            cancel,
+        }
+    }
+}
+
+impl<I, B> BBFlasher for VmDiskFlasher<I, B>
+where
+    I: Resolvable<ResolvedType = (crate::OsImage, u64)> + Send + 'static,
+    B: Resolvable<ResolvedType = Box<str>> + Send + 'static,
+{
+    async fn flash(
+        self,
+        chan: Option<futures::channel::mpsc::Sender<DownloadFlashingStatus>>,
+    ) -> anyhow::Result<()> {
+        let FlashingSdLinuxConfig {
+            customization,
+            marker,
+        } = self.customization;
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<bb_flasher_sd::Status>(2);
+
+        let t = chan.map(|mut chan| {
+            tokio::spawn(async move {
+                while let Some(x) = rx.recv().await {
+                    let _ = chan.try_send(x.into());
+                }
+            })
+        });
+
+        let res = bb_flasher_sd::flash_vm_disk(
+            self.img,
+            self.bmap,
+            self.dst.into(),
+            self.size,
+            t.as_ref().map(|_| tx),
+            customization,
+            marker,
+            self.cancel,
+        )
+        .await;
+
+        if let Some(t) = t {
+            t.abort();
+        }
+
+        res.map_err(Into::into)
+    }
+}
+
+/// Flasher to flash only some partitions of Os Images to SD Card. Partitions are matched by
+/// number, and the partition table on SD Card is left untouched.
+///
+/// Useful to reflash boot or rootfs partitions while keeping data partitions intact.
+#[derive(Debug, Clone)]
+pub struct PartitionFlasher<I: Resolvable> {
+    img: I,
+    dst: PathBuf,
+    partitions: Box<[u32]>,
+    customization: FlashingSdLinuxConfig,
+    eject_on_success: bool,
+    cancel: Option<tokio_util::sync::CancellationToken>,
+}
+
+impl<I> PartitionFlasher<I>
+where
+    I: Resolvable,
+{
+    pub fn new(
+        img: I,
+        dst: Target,
+        partitions: Box<[u32]>,
+        customization: FlashingSdLinuxConfig,
+        cancel: Option<tokio_util::sync::CancellationToken>,
+    ) -> Self {
+        Self {
+            img,
+            dst: dst.0.path,
+            partitions,
+            customization,
+            eject_on_success: true,
+            cancel,
+        }
+    }
+
+    /// Eject SD Card once flashing and customization succeed. Failure to eject is only logged
+    /// as a warning. Enabled by default.
+    pub fn with_eject_on_success(mut self, eject: bool) -> Self {
+        self.eject_on_success = eject;
+        self
+    }
+}
+
+impl<I> BBFlasher for PartitionFlasher<I>
+where
+    I: Resolvable<ResolvedType = (crate::OsImage, u64)> + Send + 'static,
+{
+    async fn flash(
+        self,
+        chan: Option<futures::channel::mpsc::Sender<DownloadFlashingStatus>>,
+    ) -> anyhow::Result<()> {
+        let customization = self.customization.customization;
+        let dst = self.dst;
+
+        if let Some(mut chan) = chan {
+            let (tx, mut rx) = tokio::sync::mpsc::channel::<bb_flasher_sd::Status>(2);
+
+            let t = tokio::spawn(async move {
+                while let Some(x) = rx.recv().await {
+                    let _ = chan.try_send(x.into());
+                }
+            });
+
+            let resp = bb_flasher_sd::flash_partitions(
+                self.img,
+                dst.into(),
+                self.partitions,
+                Some(tx),
+                customization,
+                self.eject_on_success,
+                self.cancel,
+            )
+            .await;
+
+            t.abort();
+
+            resp
+        } else {
+            bb_flasher_sd::flash_partitions(
+                self.img,
+                dst.into(),
+                self.partitions,
+                None,
+                customization,
+                self.eject_on_success,
                 self.cancel,
             )
             .await
@@ -211,3 +664,59 @@ where
         .map_err(Into::into)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_threshold() {
+        assert!(check_size(32 * 1000 * 1000 * 1000, Some(DEFAULT_MAX_SIZE)).is_ok());
+        assert!(check_size(DEFAULT_MAX_SIZE, Some(DEFAULT_MAX_SIZE)).is_ok());
+        assert_eq!(
+            check_size(DEFAULT_MAX_SIZE + 1, Some(DEFAULT_MAX_SIZE)),
+            Err(SizeLimitError {
+                size: DEFAULT_MAX_SIZE + 1,
+                max_size: DEFAULT_MAX_SIZE
+            })
+        );
+    }
+
+    fn target(path: &str, serial: Option<&str>) -> Target {
+        Target(bb_flasher_sd::Device {
+            name: "SD Card".to_string(),
+            path: PathBuf::from(path),
+            size: 32 * 1000 * 1000 * 1000,
+            serial: serial.map(ToString::to_string),
+        })
+    }
+
+    #[test]
+    fn same_device_path_change() {
+        let selected = target("/dev/sdb", Some("0x1234"));
+        let devices = [
+            target("/dev/sdc", Some("0x5678")),
+            target("/dev/sdd", Some("0x1234")),
+        ];
+
+        let found = devices
+            .iter()
+            .find(|x| x.is_same_device(&selected))
+            .unwrap();
+        assert_eq!(found.path(), PathBuf::from("/dev/sdd"));
+    }
+
+    #[test]
+    fn same_device_no_serial() {
+        let selected = target("/dev/sdb", None);
+
+        assert!(selected.is_same_device(&target("/dev/sdb", None)));
+        assert!(!selected.is_same_device(&target("/dev/sdc", None)));
+        assert!(selected.is_same_device(&target("/dev/sdb", Some("0x1234"))));
+    }
+
+    #[test]
+    fn size_threshold_override() {
+        assert!(check_size(4 * 1000 * 1000 * 1000 * 1000, None).is_ok());
+    }
+}
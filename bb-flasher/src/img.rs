@@ -2,6 +2,7 @@
 
 use bb_helper::file_stream::ReaderFileStream;
 use rc_zip_sync::{ReadZip, ReadZipStreaming};
+use sha2::{Digest, Sha256};
 use std::{
     io::{Read, Seek, SeekFrom},
     path::Path,
@@ -10,6 +11,13 @@ use std::{
 pub struct OsImage {
     size: u64,
     img: OsImageReader,
+    sha256: Option<ImageSha256>,
+}
+
+/// SHA256 of the extracted image, computed while reading.
+struct ImageSha256 {
+    hasher: Sha256,
+    on_done: Box<dyn FnOnce([u8; 32]) + Send>,
 }
 
 pub(crate) enum OsImageReader {
@@ -22,6 +30,14 @@ pub(crate) enum OsImageReader {
 }
 
 impl OsImage {
+    const fn new(size: u64, img: OsImageReader) -> Self {
+        Self {
+            size,
+            img,
+            sha256: None,
+        }
+    }
+
     pub fn from_path(path: &Path) -> std::io::Result<Self> {
         let mut file = std::fs::File::open(path)?;
 
@@ -37,10 +53,7 @@ impl OsImage {
                 file.seek(std::io::SeekFrom::Start(0))?;
                 let img = liblzma::read::XzDecoder::new_parallel(file);
 
-                Ok(Self {
-                    size,
-                    img: OsImageReader::Xz(img),
-                })
+                Ok(Self::new(size, OsImageReader::Xz(img)))
             }
             [0x50, 0x4b, 0x03, 0x04, _, _] => {
                 let temp = file.read_zip()?;
@@ -52,18 +65,18 @@ impl OsImage {
 
                 let img = file.stream_zip_entries_throwing_caution_to_the_wind()?;
 
-                Ok(Self {
-                    size: img.entry().uncompressed_size,
-                    img: OsImageReader::Zip(img),
-                })
+                Ok(Self::new(
+                    img.entry().uncompressed_size,
+                    OsImageReader::Zip(img),
+                ))
             }
             _ => {
                 let size = size(&file.metadata()?);
 
-                Ok(Self {
+                Ok(Self::new(
                     size,
-                    img: OsImageReader::Uncompressed(std::io::BufReader::new(file)),
-                })
+                    OsImageReader::Uncompressed(std::io::BufReader::new(file)),
+                ))
             }
         }
     }
@@ -74,38 +87,60 @@ impl OsImage {
         img.seek(SeekFrom::Start(0))?;
 
         match magic {
-            [0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00] => Ok(Self {
+            [0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00] => Ok(Self::new(
                 size,
-                img: OsImageReader::XzPiped(liblzma::read::XzDecoder::new_parallel(img)),
-            }),
-            [0x50, 0x4b, 0x03, 0x04, _, _] => Ok(Self {
+                OsImageReader::XzPiped(liblzma::read::XzDecoder::new_parallel(img)),
+            )),
+            [0x50, 0x4b, 0x03, 0x04, _, _] => Ok(Self::new(
                 size,
-                img: OsImageReader::ZipPiped(
-                    img.stream_zip_entries_throwing_caution_to_the_wind()?,
-                ),
-            }),
-            _ => Ok(Self {
+                OsImageReader::ZipPiped(img.stream_zip_entries_throwing_caution_to_the_wind()?),
+            )),
+            _ => Ok(Self::new(
                 size,
-                img: OsImageReader::UncompressedPiped(std::io::BufReader::new(img)),
-            }),
+                OsImageReader::UncompressedPiped(std::io::BufReader::new(img)),
+            )),
         }
     }
 
     pub(crate) const fn size(&self) -> u64 {
         self.size
     }
+
+    /// Compute SHA256 of the extracted image while it is being read. `f` is called once the
+    /// complete image has been read, so it is never called if flashing fails or is cancelled.
+    pub fn with_sha256(mut self, f: impl FnOnce([u8; 32]) + Send + 'static) -> Self {
+        self.sha256 = Some(ImageSha256 {
+            hasher: Sha256::new(),
+            on_done: Box::new(f),
+        });
+        self
+    }
 }
 
 impl std::io::Read for OsImage {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        match &mut self.img {
+        let count = match &mut self.img {
             OsImageReader::Xz(x) => x.read(buf),
             OsImageReader::Uncompressed(x) => x.read(buf),
             OsImageReader::XzPiped(x) => x.read(buf),
             OsImageReader::UncompressedPiped(x) => x.read(buf),
             OsImageReader::ZipPiped(x) => x.read(buf),
             OsImageReader::Zip(x) => x.read(buf),
+        }?;
+
+        if count > 0 {
+            if let Some(x) = &mut self.sha256 {
+                x.hasher.update(&buf[..count]);
+            }
+        } else if !buf.is_empty()
+            && let Some(x) = self.sha256.take()
+        {
+            let hash: [u8; 32] = x.hasher.finalize().into();
+            tracing::info!("Image SHA256: {}", const_hex::encode(hash));
+            (x.on_done)(hash);
         }
+
+        Ok(count)
     }
 }
 
@@ -120,3 +155,49 @@ fn size(file: &std::fs::Metadata) -> u64 {
     use std::os::windows::fs::MetadataExt;
     file.file_size()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::sync::{Arc, Mutex};
+
+    use sha2::{Digest, Sha256};
+
+    use super::OsImage;
+
+    #[test]
+    fn sha256() {
+        let content: Vec<u8> = (0..(2 * 1024 * 1024)).map(|x| (x % 251) as u8).collect();
+        let expected: [u8; 32] = Sha256::digest(&content).into();
+
+        let dir = std::env::temp_dir().join(format!("bb-flasher-img-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("image.img.xz");
+
+        let mut enc = liblzma::write::XzEncoder::new(std::fs::File::create(&path).unwrap(), 6);
+        enc.write_all(&content).unwrap();
+        enc.finish().unwrap();
+
+        let res = Arc::new(Mutex::new(None));
+        let res_clone = res.clone();
+        let mut img = OsImage::from_path(&path)
+            .unwrap()
+            .with_sha256(move |x| *res_clone.lock().unwrap() = Some(x));
+
+        let mut buf = [0u8; 4096];
+        let mut data = Vec::new();
+        loop {
+            assert!(res.lock().unwrap().is_none());
+            let count = img.read(&mut buf).unwrap();
+            if count == 0 {
+                break;
+            }
+            data.extend_from_slice(&buf[..count]);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(data, content);
+        assert_eq!(*res.lock().unwrap(), Some(expected));
+    }
+}
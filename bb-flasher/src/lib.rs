@@ -17,7 +17,7 @@
 //!     let customization =
 //!         bb_flasher::sd::FlashingSdLinuxConfig::sysconfig(None, None, None, None, None, None, None);
 //!
-//!     let flasher = bb_flasher::sd::Flasher::new(img, None::<bb_helper::resolvable::LocalStringFile>, target, customization, None)
+//!     let flasher = bb_flasher::sd::Flasher::new(img, None::<bb_helper::resolvable::LocalStringFile>, target, customization, None, None)
 //!         .flash(None)
 //!         .await
 //!         .unwrap();
@@ -31,15 +31,21 @@
 //!   applications.
 //! - `sd_macos_authopen`: Uses authopen to provide GUI prompt to open SD Cards in MacOS. Useful
 //!   for GUI applications.
+//! - `sd_macos_eject`: Uses DiskArbitration instead of `diskutil` to eject SD Cards in MacOS.
+//! - `sd_simulate_slow_device`: Throttle SD Card writes to `BB_SIMULATE_SLOW_DEVICE` bytes/sec.
+//!   Only for testing UIs. Never enable in release builds.
 //! - `bcf`: Provde support for flashing the main processor (CC1352P7) in BeagleConnect Freedom.
 //! - `bcf_msp430`: Provide support for flashing MSP430 in BeagleConnect Freedom, which acts as the
 //!   USB to UART bridge.
 //! - `pb2_mspm0`: Provides support to flash PocketBeagle 2 MSPM0. Needs root permissions.
 //! - `pb2_mspm0_dbus`: Use bb-imager-serivce to flash PocketBeagle 2 as a normal user.
+//! - `serde`: Implement serialization for [DownloadFlashingStatus]. Useful for IPC.
 
+pub mod batch;
 mod common;
 mod flasher;
 mod img;
+pub mod registry;
 
 use std::path::Path;
 
@@ -0,0 +1,241 @@
+//! Registration point for custom flashers.
+//!
+//! Downstream applications can support boards with bespoke flashing protocols by registering a
+//! [FlasherFactory] under a name, without modifying the pre-defined flashers. The same name can
+//! then be used for `Custom` flashers in distros.json.
+//!
+//! # Usage
+//!
+//! ```no_run
+//! use bb_flasher::registry::{CustomTarget, FlasherFactory, register};
+//! use bb_flasher::{Capabilities, DownloadFlashingStatus, OsImage};
+//! use futures::{channel::mpsc, future::BoxFuture};
+//!
+//! struct MyFlasher;
+//!
+//! impl FlasherFactory for MyFlasher {
+//!     fn capabilities(&self) -> Capabilities {
+//!         Capabilities {
+//!             selectable_destination: true,
+//!             supports_verify: false,
+//!             supports_customization: false,
+//!             file_types: &["bin"],
+//!         }
+//!     }
+//!
+//!     fn destinations(&self, _: bool) -> BoxFuture<'static, Vec<CustomTarget>> {
+//!         Box::pin(async { vec![CustomTarget::new("My Board", "/dev/ttyUSB0")] })
+//!     }
+//!
+//!     fn flash(
+//!         &self,
+//!         mut img: OsImage,
+//!         dst: CustomTarget,
+//!         _: Option<mpsc::Sender<DownloadFlashingStatus>>,
+//!     ) -> BoxFuture<'static, anyhow::Result<()>> {
+//!         Box::pin(async move {
+//!             let mut data = Vec::new();
+//!             std::io::Read::read_to_end(&mut img, &mut data)?;
+//!             std::fs::write(dst.identifier().as_ref(), data)?;
+//!             Ok(())
+//!         })
+//!     }
+//! }
+//!
+//! register("MyFlasher", MyFlasher);
+//! ```
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use futures::{channel::mpsc, future::BoxFuture};
+
+use crate::{Capabilities, DownloadFlashingStatus, OsImage, Resolvable};
+
+static REGISTRY: LazyLock<RwLock<HashMap<String, Arc<dyn FlasherFactory>>>> =
+    LazyLock::new(Default::default);
+
+/// Object safe counterpart of [BBFlasher] and [BBFlasherTarget] for custom flashers.
+///
+/// [BBFlasher]: crate::BBFlasher
+/// [BBFlasherTarget]: crate::BBFlasherTarget
+pub trait FlasherFactory: Send + Sync {
+    /// Features supported by the flasher
+    fn capabilities(&self) -> Capabilities;
+
+    /// A list of possible flasher targets
+    fn destinations(&self, filter: bool) -> BoxFuture<'static, Vec<CustomTarget>>;
+
+    /// Flash the image to destination. Any download of the image is already complete.
+    fn flash(
+        &self,
+        img: OsImage,
+        dst: CustomTarget,
+        chan: Option<mpsc::Sender<DownloadFlashingStatus>>,
+    ) -> BoxFuture<'static, anyhow::Result<()>>;
+}
+
+/// Destination of a custom flasher
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CustomTarget {
+    name: String,
+    identifier: String,
+}
+
+impl CustomTarget {
+    pub fn new(name: impl Into<String>, identifier: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            identifier: identifier.into(),
+        }
+    }
+
+    /// A sort of device ID (mostly a Path).
+    pub fn identifier(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.identifier)
+    }
+}
+
+impl std::fmt::Display for CustomTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.name.fmt(f)
+    }
+}
+
+/// Register a custom flasher. Replaces any flasher already registered with the same name.
+pub fn register(name: impl Into<String>, factory: impl FlasherFactory + 'static) {
+    REGISTRY
+        .write()
+        .unwrap()
+        .insert(name.into(), Arc::new(factory));
+}
+
+/// Get custom flasher registered with `name`.
+pub fn get(name: &str) -> Option<Arc<dyn FlasherFactory>> {
+    REGISTRY.read().unwrap().get(name).cloned()
+}
+
+/// Check if a custom flasher is registered with `name`.
+pub fn is_registered(name: &str) -> bool {
+    REGISTRY.read().unwrap().contains_key(name)
+}
+
+/// Destinations of custom flasher in canonical order. Returns an empty list if no flasher is
+/// registered with `name`.
+pub async fn destinations(name: &str, filter: bool) -> Vec<CustomTarget> {
+    let Some(factory) = get(name) else {
+        return Vec::new();
+    };
+
+    let mut dsts = factory.destinations(filter).await;
+    dsts.sort_by(|a, b| (&a.name, &a.identifier).cmp(&(&b.name, &b.identifier)));
+    dsts
+}
+
+/// Resolve the image and flash it using custom flasher registered with `name`.
+pub async fn flash<I>(
+    name: &str,
+    img: I,
+    dst: CustomTarget,
+    chan: Option<mpsc::Sender<DownloadFlashingStatus>>,
+) -> anyhow::Result<()>
+where
+    I: Resolvable<ResolvedType = (OsImage, u64)>,
+{
+    let factory = get(name).ok_or_else(|| anyhow::anyhow!("Unknown flasher {name}"))?;
+
+    let mut tasks = tokio::task::JoinSet::new();
+    let (img, _) = img.resolve(&mut tasks).await?;
+
+    let res = factory.flash(img, dst, chan).await;
+
+    while let Some(t) = tasks.join_next().await {
+        if let Err(e) = t.unwrap() {
+            tasks.abort_all();
+            return Err(e.into());
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use futures::{SinkExt, StreamExt};
+
+    use super::*;
+
+    struct DummyFlasher;
+
+    impl FlasherFactory for DummyFlasher {
+        fn capabilities(&self) -> Capabilities {
+            Capabilities {
+                selectable_destination: true,
+                supports_verify: false,
+                supports_customization: false,
+                file_types: &["bin"],
+            }
+        }
+
+        fn destinations(&self, _: bool) -> BoxFuture<'static, Vec<CustomTarget>> {
+            Box::pin(async {
+                vec![
+                    CustomTarget::new("Dummy", "/dev/dummy1"),
+                    CustomTarget::new("Dummy", "/dev/dummy0"),
+                ]
+            })
+        }
+
+        fn flash(
+            &self,
+            mut img: OsImage,
+            dst: CustomTarget,
+            chan: Option<mpsc::Sender<DownloadFlashingStatus>>,
+        ) -> BoxFuture<'static, anyhow::Result<()>> {
+            Box::pin(async move {
+                let mut data = Vec::new();
+                img.read_to_end(&mut data)?;
+                anyhow::ensure!(data == b"dummy image", "Unexpected image");
+                anyhow::ensure!(dst.identifier() == "/dev/dummy0", "Unexpected target");
+
+                if let Some(mut c) = chan {
+                    c.send(DownloadFlashingStatus::FlashingProgress(1.0))
+                        .await
+                        .unwrap();
+                }
+
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_flasher() {
+        assert!(!is_registered("Dummy"));
+        register("Dummy", DummyFlasher);
+        assert!(is_registered("Dummy"));
+        assert_eq!(get("Dummy").unwrap().capabilities().file_types, &["bin"]);
+
+        let dsts = destinations("Dummy", true).await;
+        assert_eq!(dsts[0].identifier(), "/dev/dummy0");
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), b"dummy image").unwrap();
+
+        let (tx, rx) = mpsc::channel(4);
+        let img = crate::LocalImage::new(file.path().into());
+        flash("Dummy", img, dsts[0].clone(), Some(tx))
+            .await
+            .unwrap();
+
+        let status: Vec<_> = rx.collect().await;
+        assert_eq!(status, [DownloadFlashingStatus::FlashingProgress(1.0)]);
+
+        assert!(destinations("Unknown", true).await.is_empty());
+    }
+}
@@ -15,15 +15,40 @@ use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 
 pub struct WriterFileStream {
     file: tokio::fs::File,
+    /// Backing file path. Deleted on drop, unless persisted.
+    path: Option<tempfile::TempPath>,
     writing: Arc<AtomicBool>,
 }
 
 impl WriterFileStream {
-    const fn new(file: tokio::fs::File, writing: Arc<AtomicBool>) -> Self {
-        Self { file, writing }
+    const fn new(
+        file: tokio::fs::File,
+        path: tempfile::TempPath,
+        writing: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            file,
+            path: Some(path),
+            writing,
+        }
     }
 
+    /// Save all data written so far to `path`.
+    ///
+    /// The backing file is moved to `path` if both are on the same filesystem, which avoids
+    /// copying the data. In that case, this handle keeps writing to the persisted file at `path`,
+    /// so data written after persisting ends up in `path` too. Else, only the data written so far
+    /// is copied, and later writes go to the backing temporary file.
     pub async fn persist(&mut self, path: &Path) -> io::Result<()> {
+        self.file.flush().await?;
+
+        if let Some(temp) = self.path.take() {
+            match temp.persist(path) {
+                Ok(()) => return Ok(()),
+                Err(e) => self.path = Some(e.path),
+            }
+        }
+
         let mut f = tokio::fs::File::create(path).await?;
         self.file.seek(io::SeekFrom::Start(0)).await?;
 
@@ -101,12 +126,59 @@ impl std::io::Seek for ReaderFileStream {
     }
 }
 
+/// Create a file stream with backing file in system temp directory.
 pub fn file_stream() -> io::Result<(WriterFileStream, ReaderFileStream)> {
-    let file = tempfile::NamedTempFile::new()?;
+    from_temp(tempfile::NamedTempFile::new()?)
+}
+
+/// Create a file stream with backing file in `dir`. Should be preferred when the stream will be
+/// persisted in `dir`, since [WriterFileStream::persist] can move the backing file instead of
+/// copying it.
+pub fn file_stream_in(dir: &Path) -> io::Result<(WriterFileStream, ReaderFileStream)> {
+    from_temp(tempfile::NamedTempFile::new_in(dir)?)
+}
+
+fn from_temp(file: tempfile::NamedTempFile) -> io::Result<(WriterFileStream, ReaderFileStream)> {
     let flag = Arc::new(AtomicBool::new(true));
 
     let reader = ReaderFileStream::new(file.reopen()?, flag.clone());
-    let writer = WriterFileStream::new(file.into_file().into(), flag);
+    let (file, path) = file.into_parts();
+    let writer = WriterFileStream::new(file.into(), path, flag);
 
     Ok((writer, reader))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut writer, mut reader) = super::file_stream_in(dir.path()).unwrap();
+
+        writer.write_all(&[1; 4096]).await.unwrap();
+        let dst = dir.path().join("img");
+        writer.persist(&dst).await.unwrap();
+        assert_eq!(std::fs::read(&dst).unwrap(), [1; 4096]);
+        // Backing file was moved
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // Reader continues to work after persist
+        writer.write_all(&[2; 100]).await.unwrap();
+        writer.flush().await.unwrap();
+        drop(writer);
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf.len(), 4196);
+
+        // Backing file cannot be moved again, so it is copied
+        let (mut writer, _) = super::file_stream_in(dir.path()).unwrap();
+        writer.write_all(&[3; 100]).await.unwrap();
+        writer.persist(&dir.path().join("a")).await.unwrap();
+        writer.persist(&dir.path().join("b")).await.unwrap();
+        assert_eq!(std::fs::read(dir.path().join("b")).unwrap(), [3; 100]);
+    }
+}
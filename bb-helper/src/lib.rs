@@ -2,3 +2,4 @@
 pub mod file_stream;
 #[cfg(feature = "resolvable")]
 pub mod resolvable;
+pub mod size;
@@ -0,0 +1,114 @@
+//! Human readable sizes.
+//!
+//! SD Cards are sold in decimal units (GB), while most operating systems report sizes in binary
+//! units (GiB). Sizes are always formatted with an explicit unit to avoid confusion.
+
+/// Unit system used to format sizes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SizeUnit {
+    /// Powers of 1000 (kB, MB, GB, ...). Used by storage manufacturers.
+    Decimal,
+    /// Powers of 1024 (KiB, MiB, GiB, ...). Used by most operating systems.
+    #[default]
+    Binary,
+}
+
+impl SizeUnit {
+    const fn base(self) -> f64 {
+        match self {
+            Self::Decimal => 1000.0,
+            Self::Binary => 1024.0,
+        }
+    }
+
+    const fn units(self) -> [&'static str; 7] {
+        match self {
+            Self::Decimal => ["B", "kB", "MB", "GB", "TB", "PB", "EB"],
+            Self::Binary => ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"],
+        }
+    }
+
+    /// The other unit system.
+    pub const fn other(self) -> Self {
+        match self {
+            Self::Decimal => Self::Binary,
+            Self::Binary => Self::Decimal,
+        }
+    }
+
+    /// Format `bytes` with 2 decimal places, using the largest unit smaller than `bytes`.
+    ///
+    /// ```
+    /// use bb_helper::size::SizeUnit;
+    ///
+    /// assert_eq!(SizeUnit::Decimal.format(32_000_000_000), "32.00 GB");
+    /// assert_eq!(SizeUnit::Binary.format(32_000_000_000), "29.80 GiB");
+    /// ```
+    pub fn format(self, bytes: u64) -> String {
+        let units = self.units();
+        let base = self.base();
+
+        let mut size = bytes as f64;
+        let mut unit = 0;
+
+        while size >= base && unit < units.len() - 1 {
+            size /= base;
+            unit += 1;
+        }
+
+        if unit == 0 {
+            format!("{} {}", bytes, units[unit])
+        } else {
+            format!("{:.2} {}", size, units[unit])
+        }
+    }
+
+    /// Format `bytes` in both unit systems, with `self` first.
+    ///
+    /// ```
+    /// use bb_helper::size::SizeUnit;
+    ///
+    /// assert_eq!(
+    ///     SizeUnit::Decimal.format_both(32_000_000_000),
+    ///     "32.00 GB (29.80 GiB)"
+    /// );
+    /// ```
+    pub fn format_both(self, bytes: u64) -> String {
+        if bytes < 1000 {
+            return self.format(bytes);
+        }
+
+        format!("{} ({})", self.format(bytes), self.other().format(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SizeUnit;
+
+    #[test]
+    fn format() {
+        let cases = [
+            (0, "0 B", "0 B"),
+            (999, "999 B", "999 B"),
+            (1000, "1.00 kB", "1000 B"),
+            (1024, "1.02 kB", "1.00 KiB"),
+            (1536, "1.54 kB", "1.50 KiB"),
+            (15_931_539_456, "15.93 GB", "14.84 GiB"),
+            (31_914_983_424, "31.91 GB", "29.72 GiB"),
+            (1_000_000_000_000, "1.00 TB", "931.32 GiB"),
+            (u64::MAX, "18.45 EB", "16.00 EiB"),
+        ];
+
+        for (bytes, decimal, binary) in cases {
+            assert_eq!(SizeUnit::Decimal.format(bytes), decimal);
+            assert_eq!(SizeUnit::Binary.format(bytes), binary);
+        }
+
+        assert_eq!(SizeUnit::Binary.format_both(512), "512 B");
+        assert_eq!(
+            SizeUnit::Binary.format_both(31_914_983_424),
+            "29.72 GiB (31.91 GB)"
+        );
+    }
+}
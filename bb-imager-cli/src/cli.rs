@@ -1,6 +1,6 @@
 use std::path::{Path, PathBuf};
 
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser, Debug)]
 #[command(version, about)]
@@ -21,6 +21,16 @@ pub enum Commands {
         #[arg(long)]
         /// Suppress standard output messages for a quieter experience.
         quiet: bool,
+
+        #[arg(long)]
+        /// Publish progress as newline delimited JSON over a Unix domain socket (or named pipe on
+        /// Windows) at this path. Any number of observers can connect.
+        progress_socket: Option<PathBuf>,
+
+        #[arg(long)]
+        /// Ring the terminal bell and show a desktop notification when flashing finishes. Useful
+        /// for long unattended flashes.
+        notify: bool,
     },
 
     /// Command to list available destinations for flashing based on the selected target.
@@ -36,6 +46,10 @@ pub enum Commands {
         /// Show all possible destinations without any sanity filters. Can be used when a device is
         /// not visible due to incorrect reporting by OS.
         no_filter: bool,
+
+        #[arg(long, value_enum, default_value_t)]
+        /// Unit used to show SD Card sizes.
+        size_unit: SizeUnitArg,
     },
 
     /// Command to format SD Card
@@ -46,6 +60,49 @@ pub enum Commands {
         #[arg(long)]
         /// Suppress standard output messages for a quieter experience.
         quiet: bool,
+
+        #[arg(long)]
+        /// Allow formatting devices larger than 256 GB, which are normally refused as a safety
+        /// measure.
+        allow_large_device: bool,
+    },
+
+    /// Command to write protect SD Card. Not supported by all card readers.
+    WriteProtect {
+        /// The destination device (e.g., `/dev/sdX` or specific device identifiers).
+        dst: PathBuf,
+
+        #[arg(long)]
+        /// Remove write protection instead.
+        unlock: bool,
+    },
+
+    /// Command to measure write and read throughput of an SD Card (or file), by writing random
+    /// data and verifying it. Useful to diagnose slow card readers.
+    ///
+    /// WARNING: Existing data at the start of the SD Card is overwritten.
+    Benchmark {
+        /// The destination device (e.g., `/dev/sdX`) or file. Files are created if not present.
+        dst: PathBuf,
+
+        #[arg(long, default_value_t = 256 * 1024 * 1024)]
+        /// Bytes to write and read back.
+        size: u64,
+    },
+
+    /// Command to generate bmap for an image. Zeroed blocks are considered unused.
+    GenBmap {
+        /// Local path to image file. Can be compressed (xz) or extracted file
+        img: Box<Path>,
+
+        /// Path to write the generated bmap file
+        output: PathBuf,
+    },
+
+    /// Commands to inspect distros.json config files.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
     },
 
     /// Command to generate shell completion
@@ -55,6 +112,37 @@ pub enum Commands {
     },
 }
 
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommands {
+    /// List all OS images in the config, including the ones in sublists.
+    List {
+        #[arg(long = "config-file", env = bb_config::CONFIG_FILE_ENV)]
+        /// Local path to config file. Uses the bundled config if not provided.
+        config: Option<PathBuf>,
+
+        #[arg(long)]
+        /// Only list images released on or after this date (e.g., "2024-01-01").
+        since: Option<chrono::NaiveDate>,
+    },
+    /// Print the config as JSON with stable ordering, for minimal diffs when editing.
+    Format {
+        #[arg(long = "config-file", env = bb_config::CONFIG_FILE_ENV)]
+        /// Local path to config file. Uses the bundled config if not provided.
+        config: Option<PathBuf>,
+
+        #[arg(long)]
+        /// Write the formatted config to this path instead of standard output.
+        output: Option<PathBuf>,
+    },
+    /// Check the config for common mistakes, such as images using device tags not declared by any
+    /// board. Exits with non-zero status if any issue is found.
+    Lint {
+        #[arg(long = "config-file", env = bb_config::CONFIG_FILE_ENV)]
+        /// Local path to config file. Uses the bundled config if not provided.
+        config: Option<PathBuf>,
+    },
+}
+
 #[derive(Subcommand, Debug)]
 pub enum TargetCommands {
     /// Flash BeagleConnect Freedom.
@@ -75,46 +163,84 @@ pub enum TargetCommands {
         /// Local path to image file. Can be compressed (xz) or extracted file
         img: Box<Path>,
 
-        /// The destination device (e.g., `/dev/sdX` or specific device identifiers).
-        dst: PathBuf,
+        #[arg(required = true)]
+        /// The destination device(s) (e.g., `/dev/sdX` or specific device identifiers). Multiple
+        /// SD Cards are flashed one after another.
+        dst: Vec<PathBuf>,
 
         #[arg(long)]
-        /// Set a custom hostname for the device (e.g., "beaglebone").
-        hostname: Option<Box<str>>,
+        /// When flashing multiple SD Cards, continue with the remaining cards if one fails. A
+        /// report of all cards is printed in the end.
+        continue_on_error: bool,
+
+        #[command(flatten)]
+        customization: SdCustomizationArgs,
 
+        /// Provide the bmap file for the image
         #[arg(long)]
-        /// Set the timezone for the device (e.g., "America/New_York").
-        timezone: Option<Box<str>>,
+        bmap: Option<Box<Path>>,
 
         #[arg(long)]
-        /// Set the keyboard layout/keymap (e.g., "us" for the US layout).
-        keymap: Option<Box<str>>,
+        /// Allow flashing devices larger than 256 GB, which are normally refused as a safety
+        /// measure.
+        allow_large_device: bool,
+
+        #[arg(
+            long,
+            value_enum,
+            num_args = 0..=1,
+            default_missing_value = "sha256",
+            conflicts_with = "partitions"
+        )]
+        /// Read back and verify the SD Card after flashing. Defaults to sha256 if no hash is given.
+        verify: Option<VerifyHash>,
 
-        #[arg(long, requires = "user_password", verbatim_doc_comment)]
-        /// Set a username for the default user. Cannot be `root`. Requires `user_password`.
-        /// Required to enter GUI session due to regulatory requirements.
-        user_name: Option<Box<str>>,
+        #[arg(long, conflicts_with = "partitions")]
+        /// Read back the SD Card before flashing, and only write the data that differs from the
+        /// image. Nothing is written if the SD Card already contains the image.
+        skip_identical: bool,
 
-        #[arg(long, requires = "user_name", verbatim_doc_comment)]
-        /// Set a password for the default user. Requires `user_name`.
-        /// Required to enter GUI session due to regulatory requirements.
-        user_password: Option<Box<str>>,
+        #[arg(long, conflicts_with_all = ["partitions", "skip_identical"])]
+        /// Clear the partition table on the SD Card before flashing, so no partitions from
+        /// previous contents remain.
+        format_before_flash: bool,
 
-        #[arg(long, requires = "wifi_password")]
-        /// Configure a Wi-Fi SSID for network access. Requires `wifi_password`.
-        wifi_ssid: Option<Box<str>>,
+        #[arg(long, value_delimiter = ',', conflicts_with = "bmap")]
+        /// Only flash the given partitions (e.g., "1,2") to the matching partitions on the SD Card.
+        /// The partition table and other partitions on the SD Card are left untouched.
+        partitions: Option<Vec<u32>>,
 
-        #[arg(long, requires = "wifi_ssid")]
-        /// Set the password for the specified Wi-Fi SSID. Requires `wifi_ssid`.
-        wifi_password: Option<Box<str>>,
+        #[arg(long, conflicts_with = "partitions")]
+        /// Write a provisioning marker (unique ID, timestamp and image SHA256) to the boot
+        /// partition after flashing, and print its ID. Useful to track cards at provisioning
+        /// stations.
+        provisioning_marker: bool,
 
         #[arg(long)]
-        /// Set SSH public key for authentication
-        ssh_key: Option<Box<str>>,
+        /// Eject the SD Card(s) after flashing succeeds, so they can be removed safely.
+        eject_after: bool,
 
         #[arg(long)]
-        /// Enable USB DHCP
-        usb_enable_dhcp: bool,
+        /// Test if the SD Card(s) can store data up to their reported size before flashing, and
+        /// warn if not. Counterfeit SD Cards report a larger size than they can actually store.
+        check_capacity: bool,
+    },
+    /// Write an image to a sparse raw disk file, which can be used as a QEMU drive (e.g.,
+    /// `-drive file=disk.img,format=raw`). Supports the same customization as SD Cards.
+    VmDisk {
+        /// Local path to image file. Can be compressed (xz) or extracted file
+        img: Box<Path>,
+
+        /// Path of the disk file to create. Any existing file is overwritten.
+        dst: PathBuf,
+
+        #[arg(long)]
+        /// Size of the disk in bytes. Defaults to the image size. Extra space is left unallocated.
+        size: Option<u64>,
+
+        #[command(flatten)]
+        customization: SdCustomizationArgs,
+
         /// Provide the bmap file for the image
         #[arg(long)]
         bmap: Option<Box<Path>>,
@@ -149,6 +275,168 @@ pub enum TargetCommands {
     },
 }
 
+/// Post-install customization options for SD Card images. Options can also be set using `BB_*`
+/// environment variables, so secrets are not passed on the command line. Flags take precedence
+/// over environment variables.
+#[derive(Args, Debug, Default)]
+pub struct SdCustomizationArgs {
+    #[arg(long)]
+    /// Write customization in Raspberry Pi OS format (custom.toml) instead of sysconf. Use for
+    /// Raspberry Pi OS based images.
+    pub raspberry: bool,
+
+    #[arg(long, env = "BB_HOSTNAME")]
+    /// Set a custom hostname for the device (e.g., "beaglebone").
+    pub hostname: Option<Box<str>>,
+
+    #[arg(long, env = "BB_TIMEZONE")]
+    /// Set the timezone for the device (e.g., "America/New_York").
+    pub timezone: Option<Box<str>>,
+
+    #[arg(long, env = "BB_KEYMAP")]
+    /// Set the keyboard layout/keymap (e.g., "us" for the US layout).
+    pub keymap: Option<Box<str>>,
+
+    #[arg(
+        long,
+        env = "BB_USER_NAME",
+        requires = "user_password",
+        verbatim_doc_comment
+    )]
+    /// Set a username for the default user. Cannot be `root`. Requires `user_password`.
+    /// Required to enter GUI session due to regulatory requirements.
+    pub user_name: Option<Box<str>>,
+
+    #[arg(
+        long,
+        env = "BB_USER_PASSWORD",
+        hide_env_values = true,
+        requires = "user_name",
+        verbatim_doc_comment
+    )]
+    /// Set a password for the default user. Requires `user_name`.
+    /// Required to enter GUI session due to regulatory requirements.
+    pub user_password: Option<Box<str>>,
+
+    #[arg(
+        long,
+        env = "BB_WIFI_SSID",
+        visible_alias = "wlan-ssid",
+        requires = "wifi_password"
+    )]
+    /// Configure a Wi-Fi SSID for network access. Requires `wifi_password`.
+    pub wifi_ssid: Option<Box<str>>,
+
+    #[arg(
+        long,
+        env = "BB_WIFI_PASSWORD",
+        hide_env_values = true,
+        visible_alias = "wlan-password",
+        requires = "wifi_ssid"
+    )]
+    /// Set the password for the specified Wi-Fi SSID. Requires `wifi_ssid`.
+    pub wifi_password: Option<Box<str>>,
+
+    #[arg(
+        long,
+        env = "BB_WIFI_COUNTRY",
+        visible_alias = "wlan-country",
+        requires = "wifi_ssid"
+    )]
+    /// Set the Wi-Fi country code (e.g., "IN"). Requires `wifi_ssid`.
+    pub wifi_country: Option<Box<str>>,
+
+    #[arg(
+        long,
+        env = "BB_WIFI_HIDDEN",
+        requires = "wifi_ssid",
+        conflicts_with = "raspberry"
+    )]
+    /// Wi-Fi network does not broadcast its SSID. Requires `wifi_ssid`.
+    pub wifi_hidden: bool,
+
+    #[arg(long, env = "BB_SSH_KEY")]
+    /// Set SSH public key for authentication
+    pub ssh_key: Option<Box<str>>,
+
+    #[arg(long, env = "BB_USB_ENABLE_DHCP", conflicts_with = "raspberry")]
+    /// Enable USB DHCP
+    pub usb_enable_dhcp: bool,
+
+    #[arg(long, env = "BB_EXPAND_ROOTFS", conflicts_with = "raspberry")]
+    /// Expand root filesystem to fill the SD Card on first boot
+    pub expand_rootfs: bool,
+}
+
+impl SdCustomizationArgs {
+    pub fn customization(self) -> bb_flasher::sd::FlashingSdLinuxConfig {
+        let user = self.user_name.zip(self.user_password);
+        let wifi = self.wifi_ssid.zip(self.wifi_password);
+
+        if self.raspberry {
+            bb_flasher::sd::FlashingSdLinuxConfig::raspberry(
+                self.hostname,
+                self.timezone,
+                self.keymap,
+                user,
+                wifi,
+                self.wifi_country,
+                self.ssh_key,
+            )
+        } else {
+            bb_flasher::sd::FlashingSdLinuxConfig::sysconfig(
+                self.hostname,
+                self.timezone,
+                self.keymap,
+                user,
+                wifi,
+                self.ssh_key,
+                Some(self.usb_enable_dhcp),
+            )
+            .with_expand_rootfs(self.expand_rootfs.then_some(true))
+            .with_wifi_hidden(self.wifi_hidden)
+            .with_wifi_country(self.wifi_country)
+        }
+    }
+}
+
+/// Hash used to verify SD Card after flashing.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum VerifyHash {
+    /// Slow, but also detects tampering.
+    Sha256,
+    /// Much faster. Only detects accidental corruption.
+    Crc32,
+}
+
+impl From<VerifyHash> for bb_flasher::sd::Verify {
+    fn from(value: VerifyHash) -> Self {
+        match value {
+            VerifyHash::Sha256 => Self::Sha256,
+            VerifyHash::Crc32 => Self::Crc32,
+        }
+    }
+}
+
+/// Unit used to show sizes.
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum SizeUnitArg {
+    /// Powers of 1000 (GB), as printed on SD Cards.
+    Decimal,
+    /// Powers of 1024 (GiB), as reported by most operating systems.
+    #[default]
+    Binary,
+}
+
+impl From<SizeUnitArg> for bb_helper::size::SizeUnit {
+    fn from(value: SizeUnitArg) -> Self {
+        match value {
+            SizeUnitArg::Decimal => Self::Decimal,
+            SizeUnitArg::Binary => Self::Binary,
+        }
+    }
+}
+
 #[derive(ValueEnum, Clone, Copy, Debug)]
 pub enum DestinationsTarget {
     /// BeagleConnect Freedom targets.
@@ -166,3 +454,181 @@ pub enum DestinationsTarget {
     #[cfg(feature = "dfu")]
     Dfu,
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::{Commands, Opt, TargetCommands};
+
+    /// Held by tests which parse customization, since environment variables are process wide.
+    static ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    fn customization(args: &[&str]) -> clap::error::Result<bb_flasher::sd::FlashingSdLinuxConfig> {
+        let opt = Opt::try_parse_from(
+            ["bb-imager-cli", "flash", "sd", "img.xz", "/dev/sdb"]
+                .iter()
+                .chain(args),
+        )?;
+
+        match opt.command {
+            Commands::Flash { target, .. } => match *target {
+                TargetCommands::Sd { customization, .. } => Ok(customization.customization()),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn sd_customization() {
+        use bb_flasher::sd::FlashingSdLinuxConfig;
+        let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        assert_eq!(
+            customization(&["--hostname", "beagle", "--usb-enable-dhcp"]).unwrap(),
+            FlashingSdLinuxConfig::sysconfig(
+                Some("beagle".into()),
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(true)
+            )
+        );
+
+        assert_eq!(
+            customization(&[
+                "--raspberry",
+                "--hostname",
+                "pi",
+                "--wlan-ssid",
+                "Home",
+                "--wlan-password",
+                "secret",
+                "--wlan-country",
+                "IN"
+            ])
+            .unwrap(),
+            FlashingSdLinuxConfig::raspberry(
+                Some("pi".into()),
+                None,
+                None,
+                None,
+                Some(("Home".into(), "secret".into())),
+                Some("IN".into()),
+                None
+            )
+        );
+
+        // Sysconf only options
+        assert!(customization(&["--raspberry", "--usb-enable-dhcp"]).is_err());
+        assert!(customization(&["--raspberry", "--wifi-hidden"]).is_err());
+
+        assert_eq!(
+            customization(&[
+                "--wifi-ssid",
+                "Home",
+                "--wifi-password",
+                "x",
+                "--wifi-country",
+                "IN",
+                "--wifi-hidden"
+            ])
+            .unwrap(),
+            FlashingSdLinuxConfig::sysconfig(
+                None,
+                None,
+                None,
+                None,
+                Some(("Home".into(), "x".into())),
+                None,
+                Some(false)
+            )
+            .with_wifi_hidden(true)
+            .with_wifi_country(Some("IN".into()))
+        );
+        // Wi-Fi options require SSID
+        assert!(customization(&["--wifi-country", "IN"]).is_err());
+    }
+
+    #[test]
+    fn completion_flags() {
+        let parse = |args: &[&str]| match Opt::try_parse_from(args).unwrap().command {
+            Commands::Flash { target, notify, .. } => match *target {
+                TargetCommands::Sd { eject_after, .. } => (notify, eject_after),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+
+        assert_eq!(
+            parse(&["bb-imager-cli", "flash", "sd", "img.xz", "/dev/sdb"]),
+            (false, false)
+        );
+        assert_eq!(
+            parse(&[
+                "bb-imager-cli",
+                "flash",
+                "--notify",
+                "sd",
+                "img.xz",
+                "/dev/sdb",
+                "--eject-after"
+            ]),
+            (true, true)
+        );
+    }
+
+    #[test]
+    fn sd_customization_env() {
+        use bb_flasher::sd::FlashingSdLinuxConfig;
+
+        const VARS: [(&str, &str); 4] = [
+            ("BB_HOSTNAME", "beagle"),
+            ("BB_WIFI_SSID", "Home"),
+            ("BB_WIFI_PASSWORD", "secret"),
+            ("BB_SSH_KEY", "ssh-ed25519 AAAA"),
+        ];
+
+        let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        for (k, v) in VARS {
+            // SAFETY: Environment is only modified while holding ENV_LOCK
+            unsafe { std::env::set_var(k, v) };
+        }
+        let from_env = customization(&[]);
+        let with_flags = customization(&["--hostname", "override", "--wifi-ssid", "Office"]);
+        for (k, _) in VARS {
+            // SAFETY: Environment is only modified while holding ENV_LOCK
+            unsafe { std::env::remove_var(k) };
+        }
+
+        assert_eq!(
+            from_env.unwrap(),
+            FlashingSdLinuxConfig::sysconfig(
+                Some("beagle".into()),
+                None,
+                None,
+                None,
+                Some(("Home".into(), "secret".into())),
+                Some("ssh-ed25519 AAAA".into()),
+                Some(false)
+            )
+        );
+
+        // Flags take precedence
+        assert_eq!(
+            with_flags.unwrap(),
+            FlashingSdLinuxConfig::sysconfig(
+                Some("override".into()),
+                None,
+                None,
+                None,
+                Some(("Office".into(), "secret".into())),
+                Some("ssh-ed25519 AAAA".into()),
+                Some(false)
+            )
+        );
+    }
+}
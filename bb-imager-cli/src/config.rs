@@ -0,0 +1,56 @@
+//! Commands to inspect distros.json config files.
+
+use std::path::Path;
+
+const DEFAULT_CONFIG: &[u8] = include_bytes!("../../config.json");
+
+fn load(path: Option<&Path>) -> bb_config::Config {
+    match path {
+        Some(p) => {
+            let data = std::fs::read(p).expect("Failed to read config");
+            serde_json::from_slice(&data).expect("Failed to parse config")
+        }
+        None => serde_json::from_slice(DEFAULT_CONFIG).expect("Failed to parse bundled config"),
+    }
+}
+
+pub(crate) fn list(path: Option<&Path>, since: Option<chrono::NaiveDate>) {
+    let config = load(path);
+    let term = console::Term::stdout();
+
+    let images: Box<dyn Iterator<Item = &bb_config::config::OsImage>> = match since {
+        Some(date) => Box::new(config.images_since(date)),
+        None => Box::new(config.images()),
+    };
+
+    for img in images {
+        term.write_line(&format!("{}  {}", img.release_date, img.name))
+            .unwrap();
+    }
+}
+
+pub(crate) fn lint(path: Option<&Path>) {
+    let config = load(path);
+    let term = console::Term::stderr();
+
+    let warnings = config.validate();
+    for w in &warnings {
+        term.write_line(&format!("warning: {w}")).unwrap();
+    }
+
+    if !warnings.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+pub(crate) fn format(path: Option<&Path>, output: Option<&Path>) {
+    let config = load(path);
+    let json = config
+        .to_canonical_json()
+        .expect("Failed to serialize config");
+
+    match output {
+        Some(p) => std::fs::write(p, json + "\n").expect("Failed to write config"),
+        None => console::Term::stdout().write_line(&json).unwrap(),
+    }
+}
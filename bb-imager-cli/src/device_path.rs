@@ -0,0 +1,82 @@
+//! Resolve device paths passed by users. Paths can be relative, or symlinks like
+//! `/dev/disk/by-id/...`, while enumerated destinations always use the real device path.
+
+use std::path::PathBuf;
+
+/// Canonicalize `dst` to the real device, so it matches the enumerated destinations and all
+/// checks apply to the real device. Fails if `dst` is not a device.
+#[cfg(unix)]
+pub(crate) fn resolve(dst: PathBuf) -> anyhow::Result<PathBuf> {
+    resolve_with(&dst, is_device)
+}
+
+/// Device paths on Windows (e.g. `\\.\PhysicalDrive1`) cannot be canonicalized.
+#[cfg(not(unix))]
+pub(crate) fn resolve(dst: PathBuf) -> anyhow::Result<PathBuf> {
+    Ok(dst)
+}
+
+#[cfg(unix)]
+fn resolve_with(
+    dst: &std::path::Path,
+    is_device: impl Fn(&std::fs::FileType) -> bool,
+) -> anyhow::Result<PathBuf> {
+    use anyhow::Context;
+
+    let real = std::fs::canonicalize(dst)
+        .with_context(|| format!("Failed to resolve device path {}", dst.display()))?;
+    let meta = std::fs::metadata(&real)
+        .with_context(|| format!("Failed to read device {}", real.display()))?;
+
+    anyhow::ensure!(
+        is_device(&meta.file_type()),
+        "{} is not a block device",
+        real.display()
+    );
+
+    Ok(real)
+}
+
+/// Raw disks (`/dev/rdiskN`) on MacOS are character devices.
+#[cfg(unix)]
+fn is_device(t: &std::fs::FileType) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    t.is_block_device() || (cfg!(target_os = "macos") && t.is_char_device())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::FileTypeExt;
+
+    use super::resolve_with;
+
+    #[test]
+    fn symlink() {
+        let dir = std::env::temp_dir().join(format!("bb-imager-cli-dev-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let link = dir.join("by-id");
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink("/dev/null", &link).unwrap();
+
+        // /dev/null is used as a stand-in, since tests cannot rely on any block device
+        let is_char = |t: &std::fs::FileType| t.is_char_device();
+        assert_eq!(
+            resolve_with(&link, is_char).unwrap(),
+            std::path::Path::new("/dev/null")
+        );
+        // Non-canonical path to symlink
+        let path = dir.join("..").join(dir.file_name().unwrap()).join("by-id");
+        assert_eq!(
+            resolve_with(&path, is_char).unwrap(),
+            std::path::Path::new("/dev/null")
+        );
+
+        // Not a block device
+        assert!(resolve_with(&link, |t| t.is_block_device()).is_err());
+        assert!(resolve_with(&dir, is_char).is_err());
+        assert!(resolve_with(&dir.join("missing"), is_char).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
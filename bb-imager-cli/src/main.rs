@@ -1,9 +1,12 @@
 mod cli;
+mod config;
+mod device_path;
+mod progress_socket;
 
 use bb_flasher::{BBFlasher, BBFlasherTarget, DownloadFlashingStatus, LocalImage};
 use bb_helper::resolvable::LocalStringFile;
 use clap::{CommandFactory, Parser};
-use cli::{Commands, DestinationsTarget, Opt, TargetCommands};
+use cli::{Commands, ConfigCommands, DestinationsTarget, Opt, TargetCommands};
 use futures::StreamExt;
 use std::path::PathBuf;
 
@@ -12,22 +15,54 @@ async fn main() {
     let opt = Opt::parse();
 
     match opt.command {
-        Commands::Flash { target, quiet } => flash(*target, quiet).await,
-        Commands::Format { dst, quiet } => format(dst, quiet).await,
+        Commands::Flash {
+            target,
+            quiet,
+            progress_socket,
+            notify,
+        } => flash(*target, quiet, progress_socket, notify).await,
+        Commands::Format {
+            dst,
+            quiet,
+            allow_large_device,
+        } => format(dst, quiet, allow_large_device).await,
         Commands::ListDestinations {
             target,
             no_frills,
             no_filter,
+            size_unit,
         } => {
-            list_destinations(target, no_frills, no_filter).await;
+            list_destinations(target, no_frills, no_filter, size_unit.into()).await;
         }
+        Commands::WriteProtect { dst, unlock } => write_protect(dst, unlock),
+        Commands::Benchmark { dst, size } => benchmark(&dst, size).await,
+        Commands::GenBmap { img, output } => gen_bmap(&img, &output),
+        Commands::Config { command } => match command {
+            ConfigCommands::List { config, since } => config::list(config.as_deref(), since),
+            ConfigCommands::Lint { config } => config::lint(config.as_deref()),
+            ConfigCommands::Format { config, output } => {
+                config::format(config.as_deref(), output.as_deref())
+            }
+        },
         Commands::GenerateCompletion { shell } => generate_completion(shell),
     }
 }
 
-async fn flash(target: TargetCommands, quite: bool) {
-    if quite {
-        flash_internal(target, None).await
+async fn flash(
+    target: TargetCommands,
+    quite: bool,
+    progress_socket: Option<PathBuf>,
+    notify: bool,
+) {
+    let socket = progress_socket
+        .map(|p| progress_socket::serve(&p).expect("Failed to create progress socket"));
+
+    let res = if quite {
+        flash_internal(
+            target,
+            socket.as_ref().map(|s| progress_socket::forward(None, s)),
+        )
+        .await
     } else {
         let (tx, mut rx) = futures::channel::mpsc::channel(20);
         tokio::task::spawn(async move {
@@ -81,7 +116,8 @@ async fn flash(target: TargetCommands, quite: bool) {
                     // Print stage when entering a new stage without progress
                     (DownloadFlashingStatus::Verifying, _)
                     | (DownloadFlashingStatus::Customizing, _)
-                    | (DownloadFlashingStatus::Preparing, _) => {
+                    | (DownloadFlashingStatus::Preparing, _)
+                    | (DownloadFlashingStatus::UpToDate, _) => {
                         if let Some(b) = last_bar.take() {
                             b.finish();
                         }
@@ -99,9 +135,46 @@ async fn flash(target: TargetCommands, quite: bool) {
             }
         });
 
-        flash_internal(target, Some(tx)).await
+        let chan = match &socket {
+            Some(s) => progress_socket::forward(Some(tx), s),
+            None => tx,
+        };
+
+        flash_internal(target, Some(chan)).await
+    };
+
+    if notify {
+        notify_completion(&res).await;
+    }
+
+    // Remove socket file before exiting
+    drop(socket);
+    res.expect("Filed to flash")
+}
+
+/// Ring the terminal bell and show a desktop notification. Failure to show the notification is
+/// ignored, since the bell is enough when no notification server is running.
+async fn notify_completion(res: &anyhow::Result<()>) {
+    let body = match res {
+        Ok(()) => "Flashing successful".to_string(),
+        Err(e) => format!("Flashing failed: {e}"),
+    };
+
+    let _ = console::Term::stderr().write_str("\x07");
+
+    let res = tokio::task::spawn_blocking(move || {
+        notify_rust::Notification::new()
+            .appname("BeagleBoard Imager")
+            .body(&body)
+            .show()
+            .map(|_| ())
+    })
+    .await
+    .unwrap();
+
+    if let Err(e) = res {
+        eprintln!("Failed to show notification: {e}");
     }
-    .expect("Filed to flash")
 }
 
 async fn flash_internal(
@@ -111,38 +184,128 @@ async fn flash_internal(
     match target {
         TargetCommands::Sd {
             dst,
-            hostname,
-            timezone,
-            keymap,
-            user_name,
-            user_password,
-            wifi_ssid,
-            wifi_password,
+            continue_on_error,
             img,
-            ssh_key,
-            usb_enable_dhcp,
+            customization,
             bmap,
+            allow_large_device,
+            verify,
+            skip_identical,
+            format_before_flash,
+            partitions,
+            provisioning_marker,
+            eject_after,
+            check_capacity,
         } => {
-            let user = user_name.map(|x| (x, user_password.unwrap()));
-            let wifi = wifi_ssid.map(|x| (x, wifi_password.unwrap()));
-
-            let dst = check_macos_device_path(dst);
-
-            let customization = bb_flasher::sd::FlashingSdLinuxConfig::sysconfig(
-                hostname,
-                timezone,
-                keymap,
-                user,
-                wifi,
-                ssh_key,
-                Some(usb_enable_dhcp),
-            );
+            let dsts: Vec<PathBuf> = dst
+                .into_iter()
+                .map(|x| device_path::resolve(x).map(check_macos_device_path))
+                .collect::<anyhow::Result<_>>()?;
+            let image_sha = provisioning_marker.then(|| sha256_file(&img)).transpose()?;
+            let customization = customization.customization();
+            let policy = if continue_on_error {
+                bb_flasher::batch::BatchPolicy::ContinueOnError
+            } else {
+                bb_flasher::batch::BatchPolicy::StopOnError
+            };
+
+            let is_batch = dsts.len() > 1;
+            let (img, bmap, partitions, customization, chan) =
+                (&img, &bmap, &partitions, &customization, &chan);
+
+            let report = bb_flasher::batch::flash_many(dsts, policy, |dst| async move {
+                if is_batch {
+                    println!("Flashing {}", dst.display());
+                }
 
-            bb_flasher::sd::Flasher::new(
+                // Each SD Card gets an unique marker
+                let marker =
+                    image_sha.map(|sha| bb_flasher::sd::ProvisioningMarker::new(Some(sha)));
+                let marker_id = marker.as_ref().map(|x| x.id());
+                let customization = customization.clone().with_marker(marker);
+
+                let dst: bb_flasher::sd::Target = dst.try_into()?;
+                dst.check_size(max_device_size(allow_large_device))?;
+
+                if check_capacity {
+                    let report = bb_flasher::sd::check_capacity(dst.path()).await?;
+                    if !report.is_genuine() {
+                        let unit = bb_helper::size::SizeUnit::Binary;
+                        eprintln!(
+                            "Warning: {} reports {}, but only {} could be verified. It is likely a counterfeit SD Card.",
+                            dst.path().display(),
+                            unit.format(report.reported),
+                            unit.format(report.verified)
+                        );
+                    }
+                }
+
+                if let Some(partitions) = partitions {
+                    bb_flasher::sd::PartitionFlasher::new(
+                        LocalImage::new(img.clone()),
+                        dst,
+                        partitions.clone().into(),
+                        customization,
+                        None,
+                    )
+                    .with_eject_on_success(eject_after)
+                    .flash(chan.clone())
+                    .await?;
+                } else {
+                    bb_flasher::sd::Flasher::new(
+                        LocalImage::new(img.clone()),
+                        bmap.clone().map(LocalStringFile::new),
+                        dst,
+                        customization,
+                        verify.map(Into::into),
+                        None,
+                    )
+                    .with_skip_identical(skip_identical)
+                    .with_format(format_before_flash)
+                    .with_eject_on_success(eject_after)
+                    .flash_with_stats(chan.clone())
+                    .await
+                    .map(print_stats)?;
+                }
+
+                if let Some(id) = marker_id {
+                    println!("Provisioning marker: {id}");
+                }
+
+                Ok(())
+            })
+            .await;
+
+            if is_batch {
+                println!("\nSummary:");
+                for (dst, outcome) in &report {
+                    println!("  {}: {outcome}", dst.display());
+                }
+            }
+
+            let failed = report.iter().filter(|(_, x)| !x.is_success()).count();
+            match report.into_iter().find_map(|(_, x)| match x {
+                bb_flasher::batch::BatchOutcome::Failed(e) if !is_batch => Some(e),
+                _ => None,
+            }) {
+                Some(e) => Err(e),
+                None if failed > 0 => Err(anyhow::anyhow!("{failed} SD Card(s) not flashed")),
+                None => Ok(()),
+            }
+        }
+        TargetCommands::VmDisk {
+            img,
+            dst,
+            size,
+            customization,
+            bmap,
+        } => {
+            bb_flasher::sd::VmDiskFlasher::new(
                 LocalImage::new(img),
                 bmap.map(LocalStringFile::new),
-                dst.try_into().unwrap(),
-                customization,
+                dst,
+                size,
+                customization.customization(),
                 None,
             )
             .flash(chan)
@@ -243,11 +406,34 @@ fn check_macos_device_path(dst: PathBuf) -> PathBuf {
     dst
 }
 
-async fn format(dst: PathBuf, quite: bool) {
+fn print_stats(stats: bb_flasher::FlashStats) {
+    let unit = bb_helper::size::SizeUnit::Binary;
+    println!(
+        "Wrote {} in {:.1?}{}",
+        unit.format(stats.bytes_written),
+        stats.duration,
+        if stats.verified { ", verified" } else { "" }
+    );
+}
+
+const fn max_device_size(allow_large_device: bool) -> Option<u64> {
+    if allow_large_device {
+        None
+    } else {
+        Some(bb_flasher::sd::DEFAULT_MAX_SIZE)
+    }
+}
+
+async fn format(dst: PathBuf, quite: bool, allow_large_device: bool) {
     let (tx, _) = futures::channel::mpsc::channel(20);
     let term = console::Term::stdout();
 
-    let config = bb_flasher::sd::FormatFlasher::new(dst.try_into().unwrap());
+    let dst = device_path::resolve(dst).expect("Invalid device");
+    let dst: bb_flasher::sd::Target = dst.try_into().unwrap();
+    dst.check_size(max_device_size(allow_large_device))
+        .expect("Failed to format");
+
+    let config = bb_flasher::sd::FormatFlasher::new(dst);
     config.flash(Some(tx)).await.unwrap();
 
     if !quite {
@@ -255,16 +441,58 @@ async fn format(dst: PathBuf, quite: bool) {
     }
 }
 
-async fn no_frills_list_destinations<T: BBFlasherTarget>(no_filter: bool) {
+fn write_protect(dst: PathBuf, unlock: bool) {
+    let dst = device_path::resolve(dst).expect("Invalid device");
+    let dst: bb_flasher::sd::Target = dst.try_into().unwrap();
+
+    if unlock {
+        dst.unlock().expect("Failed to remove write protection");
+    } else {
+        dst.lock().expect("Failed to write protect");
+    }
+}
+
+async fn benchmark(dst: &std::path::Path, size: u64) {
+    let unit = bb_helper::size::SizeUnit::Binary;
+    let report = bb_flasher::sd::benchmark(dst, size)
+        .await
+        .expect("Benchmark failed");
+
+    println!("Written and verified {}", unit.format(report.size));
+    println!(
+        "Write: {}/s ({:.2?})",
+        unit.format(report.write_throughput() as u64),
+        report.write_time
+    );
+    println!(
+        "Read:  {}/s ({:.2?})",
+        unit.format(report.read_throughput() as u64),
+        report.read_time
+    );
+}
+
+fn gen_bmap(img: &std::path::Path, output: &std::path::Path) {
+    let img = bb_flasher::OsImage::from_path(img).expect("Failed to open image");
+    let bmap = bb_flasher::sd::generate_bmap(img).expect("Failed to generate bmap");
+
+    std::fs::write(output, bmap).expect("Failed to write bmap");
+}
+
+async fn no_frills_list_destinations<T: BBFlasherTarget + std::fmt::Display>(no_filter: bool) {
     let term = console::Term::stdout();
-    let dsts = T::destinations(!no_filter).await;
+    let dsts = bb_flasher::sort_destinations(T::destinations(!no_filter).await);
 
     for d in dsts {
         term.write_line(&d.identifier()).unwrap();
     }
 }
 
-async fn list_destinations(target: DestinationsTarget, no_frills: bool, no_filter: bool) {
+async fn list_destinations(
+    target: DestinationsTarget,
+    no_frills: bool,
+    no_filter: bool,
+    size_unit: bb_helper::size::SizeUnit,
+) {
     if no_frills {
         match target {
             DestinationsTarget::Sd => {
@@ -296,20 +524,20 @@ async fn list_destinations(target: DestinationsTarget, no_frills: bool, no_filte
         DestinationsTarget::Sd => {
             const NAME_HEADER: &str = "SD Card";
             const PATH_HEADER: &str = "Path";
-            const SIZE_HEADER: &str = "Size (in G)";
-            const BYTES_IN_GB: u64 = 1024 * 1024 * 1024;
+            const SIZE_HEADER: &str = "Size";
 
-            let dsts_str: Vec<_> = bb_flasher::sd::Target::destinations(!no_filter)
-                .await
-                .into_iter()
-                .map(|x| {
-                    (
-                        x.to_string().trim().to_string(),
-                        x.identifier().to_string(),
-                        (x.size() / BYTES_IN_GB).to_string(),
-                    )
-                })
-                .collect();
+            let dsts_str: Vec<_> = bb_flasher::sort_destinations(
+                bb_flasher::sd::Target::destinations(!no_filter).await,
+            )
+            .into_iter()
+            .map(|x| {
+                (
+                    x.to_string().trim().to_string(),
+                    x.identifier().to_string(),
+                    size_unit.format(x.size()),
+                )
+            })
+            .collect();
 
             let max_name_len = dsts_str
                 .iter()
@@ -334,13 +562,13 @@ async fn list_destinations(target: DestinationsTarget, no_frills: bool, no_filte
                 "+-{}-+-{}-+-{}-+",
                 std::iter::repeat_n('-', max_name_len).collect::<String>(),
                 std::iter::repeat_n('-', max_path_len).collect::<String>(),
-                std::iter::repeat_n('-', SIZE_HEADER.len()).collect::<String>(),
+                std::iter::repeat_n('-', max_size_len).collect::<String>(),
             );
 
             term.write_line(&table_border).unwrap();
 
             term.write_line(&format!(
-                "| {} | {} | {: <6} |",
+                "| {} | {} | {} |",
                 console::pad_str(NAME_HEADER, max_name_len, console::Alignment::Left, None),
                 console::pad_str(PATH_HEADER, max_path_len, console::Alignment::Left, None),
                 console::pad_str(SIZE_HEADER, max_size_len, console::Alignment::Left, None),
@@ -369,19 +597,20 @@ async fn list_destinations(target: DestinationsTarget, no_frills: bool, no_filte
             const VENDOR_ID_HEADER: &str = "Vendor Id";
             const PRODUCT_ID_HEADER: &str = "Product Id";
 
-            let dsts_str: Vec<_> = bb_flasher::dfu::Target::destinations(!no_filter)
-                .await
-                .into_iter()
-                .map(|x| {
-                    (
-                        x.to_string().trim().to_string(),
-                        format!("{:#04x}", x.bus_number()),
-                        format!("{:#04x}", x.port_num()),
-                        format!("{:#06x}", x.vendor_id()),
-                        format!("{:#06x}", x.product_id()),
-                    )
-                })
-                .collect();
+            let dsts_str: Vec<_> = bb_flasher::sort_destinations(
+                bb_flasher::dfu::Target::destinations(!no_filter).await,
+            )
+            .into_iter()
+            .map(|x| {
+                (
+                    x.to_string().trim().to_string(),
+                    format!("{:#04x}", x.bus_number()),
+                    format!("{:#04x}", x.port_num()),
+                    format!("{:#06x}", x.vendor_id()),
+                    format!("{:#06x}", x.product_id()),
+                )
+            })
+            .collect();
 
             let max_name_len = dsts_str
                 .iter()
@@ -464,6 +693,7 @@ const fn progress_msg(status: DownloadFlashingStatus) -> &'static str {
         DownloadFlashingStatus::FlashingProgress(_) => "Flashing",
         DownloadFlashingStatus::Verifying => "Verifying",
         DownloadFlashingStatus::Customizing => "Customizing",
+        DownloadFlashingStatus::UpToDate => "Already up to date",
     }
 }
 
@@ -477,3 +707,12 @@ fn generate_completion(target: clap_complete::Shell) {
 
     clap_complete::generate(target, &mut cmd, BIN_NAME, &mut std::io::stdout())
 }
+
+/// SHA256 of image file, as published in distros.json for downloads.
+fn sha256_file(path: &std::path::Path) -> std::io::Result<[u8; 32]> {
+    use sha2::Digest;
+
+    let mut hasher = sha2::Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
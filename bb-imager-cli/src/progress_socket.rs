@@ -0,0 +1,166 @@
+//! Publish flashing progress as newline delimited JSON over a Unix domain socket (or named pipe on
+//! Windows). Allows external applications to observe a running flash.
+
+use std::{io, path::Path};
+
+use bb_flasher::DownloadFlashingStatus;
+use futures::{SinkExt, StreamExt, channel::mpsc};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::broadcast,
+};
+
+const BUFFER_LEN: usize = 64;
+
+/// Handle to a listening progress socket. The socket file is removed on drop, so that the next run
+/// can bind to the same path.
+pub(crate) struct ProgressSocket {
+    tx: broadcast::Sender<String>,
+    #[cfg(unix)]
+    path: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl Drop for ProgressSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Start listening for observers on `path`. Any number of observers can connect.
+#[cfg(unix)]
+pub(crate) fn serve(path: &Path) -> io::Result<ProgressSocket> {
+    // Remove stale socket from an earlier run
+    let _ = std::fs::remove_file(path);
+
+    let listener = tokio::net::UnixListener::bind(path)?;
+    let (tx, _) = broadcast::channel(BUFFER_LEN);
+
+    let tx_clone = tx.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(publish(stream, tx_clone.subscribe()));
+        }
+    });
+
+    Ok(ProgressSocket {
+        tx,
+        path: path.to_path_buf(),
+    })
+}
+
+/// Start listening for observers on named pipe `path` (e.g. `\\.\pipe\bb-imager`). Any number of
+/// observers can connect.
+#[cfg(windows)]
+pub(crate) fn serve(path: &Path) -> io::Result<ProgressSocket> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let path = path.to_path_buf();
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(&path)?;
+    let (tx, _) = broadcast::channel(BUFFER_LEN);
+
+    let tx_clone = tx.clone();
+    tokio::spawn(async move {
+        while server.connect().await.is_ok() {
+            // A new pipe instance is needed for the next observer
+            let next = match ServerOptions::new().create(&path) {
+                Ok(x) => x,
+                Err(_) => break,
+            };
+
+            let client = std::mem::replace(&mut server, next);
+            tokio::spawn(publish(client, tx_clone.subscribe()));
+        }
+    });
+
+    Ok(ProgressSocket { tx })
+}
+
+async fn publish(mut stream: impl AsyncWrite + Unpin, mut rx: broadcast::Receiver<String>) {
+    loop {
+        match rx.recv().await {
+            Ok(mut msg) => {
+                msg.push('\n');
+                if stream.write_all(msg.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+            // Slow observers simply miss some updates
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Returns a channel which publishes all progress to the socket, and forwards it to `chan`.
+pub(crate) fn forward(
+    chan: Option<mpsc::Sender<DownloadFlashingStatus>>,
+    socket: &ProgressSocket,
+) -> mpsc::Sender<DownloadFlashingStatus> {
+    let (tx, mut rx) = mpsc::channel(20);
+    let socket = socket.tx.clone();
+
+    tokio::spawn(async move {
+        let mut chan = chan;
+
+        while let Some(progress) = rx.next().await {
+            if let Ok(msg) = serde_json::to_string(&progress) {
+                // Fails only when no observer is connected
+                let _ = socket.send(msg);
+            }
+
+            if let Some(c) = chan.as_mut() {
+                let _ = c.send(progress).await;
+            }
+        }
+    });
+
+    tx
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use bb_flasher::DownloadFlashingStatus;
+    use futures::SinkExt;
+    use tokio::io::AsyncBufReadExt;
+
+    #[tokio::test]
+    async fn progress_socket() {
+        const STATUS: [DownloadFlashingStatus; 4] = [
+            DownloadFlashingStatus::Preparing,
+            DownloadFlashingStatus::FlashingProgress(0.5),
+            DownloadFlashingStatus::Verifying,
+            DownloadFlashingStatus::Customizing,
+        ];
+
+        let path = std::env::temp_dir().join(format!("bb-imager-cli-{}.sock", std::process::id()));
+        let socket = super::serve(&path).unwrap();
+
+        let client = tokio::net::UnixStream::connect(&path).await.unwrap();
+        while socket.tx.receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let mut tx = super::forward(None, &socket);
+        for s in STATUS {
+            tx.send(s).await.unwrap();
+        }
+
+        let mut lines = tokio::io::BufReader::new(client).lines();
+        for s in STATUS {
+            let line = lines.next_line().await.unwrap().unwrap();
+            assert_eq!(
+                serde_json::from_str::<DownloadFlashingStatus>(&line).unwrap(),
+                s
+            );
+        }
+
+        drop(socket);
+        assert!(!path.exists());
+
+        // Path can be reused by the next run
+        let _socket = super::serve(&path).unwrap();
+    }
+}
@@ -5,9 +5,35 @@ pub(crate) const OSHW_BASE_URL: &str = "https://certification.oshwa.org";
 pub(crate) const LATEST_RELEASE_URL: &str =
     "https://api.github.com/repos/beagleboard/bb-imager-rs/releases/latest";
 
+pub(crate) const ISSUE_TRACKER_URL: &str = concat!(env!("CARGO_PKG_REPOSITORY"), "/-/issues/new");
+
 pub(crate) const PACKAGE_QUALIFIER: (&str, &str, &str) = ("org", "beagleboard", "imagingutility");
 
 pub(crate) const DEFAULT_CONFIG: &[u8] = include_bytes!("../../config.json");
+
+/// Cached files not modified in this many days are removed.
+pub(crate) const DEFAULT_CACHE_MAX_AGE_DAYS: u64 = 30;
+/// Warn on review page when image is smaller than this fraction (1 / N) of the destination.
+pub(crate) const DEFAULT_SMALL_IMAGE_RATIO: u64 = 32;
+/// Minimum time between two cache prunes.
+pub(crate) const CACHE_PRUNE_INTERVAL: std::time::Duration =
+    std::time::Duration::from_secs(24 * 60 * 60);
+/// How often to check if cache should be pruned while idle.
+pub(crate) const CACHE_PRUNE_CHECK_INTERVAL: std::time::Duration =
+    std::time::Duration::from_secs(5 * 60);
+
+/// Maximum number of icons downloaded at the same time.
+pub(crate) const ICON_DOWNLOAD_CONCURRENCY: usize = 6;
+
+/// Number of log lines shown on failure.
+pub(crate) const LOG_EXCERPT_LINES: usize = 200;
+/// How often log viewer reloads the log file.
+pub(crate) const LOG_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+/// Number of log lines included in problem reports. Browsers limit the length of URLs.
+pub(crate) const REPORT_LOG_LINES: usize = 40;
+/// Maximum length of logs included in problem reports.
+pub(crate) const REPORT_LOG_MAX_LEN: usize = 4000;
+
 pub(crate) const WINDOW_SIZE: iced::Size = iced::Size::new(680.0, 450.0);
 pub(crate) const APP_NAME: &str = "BeagleBoard Imager";
 pub(crate) const APP_RELEASE: &str = if option_env!("PRE_RELEASE").is_some() {
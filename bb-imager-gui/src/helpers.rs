@@ -1,8 +1,10 @@
 use std::{collections::HashMap, fmt::Display, path::PathBuf, sync::LazyLock, time::Duration};
 
-use crate::{BBImagerMessage, PACKAGE_QUALIFIER, constants};
+use crate::{BBImagerMessage, PACKAGE_QUALIFIER, constants, persistance::LocalImageInfo};
 use bb_config::config::{self, OsListItem};
+use bb_downloader::Fetcher;
 use bb_flasher::{BBFlasher, BBFlasherTarget, DownloadFlashingStatus, sd::FlashingSdLinuxConfig};
+use bb_helper::size::SizeUnit;
 use iced::{futures, widget};
 use url::Url;
 
@@ -12,7 +14,8 @@ pub(crate) struct Boards {
 }
 
 impl Boards {
-    pub(crate) fn merge(&mut self, config: bb_config::Config) {
+    pub(crate) fn merge(&mut self, mut config: bb_config::Config) {
+        config.inherit();
         self.config.extend([config])
     }
 
@@ -96,13 +99,17 @@ impl Boards {
         }
 
         if let OsListItem::RemoteSubList(item) = res.get(*last).unwrap().clone() {
-            res[*last] = OsListItem::SubList(item.resolve(subitems))
+            let mut item = item.resolve(subitems);
+            item.inherit();
+            res[*last] = OsListItem::SubList(item)
         } else {
             tracing::warn!("Unexpected item")
         }
     }
 
-    pub(crate) fn from_config(value: config::Config) -> Self {
+    pub(crate) fn from_config(mut value: config::Config) -> Self {
+        value.inherit();
+
         let filtered = config::Config {
             imager: config::Imager {
                 remote_configs: value.imager.remote_configs,
@@ -119,9 +126,22 @@ impl Boards {
         Self { config: filtered }
     }
 
+    /// Load the bundled config, or the local config set using [bb_config::CONFIG_FILE_ENV].
     pub(crate) fn new() -> Self {
-        let cfg = serde_json::from_slice::<config::Config>(crate::constants::DEFAULT_CONFIG)
-            .expect("Failed to parse config");
+        let cfg = match bb_config::config_file_override() {
+            Some(p) => {
+                tracing::info!("Using local config: {:?}", p);
+                let data = std::fs::read(&p).expect("Failed to read local config");
+                let mut cfg = serde_json::from_slice::<config::Config>(&data)
+                    .expect("Failed to parse local config");
+
+                // Local config should be used as is
+                cfg.imager.remote_configs.clear();
+                cfg
+            }
+            None => serde_json::from_slice::<config::Config>(crate::constants::DEFAULT_CONFIG)
+                .expect("Failed to parse config"),
+        };
 
         Self::from_config(cfg)
     }
@@ -131,6 +151,7 @@ impl Boards {
 pub(crate) enum BoardImageIcon {
     Remote(url::Url),
     Local,
+    Url,
     Format,
 }
 
@@ -145,46 +166,58 @@ pub(crate) enum BoardImage {
         init_format: config::InitFormat,
         img: SelectedImage,
         bmap: Option<Bmap>,
+        /// Overrides the image name
+        name: Option<String>,
         info_text: Option<String>,
         description: Option<String>,
+        release_notes: Option<String>,
+        changelog_url: Option<Url>,
         icon: BoardImageIcon,
         details: Vec<(&'static str, String)>,
     },
 }
 
 impl BoardImage {
-    pub(crate) fn local(path: PathBuf, flasher: config::Flasher) -> Self {
+    /// Local image. `info` allows overriding the name (file name by default) and description.
+    pub(crate) fn local(path: PathBuf, flasher: config::Flasher, info: LocalImageInfo) -> Self {
         let metadata = std::fs::metadata(&path).expect("File does not exist");
         let details = vec![
             ("Path", path.to_string_lossy().to_string()),
             ("Size", metadata.len().to_string()),
         ];
 
-        Self::Image {
+        let mut img = Self::Image {
             img: bb_flasher::LocalImage::new(path.into()).into(),
             bmap: None,
             flasher,
             // Do not try to apply customization for local images
             init_format: config::InitFormat::None,
+            name: None,
             info_text: None,
             description: None,
+            release_notes: None,
+            changelog_url: None,
             icon: BoardImageIcon::Local,
             details,
-        }
+        };
+        img.update_local_info(info);
+
+        img
     }
 
     pub(crate) fn remote(
         image: config::OsImage,
         flasher: config::Flasher,
         downloader: bb_downloader::Downloader,
+        size_unit: SizeUnit,
     ) -> Self {
         let mut details = vec![
             ("Release Date", image.release_date.to_string()),
-            ("Image Size", pretty_bytes(image.extract_size)),
+            ("Image Size", size_unit.format(image.extract_size)),
         ];
 
         if let Some(x) = image.image_download_size {
-            details.push(("Download Size", pretty_bytes(x)))
+            details.push(("Download Size", size_unit.format(x)))
         }
 
         Self::Image {
@@ -202,13 +235,49 @@ impl BoardImage {
             }),
             flasher,
             init_format: image.init_format,
+            name: None,
             info_text: image.info_text,
             description: Some(image.description),
+            release_notes: image.release_notes,
+            changelog_url: image.changelog_url,
             icon: BoardImageIcon::Remote(image.icon),
             details,
         }
     }
 
+    /// Image from a pasted URL, not present in config.
+    pub(crate) fn url(
+        url: url::Url,
+        sha256: Option<[u8; 32]>,
+        flasher: config::Flasher,
+        downloader: bb_downloader::Downloader,
+    ) -> Self {
+        let details = vec![
+            ("URL", url.to_string()),
+            (
+                "SHA256",
+                sha256
+                    .map(|x| x.iter().map(|b| format!("{b:02x}")).collect())
+                    .unwrap_or_else(|| "Not provided".to_string()),
+            ),
+        ];
+
+        Self::Image {
+            img: RemoteImage::from_url(url, sha256, downloader).into(),
+            bmap: None,
+            flasher,
+            // Do not try to apply customization for unknown images
+            init_format: config::InitFormat::None,
+            name: None,
+            info_text: None,
+            description: None,
+            release_notes: None,
+            changelog_url: None,
+            icon: BoardImageIcon::Url,
+            details,
+        }
+    }
+
     pub(crate) fn format() -> Self {
         Self::SdFormat {
             details: vec![("Format", "FAT32".to_string())],
@@ -222,6 +291,18 @@ impl BoardImage {
         }
     }
 
+    /// Release notes and link to full changelog. Only present for images in config.
+    pub(crate) fn release_notes(&self) -> (Option<&str>, Option<&Url>) {
+        match self {
+            BoardImage::SdFormat { .. } => (None, None),
+            BoardImage::Image {
+                release_notes,
+                changelog_url,
+                ..
+            } => (release_notes.as_deref(), changelog_url.as_ref()),
+        }
+    }
+
     pub(crate) fn icon(&self) -> &BoardImageIcon {
         match self {
             BoardImage::SdFormat { .. } => &BoardImageIcon::Format,
@@ -243,6 +324,48 @@ impl BoardImage {
         }
     }
 
+    /// Size of extracted image, if known.
+    pub(crate) const fn extract_size(&self) -> Option<u64> {
+        match self {
+            BoardImage::Image { img, .. } => img.extract_size(),
+            BoardImage::SdFormat { .. } => None,
+        }
+    }
+
+    /// URL of remote images. Used to identify the image across flashing attempts.
+    pub(crate) fn remote_url(&self) -> Option<&url::Url> {
+        match self {
+            BoardImage::Image {
+                img: SelectedImage::RemoteImage(x),
+                ..
+            } => Some(&x.url),
+            _ => None,
+        }
+    }
+
+    /// Remote image is present in cache. Always false for local images.
+    pub(crate) async fn is_cached(&self) -> bool {
+        match self {
+            BoardImage::Image {
+                img: SelectedImage::RemoteImage(x),
+                ..
+            } => x.is_cached().await,
+            _ => false,
+        }
+    }
+
+    /// Send SHA256 of the extracted image to `tx` once it has been computed while flashing. Only
+    /// supported for remote images.
+    pub(crate) fn set_sha256_channel(&mut self, tx: futures::channel::mpsc::Sender<[u8; 32]>) {
+        if let BoardImage::Image {
+            img: SelectedImage::RemoteImage(x),
+            ..
+        } = self
+        {
+            x.sha256_tx = Some(tx);
+        }
+    }
+
     pub(crate) fn info_text(&self) -> Option<&str> {
         match self {
             BoardImage::Image { info_text, .. } => info_text.as_ref().map(|x| x.as_str()),
@@ -257,6 +380,45 @@ impl BoardImage {
         }
     }
 
+    /// Path, name and description of local images.
+    pub(crate) fn local_info(&self) -> Option<(&std::path::Path, LocalImageInfo)> {
+        match self {
+            Self::Image {
+                img: SelectedImage::LocalImage(x),
+                name,
+                description,
+                ..
+            } => Some((
+                x.path(),
+                LocalImageInfo {
+                    name: name.clone(),
+                    description: description.clone(),
+                },
+            )),
+            _ => None,
+        }
+    }
+
+    /// Update name and description of local images. nop for other images.
+    pub(crate) fn update_local_info(&mut self, info: LocalImageInfo) {
+        if let Self::Image {
+            img: SelectedImage::LocalImage(_),
+            name,
+            description,
+            details,
+            ..
+        } = self
+        {
+            details.retain(|(k, _)| *k != "Name");
+            if let Some(x) = &info.name {
+                details.insert(0, ("Name", x.clone()));
+            }
+
+            *name = info.name;
+            *description = info.description;
+        }
+    }
+
     pub(crate) fn details(&self) -> &[(&'static str, String)] {
         match self {
             BoardImage::SdFormat { details } => details,
@@ -269,6 +431,9 @@ impl std::fmt::Display for BoardImage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             BoardImage::SdFormat { .. } => write!(f, "Format SD Card"),
+            BoardImage::Image {
+                name: Some(name), ..
+            } => name.fmt(f),
             BoardImage::Image { img: image, .. } => image.fmt(f),
         }
     }
@@ -307,36 +472,111 @@ pub(crate) fn system_keymap() -> String {
         .unwrap_or_else(|| String::from("us"))
 }
 
+/// Directory containing Raspberry Pi Imager settings (`Imager.conf`), if present.
+pub(crate) fn rpi_imager_config_dir() -> Option<PathBuf> {
+    let dir = directories::BaseDirs::new()?
+        .config_dir()
+        .join("Raspberry Pi");
+    dir.exists().then_some(dir)
+}
+
+/// Settings of this computer. Used to prefill customization.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HostSettings {
+    pub(crate) hostname: Option<String>,
+    pub(crate) timezone: Option<String>,
+    pub(crate) keymap: String,
+    pub(crate) username: Option<String>,
+}
+
+impl HostSettings {
+    pub(crate) fn current() -> Self {
+        Self {
+            hostname: whoami::hostname().ok(),
+            timezone: system_timezone().cloned(),
+            keymap: system_keymap(),
+            username: whoami::username().ok(),
+        }
+    }
+}
+
+/// Generic over [Fetcher], so resolving can be tested without network access.
 #[derive(Debug, Clone)]
-pub(crate) struct RemoteImage {
+pub(crate) struct RemoteImage<F = bb_downloader::Downloader> {
     name: Box<str>,
     url: Box<url::Url>,
-    extract_sha256: [u8; 32],
-    extract_size: u64,
-    downloader: bb_downloader::Downloader,
+    /// Not known for images from pasted URLs without SHA256
+    extract_sha256: Option<[u8; 32]>,
+    /// Not known for images from pasted URLs
+    extract_size: Option<u64>,
+    downloader: F,
+    /// Receives SHA256 of the extracted image, computed while flashing
+    sha256_tx: Option<futures::channel::mpsc::Sender<[u8; 32]>>,
 }
 
-impl RemoteImage {
+impl<F: Fetcher> RemoteImage<F> {
     pub(crate) fn new(
         name: Box<str>,
         url: Box<url::Url>,
         extract_sha256: [u8; 32],
         extract_size: u64,
-        downloader: bb_downloader::Downloader,
+        downloader: F,
     ) -> Self {
         Self {
             name,
             url,
-            extract_sha256,
-            extract_size,
+            extract_sha256: Some(extract_sha256),
+            extract_size: Some(extract_size),
             downloader,
+            sha256_tx: None,
         }
     }
 
+    /// Image from a URL not present in config. Name is the file name in URL.
+    pub(crate) fn from_url(url: url::Url, sha256: Option<[u8; 32]>, downloader: F) -> Self {
+        let mut img = Self {
+            name: Default::default(),
+            url: Box::new(url),
+            extract_sha256: sha256,
+            extract_size: None,
+            downloader,
+            sha256_tx: None,
+        };
+        img.name = img.file_name().into();
+
+        img
+    }
+
     fn file_name(&self) -> &str {
         self.url.path_segments().unwrap().next_back().unwrap()
     }
 
+    /// Size of extracted image. [None] for images from pasted URLs.
+    pub(crate) const fn extract_size(&self) -> Option<u64> {
+        self.extract_size
+    }
+
+    /// Image is present in cache, and will not be downloaded again.
+    async fn is_cached(&self) -> bool {
+        match self.extract_sha256 {
+            Some(sha) => self.downloader.check_cache_from_sha(sha).await.is_some(),
+            None => self
+                .downloader
+                .check_cache_from_url(*self.url.clone())
+                .is_some(),
+        }
+    }
+
+    /// Download the complete image to cache. SHA256 is verified if known.
+    async fn download(
+        &self,
+        chan: Option<futures::channel::mpsc::Sender<bb_downloader::DownloadEvent>>,
+    ) -> std::io::Result<PathBuf> {
+        self.downloader
+            .download_tracked(*self.url.clone(), self.extract_sha256, chan)
+            .await
+    }
+
     async fn save(
         &self,
         path: &std::path::Path,
@@ -346,41 +586,49 @@ impl RemoteImage {
 
         let handle = tokio::spawn(async move {
             while let Some(x) = futures::StreamExt::next(&mut rx).await {
-                let _ = chan.try_send(DownloadFlashingStatus::DownloadingProgress(x));
+                match x {
+                    bb_downloader::DownloadEvent::Progress(x) => {
+                        let _ = chan.try_send(DownloadFlashingStatus::DownloadingProgress(x));
+                    }
+                    bb_downloader::DownloadEvent::Resumed { offset } => {
+                        tracing::info!("Resumed download from {offset} bytes");
+                    }
+                    // Size not known, so show indeterminate progress
+                    bb_downloader::DownloadEvent::Stats(x) if x.total.is_none() => {
+                        let _ = chan.try_send(DownloadFlashingStatus::Preparing);
+                    }
+                    bb_downloader::DownloadEvent::Stats(_) => {}
+                }
             }
         });
 
-        let p = self
-            .downloader
-            .download_with_sha(*self.url.clone(), self.extract_sha256, Some(tx))
-            .await?;
+        let p = self.download(Some(tx)).await?;
         tokio::fs::copy(p, path).await?;
         handle.abort();
 
         Ok(())
     }
-}
-
-impl bb_flasher::Resolvable for RemoteImage {
-    type ResolvedType = (bb_flasher::OsImage, u64);
 
-    async fn resolve(
+    async fn open(
         &self,
         rt: &mut tokio::task::JoinSet<std::io::Result<()>>,
-    ) -> std::io::Result<Self::ResolvedType> {
-        if let Some(path) = self
-            .downloader
-            .check_cache_from_sha(self.extract_sha256)
-            .await
-        {
+    ) -> std::io::Result<(bb_flasher::OsImage, u64)> {
+        let (Some(sha), Some(extract_size)) = (self.extract_sha256, self.extract_size) else {
+            // Extracted size is required to stream the image. So download the whole image first.
+            tracing::info!("Downloading image from URL");
+            let path = self.download(None).await?;
+            let img = bb_flasher::LocalImage::new(path.into());
+            return bb_flasher::Resolvable::resolve(&img, rt).await;
+        };
+
+        if let Some(path) = self.downloader.check_cache_from_sha(sha).await {
             tracing::info!("Found the remote image in cache");
-            Ok((bb_flasher::OsImage::from_path(&path)?, self.extract_size))
+            Ok((bb_flasher::OsImage::from_path(&path)?, extract_size))
         } else {
             tracing::info!("Remote image not found in cache. Downloading");
             let (tx, rx) = bb_helper::file_stream::file_stream()?;
             let downloader = self.downloader.clone();
             let url = self.url.clone();
-            let sha = self.extract_sha256;
             rt.spawn(async move {
                 downloader
                     .download_to_stream(*url, sha, tx)
@@ -394,18 +642,37 @@ impl bb_flasher::Resolvable for RemoteImage {
                 Ok(())
             });
 
-            let extract_size = self.extract_size;
             let img = tokio::task::spawn_blocking(move || {
                 bb_flasher::OsImage::from_piped(rx, extract_size)
             })
             .await
             .unwrap()?;
-            Ok((img, self.extract_size))
+            Ok((img, extract_size))
         }
     }
 }
 
-impl std::fmt::Display for RemoteImage {
+impl<F: Fetcher> bb_flasher::Resolvable for RemoteImage<F> {
+    type ResolvedType = (bb_flasher::OsImage, u64);
+
+    async fn resolve(
+        &self,
+        rt: &mut tokio::task::JoinSet<std::io::Result<()>>,
+    ) -> std::io::Result<Self::ResolvedType> {
+        let (img, size) = self.open(rt).await?;
+
+        let img = match self.sha256_tx.clone() {
+            Some(mut tx) => img.with_sha256(move |x| {
+                let _ = tx.try_send(x);
+            }),
+            None => img,
+        };
+
+        Ok((img, size))
+    }
+}
+
+impl<F> std::fmt::Display for RemoteImage<F> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.name)
     }
@@ -424,13 +691,17 @@ impl bb_flasher::Resolvable for Bmap {
         &self,
         _: &mut tokio::task::JoinSet<std::io::Result<()>>,
     ) -> std::io::Result<Self::ResolvedType> {
-        let p = self.downloader.download(*self.url.clone(), None).await?;
+        let p = self
+            .downloader
+            .download(*self.url.clone(), None, None)
+            .await?;
 
         tokio::fs::read_to_string(p).await.map(Into::into)
     }
 }
 
 #[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum SelectedImage {
     LocalImage(bb_flasher::LocalImage),
     RemoteImage(RemoteImage),
@@ -444,6 +715,14 @@ impl SelectedImage {
         }
     }
 
+    /// Size of extracted image. Local images might be compressed, so their size is unknown.
+    const fn extract_size(&self) -> Option<u64> {
+        match self {
+            Self::LocalImage(_) => None,
+            Self::RemoteImage(x) => x.extract_size(),
+        }
+    }
+
     async fn save(
         &self,
         path: &std::path::Path,
@@ -508,11 +787,25 @@ pub(crate) async fn flash(
                 .await
         }
         (
-            BoardImage::Image { img, bmap, .. },
+            BoardImage::Image {
+                img,
+                bmap,
+                init_format,
+                ..
+            },
             FlashingCustomization::LinuxSdSysconfig(customization),
             Destination::SdCard(t),
         ) => {
-            bb_flasher::sd::Flasher::new(img, bmap, t, customization.into(), Some(cancel))
+            let verify = customization.verify.map(Into::into);
+            let format = customization.format_before_flash;
+            let customization = match init_format {
+                config::InitFormat::CloudInit => customization.cloud_init(),
+                config::InitFormat::Armbian => customization.armbian(),
+                config::InitFormat::Raspberry => customization.raspberry(),
+                _ => customization.into(),
+            };
+            bb_flasher::sd::Flasher::new(img, bmap, t, customization, verify, Some(cancel))
+                .with_format(format)
                 .flash(Some(chan))
                 .await
         }
@@ -521,9 +814,16 @@ pub(crate) async fn flash(
             FlashingCustomization::NoneSd,
             Destination::SdCard(t),
         ) => {
-            bb_flasher::sd::Flasher::new(img, bmap, t, FlashingSdLinuxConfig::none(), Some(cancel))
-                .flash(Some(chan))
-                .await
+            bb_flasher::sd::Flasher::new(
+                img,
+                bmap,
+                t,
+                FlashingSdLinuxConfig::none(),
+                None,
+                Some(cancel),
+            )
+            .flash(Some(chan))
+            .await
         }
         #[cfg(feature = "bcf_cc1352p7")]
         (
@@ -551,6 +851,15 @@ pub(crate) async fn flash(
                 .flash(Some(chan))
                 .await
         }
+        (
+            BoardImage::Image {
+                img,
+                flasher: config::Flasher::Custom(f),
+                ..
+            },
+            FlashingCustomization::Custom,
+            Destination::Custom(t),
+        ) => bb_flasher::registry::flash(f.name(), img, t, Some(chan)).await,
         _ => unimplemented!(),
     }
 }
@@ -565,6 +874,7 @@ pub(crate) enum Destination {
     Msp430(bb_flasher::bcf::msp430::Target),
     #[cfg(feature = "pb2_mspm0")]
     Pb2Mspm0,
+    Custom(bb_flasher::registry::CustomTarget),
 }
 
 impl Display for Destination {
@@ -578,6 +888,7 @@ impl Display for Destination {
             Destination::Msp430(target) => target.fmt(f),
             #[cfg(feature = "pb2_mspm0")]
             Destination::Pb2Mspm0 => write!(f, "PocketBeagle 2 MSPM0"),
+            Destination::Custom(target) => target.fmt(f),
         }
     }
 }
@@ -597,12 +908,12 @@ impl Destination {
         matches!(self, Self::LocalFile(_))
     }
 
-    pub(crate) fn details(&self) -> Vec<(&'static str, String)> {
+    pub(crate) fn details(&self, size_unit: SizeUnit) -> Vec<(&'static str, String)> {
         match self {
             Self::LocalFile(p) => vec![("Path", p.to_string_lossy().to_string())],
             Self::SdCard(t) => vec![
                 ("Path", t.path().to_string_lossy().to_string()),
-                ("Size", pretty_bytes(t.size())),
+                ("Size", size_unit.format_both(t.size())),
             ],
             #[cfg(feature = "bcf_cc1352p7")]
             Self::BeagleConnectFreedom(t) => vec![("Path", t.path().to_string())],
@@ -610,51 +921,111 @@ impl Destination {
             Self::Msp430(t) => vec![("Path", t.path().to_string())],
             #[cfg(feature = "pb2_mspm0")]
             Self::Pb2Mspm0 => Vec::new(),
+            Self::Custom(t) => vec![("Path", t.identifier().to_string())],
         }
     }
 }
 
 pub(crate) async fn destinations(flasher: config::Flasher, filter: bool) -> Vec<Destination> {
     match flasher {
-        config::Flasher::SdCard => bb_flasher::sd::Target::destinations(filter)
-            .await
-            .into_iter()
-            .map(Destination::SdCard)
-            .collect(),
-        #[cfg(feature = "bcf_cc1352p7")]
-        config::Flasher::BeagleConnectFreedom => {
-            bb_flasher::bcf::cc1352p7::Target::destinations(filter)
-                .await
+        config::Flasher::SdCard => {
+            bb_flasher::sort_destinations(bb_flasher::sd::Target::destinations(filter).await)
                 .into_iter()
-                .map(Destination::BeagleConnectFreedom)
+                .map(Destination::SdCard)
                 .collect()
         }
+        #[cfg(feature = "bcf_cc1352p7")]
+        config::Flasher::BeagleConnectFreedom => bb_flasher::sort_destinations(
+            bb_flasher::bcf::cc1352p7::Target::destinations(filter).await,
+        )
+        .into_iter()
+        .map(Destination::BeagleConnectFreedom)
+        .collect(),
         #[cfg(feature = "bcf_msp430")]
-        config::Flasher::Msp430Usb => bb_flasher::bcf::msp430::Target::destinations(filter)
+        config::Flasher::Msp430Usb => bb_flasher::sort_destinations(
+            bb_flasher::bcf::msp430::Target::destinations(filter).await,
+        )
+        .into_iter()
+        .map(Destination::Msp430)
+        .collect(),
+        #[cfg(feature = "pb2_mspm0")]
+        config::Flasher::Pb2Mspm0 => vec![Destination::Pb2Mspm0],
+        config::Flasher::Custom(f) => bb_flasher::registry::destinations(f.name(), filter)
             .await
             .into_iter()
-            .map(Destination::Msp430)
+            .map(Destination::Custom)
             .collect(),
-        #[cfg(feature = "pb2_mspm0")]
-        config::Flasher::Pb2Mspm0 => vec![Destination::Pb2Mspm0],
         _ => unimplemented!(),
     }
 }
 
-pub(crate) fn file_filter(flasher: config::Flasher) -> &'static [&'static str] {
+/// Generation of the destinations for `flasher`, if the flasher can cheaply detect changes.
+/// [destinations] needs to be called again only when this changes. Returns [None] if unsupported.
+pub(crate) async fn destinations_generation(flasher: config::Flasher) -> Option<u64> {
     match flasher {
-        config::Flasher::SdCard => bb_flasher::sd::Target::FILE_TYPES,
+        // Checking the generation can enumerate drives, which blocks
+        config::Flasher::SdCard => tokio::task::spawn_blocking(bb_flasher::sd::Target::generation)
+            .await
+            .ok(),
+        _ => None,
+    }
+}
+
+/// Find `dst` in `available`. SD Card paths can change between enumerations, so they are matched
+/// by device instead.
+pub(crate) fn find_destination<'a>(
+    dst: &Destination,
+    available: &'a [Destination],
+) -> Option<&'a Destination> {
+    available.iter().find(|x| match (dst, x) {
+        (Destination::SdCard(a), Destination::SdCard(b)) => a.is_same_device(b),
+        _ => dst == *x,
+    })
+}
+
+/// Enumerate destinations again and return the current version of `dst`. Returns [None] if `dst`
+/// was removed after selection.
+pub(crate) async fn connected_destination(
+    dst: Destination,
+    flasher: config::Flasher,
+) -> Option<Destination> {
+    if dst.is_download_action() || static_destination(flasher).is_some() {
+        return Some(dst);
+    }
+
+    let available = destinations(flasher, false).await;
+    find_destination(&dst, &available).cloned()
+}
+
+/// Check if an image of `image_size` is smaller than `1 / ratio` of the destination. Usually
+/// means that the wrong destination is selected. A `ratio` of 0 disables the check.
+pub(crate) const fn is_image_much_smaller(image_size: u64, dest_size: u64, ratio: u64) -> bool {
+    ratio != 0 && image_size.saturating_mul(ratio) < dest_size
+}
+
+/// Features supported by `flasher`. Returns [None] if the flasher is not available in this build,
+/// or a custom flasher is not registered.
+pub(crate) fn capabilities(flasher: config::Flasher) -> Option<bb_flasher::Capabilities> {
+    match flasher {
+        config::Flasher::SdCard => Some(bb_flasher::sd::Target::capabilities()),
         #[cfg(feature = "bcf_cc1352p7")]
-        config::Flasher::BeagleConnectFreedom => bb_flasher::bcf::cc1352p7::Target::FILE_TYPES,
+        config::Flasher::BeagleConnectFreedom => {
+            Some(bb_flasher::bcf::cc1352p7::Target::capabilities())
+        }
         #[cfg(feature = "bcf_msp430")]
-        config::Flasher::Msp430Usb => bb_flasher::bcf::msp430::Target::FILE_TYPES,
+        config::Flasher::Msp430Usb => Some(bb_flasher::bcf::msp430::Target::capabilities()),
         #[cfg(feature = "pb2_mspm0")]
-        config::Flasher::Pb2Mspm0 => bb_flasher::pb2::mspm0::Target::FILE_TYPES,
-        _ => unimplemented!(),
+        config::Flasher::Pb2Mspm0 => Some(bb_flasher::pb2::mspm0::Target::capabilities()),
+        config::Flasher::Custom(f) => bb_flasher::registry::get(f.name()).map(|x| x.capabilities()),
+        _ => None,
     }
 }
 
-const fn flasher_supported(flasher: config::Flasher) -> bool {
+pub(crate) fn file_filter(flasher: config::Flasher) -> &'static [&'static str] {
+    capabilities(flasher).map_or(&[], |x| x.file_types)
+}
+
+fn flasher_supported(flasher: config::Flasher) -> bool {
     match flasher {
         config::Flasher::SdCard => true,
         #[cfg(feature = "bcf_cc1352p7")]
@@ -663,11 +1034,13 @@ const fn flasher_supported(flasher: config::Flasher) -> bool {
         config::Flasher::Msp430Usb => true,
         #[cfg(feature = "pb2_mspm0")]
         config::Flasher::Pb2Mspm0 => true,
+        config::Flasher::Custom(f) => bb_flasher::registry::is_registered(f.name()),
         _ => false,
     }
 }
 
 #[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum FlashingCustomization {
     NoneSd,
     LinuxSdSysconfig(crate::persistance::SdSysconfCustomization),
@@ -675,6 +1048,8 @@ pub(crate) enum FlashingCustomization {
     Msp430,
     #[cfg(feature = "pb2_mspm0")]
     Pb2Mspm0(crate::persistance::Pb2Mspm0Customization),
+    /// Flashers registered using [bb_flasher::registry] do not support customization
+    Custom,
 }
 
 impl FlashingCustomization {
@@ -684,7 +1059,15 @@ impl FlashingCustomization {
         app_config: &crate::persistance::GuiConfiguration,
     ) -> Self {
         match flasher {
-            config::Flasher::SdCard if img.init_format() == config::InitFormat::Sysconf => {
+            config::Flasher::SdCard
+                if matches!(
+                    img.init_format(),
+                    config::InitFormat::Sysconf
+                        | config::InitFormat::CloudInit
+                        | config::InitFormat::Armbian
+                        | config::InitFormat::Raspberry
+                ) =>
+            {
                 Self::LinuxSdSysconfig(
                     app_config
                         .sd_customization()
@@ -704,10 +1087,109 @@ impl FlashingCustomization {
                     .cloned()
                     .unwrap_or_default(),
             ),
+            config::Flasher::Custom(_) => Self::Custom,
             _ => unimplemented!(),
         }
     }
 
+    /// Sysconf customization with hostname, timezone, keymap and username from `host`. Other
+    /// options, including password, are kept from `base`.
+    pub(crate) fn from_host(
+        base: crate::persistance::SdSysconfCustomization,
+        host: &HostSettings,
+    ) -> Self {
+        let hostname = host.hostname.clone().or_else(|| base.hostname.clone());
+        let timezone = host.timezone.clone().or_else(|| base.timezone.clone());
+        let user = match &host.username {
+            Some(x) => Some(
+                base.user
+                    .clone()
+                    .unwrap_or_default()
+                    .update_username(x.clone()),
+            ),
+            None => base.user.clone(),
+        };
+
+        Self::LinuxSdSysconfig(
+            base.update_hostname(hostname)
+                .update_timezone(timezone)
+                .update_keymap(Some(host.keymap.clone()))
+                .update_user(user),
+        )
+    }
+
+    /// Sysconf customization with settings imported from Raspberry Pi Imager. Options not present
+    /// in `settings` are kept from `base`. Returns the names of settings that were not imported.
+    pub(crate) fn from_rpi_imager(
+        base: crate::persistance::SdSysconfCustomization,
+        settings: &bb_flasher::sd::RpiImagerSettings,
+    ) -> (Self, Vec<String>) {
+        let s = |x: &Option<Box<str>>| x.as_deref().map(String::from);
+
+        let mut ignored: Vec<String> = settings.ignored.iter().map(|x| x.to_string()).collect();
+        if settings.wifi_country.is_some() {
+            ignored.push("wifiCountry".to_string());
+        }
+
+        let user = match s(&settings.user_name) {
+            // Password is not known, so keep it only if the user is the same
+            Some(x) => Some(match base.user.clone() {
+                Some(u) if u.username == x => u,
+                _ => crate::persistance::SdCustomizationUser::new(x, String::new()),
+            }),
+            None => base.user.clone(),
+        };
+        let wifi = match s(&settings.wifi_ssid) {
+            Some(x) => {
+                let password = s(&settings.wifi_password)
+                    .or_else(|| {
+                        base.wifi
+                            .clone()
+                            .filter(|w| w.ssid == x)
+                            .map(|w| w.password)
+                    })
+                    .unwrap_or_default();
+                Some(
+                    crate::persistance::SdCustomizationWifi::default()
+                        .update_ssid(x)
+                        .update_password(password),
+                )
+            }
+            None => base.wifi.clone(),
+        };
+
+        let hostname = s(&settings.hostname).or_else(|| base.hostname.clone());
+        let timezone = s(&settings.timezone).or_else(|| base.timezone.clone());
+        let keymap = s(&settings.keymap).or_else(|| base.keymap.clone());
+        let ssh = s(&settings.ssh).or_else(|| base.ssh.clone());
+
+        let res = Self::LinuxSdSysconfig(
+            base.update_hostname(hostname)
+                .update_timezone(timezone)
+                .update_keymap(keymap)
+                .update_user(user)
+                .update_wifi(wifi)
+                .update_ssh(ssh),
+        );
+
+        (res, ignored)
+    }
+
+    /// Enable root filesystem expansion by default when the SD Card is much larger than the image.
+    pub(crate) fn default_expand_rootfs(
+        &mut self,
+        img: &BoardImage,
+        dst: &Destination,
+        ratio: u64,
+    ) {
+        if let Self::LinuxSdSysconfig(x) = self
+            && x.expand_rootfs.is_none()
+            && let (Some(image_size), Some(dest_size)) = (img.extract_size(), dst.size())
+        {
+            x.expand_rootfs = Some(is_image_much_smaller(image_size, dest_size, ratio));
+        }
+    }
+
     pub(crate) fn reset(&mut self) {
         match self {
             Self::LinuxSdSysconfig(_) => {
@@ -724,14 +1206,159 @@ impl FlashingCustomization {
         };
     }
 
-    pub(crate) fn validate(&self) -> bool {
+    /// Check for missing or conflicting options. All problems are returned, so they can be shown
+    /// to the user at once.
+    pub(crate) fn validate(&self) -> Result<(), Vec<CustomizationError>> {
+        let errors = match self {
+            FlashingCustomization::LinuxSdSysconfig(x) => sd_customization_errors(x),
+            _ => Vec::new(),
+        };
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Values which should never leave the machine. Eg: passwords and keys.
+    pub(crate) fn secrets(&self) -> Vec<&str> {
         match self {
-            FlashingCustomization::LinuxSdSysconfig(sd_customization) => {
-                sd_customization.validate_user()
+            FlashingCustomization::LinuxSdSysconfig(x) => [
+                x.user.as_ref().map(|u| u.password.as_str()),
+                x.wifi.as_ref().map(|w| w.password.as_str()),
+                x.ssh.as_deref(),
+            ]
+            .into_iter()
+            .flatten()
+            .filter(|s| !s.is_empty())
+            .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Customization options which cannot be applied to the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CustomizationError {
+    RootUser,
+    EmptyUsername,
+    InvalidUsername,
+    EmptyUserPassword,
+    /// Wi-Fi password is set, but SSID is not.
+    MissingWifiSsid,
+    EmptyWifiSsid,
+    InvalidWifiSsid,
+    InvalidWifiPassword,
+    InvalidHostname,
+    EmptySshKey,
+}
+
+impl CustomizationError {
+    pub(crate) const fn is_user_error(self) -> bool {
+        matches!(
+            self,
+            Self::RootUser | Self::EmptyUsername | Self::InvalidUsername
+        )
+    }
+
+    pub(crate) const fn is_wifi_ssid_error(self) -> bool {
+        matches!(
+            self,
+            Self::MissingWifiSsid | Self::EmptyWifiSsid | Self::InvalidWifiSsid
+        )
+    }
+}
+
+impl Display for CustomizationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RootUser => write!(f, "Username cannot be root"),
+            Self::EmptyUsername => write!(f, "Username is required"),
+            Self::InvalidUsername => write!(
+                f,
+                "Username should start with a lowercase letter, and only contain lowercase letters, digits, '-' and '_'"
+            ),
+            Self::EmptyUserPassword => write!(f, "User password is required"),
+            Self::MissingWifiSsid => write!(f, "Wi-Fi password is set without an SSID"),
+            Self::EmptyWifiSsid => write!(f, "Wi-Fi SSID is required"),
+            Self::InvalidWifiSsid => write!(f, "Wi-Fi SSID cannot be longer than 32 bytes"),
+            Self::InvalidWifiPassword => {
+                write!(f, "Wi-Fi password should be 8 to 63 characters long")
             }
-            _ => true,
+            Self::InvalidHostname => write!(
+                f,
+                "Hostname should only contain letters, digits and '-', and cannot start or end with '-'"
+            ),
+            Self::EmptySshKey => write!(f, "SSH key cannot be empty"),
+        }
+    }
+}
+
+fn sd_customization_errors(
+    config: &crate::persistance::SdSysconfCustomization,
+) -> Vec<CustomizationError> {
+    let mut errors = Vec::new();
+
+    if let Some(user) = &config.user {
+        if user.username.is_empty() {
+            errors.push(CustomizationError::EmptyUsername);
+        } else if user.username == "root" {
+            errors.push(CustomizationError::RootUser);
+        } else if !is_valid_username(&user.username) {
+            errors.push(CustomizationError::InvalidUsername);
+        }
+
+        if user.password.is_empty() {
+            errors.push(CustomizationError::EmptyUserPassword);
         }
     }
+
+    if let Some(wifi) = &config.wifi {
+        match (wifi.ssid.is_empty(), wifi.password.is_empty()) {
+            (true, false) => errors.push(CustomizationError::MissingWifiSsid),
+            (true, true) => errors.push(CustomizationError::EmptyWifiSsid),
+            (false, _) if wifi.ssid.len() > 32 => errors.push(CustomizationError::InvalidWifiSsid),
+            _ => {}
+        }
+
+        // WPA passphrase
+        if !(8..=63).contains(&wifi.password.chars().count()) {
+            errors.push(CustomizationError::InvalidWifiPassword);
+        }
+    }
+
+    if let Some(hostname) = &config.hostname
+        && !is_valid_hostname(hostname)
+    {
+        errors.push(CustomizationError::InvalidHostname);
+    }
+
+    if config.ssh.as_ref().is_some_and(|x| x.trim().is_empty()) {
+        errors.push(CustomizationError::EmptySshKey);
+    }
+
+    errors
+}
+
+/// Same rules as the default `NAME_REGEX` used by `adduser` on Debian.
+fn is_valid_username(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    name.len() <= 32
+        && chars.next().is_some_and(|x| x.is_ascii_lowercase())
+        && chars.all(|x| x.is_ascii_lowercase() || x.is_ascii_digit() || x == '-' || x == '_')
+}
+
+/// RFC 1123 hostname. Can be fully qualified.
+fn is_valid_hostname(hostname: &str) -> bool {
+    hostname.len() <= 253
+        && hostname.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|x| x.is_ascii_alphanumeric() || x == '-')
+        })
 }
 
 /// Fetches the main remote os_list file from `bb_config::DISTROS_URL` and merges it with the base
@@ -801,6 +1428,58 @@ pub(crate) fn project_dirs() -> Option<directories::ProjectDirs> {
     )
 }
 
+/// Minimum level of log lines shown in log viewer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum LogLevel {
+    /// Show all lines
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    pub(crate) const ALL: [Self; 3] = [Self::Info, Self::Warn, Self::Error];
+
+    /// Level of a line written by [tracing_subscriber::fmt]. Eg:
+    /// `2025-01-01T00:00:00.000000Z  WARN bb_imager_gui: Message`. Lines with DEBUG and TRACE
+    /// levels are treated as INFO.
+    fn from_line(line: &str) -> Option<Self> {
+        match line.split_whitespace().nth(1)? {
+            "ERROR" => Some(Self::Error),
+            "WARN" => Some(Self::Warn),
+            "INFO" | "DEBUG" | "TRACE" => Some(Self::Info),
+            _ => None,
+        }
+    }
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Info => write!(f, "INFO"),
+            Self::Warn => write!(f, "WARN"),
+            Self::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+/// Lines of `logs` with level `min` or higher. Lines without a level (Eg: multi-line messages)
+/// belong to the previous line.
+pub(crate) fn filter_logs(logs: &str, min: LogLevel) -> String {
+    let mut cur = LogLevel::default();
+
+    logs.lines()
+        .filter(|line| {
+            if let Some(x) = LogLevel::from_line(line) {
+                cur = x;
+            }
+            cur >= min
+        })
+        .flat_map(|line| [line, "\n"])
+        .collect()
+}
+
 pub(crate) fn log_file_path() -> PathBuf {
     let dirs = project_dirs().unwrap();
     dirs.cache_dir().with_file_name(format!(
@@ -870,32 +1549,18 @@ impl FromIterator<(url::Url, PathBuf)> for ImageHandleCache {
     }
 }
 
-pub(crate) fn pretty_bytes(bytes: u64) -> String {
-    const UNITS: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
-
-    if bytes == 0 {
-        return "0 B".to_string();
-    }
-
-    let mut size = bytes as f64;
-    let mut unit = 0;
-
-    while size >= 1024.0 && unit < UNITS.len() - 1 {
-        size /= 1024.0;
-        unit += 1;
+/// Return the destination for flashers which do not allow selecting one
+pub(crate) fn static_destination(flasher: config::Flasher) -> Option<Destination> {
+    if capabilities(flasher).is_none_or(|x| x.selectable_destination) {
+        return None;
     }
 
-    if unit == 0 {
-        format!("{} {}", bytes, UNITS[unit])
-    } else {
-        format!("{:.2} {}", size, UNITS[unit])
-    }
-}
-
-pub(crate) const fn static_destination(flasher: config::Flasher) -> Option<Destination> {
     match flasher {
         #[cfg(feature = "pb2_mspm0")]
         config::Flasher::Pb2Mspm0 => Some(Destination::Pb2Mspm0),
+        config::Flasher::Custom(f) => Some(Destination::Custom(
+            bb_flasher::registry::CustomTarget::new(f.name(), f.name()),
+        )),
         _ => None,
     }
 }
@@ -910,10 +1575,29 @@ pub(crate) fn no_customization(
         return Some(FlashingCustomization::NoneSd);
     }
 
+    // SD Card customization also depends on the image
+    if flasher == config::Flasher::SdCard
+        && !matches!(
+            img.init_format(),
+            config::InitFormat::Sysconf
+                | config::InitFormat::CloudInit
+                | config::InitFormat::Armbian
+                | config::InitFormat::Raspberry
+        )
+    {
+        return Some(FlashingCustomization::NoneSd);
+    }
+
+    if capabilities(flasher).is_none_or(|x| x.supports_customization) {
+        return None;
+    }
+
     match flasher {
-        config::Flasher::SdCard if img.init_format() == config::InitFormat::Sysconf => None,
-        config::Flasher::SdCard => Some(FlashingCustomization::NoneSd),
+        config::Flasher::BeagleConnectFreedom => {
+            Some(FlashingCustomization::Bcf(Default::default()))
+        }
         config::Flasher::Msp430Usb => Some(FlashingCustomization::Msp430),
+        config::Flasher::Custom(_) => Some(FlashingCustomization::Custom),
         _ => None,
     }
 }
@@ -944,6 +1628,81 @@ pub(crate) enum OsImageId {
     Local(Vec<usize>),
     // Vec points to OsImage
     Remote(Vec<usize>),
+    // Vec points to parent
+    Url(Vec<usize>),
+}
+
+/// Inputs for flashing an image from a pasted URL.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct UrlImageForm {
+    pub(crate) url: String,
+    pub(crate) sha256: String,
+}
+
+impl UrlImageForm {
+    pub(crate) fn update_url(mut self, url: String) -> Self {
+        self.url = url;
+        self
+    }
+
+    pub(crate) fn update_sha256(mut self, sha256: String) -> Self {
+        self.sha256 = sha256;
+        self
+    }
+
+    /// Validate the inputs. URL should be http(s) and point to a file supported by `flasher`.
+    /// SHA256 is optional.
+    pub(crate) fn parse(
+        &self,
+        flasher: config::Flasher,
+    ) -> Result<(url::Url, Option<[u8; 32]>), String> {
+        let url = url::Url::parse(self.url.trim()).map_err(|e| format!("Invalid URL: {e}"))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err("Only http and https URLs are supported".to_string());
+        }
+
+        let extensions = file_filter(flasher);
+        let file_name = url
+            .path_segments()
+            .and_then(|mut x| x.next_back())
+            .unwrap_or_default();
+        match file_name.rsplit_once('.') {
+            Some((name, ext)) if !name.is_empty() && extensions.contains(&ext) => {}
+            _ => {
+                return Err(format!(
+                    "URL should point to a file with one of the extensions: {}",
+                    extensions.join(", ")
+                ));
+            }
+        }
+
+        let sha256 = self.sha256.trim();
+        if sha256.is_empty() {
+            return Ok((url, None));
+        }
+
+        let mut res = [0u8; 32];
+        if sha256.len() != res.len() * 2 || !sha256.is_ascii() {
+            return Err("SHA256 should be 64 hexadecimal characters".to_string());
+        }
+        for (i, x) in res.iter_mut().enumerate() {
+            *x = u8::from_str_radix(&sha256[i * 2..i * 2 + 2], 16)
+                .map_err(|_| "SHA256 should be 64 hexadecimal characters".to_string())?;
+        }
+
+        Ok((url, Some(res)))
+    }
+}
+
+/// Minimum app version required by `item`. [None] if the running app is new enough.
+pub(crate) fn update_required(item: &OsListItem) -> Option<&semver::Version> {
+    static VERSION: LazyLock<semver::Version> = LazyLock::new(crate::updater::current_version);
+
+    if item.is_supported(&VERSION) {
+        None
+    } else {
+        item.min_imager_version()
+    }
 }
 
 pub(crate) struct OsImageItem<'a> {
@@ -951,6 +1710,8 @@ pub(crate) struct OsImageItem<'a> {
     pub(crate) icon: Option<&'a url::Url>,
     pub(crate) label: &'a str,
     pub(crate) is_sublist: bool,
+    /// Minimum app version required by the item, if newer than the running app.
+    pub(crate) update_required: Option<&'a semver::Version>,
 }
 
 impl<'a> OsImageItem<'a> {
@@ -960,6 +1721,7 @@ impl<'a> OsImageItem<'a> {
             icon: None,
             label,
             is_sublist: false,
+            update_required: None,
         }
     }
 
@@ -969,20 +1731,27 @@ impl<'a> OsImageItem<'a> {
             icon: None,
             label: "Select Local Image",
             is_sublist: false,
+            update_required: None,
         }
     }
 
-    pub(crate) fn remote(
-        id: Vec<usize>,
-        url: &'a url::Url,
-        label: &'a str,
-        is_sublist: bool,
-    ) -> Self {
+    pub(crate) fn url(parent: Vec<usize>) -> Self {
+        Self {
+            id: OsImageId::Url(parent),
+            icon: None,
+            label: "Flash from URL",
+            is_sublist: false,
+            update_required: None,
+        }
+    }
+
+    pub(crate) fn remote(id: Vec<usize>, item: &'a OsListItem) -> Self {
         Self {
             id: OsImageId::Remote(id),
-            icon: Some(url),
-            label,
-            is_sublist,
+            icon: Some(item.icon()),
+            label: item.name(),
+            is_sublist: matches!(item, OsListItem::SubList(_) | OsListItem::RemoteSubList(_)),
+            update_required: update_required(item),
         }
     }
 }
@@ -1017,3 +1786,533 @@ impl<'a> DestinationItem<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_host() {
+        let host = HostSettings {
+            hostname: Some("my-laptop".to_string()),
+            timezone: Some("Asia/Kolkata".to_string()),
+            keymap: "de".to_string(),
+            username: Some("jdoe".to_string()),
+        };
+        let base = crate::persistance::SdSysconfCustomization::default()
+            .update_hostname(Some("beagle".to_string()))
+            .update_user(Some(crate::persistance::SdCustomizationUser::new(
+                "debian".to_string(),
+                "temppwd".to_string(),
+            )))
+            .update_ssh(Some("ssh-ed25519 AAAA".to_string()));
+
+        let FlashingCustomization::LinuxSdSysconfig(x) =
+            FlashingCustomization::from_host(base.clone(), &host)
+        else {
+            panic!("Expected sysconf customization");
+        };
+        assert_eq!(x.hostname, host.hostname);
+        assert_eq!(x.timezone, host.timezone);
+        assert_eq!(x.keymap.as_deref(), Some("de"));
+        let user = x.user.unwrap();
+        assert_eq!(user.username, "jdoe");
+        // Not available on host, so kept
+        assert_eq!(user.password, "temppwd");
+        assert_eq!(x.ssh, base.ssh);
+
+        // Missing host values do not clear existing ones
+        let host = HostSettings {
+            hostname: None,
+            timezone: None,
+            keymap: "us".to_string(),
+            username: None,
+        };
+        let FlashingCustomization::LinuxSdSysconfig(x) =
+            FlashingCustomization::from_host(base, &host)
+        else {
+            panic!("Expected sysconf customization");
+        };
+        assert_eq!(x.hostname.as_deref(), Some("beagle"));
+        assert_eq!(x.user.unwrap().username, "debian");
+    }
+
+    #[test]
+    fn from_rpi_imager() {
+        const CONF: &str = "\
+[imagecustomization]
+hostname=mypi
+keyboardLayout=gb
+sshAuthorizedKeys=ssh-ed25519 BBBB user@host
+sshEnabled=true
+sshUserName=pi
+sshUserPassword=$5$salt$hash
+timezone=Europe/London
+wifiCountry=GB
+wifiPassword=wifi-pass
+wifiSSID=Home
+";
+        let settings = bb_flasher::sd::RpiImagerSettings::parse(CONF);
+        let base = crate::persistance::SdSysconfCustomization::default()
+            .update_hostname(Some("beagle".to_string()))
+            .update_user(Some(crate::persistance::SdCustomizationUser::new(
+                "debian".to_string(),
+                "temppwd".to_string(),
+            )))
+            .update_usb_enable_dhcp(Some(true));
+
+        let (FlashingCustomization::LinuxSdSysconfig(x), ignored) =
+            FlashingCustomization::from_rpi_imager(base, &settings)
+        else {
+            panic!("Expected sysconf customization");
+        };
+
+        assert_eq!(x.hostname.as_deref(), Some("mypi"));
+        assert_eq!(x.timezone.as_deref(), Some("Europe/London"));
+        assert_eq!(x.keymap.as_deref(), Some("gb"));
+        assert_eq!(x.ssh.as_deref(), Some("ssh-ed25519 BBBB user@host"));
+        // Not present in Raspberry Pi Imager, so kept
+        assert_eq!(x.usb_enable_dhcp, Some(true));
+
+        // Password is hashed, so it needs to be entered again
+        let user = x.user.unwrap();
+        assert_eq!(user.username, "pi");
+        assert_eq!(user.password, "");
+
+        let wifi = x.wifi.unwrap();
+        assert_eq!(wifi.ssid, "Home");
+        assert_eq!(wifi.password, "wifi-pass");
+
+        assert_eq!(ignored, ["sshUserPassword (hashed)", "wifiCountry"]);
+    }
+
+    #[test]
+    fn image_much_smaller() {
+        const GIB: u64 = 1024 * 1024 * 1024;
+
+        let cases = [
+            (2 * GIB, 256 * GIB, 32, true),
+            (2 * GIB, 64 * GIB, 32, false),
+            (2 * GIB, 65 * GIB, 32, true),
+            (4 * GIB, 32 * GIB, 32, false),
+            (4 * GIB, 32 * GIB, 4, true),
+            (32 * GIB, 16 * GIB, 32, false),
+            (2 * GIB, 256 * GIB, 0, false),
+            (u64::MAX, u64::MAX, 32, false),
+        ];
+
+        for (image, dest, ratio, expected) in cases {
+            assert_eq!(
+                is_image_much_smaller(image, dest, ratio),
+                expected,
+                "{image} {dest} {ratio}"
+            );
+        }
+    }
+
+    #[test]
+    fn local_image_info() {
+        let path = std::env::temp_dir().join("bb-imager-gui-local-image-test.img");
+        std::fs::write(&path, [0u8; 512]).unwrap();
+
+        let img = BoardImage::local(
+            path.clone(),
+            config::Flasher::SdCard,
+            LocalImageInfo::default(),
+        );
+        assert_eq!(img.to_string(), "bb-imager-gui-local-image-test.img");
+        assert_eq!(img.description(), None);
+        assert!(img.details().iter().all(|(k, _)| *k != "Name"));
+
+        let info = LocalImageInfo::default()
+            .update_name(Some("My Image".to_string()))
+            .update_description(Some("Nightly build".to_string()));
+        let mut img = BoardImage::local(path.clone(), config::Flasher::SdCard, info.clone());
+        assert_eq!(img.to_string(), "My Image");
+        assert_eq!(img.description(), Some("Nightly build"));
+        assert_eq!(img.details()[0], ("Name", "My Image".to_string()));
+        assert_eq!(img.local_info(), Some((path.as_path(), info)));
+
+        // Name detail is replaced, not duplicated
+        img.update_local_info(LocalImageInfo::default().update_name(Some("Other".to_string())));
+        assert_eq!(img.to_string(), "Other");
+        assert_eq!(
+            img.details().iter().filter(|(k, _)| *k == "Name").count(),
+            1
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Serve `body` for each of the next `count` requests.
+    fn mock_server(body: Vec<u8>, count: usize) -> std::net::SocketAddr {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(count) {
+                let mut stream = stream.unwrap();
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+
+        addr
+    }
+
+    /// Serves fixtures from memory instead of network. Nothing is ever cached.
+    #[derive(Debug, Clone)]
+    struct MockFetcher {
+        files: std::sync::Arc<HashMap<Url, Vec<u8>>>,
+        dir: PathBuf,
+    }
+
+    impl MockFetcher {
+        fn file(&self, url: &Url) -> std::io::Result<Vec<u8>> {
+            self.files
+                .get(url)
+                .cloned()
+                .ok_or(std::io::ErrorKind::NotFound.into())
+        }
+    }
+
+    impl Fetcher for MockFetcher {
+        async fn check_cache_from_sha(&self, _: [u8; 32]) -> Option<PathBuf> {
+            None
+        }
+
+        fn check_cache_from_url(&self, _: Url) -> Option<PathBuf> {
+            None
+        }
+
+        async fn download_tracked(
+            &self,
+            url: Url,
+            _: Option<[u8; 32]>,
+            _: Option<futures::channel::mpsc::Sender<bb_downloader::DownloadEvent>>,
+        ) -> std::io::Result<PathBuf> {
+            let path = self
+                .dir
+                .join(url.path_segments().unwrap().next_back().unwrap());
+            tokio::fs::write(&path, self.file(&url)?).await?;
+            Ok(path)
+        }
+
+        async fn download_with_sha_tracked(
+            &self,
+            url: Url,
+            _: [u8; 32],
+            chan: Option<futures::channel::mpsc::Sender<bb_downloader::DownloadEvent>>,
+        ) -> std::io::Result<PathBuf> {
+            self.download_tracked(url, None, chan).await
+        }
+
+        async fn download_to_stream(
+            self,
+            url: Url,
+            _: [u8; 32],
+            mut writer: bb_helper::file_stream::WriterFileStream,
+        ) -> std::io::Result<()> {
+            use tokio::io::AsyncWriteExt;
+
+            writer.write_all(&self.file(&url)?).await?;
+            writer.flush().await
+        }
+    }
+
+    #[test]
+    fn url_image_form() {
+        let parse = |url: &str, sha256: &str| {
+            UrlImageForm::default()
+                .update_url(url.to_string())
+                .update_sha256(sha256.to_string())
+                .parse(config::Flasher::SdCard)
+        };
+
+        let (url, sha256) = parse(" https://example.com/image.img.xz ", "").unwrap();
+        assert_eq!(url.as_str(), "https://example.com/image.img.xz");
+        assert_eq!(sha256, None);
+
+        let (_, sha256) = parse("http://example.com/image.img", &"aB".repeat(32)).unwrap();
+        assert_eq!(sha256, Some([0xab; 32]));
+
+        assert!(parse("example.com/image.img.xz", "").is_err());
+        assert!(parse("ftp://example.com/image.img.xz", "").is_err());
+        assert!(parse("https://example.com/image.bin", "").is_err());
+        assert!(parse("https://example.com/", "").is_err());
+        assert!(parse("https://example.com/image.img", "abcd").is_err());
+        assert!(parse("https://example.com/image.img", &"zz".repeat(32)).is_err());
+    }
+
+    #[tokio::test]
+    async fn url_image_resolve() {
+        use bb_flasher::Resolvable;
+        use std::io::Read;
+
+        let data: Vec<u8> = (0..4096).map(|x| x as u8).collect();
+        let addr = mock_server(data.clone(), 2);
+        let cache = std::env::temp_dir().join(format!("bb-imager-gui-url-{}", std::process::id()));
+        let downloader = bb_downloader::Downloader::new(&cache).unwrap();
+        let mut rt = tokio::task::JoinSet::new();
+
+        let form = UrlImageForm::default().update_url(format!("http://{addr}/test.img"));
+
+        // Wrong SHA256
+        let (url, _) = form.parse(config::Flasher::SdCard).unwrap();
+        let img = RemoteImage::from_url(url, Some([0; 32]), downloader.clone());
+        assert!(img.resolve(&mut rt).await.is_err());
+
+        let (url, sha256) = form.parse(config::Flasher::SdCard).unwrap();
+        let img = BoardImage::url(url, sha256, config::Flasher::SdCard, downloader);
+        assert_eq!(img.to_string(), "test.img");
+        assert_eq!(img.init_format(), config::InitFormat::None);
+
+        let BoardImage::Image { img, .. } = img else {
+            panic!("Expected image");
+        };
+        let (mut os_img, size) = img.resolve(&mut rt).await.unwrap();
+        assert_eq!(size, data.len() as u64);
+
+        let mut buf = Vec::new();
+        os_img.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, data);
+
+        std::fs::remove_dir_all(cache).unwrap();
+    }
+
+    #[tokio::test]
+    async fn image_sha256() {
+        use bb_flasher::Resolvable;
+        use std::io::Read;
+
+        const DATA: &[u8] = b"hello world";
+        const SHA256: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        let addr = mock_server(DATA.to_vec(), 1);
+        let cache = std::env::temp_dir().join(format!("bb-imager-gui-sha-{}", std::process::id()));
+        let downloader = bb_downloader::Downloader::new(&cache).unwrap();
+        let mut common = crate::state::BBImagerCommon::new(Default::default(), downloader.clone());
+        let mut rt = tokio::task::JoinSet::new();
+
+        let url = Url::parse(&format!("http://{addr}/test.img")).unwrap();
+        let mut board_img = BoardImage::url(url.clone(), None, config::Flasher::SdCard, downloader);
+        assert_eq!(board_img.remote_url(), Some(&url));
+        assert_eq!(common.image_sha256(&board_img), None);
+
+        let (tx, mut rx) = futures::channel::mpsc::channel(1);
+        board_img.set_sha256_channel(tx);
+
+        let BoardImage::Image { img, .. } = &board_img else {
+            panic!("Expected image");
+        };
+        let (mut os_img, _) = img.resolve(&mut rt).await.unwrap();
+
+        let mut buf = Vec::new();
+        os_img.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, DATA);
+
+        common
+            .image_sha256
+            .insert(url, rx.try_next().unwrap().unwrap());
+        assert_eq!(common.image_sha256(&board_img).as_deref(), Some(SHA256));
+
+        std::fs::remove_dir_all(cache).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resolve_mock_fetcher() {
+        use bb_flasher::Resolvable;
+        use std::io::Read;
+
+        const DATA: &[u8] = b"hello world";
+
+        let dir = std::env::temp_dir().join(format!("bb-imager-gui-mock-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let url = Url::parse("https://example.com/test.img").unwrap();
+        let fetcher = MockFetcher {
+            files: std::sync::Arc::new(HashMap::from([(url.clone(), DATA.to_vec())])),
+            dir: dir.clone(),
+        };
+
+        // Streamed while downloading
+        let img = RemoteImage::new(
+            "Test".into(),
+            Box::new(url.clone()),
+            [0; 32],
+            DATA.len() as u64,
+            fetcher.clone(),
+        );
+        let mut rt = tokio::task::JoinSet::new();
+        let (mut os_img, size) = img.resolve(&mut rt).await.unwrap();
+        let mut buf = Vec::new();
+        os_img.read_to_end(&mut buf).unwrap();
+        assert_eq!((buf.as_slice(), size), (DATA, DATA.len() as u64));
+        while let Some(t) = rt.join_next().await {
+            t.unwrap().unwrap();
+        }
+
+        // Downloaded completely, since size is unknown
+        let img = RemoteImage::from_url(url, None, fetcher.clone());
+        let (mut os_img, _) = img.resolve(&mut rt).await.unwrap();
+        let mut buf = Vec::new();
+        os_img.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, DATA);
+
+        let missing = Url::parse("https://example.com/missing.img").unwrap();
+        let img = RemoteImage::from_url(missing, None, fetcher);
+        assert!(img.resolve(&mut rt).await.is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn filter_logs() {
+        const LOGS: &str = "\
+2025-01-01T00:00:00.000000Z  INFO bb_imager_gui: Starting
+2025-01-01T00:00:01.000000Z  WARN bb_downloader: Slow download
+2025-01-01T00:00:02.000000Z ERROR bb_flasher: Flashing failed
+Caused by: Permission denied
+2025-01-01T00:00:03.000000Z DEBUG bb_imager_gui: Retrying
+";
+
+        assert_eq!(super::filter_logs(LOGS, LogLevel::Info), LOGS);
+        assert_eq!(
+            super::filter_logs(LOGS, LogLevel::Warn)
+                .lines()
+                .collect::<Vec<_>>(),
+            [
+                "2025-01-01T00:00:01.000000Z  WARN bb_downloader: Slow download",
+                "2025-01-01T00:00:02.000000Z ERROR bb_flasher: Flashing failed",
+                "Caused by: Permission denied",
+            ]
+        );
+        assert_eq!(
+            super::filter_logs(LOGS, LogLevel::Error),
+            "2025-01-01T00:00:02.000000Z ERROR bb_flasher: Flashing failed\nCaused by: Permission denied\n"
+        );
+        assert_eq!(super::filter_logs("", LogLevel::Error), "");
+    }
+
+    #[test]
+    fn min_imager_version() {
+        let cur = crate::updater::current_version();
+        let item = |min_imager_version: Option<semver::Version>| {
+            OsListItem::Image(config::OsImage {
+                name: "Image".to_string(),
+                description: String::new(),
+                icon: Url::parse("https://example.com/icon.png").unwrap(),
+                url: Url::parse("https://example.com/image.img.xz").unwrap(),
+                image_download_size: None,
+                image_download_sha256: [0; 32],
+                extract_size: 1024,
+                release_date: "2024-01-01".parse().unwrap(),
+                devices: Default::default(),
+                tags: Default::default(),
+                init_format: config::InitFormat::Sysconf,
+                bmap: None,
+                info_text: None,
+                min_imager_version,
+                release_notes: None,
+                changelog_url: None,
+            })
+        };
+
+        let newer = semver::Version::new(cur.major + 1, 0, 0);
+        let img = item(Some(newer.clone()));
+        let os_item = OsImageItem::remote(vec![0], &img);
+        assert_eq!(os_item.update_required, Some(&newer));
+
+        for min_imager_version in [None, Some(cur)] {
+            let img = item(min_imager_version);
+            assert_eq!(OsImageItem::remote(vec![0], &img).update_required, None);
+        }
+    }
+
+    #[test]
+    fn customization_errors() {
+        use crate::persistance::{
+            SdCustomizationUser, SdCustomizationWifi, SdSysconfCustomization,
+        };
+
+        let wifi = |ssid: &str, password: &str| {
+            SdCustomizationWifi::default()
+                .update_ssid(ssid.to_string())
+                .update_password(password.to_string())
+        };
+        let errors = |c: SdSysconfCustomization| {
+            FlashingCustomization::LinuxSdSysconfig(c)
+                .validate()
+                .err()
+                .unwrap_or_default()
+        };
+
+        let valid = SdSysconfCustomization::default()
+            .update_user(Some(SdCustomizationUser::new(
+                "beagle".to_string(),
+                "temppwd".to_string(),
+            )))
+            .update_wifi(Some(wifi("Home", "password")))
+            .update_hostname(Some("beagle.local".to_string()));
+        assert!(errors(valid.clone()).is_empty());
+
+        // Password without SSID
+        assert_eq!(
+            errors(valid.clone().update_wifi(Some(wifi("", "password")))),
+            [CustomizationError::MissingWifiSsid]
+        );
+        assert_eq!(
+            errors(valid.clone().update_wifi(Some(wifi("", "")))),
+            [
+                CustomizationError::EmptyWifiSsid,
+                CustomizationError::InvalidWifiPassword
+            ]
+        );
+
+        // Incomplete user
+        assert_eq!(
+            errors(valid.clone().update_user(Some(SdCustomizationUser::new(
+                "root".to_string(),
+                String::new()
+            )))),
+            [
+                CustomizationError::RootUser,
+                CustomizationError::EmptyUserPassword
+            ]
+        );
+        assert_eq!(
+            errors(valid.clone().update_user(Some(SdCustomizationUser::new(
+                "John Doe".to_string(),
+                "temppwd".to_string()
+            )))),
+            [CustomizationError::InvalidUsername]
+        );
+
+        assert_eq!(
+            errors(
+                valid
+                    .clone()
+                    .update_hostname(Some("-beagle".to_string()))
+                    .update_ssh(Some(" ".to_string()))
+            ),
+            [
+                CustomizationError::InvalidHostname,
+                CustomizationError::EmptySshKey
+            ]
+        );
+
+        assert!(FlashingCustomization::NoneSd.validate().is_ok());
+    }
+}
@@ -128,52 +128,10 @@ impl BBImager {
         )
         .unwrap();
 
-        // Fetch old config
-        let client = downloader.clone();
-        let config_task = helpers::refresh_config_task(client, &helpers::Boards::new());
-        let boards = helpers::Boards::new();
-
-        let img_handle_cache = helpers::ImageHandleCache::from_iter(
-            boards
-                .devices()
-                .filter_map(|(_, dev)| dev.icon.clone())
-                .filter_map(|icon| {
-                    let path = downloader.check_cache_from_url(icon.clone())?;
-                    Some((icon, path))
-                }),
-        );
+        let common = BBImagerCommon::new(app_config, downloader);
 
-        let common = BBImagerCommon {
-            app_config,
-            downloader: downloader.clone(),
-            timezones: widget::combo_box::State::new(
-                constants::TIMEZONES.iter().map(|x| x.to_string()).collect(),
-            ),
-            keymaps: widget::combo_box::State::new(
-                constants::KEYMAP_LAYOUTS
-                    .iter()
-                    .map(|x| x.to_string())
-                    .collect(),
-            ),
-            boards,
-            board_svg_handle: widget::svg::Handle::from_memory(constants::BOARD_ICON),
-            downloading_svg_handle: widget::svg::Handle::from_memory(constants::DOWNLOADING_ICON),
-            arrow_forward_svg_handle: widget::svg::Handle::from_memory(
-                constants::ARROW_FORWARD_IOS_ICON,
-            ),
-            format_svg_handle: widget::svg::Handle::from_memory(constants::FORMAT_ICON),
-            file_add_svg_handle: widget::svg::Handle::from_memory(constants::FILE_ADD_ICON),
-            arrow_back_svg_handle: widget::svg::Handle::from_memory(constants::ARROW_BACK_ICON),
-            usb_svg_handle: widget::svg::Handle::from_memory(constants::USB_ICON),
-            file_save_icon: widget::svg::Handle::from_memory(constants::FILE_SAVE_ICON),
-            info_svg_handle: widget::svg::Handle::from_memory(constants::INFO_ICON),
-            window_icon_handle: widget::image::Handle::from_bytes(crate::constants::WINDOW_ICON),
-            copy_svg_handle: widget::svg::Handle::from_memory(constants::COPY_ICON),
-
-            img_handle_cache,
-
-            scroll_id: widget::Id::unique(),
-        };
+        // Fetch old config
+        let config_task = common.refresh_config_task();
 
         // Fetch all board images
         let board_image_task = common.fetch_board_images();
@@ -282,26 +240,45 @@ impl BBImager {
     }
 
     fn subscription(&self) -> Subscription<BBImagerMessage> {
-        match self {
+        let destinations = match self {
             Self::ChooseDest(x) => Subscription::run_with(
                 (x.selected_image.1.flasher(), x.filter_destination),
                 |(flasher, filter)| {
                     iced::futures::stream::unfold(
-                        (*flasher, *filter),
-                        async move |(flasher, filter)| {
-                            let mut dest = helpers::destinations(flasher, filter).await;
-
-                            dest.sort_by_key(|x| x.to_string());
-
+                        (*flasher, *filter, None),
+                        async move |(flasher, filter, generation)| {
+                            // Skip listing destinations again if nothing changed
+                            let current = helpers::destinations_generation(flasher).await;
+                            if current.is_some() && current == generation {
+                                return Some((None, (flasher, filter, generation)));
+                            }
+
+                            let dest = helpers::destinations(flasher, filter).await;
                             let msg = BBImagerMessage::Destinations(dest);
-                            Some((msg, (flasher, filter)))
+                            Some((Some(msg), (flasher, filter, current)))
                         },
                     )
                     .throttle(Duration::from_secs(1))
+                    .filter_map(std::convert::identity)
                 },
             ),
             _ => Subscription::none(),
-        }
+        };
+
+        // Only prune cache when idle, so that it does not compete with downloads.
+        let prune_cache = match self {
+            Self::Flashing(_) => Subscription::none(),
+            _ => iced::time::every(constants::CACHE_PRUNE_CHECK_INTERVAL)
+                .map(|_| BBImagerMessage::PruneCache),
+        };
+
+        let refresh_logs = match self {
+            Self::AppInfo(_) => iced::time::every(constants::LOG_REFRESH_INTERVAL)
+                .map(|_| BBImagerMessage::RefreshLogs),
+            _ => Subscription::none(),
+        };
+
+        Subscription::batch([destinations, prune_cache, refresh_logs])
     }
 
     fn start_flashing(&mut self) -> Task<BBImagerMessage> {
@@ -313,9 +290,21 @@ impl BBImager {
         let board = state.common.boards.device(state.selected_board);
 
         let is_download = state.is_download();
+        let request = state::FlashingRequest {
+            selected_image: state.selected_image.clone(),
+            selected_dest: state.selected_dest.clone(),
+            customization: state.customization.clone(),
+        };
         let customization = state.customization;
-        let img = state.selected_image.1.clone();
+        let mut img = state.selected_image.1.clone();
+        let flasher = img.flasher();
+        let sha256_rx = img.remote_url().cloned().map(|url| {
+            let (tx, rx) = iced::futures::channel::mpsc::channel(1);
+            img.set_sha256_channel(tx);
+            (url, rx)
+        });
         let dst = state.selected_dest;
+        let max_device_size = state.common.app_config.max_device_size();
 
         tracing::info!("Starting Flashing Process");
         tracing::info!("Selected Board: {:#?}", board);
@@ -326,10 +315,21 @@ impl BBImager {
         let cancel = tokio_util::sync::CancellationToken::new();
 
         let s = iced::stream::channel(20, async move |mut chan| {
+            // Destination could have been removed after selection
+            let Some(dst) = helpers::connected_destination(dst, flasher).await else {
+                tracing::error!("Selected destination is no longer connected");
+                let _ = chan.send(BBImagerMessage::DestinationRemoved).await;
+                return;
+            };
+
             let (tx, mut rx) = iced::futures::channel::mpsc::channel(19);
 
             let cancel_child = cancel.child_token();
             let flash_task = tokio::spawn(async move {
+                if let helpers::Destination::SdCard(t) = &dst {
+                    t.check_size(max_device_size)?;
+                }
+
                 helpers::flash(img, customization, dst, tx, cancel_child).await
             });
             let mut chan_clone = chan.clone();
@@ -338,6 +338,15 @@ impl BBImager {
                     let _ = chan_clone.try_send(BBImagerMessage::FlashProgress(progress));
                 }
             });
+            // Sender is dropped along with the image, so no need to abort
+            if let Some((url, mut sha256_rx)) = sha256_rx {
+                let mut chan_clone = chan.clone();
+                tokio::spawn(async move {
+                    if let Some(x) = sha256_rx.next().await {
+                        let _ = chan_clone.send(BBImagerMessage::ImageSha256(url, x)).await;
+                    }
+                });
+            }
             let _guard = cancel.drop_guard();
 
             let res = flash_task
@@ -368,11 +377,56 @@ impl BBImager {
             cancel_flashing: h,
             progress: bb_flasher::DownloadFlashingStatus::Preparing,
             start_timestamp: None,
+            request,
         });
 
         t
     }
 
+    /// Current step of the wizard. [None] for pages that are not part of the wizard.
+    const fn expert_step(&self) -> Option<state::ExpertStep> {
+        match self {
+            Self::ChooseBoard(_) => Some(state::ExpertStep::Board),
+            Self::ChooseOs(_) => Some(state::ExpertStep::Os),
+            Self::ChooseDest(_) => Some(state::ExpertStep::Destination),
+            Self::Customize(_) => Some(state::ExpertStep::Customize),
+            Self::Review(_) => Some(state::ExpertStep::Review),
+            _ => None,
+        }
+    }
+
+    /// In expert mode, move to the next step as soon as a selection is made.
+    fn expert_next(&mut self) -> Task<BBImagerMessage> {
+        if self.common().app_config.expert_mode() {
+            self.next()
+        } else {
+            Task::none()
+        }
+    }
+
+    /// Go back to an earlier step. Uses the same transitions as the BACK button, so that any
+    /// steps which do not apply to the selected image are skipped.
+    fn expert_goto(&mut self, step: state::ExpertStep) -> Task<BBImagerMessage> {
+        let mut task = Task::none();
+
+        while self.expert_step().is_some_and(|x| x > step) {
+            task = self.back();
+        }
+
+        task
+    }
+
+    /// Start flashing directly from customization page. Customization is saved the same way as
+    /// moving to review page.
+    fn expert_flash(&mut self) -> Task<BBImagerMessage> {
+        let save_task = match self {
+            Self::Customize(_) => self.next(),
+            _ => Task::none(),
+        };
+
+        Task::batch([save_task, self.start_flashing()])
+    }
+
     fn scroll_reset(&self) -> Task<BBImagerMessage> {
         widget::operation::snap_to(
             self.common().scroll_id.clone(),
@@ -418,6 +472,57 @@ impl BBImager {
         self.scroll_reset()
     }
 
+    /// Go back to destination selection when the selected destination is removed before flashing
+    /// starts.
+    fn destination_removed(&mut self) -> Task<BBImagerMessage> {
+        *self = match std::mem::take(self) {
+            Self::Flashing(inner) => Self::ChooseDest(inner.into()),
+            Self::AppInfo(inner) => match inner.page {
+                state::OverlayData::Flashing(x) => Self::AppInfo(state::OverlayState {
+                    page: state::OverlayData::ChooseDest(x.into()),
+                    ..inner
+                }),
+                _ => panic!("Unexpected page"),
+            },
+            _ => panic!("Unexpected page"),
+        };
+
+        self.scroll_reset()
+    }
+
+    /// Flash the same image with the same customization to another destination. The image is
+    /// reused from cache.
+    fn flash_another(&mut self) -> Task<BBImagerMessage> {
+        let inner = match std::mem::take(self) {
+            Self::FlashingSuccess(inner) => inner,
+            _ => panic!("Unexpected page"),
+        };
+        let request = inner.request;
+
+        *self = match helpers::static_destination(request.selected_image.1.flasher()) {
+            // Nothing to choose
+            Some(selected_dest) => Self::Review(state::CustomizeState {
+                common: inner.common,
+                selected_board: inner.selected_board,
+                selected_image: request.selected_image,
+                selected_dest,
+                customization: request.customization,
+            }),
+            None => Self::ChooseDest(state::ChooseDestState {
+                common: inner.common,
+                selected_board: inner.selected_board,
+                selected_image: request.selected_image,
+                selected_dest: None,
+                destinations: Vec::new(),
+                filter_destination: true,
+                customization: Some(request.customization),
+                removed_dest: None,
+            }),
+        };
+
+        self.scroll_reset()
+    }
+
     fn next(&mut self) -> Task<BBImagerMessage> {
         *self = match std::mem::take(self) {
             Self::ChooseBoard(inner) => {
@@ -429,6 +534,7 @@ impl BBImager {
                     selected_board,
                     pos: Vec::with_capacity(5),
                     selected_image: None,
+                    url_form: None,
                 })
             }
             Self::ChooseOs(inner) => {
@@ -472,6 +578,8 @@ impl BBImager {
                         selected_dest: None,
                         destinations: Vec::new(),
                         filter_destination: true,
+                        customization: None,
+                        removed_dest: None,
                     })
                 }
             }
@@ -493,10 +601,17 @@ impl BBImager {
                         customization,
                     })
                 } else {
-                    let temp = helpers::FlashingCustomization::new(
-                        inner.selected_image.1.flasher(),
+                    let mut temp = inner.customization.unwrap_or_else(|| {
+                        helpers::FlashingCustomization::new(
+                            inner.selected_image.1.flasher(),
+                            &inner.selected_image.1,
+                            &inner.common.app_config,
+                        )
+                    });
+                    temp.default_expand_rootfs(
                         &inner.selected_image.1,
-                        &inner.common.app_config,
+                        &selected_dest,
+                        inner.common.app_config.small_image_ratio(),
                     );
 
                     Self::Customize(state::CustomizeState {
@@ -548,3 +663,152 @@ impl BBImager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expert_mode_persists() {
+        let mut config = persistance::GuiConfiguration::default();
+        let data = serde_json::to_string(&config).unwrap();
+        assert!(!config.expert_mode());
+        assert!(!data.contains("expert_mode"));
+
+        config.update_expert_mode(true);
+        let config: persistance::GuiConfiguration =
+            serde_json::from_str(&serde_json::to_string(&config).unwrap()).unwrap();
+        assert!(config.expert_mode());
+    }
+
+    #[test]
+    fn disable_update_check() {
+        let mut config = persistance::GuiConfiguration::default();
+        assert!(config.update_check());
+        let data = serde_json::to_string(&config).unwrap();
+        assert!(!data.contains("update_check"));
+
+        config.update_update_check(false);
+        config.update_local_config_only(true);
+        let config: persistance::GuiConfiguration =
+            serde_json::from_str(&serde_json::to_string(&config).unwrap()).unwrap();
+        assert!(!config.update_check());
+        assert!(config.local_config_only());
+
+        let downloader = bb_downloader::Downloader::new(
+            std::env::temp_dir().join("bb-imager-gui-update-check-test"),
+        )
+        .unwrap();
+        let common = BBImagerCommon::new(config, downloader);
+        assert_eq!(common.updater_task().units(), 0);
+        assert_eq!(common.refresh_config_task().units(), 0);
+    }
+
+    #[test]
+    fn flash_another() {
+        let downloader =
+            bb_downloader::Downloader::new(std::env::temp_dir().join("bb-imager-gui-another-test"))
+                .unwrap();
+        let customization = persistance::SdSysconfCustomization::default()
+            .update_hostname(Some("beagle".to_string()));
+        let mut state = BBImager::FlashingSuccess(state::FlashingFinishState {
+            common: BBImagerCommon::new(Default::default(), downloader),
+            selected_board: 0,
+            is_download: false,
+            request: state::FlashingRequest {
+                selected_image: (
+                    helpers::OsImageId::Format(Vec::new()),
+                    helpers::BoardImage::format(),
+                ),
+                selected_dest: helpers::Destination::LocalFile(
+                    std::env::temp_dir().join("bb-imager-gui-another-test.img"),
+                ),
+                customization: helpers::FlashingCustomization::LinuxSdSysconfig(customization),
+            },
+            image_cached: false,
+        });
+
+        let _ = state.flash_another();
+        let BBImager::ChooseDest(inner) = &state else {
+            panic!("Expected destination selection");
+        };
+        assert_eq!(inner.selected_board, 0);
+        assert_eq!(
+            inner.selected_image.0,
+            helpers::OsImageId::Format(Vec::new())
+        );
+        assert!(inner.selected_dest.is_none());
+        assert!(matches!(
+            &inner.customization,
+            Some(helpers::FlashingCustomization::LinuxSdSysconfig(x))
+                if x.hostname.as_deref() == Some("beagle")
+        ));
+    }
+
+    #[test]
+    fn destination_removed() {
+        let downloader =
+            bb_downloader::Downloader::new(std::env::temp_dir().join("bb-imager-gui-removed-test"))
+                .unwrap();
+        let dst = helpers::Destination::LocalFile(
+            std::env::temp_dir().join("bb-imager-gui-removed-test.img"),
+        );
+        let request = state::FlashingRequest {
+            selected_image: (
+                helpers::OsImageId::Format(Vec::new()),
+                helpers::BoardImage::format(),
+            ),
+            selected_dest: dst.clone(),
+            customization: helpers::FlashingCustomization::LinuxSdSysconfig(Default::default()),
+        };
+        let mut state = BBImager::Flashing(state::FlashingState {
+            common: BBImagerCommon::new(Default::default(), downloader),
+            selected_board: 0,
+            cancel_flashing: Task::<BBImagerMessage>::none().abortable().1,
+            progress: bb_flasher::DownloadFlashingStatus::Preparing,
+            start_timestamp: None,
+            is_download: false,
+            request,
+        });
+
+        // Destination vanished between selection and flashing
+        assert!(helpers::find_destination(&dst, &[]).is_none());
+        assert!(helpers::find_destination(&dst, std::slice::from_ref(&dst)).is_some());
+
+        let _ = state.destination_removed();
+        let BBImager::ChooseDest(inner) = &state else {
+            panic!("Expected destination selection");
+        };
+        assert!(inner.selected_dest.is_none());
+        assert_eq!(inner.removed_dest.as_ref(), Some(&dst));
+        assert!(inner.customization.is_some());
+    }
+
+    #[test]
+    fn expert_flash() {
+        let mut config = persistance::GuiConfiguration::default();
+        config.update_expert_mode(true);
+
+        let downloader =
+            bb_downloader::Downloader::new(std::env::temp_dir().join("bb-imager-gui-expert-test"))
+                .unwrap();
+        let mut state = BBImager::Customize(state::CustomizeState {
+            common: BBImagerCommon::new(config, downloader),
+            selected_board: 0,
+            selected_image: (
+                helpers::OsImageId::Format(Vec::new()),
+                helpers::BoardImage::format(),
+            ),
+            selected_dest: helpers::Destination::LocalFile(
+                std::env::temp_dir().join("bb-imager-gui-expert-test.img"),
+            ),
+            customization: helpers::FlashingCustomization::NoneSd,
+        });
+        assert_eq!(state.expert_step(), Some(state::ExpertStep::Customize));
+
+        // Goes through review page, and then starts flashing
+        let _ = state.expert_flash();
+        assert!(matches!(&state, BBImager::Flashing(_)));
+        assert_eq!(state.expert_step(), None);
+    }
+}
@@ -4,7 +4,7 @@ use iced::{Task, widget::operation};
 
 use crate::{
     BBImager, helpers,
-    state::{OverlayData, OverlayState},
+    state::{ExpertStep, FlashingFailState, OverlayData, OverlayState},
 };
 
 #[derive(Debug, Clone)]
@@ -26,17 +26,33 @@ pub(crate) enum BBImagerMessage {
 
     /// ChooseOs Page
     SelectOs(helpers::OsImageId),
-    SelectLocalOs((Vec<usize>, helpers::BoardImage)),
+    SelectLocalOs((Vec<usize>, std::path::PathBuf)),
+    /// Update name and description of selected local image.
+    UpdateLocalImageInfo(crate::persistance::LocalImageInfo),
+    /// Update inputs of "Flash from URL".
+    UpdateUrlImageForm(helpers::UrlImageForm),
+    /// Replace URL input of "Flash from URL" with clipboard contents.
+    PasteImageUrl,
+    /// Use the image from URL input. Vec points to parent.
+    SelectUrlOs(Vec<usize>),
     GotoOsListParent,
 
     /// Choose Destination page
     SelectDest(helpers::Destination),
     SelectFileDest(String),
     DestinationFilter(bool),
+    /// Primary unit used to show sizes. Persisted in app config.
+    SizeUnit(bb_helper::size::SizeUnit),
 
     // Customization Page
     UpdateFlashConfig(crate::helpers::FlashingCustomization),
     ResetFlashingConfig,
+    /// Fill customization with settings of this computer.
+    HostFlashingConfig,
+    /// Select Raspberry Pi Imager settings file to import customization from.
+    ImportRpiImager,
+    /// Fill customization with settings imported from Raspberry Pi Imager.
+    RpiImagerFlashingConfig(bb_flasher::sd::RpiImagerSettings),
 
     // Review Page
     FlashStart,
@@ -44,8 +60,18 @@ pub(crate) enum BBImagerMessage {
     // Flashing Page
     FlashProgress(bb_flasher::DownloadFlashingStatus),
     FlashSuccess,
+    /// SHA256 of the extracted remote image, computed while flashing
+    ImageSha256(url::Url, [u8; 32]),
+    /// Image is present in cache after cancelling flashing
+    ImageCached(bool),
     FlashCancel,
     FlashFail(String),
+    /// Selected destination was not found when flashing started.
+    DestinationRemoved,
+    /// Retry with the same choices after failure
+    FlashRetry,
+    /// Flash the same image to another destination after success
+    FlashAnother,
 
     // Reset to start from beginning.
     Restart,
@@ -69,23 +95,55 @@ pub(crate) enum BBImagerMessage {
 
     /// Show application information
     AppInfo,
+    /// Minimum level of lines shown in log viewer.
+    LogLevel(helpers::LogLevel),
+    /// Reload log viewer. Sent periodically when application information is shown.
+    RefreshLogs,
+    /// Log viewer event
+    LogViewerEvent(iced::widget::text_editor::Action),
 
     /// Copy text to clipboard.
     CopyToClipboard(String),
+
+    /// Remove old files from cache. Only sent when idle.
+    PruneCache,
+
+    /// Show all steps on a single screen. Persisted in app config.
+    ExpertMode(bool),
+    /// Check for application updates on startup. Persisted in app config.
+    UpdateCheck(bool),
+    /// Only use embedded config. Persisted in app config, and takes effect on restart.
+    LocalConfigOnly(bool),
+    /// Store passwords in OS keyring. Persisted in app config.
+    SecretsInKeyring(bool),
+    /// Go back to an earlier step in expert mode.
+    ExpertGoto(ExpertStep),
+    /// Start flashing from customization or review page in expert mode.
+    ExpertFlash,
 }
 
 pub(crate) fn update(state: &mut BBImager, message: BBImagerMessage) -> Task<BBImagerMessage> {
     match message {
-        BBImagerMessage::SelectBoard(id) => match state {
-            BBImager::ChooseBoard(inner) => {
-                inner.selected_board = Some(id);
+        BBImagerMessage::SelectBoard(id) => {
+            match state {
+                BBImager::ChooseBoard(inner) => {
+                    inner.selected_board = Some(id);
+                }
+                _ => panic!("Unexpected message"),
             }
-            _ => panic!("Unexpected message"),
-        },
+
+            return state.expert_next();
+        }
         BBImagerMessage::SelectOs(id) => match state {
             BBImager::ChooseOs(inner) => match id {
                 helpers::OsImageId::Format(_) => {
-                    inner.selected_image = Some((id, helpers::BoardImage::format()))
+                    inner.url_form = None;
+                    inner.selected_image = Some((id, helpers::BoardImage::format()));
+                    return state.expert_next();
+                }
+                helpers::OsImageId::Url(_) => {
+                    inner.selected_image = None;
+                    inner.url_form = Some(Default::default());
                 }
                 helpers::OsImageId::Local(parent) => {
                     let flasher = inner.flasher();
@@ -100,24 +158,29 @@ pub(crate) fn update(state: &mut BBImager, message: BBImagerMessage) -> Task<BBI
                                 .map(|x| x.inner().to_path_buf())
                         },
                         move |x| match x {
-                            Some(y) => BBImagerMessage::SelectLocalOs((
-                                parent,
-                                helpers::BoardImage::local(y, flasher),
-                            )),
+                            Some(y) => BBImagerMessage::SelectLocalOs((parent, y)),
                             None => BBImagerMessage::Null,
                         },
                     );
                 }
                 helpers::OsImageId::Remote(target) => {
-                    if let bb_config::config::OsListItem::Image(x) = inner.image(&target) {
+                    let img = match inner.image(&target) {
+                        bb_config::config::OsListItem::Image(x) => Some(x.clone()),
+                        _ => None,
+                    };
+
+                    if let Some(x) = img {
+                        inner.url_form = None;
                         inner.selected_image = Some((
                             helpers::OsImageId::Remote(target),
                             helpers::BoardImage::remote(
-                                x.clone(),
+                                x,
                                 inner.flasher(),
                                 inner.downloader().clone(),
+                                inner.common.app_config.size_unit(),
                             ),
-                        ))
+                        ));
+                        return state.expert_next();
                     } else {
                         inner.pos = target
                     }
@@ -125,9 +188,51 @@ pub(crate) fn update(state: &mut BBImager, message: BBImagerMessage) -> Task<BBI
             },
             _ => panic!("Unexpected message"),
         },
-        BBImagerMessage::SelectLocalOs((parent, image)) => match state {
+        BBImagerMessage::SelectLocalOs((parent, path)) => match state {
+            BBImager::ChooseOs(inner) => {
+                let info = inner.common.app_config.local_image_info(&path);
+                let image = helpers::BoardImage::local(path, inner.flasher(), info);
+                inner.url_form = None;
+                inner.selected_image = Some((helpers::OsImageId::Local(parent), image));
+                return state.expert_next();
+            }
+            _ => panic!("Unexpected message"),
+        },
+        BBImagerMessage::UpdateUrlImageForm(form) => match state {
+            BBImager::ChooseOs(inner) => inner.url_form = Some(form),
+            _ => panic!("Unexpected message"),
+        },
+        BBImagerMessage::PasteImageUrl => match state {
             BBImager::ChooseOs(inner) => {
-                inner.selected_image = Some((helpers::OsImageId::Local(parent), image))
+                let form = inner.url_form.clone().unwrap_or_default();
+                return iced::clipboard::read().map(move |x| match x {
+                    Some(url) => BBImagerMessage::UpdateUrlImageForm(form.clone().update_url(url)),
+                    None => BBImagerMessage::Null,
+                });
+            }
+            _ => panic!("Unexpected message"),
+        },
+        BBImagerMessage::SelectUrlOs(parent) => match state {
+            BBImager::ChooseOs(inner) => {
+                let flasher = inner.flasher();
+                let Some(Ok((url, sha256))) = inner.url_form.as_ref().map(|x| x.parse(flasher))
+                else {
+                    return Task::none();
+                };
+
+                let image =
+                    helpers::BoardImage::url(url, sha256, flasher, inner.downloader().clone());
+                inner.url_form = None;
+                inner.selected_image = Some((helpers::OsImageId::Url(parent), image));
+                return state.expert_next();
+            }
+            _ => panic!("Unexpected message"),
+        },
+        BBImagerMessage::UpdateLocalImageInfo(info) => match state {
+            BBImager::ChooseOs(inner) => {
+                if let Some((_, img)) = &mut inner.selected_image {
+                    img.update_local_info(info);
+                }
             }
             _ => panic!("Unexpected message"),
         },
@@ -138,7 +243,13 @@ pub(crate) fn update(state: &mut BBImager, message: BBImagerMessage) -> Task<BBI
                 BBImagerMessage::Null
             });
         }
-        BBImagerMessage::Next => return state.next(),
+        BBImagerMessage::Next => {
+            let save_task = match state {
+                BBImager::ChooseOs(inner) => inner.save_local_image_info(),
+                _ => Task::none(),
+            };
+            return Task::batch([save_task, state.next()]);
+        }
         BBImagerMessage::Back => return state.back(),
         BBImagerMessage::ResolveImage(k, v) => state.image_cache_insert(k, v),
         BBImagerMessage::ExtendConfig(c) => {
@@ -163,12 +274,20 @@ pub(crate) fn update(state: &mut BBImager, message: BBImagerMessage) -> Task<BBI
             if let BBImager::ChooseDest(inner) = state
                 && x != inner.destinations
             {
+                // SD Card paths can change between polls. Keep the selection on the same device.
+                if let Some(selected) = &inner.selected_dest
+                    && let Some(dst) = helpers::find_destination(selected, &x)
+                {
+                    inner.selected_dest = Some(dst.clone());
+                }
+
                 inner.destinations = x;
             }
         }
         BBImagerMessage::SelectDest(x) => match state {
             BBImager::ChooseDest(inner) => {
                 inner.selected_dest = Some(x);
+                return state.expert_next();
             }
             _ => panic!("Unexpected message"),
         },
@@ -193,6 +312,13 @@ pub(crate) fn update(state: &mut BBImager, message: BBImagerMessage) -> Task<BBI
             }
             _ => panic!("Unexpected message"),
         },
+        BBImagerMessage::SizeUnit(x) => match state {
+            BBImager::ChooseDest(inner) => {
+                inner.common.app_config.update_size_unit(x);
+                return inner.common.save_app_config();
+            }
+            _ => panic!("Unexpected message"),
+        },
         BBImagerMessage::UpdateFlashConfig(x) => match state {
             BBImager::Customize(inner) => {
                 inner.customization = x;
@@ -205,8 +331,61 @@ pub(crate) fn update(state: &mut BBImager, message: BBImagerMessage) -> Task<BBI
             }
             _ => panic!("Unexpected message"),
         },
+        BBImagerMessage::HostFlashingConfig => match state {
+            BBImager::Customize(inner) => {
+                if let helpers::FlashingCustomization::LinuxSdSysconfig(x) = &inner.customization {
+                    inner.customization = helpers::FlashingCustomization::from_host(
+                        x.clone(),
+                        &helpers::HostSettings::current(),
+                    );
+                }
+            }
+            _ => panic!("Unexpected message"),
+        },
+        BBImagerMessage::ImportRpiImager => {
+            return Task::perform(
+                async move {
+                    let mut dialog = rfd::AsyncFileDialog::new()
+                        .set_title("Select Raspberry Pi Imager settings")
+                        .add_filter("Raspberry Pi Imager settings", &["conf", "ini"]);
+                    if let Some(x) = helpers::rpi_imager_config_dir() {
+                        dialog = dialog.set_directory(x);
+                    }
+
+                    let path = dialog.pick_file().await?;
+                    tokio::fs::read_to_string(path.path())
+                        .await
+                        .inspect_err(|e| tracing::error!("Failed to read {path:?}: {e}"))
+                        .ok()
+                },
+                |x| match x {
+                    Some(y) => BBImagerMessage::RpiImagerFlashingConfig(
+                        bb_flasher::sd::RpiImagerSettings::parse(&y),
+                    ),
+                    None => BBImagerMessage::Null,
+                },
+            );
+        }
+        BBImagerMessage::RpiImagerFlashingConfig(settings) => {
+            if let BBImager::Customize(inner) = state
+                && let helpers::FlashingCustomization::LinuxSdSysconfig(x) = &inner.customization
+            {
+                let (customization, ignored) =
+                    helpers::FlashingCustomization::from_rpi_imager(x.clone(), &settings);
+                inner.customization = customization;
+
+                if !ignored.is_empty() {
+                    let ignored = ignored.join(", ");
+                    tracing::warn!("Raspberry Pi Imager settings not imported: {ignored}");
+                    return show_notification(format!(
+                        "Some Raspberry Pi Imager settings could not be imported: {ignored}"
+                    ));
+                }
+            }
+        }
         BBImagerMessage::FlashCancel => {
             let mut msg = "Flashing cancelled by user";
+            let img;
 
             *state = match std::mem::take(state) {
                 BBImager::Flashing(inner) => {
@@ -215,6 +394,7 @@ pub(crate) fn update(state: &mut BBImager, message: BBImagerMessage) -> Task<BBI
                     if inner.is_download {
                         msg = "Download cancelled by user";
                     }
+                    img = inner.request.selected_image.1.clone();
                     BBImager::FlashingCancel(inner.into())
                 }
                 BBImager::AppInfo(inner) => match inner.page {
@@ -224,6 +404,7 @@ pub(crate) fn update(state: &mut BBImager, message: BBImagerMessage) -> Task<BBI
                         if flashing_state.is_download {
                             msg = "Download cancelled by user";
                         }
+                        img = flashing_state.request.selected_image.1.clone();
 
                         BBImager::AppInfo(OverlayState {
                             page: OverlayData::FlashingCancel(flashing_state.into()),
@@ -235,42 +416,34 @@ pub(crate) fn update(state: &mut BBImager, message: BBImagerMessage) -> Task<BBI
                 _ => panic!("Unexpected message"),
             };
 
-            return show_notification(msg.to_string());
+            return Task::batch([
+                show_notification(msg.to_string()),
+                Task::perform(
+                    async move { img.is_cached().await },
+                    BBImagerMessage::ImageCached,
+                ),
+            ]);
         }
         BBImagerMessage::Restart => {
             state.restart();
         }
         BBImagerMessage::FlashFail(err) => {
-            let mut msg = "Flashing failed";
-
             let logs =
                 std::fs::read_to_string(helpers::log_file_path()).expect("Failed to read logs");
-            let logs = iced::widget::text_editor::Content::with_text(&logs);
 
+            let msg;
             *state = match std::mem::take(state) {
                 BBImager::Flashing(inner) => {
-                    if inner.is_download {
-                        msg = "Download failed";
-                    }
-
-                    BBImager::FlashingFail(crate::state::FlashingFailState {
-                        common: inner.common,
-                        err,
-                        logs,
-                    })
+                    let fail_state = FlashingFailState::new(inner, err, &logs);
+                    msg = fail_state.origin.title();
+                    BBImager::FlashingFail(fail_state)
                 }
                 BBImager::AppInfo(inner) => match inner.page {
                     OverlayData::Flashing(flashing_state) => {
-                        if flashing_state.is_download {
-                            msg = "Download failed";
-                        }
-
+                        let fail_state = FlashingFailState::new(flashing_state, err, &logs);
+                        msg = fail_state.origin.title();
                         BBImager::AppInfo(OverlayState {
-                            page: OverlayData::FlashingFail(crate::state::FlashingFailState {
-                                common: flashing_state.common,
-                                err,
-                                logs,
-                            }),
+                            page: OverlayData::FlashingFail(fail_state),
                             ..inner
                         })
                     }
@@ -281,6 +454,15 @@ pub(crate) fn update(state: &mut BBImager, message: BBImagerMessage) -> Task<BBI
 
             return show_notification(msg.to_string());
         }
+        BBImagerMessage::FlashRetry => {
+            *state = match std::mem::take(state) {
+                BBImager::FlashingFail(inner) => BBImager::Review(inner.into()),
+                _ => panic!("Unexpected message"),
+            };
+
+            return state.start_flashing();
+        }
+        BBImagerMessage::FlashAnother => return state.flash_another(),
         BBImagerMessage::FlashProgress(x) => match state {
             BBImager::Flashing(inner) => {
                 inner.progress_update(x);
@@ -294,6 +476,25 @@ pub(crate) fn update(state: &mut BBImager, message: BBImagerMessage) -> Task<BBI
         BBImagerMessage::FlashStart => {
             return state.start_flashing();
         }
+        BBImagerMessage::ImageSha256(url, x) => {
+            state.common_mut().image_sha256.insert(url, x);
+        }
+        BBImagerMessage::ImageCached(x) => match state {
+            BBImager::FlashingCancel(inner) => inner.image_cached = x,
+            BBImager::AppInfo(inner) => {
+                if let OverlayData::FlashingCancel(flashing_state) = &mut inner.page {
+                    flashing_state.image_cached = x;
+                }
+            }
+            // User might have already moved on
+            _ => {}
+        },
+        BBImagerMessage::DestinationRemoved => {
+            return Task::batch([
+                state.destination_removed(),
+                show_notification("Destination removed before flashing".to_string()),
+            ]);
+        }
         BBImagerMessage::FlashSuccess => {
             let mut msg = "Flashing finished successfully";
 
@@ -340,9 +541,52 @@ pub(crate) fn update(state: &mut BBImager, message: BBImagerMessage) -> Task<BBI
                 operation::RelativeOffset::START,
             );
         }
+        BBImagerMessage::LogLevel(x) => match state {
+            BBImager::AppInfo(inner) => {
+                inner.log_level = x;
+                inner.reload_logs();
+            }
+            _ => panic!("Unexpected message"),
+        },
+        BBImagerMessage::RefreshLogs => {
+            // Can arrive just after leaving the page
+            if let BBImager::AppInfo(inner) = state {
+                inner.reload_logs();
+            }
+        }
+        BBImagerMessage::LogViewerEvent(evt) => match evt {
+            iced::widget::text_editor::Action::Edit(_) => {}
+            _ => match state {
+                BBImager::AppInfo(x) => x.logs.perform(evt),
+                _ => panic!("Unexpected message"),
+            },
+        },
         BBImagerMessage::CopyToClipboard(data) => {
             return iced::clipboard::write(data);
         }
+        BBImagerMessage::PruneCache => return state.common_mut().prune_cache_task(),
+        BBImagerMessage::ExpertMode(x) => {
+            let common = state.common_mut();
+            common.app_config.update_expert_mode(x);
+            return common.save_app_config();
+        }
+        BBImagerMessage::UpdateCheck(x) => {
+            let common = state.common_mut();
+            common.app_config.update_update_check(x);
+            return common.save_app_config();
+        }
+        BBImagerMessage::LocalConfigOnly(x) => {
+            let common = state.common_mut();
+            common.app_config.update_local_config_only(x);
+            return common.save_app_config();
+        }
+        BBImagerMessage::SecretsInKeyring(x) => {
+            let common = state.common_mut();
+            common.app_config.update_secrets_in_keyring(x);
+            return common.save_app_config();
+        }
+        BBImagerMessage::ExpertGoto(step) => return state.expert_goto(step),
+        BBImagerMessage::ExpertFlash => return state.expert_flash(),
         BBImagerMessage::Null => {}
     }
 
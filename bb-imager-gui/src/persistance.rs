@@ -1,7 +1,13 @@
 //! This module contains persistance for configuration
 
-use std::{io::Read, path::PathBuf};
-
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bb_helper::size::SizeUnit;
 use serde::{Deserialize, Serialize};
 use tokio::io::AsyncWriteExt;
 
@@ -15,6 +21,39 @@ pub(crate) struct GuiConfiguration {
     #[cfg(feature = "pb2_mspm0")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pb2_mspm0_customization: Option<Pb2Mspm0Customization>,
+    /// Disable safe-mode, allowing flashing devices larger than [DEFAULT_MAX_SIZE].
+    ///
+    /// [DEFAULT_MAX_SIZE]: bb_flasher::sd::DEFAULT_MAX_SIZE
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    allow_large_device: bool,
+    /// Show sizes in decimal units (GB), as printed on SD Cards, instead of binary units (GiB).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    decimal_size: bool,
+    /// Show all steps on a single screen instead of the step by step wizard.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    expert_mode: bool,
+    /// Do not check for application updates on startup.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    disable_update_check: bool,
+    /// Offline mode. Only use config embedded in application, and do not fetch remote configs
+    /// and icons.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    local_config_only: bool,
+    /// Remove cached files not modified in this many days.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_max_age_days: Option<u64>,
+    /// Warn when the image is smaller than 1 / N of the destination. 0 disables the warning.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    small_image_ratio: Option<u64>,
+    /// Unix timestamp (in seconds) of the last cache prune.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_cache_prune: Option<u64>,
+    /// User provided name and description of local images.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    local_images: HashMap<PathBuf, LocalImageInfo>,
+    /// Store passwords in OS keyring instead of this config.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    secrets_in_keyring: bool,
 }
 
 impl GuiConfiguration {
@@ -25,11 +64,18 @@ impl GuiConfiguration {
         let mut config = std::fs::File::open(config_p)?;
         config.read_to_end(&mut data)?;
 
-        Ok(serde_json::from_slice(&data).unwrap())
+        let mut config: Self = serde_json::from_slice(&data).unwrap();
+        config.load_secrets(&Keyring);
+
+        Ok(config)
     }
 
     pub(crate) async fn save(&self) -> std::io::Result<()> {
-        let data = serde_json::to_string_pretty(self).unwrap();
+        let config = self.clone();
+        // Keyring access can block
+        let data = tokio::task::spawn_blocking(move || config.to_json(&Keyring))
+            .await
+            .unwrap();
         let config_p = Self::config_path().unwrap();
 
         tracing::info!("Configuration Path: {:?}", config_p);
@@ -47,6 +93,34 @@ impl GuiConfiguration {
         Ok(())
     }
 
+    /// Serialize config. If enabled, secrets are moved to `store`, and the config only contains
+    /// their keys.
+    fn to_json(&self, store: &impl CredentialStore) -> String {
+        let mut config = self.clone();
+
+        if config.secrets_in_keyring
+            && let Some(x) = config
+                .sd_customization
+                .as_mut()
+                .and_then(|x| x.sysconf.as_mut())
+        {
+            x.store_secrets(store);
+        }
+
+        serde_json::to_string_pretty(&config).unwrap()
+    }
+
+    /// Replace keys of secrets in config with the secrets from `store`.
+    fn load_secrets(&mut self, store: &impl CredentialStore) {
+        if let Some(x) = self
+            .sd_customization
+            .as_mut()
+            .and_then(|x| x.sysconf.as_mut())
+        {
+            x.load_secrets(store);
+        }
+    }
+
     fn config_path() -> Option<PathBuf> {
         let dirs = crate::helpers::project_dirs()?;
         Some(dirs.config_local_dir().join("config.json").to_owned())
@@ -65,6 +139,101 @@ impl GuiConfiguration {
         self.pb2_mspm0_customization.as_ref()
     }
 
+    /// Maximum SD Card size that can be flashed. [None] if safe-mode is disabled.
+    pub(crate) const fn max_device_size(&self) -> Option<u64> {
+        if self.allow_large_device {
+            None
+        } else {
+            Some(bb_flasher::sd::DEFAULT_MAX_SIZE)
+        }
+    }
+
+    /// Primary unit used to show sizes
+    pub(crate) const fn size_unit(&self) -> SizeUnit {
+        if self.decimal_size {
+            SizeUnit::Decimal
+        } else {
+            SizeUnit::Binary
+        }
+    }
+
+    pub(crate) const fn expert_mode(&self) -> bool {
+        self.expert_mode
+    }
+
+    pub(crate) fn update_expert_mode(&mut self, t: bool) {
+        self.expert_mode = t;
+    }
+
+    pub(crate) const fn update_check(&self) -> bool {
+        !self.disable_update_check
+    }
+
+    pub(crate) fn update_update_check(&mut self, t: bool) {
+        self.disable_update_check = !t;
+    }
+
+    pub(crate) const fn local_config_only(&self) -> bool {
+        self.local_config_only
+    }
+
+    pub(crate) fn update_local_config_only(&mut self, t: bool) {
+        self.local_config_only = t;
+    }
+
+    pub(crate) const fn secrets_in_keyring(&self) -> bool {
+        self.secrets_in_keyring
+    }
+
+    pub(crate) fn update_secrets_in_keyring(&mut self, t: bool) {
+        self.secrets_in_keyring = t;
+    }
+
+    /// Cached files not modified in this duration are pruned.
+    pub(crate) fn cache_max_age(&self) -> Duration {
+        let days = self
+            .cache_max_age_days
+            .unwrap_or(crate::constants::DEFAULT_CACHE_MAX_AGE_DAYS);
+        Duration::from_secs(days.saturating_mul(24 * 60 * 60))
+    }
+
+    /// Ratio of destination size to image size above which a warning is shown before flashing.
+    pub(crate) fn small_image_ratio(&self) -> u64 {
+        self.small_image_ratio
+            .unwrap_or(crate::constants::DEFAULT_SMALL_IMAGE_RATIO)
+    }
+
+    /// Check if [CACHE_PRUNE_INTERVAL] has elapsed since the last cache prune.
+    ///
+    /// [CACHE_PRUNE_INTERVAL]: crate::constants::CACHE_PRUNE_INTERVAL
+    pub(crate) fn cache_prune_due(&self, now: SystemTime) -> bool {
+        // Also prune if the last prune time is in the future, for example after a clock change.
+        self.last_cache_prune
+            .and_then(|x| UNIX_EPOCH.checked_add(Duration::from_secs(x)))
+            .and_then(|x| now.duration_since(x).ok())
+            .is_none_or(|x| x >= crate::constants::CACHE_PRUNE_INTERVAL)
+    }
+
+    pub(crate) fn update_last_cache_prune(&mut self, t: SystemTime) {
+        self.last_cache_prune = t.duration_since(UNIX_EPOCH).ok().map(|x| x.as_secs());
+    }
+
+    pub(crate) fn update_size_unit(&mut self, t: SizeUnit) {
+        self.decimal_size = t == SizeUnit::Decimal;
+    }
+
+    pub(crate) fn local_image_info(&self, path: &Path) -> LocalImageInfo {
+        self.local_images.get(path).cloned().unwrap_or_default()
+    }
+
+    pub(crate) fn update_local_image_info(&mut self, path: PathBuf, t: LocalImageInfo) {
+        if t == LocalImageInfo::default() {
+            self.local_images.remove(&path);
+        } else {
+            self.local_images.insert(path, t);
+        }
+    }
+
     pub(crate) fn update_sd_customization(&mut self, t: SdCustomization) {
         self.sd_customization = Some(t);
     }
@@ -74,6 +243,27 @@ impl GuiConfiguration {
     }
 }
 
+/// Name and description shown for a local image instead of the file name.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct LocalImageInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) description: Option<String>,
+}
+
+impl LocalImageInfo {
+    pub(crate) fn update_name(mut self, t: Option<String>) -> Self {
+        self.name = t;
+        self
+    }
+
+    pub(crate) fn update_description(mut self, t: Option<String>) -> Self {
+        self.description = t;
+        self
+    }
+}
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub(crate) struct SdCustomization {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -106,6 +296,15 @@ pub(crate) struct SdSysconfCustomization {
     pub(crate) ssh: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) usb_enable_dhcp: Option<bool>,
+    /// Not persisted, since the default depends on SD Card and image size.
+    #[serde(skip)]
+    pub(crate) expand_rootfs: Option<bool>,
+    /// Read back and verify SD Card after flashing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) verify: Option<SdVerify>,
+    /// Clear partition table on SD Card before flashing
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) format_before_flash: bool,
 }
 
 impl Default for SdSysconfCustomization {
@@ -122,11 +321,17 @@ impl Default for SdSysconfCustomization {
             } else {
                 None
             },
+            expand_rootfs: None,
+            verify: None,
+            format_before_flash: false,
         }
     }
 }
 
 impl SdSysconfCustomization {
+    const USER_PASSWORD_KEY: &str = "sd-user-password";
+    const WIFI_PASSWORD_KEY: &str = "sd-wifi-password";
+
     pub(crate) fn update_hostname(mut self, t: Option<String>) -> Self {
         self.hostname = t;
         self
@@ -162,16 +367,81 @@ impl SdSysconfCustomization {
         self
     }
 
-    pub(crate) fn validate_user(&self) -> bool {
-        match &self.user {
-            Some(x) => x.validate_username(),
-            None => true,
+    pub(crate) fn update_expand_rootfs(mut self, t: Option<bool>) -> Self {
+        self.expand_rootfs = t;
+        self
+    }
+
+    pub(crate) fn update_verify(mut self, t: Option<SdVerify>) -> Self {
+        self.verify = t;
+        self
+    }
+
+    pub(crate) fn update_format_before_flash(mut self, t: bool) -> Self {
+        self.format_before_flash = t;
+        self
+    }
+
+    /// Customization for cloud-init based images. USB DHCP and rootfs expansion are left to the
+    /// image.
+    pub(crate) fn cloud_init(self) -> bb_flasher::sd::FlashingSdLinuxConfig {
+        bb_flasher::sd::FlashingSdLinuxConfig::cloud_init(
+            self.hostname.map(Into::into),
+            self.timezone.map(Into::into),
+            self.keymap.map(Into::into),
+            self.user.map(|x| (x.username.into(), x.password.into())),
+            self.wifi.map(|x| (x.ssid.into(), x.password.into())),
+            self.ssh.map(Into::into),
+        )
+    }
+
+    /// USB DHCP is not supported by Raspberry Pi OS, and is ignored.
+    pub(crate) fn raspberry(self) -> bb_flasher::sd::FlashingSdLinuxConfig {
+        bb_flasher::sd::FlashingSdLinuxConfig::raspberry(
+            self.hostname.map(Into::into),
+            self.timezone.map(Into::into),
+            self.keymap.map(Into::into),
+            self.user.map(|x| (x.username.into(), x.password.into())),
+            self.wifi.map(|x| (x.ssid.into(), x.password.into())),
+            None,
+            self.ssh.map(Into::into),
+        )
+    }
+
+    /// SSH key and USB DHCP are not supported by Armbian, and are ignored.
+    pub(crate) fn armbian(self) -> bb_flasher::sd::FlashingSdLinuxConfig {
+        bb_flasher::sd::FlashingSdLinuxConfig::armbian(
+            self.hostname.map(Into::into),
+            self.timezone.map(Into::into),
+            self.keymap.map(Into::into),
+            self.user.map(|x| (x.username.into(), x.password.into())),
+            self.wifi.map(|x| (x.ssid.into(), x.password.into())),
+        )
+    }
+
+    fn store_secrets(&mut self, store: &impl CredentialStore) {
+        if let Some(x) = &mut self.user {
+            x.password_key = store_secret(store, Self::USER_PASSWORD_KEY, &mut x.password);
+        }
+        if let Some(x) = &mut self.wifi {
+            x.password_key = store_secret(store, Self::WIFI_PASSWORD_KEY, &mut x.password);
+        }
+    }
+
+    fn load_secrets(&mut self, store: &impl CredentialStore) {
+        if let Some(x) = &mut self.user {
+            load_secret(store, x.password_key.take(), &mut x.password);
+        }
+        if let Some(x) = &mut self.wifi {
+            load_secret(store, x.password_key.take(), &mut x.password);
         }
     }
 }
 
 impl From<SdSysconfCustomization> for bb_flasher::sd::FlashingSdLinuxConfig {
     fn from(value: SdSysconfCustomization) -> Self {
+        let wifi_hidden = value.wifi.as_ref().is_some_and(|x| x.hidden);
+
         Self::sysconfig(
             value.hostname.map(Into::into),
             value.timezone.map(Into::into),
@@ -181,18 +451,46 @@ impl From<SdSysconfCustomization> for bb_flasher::sd::FlashingSdLinuxConfig {
             value.ssh.map(Into::into),
             value.usb_enable_dhcp,
         )
+        .with_expand_rootfs(value.expand_rootfs)
+        .with_wifi_hidden(wifi_hidden)
+    }
+}
+
+/// Hash used for SD Card verification
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SdVerify {
+    #[default]
+    Sha256,
+    Crc32,
+}
+
+impl From<SdVerify> for bb_flasher::sd::Verify {
+    fn from(value: SdVerify) -> Self {
+        match value {
+            SdVerify::Sha256 => Self::Sha256,
+            SdVerify::Crc32 => Self::Crc32,
+        }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct SdCustomizationUser {
     pub(crate) username: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub(crate) password: String,
+    /// Key of password in [CredentialStore]. Only used in config file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password_key: Option<String>,
 }
 
 impl SdCustomizationUser {
     pub(crate) const fn new(username: String, password: String) -> Self {
-        Self { username, password }
+        Self {
+            username,
+            password,
+            password_key: None,
+        }
     }
 
     pub(crate) fn update_username(mut self, t: String) -> Self {
@@ -204,10 +502,6 @@ impl SdCustomizationUser {
         self.password = t;
         self
     }
-
-    pub(crate) fn validate_username(&self) -> bool {
-        self.username != "root"
-    }
 }
 
 impl Default for SdCustomizationUser {
@@ -219,7 +513,14 @@ impl Default for SdCustomizationUser {
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct SdCustomizationWifi {
     pub(crate) ssid: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub(crate) password: String,
+    /// Network does not broadcast its SSID
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) hidden: bool,
+    /// Key of password in [CredentialStore]. Only used in config file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password_key: Option<String>,
 }
 
 impl SdCustomizationWifi {
@@ -232,6 +533,11 @@ impl SdCustomizationWifi {
         self.password = t;
         self
     }
+
+    pub(crate) fn update_hidden(mut self, t: bool) -> Self {
+        self.hidden = t;
+        self
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -274,3 +580,146 @@ impl Default for Pb2Mspm0Customization {
         }
     }
 }
+
+/// Storage for secrets, so that they are not persisted in plaintext config.
+pub(crate) trait CredentialStore {
+    fn get(&self, key: &str) -> Option<String>;
+    fn set(&self, key: &str, secret: &str) -> anyhow::Result<()>;
+}
+
+/// OS keyring (Keychain, Windows Credential Manager, Secret Service).
+pub(crate) struct Keyring;
+
+impl Keyring {
+    const SERVICE: &str = "org.beagleboard.imagingutility";
+}
+
+impl CredentialStore for Keyring {
+    fn get(&self, key: &str) -> Option<String> {
+        keyring::Entry::new(Self::SERVICE, key)
+            .and_then(|x| x.get_password())
+            .inspect_err(|e| tracing::warn!("Failed to get {key} from keyring: {e}"))
+            .ok()
+    }
+
+    fn set(&self, key: &str, secret: &str) -> anyhow::Result<()> {
+        keyring::Entry::new(Self::SERVICE, key)?.set_password(secret)?;
+        Ok(())
+    }
+}
+
+/// Move `secret` to `store`. Returns the key to save in config, or [None] if the secret should be
+/// saved in plaintext.
+fn store_secret(store: &impl CredentialStore, key: &str, secret: &mut String) -> Option<String> {
+    if secret.is_empty() {
+        return None;
+    }
+
+    match store.set(key, secret) {
+        Ok(()) => {
+            secret.clear();
+            Some(key.to_string())
+        }
+        Err(e) => {
+            tracing::warn!("Keyring not available, saving {key} in plaintext: {e}");
+            None
+        }
+    }
+}
+
+fn load_secret(store: &impl CredentialStore, key: Option<String>, secret: &mut String) {
+    if let Some(key) = key {
+        *secret = store.get(&key).unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::HashMap};
+
+    use super::{
+        CredentialStore, GuiConfiguration, SdCustomization, SdCustomizationUser,
+        SdCustomizationWifi, SdSysconfCustomization,
+    };
+
+    #[derive(Default)]
+    struct MockKeyring(Option<RefCell<HashMap<String, String>>>);
+
+    impl CredentialStore for MockKeyring {
+        fn get(&self, key: &str) -> Option<String> {
+            self.0.as_ref()?.borrow().get(key).cloned()
+        }
+
+        fn set(&self, key: &str, secret: &str) -> anyhow::Result<()> {
+            let store = self.0.as_ref().ok_or(anyhow::anyhow!("No keyring"))?;
+            store
+                .borrow_mut()
+                .insert(key.to_string(), secret.to_string());
+            Ok(())
+        }
+    }
+
+    fn config() -> GuiConfiguration {
+        let mut sd = SdCustomization::default();
+        sd.update_sysconfig(
+            SdSysconfCustomization::default()
+                .update_user(Some(SdCustomizationUser::new(
+                    "beagle".to_string(),
+                    "user-secret".to_string(),
+                )))
+                .update_wifi(Some(SdCustomizationWifi {
+                    ssid: "home".to_string(),
+                    password: "wifi-secret".to_string(),
+                    hidden: false,
+                    password_key: None,
+                })),
+        );
+
+        let mut config = GuiConfiguration::default();
+        config.update_sd_customization(sd);
+        config.update_secrets_in_keyring(true);
+        config
+    }
+
+    fn passwords(config: &GuiConfiguration) -> (String, String) {
+        let sysconf = config
+            .sd_customization()
+            .and_then(|x| x.sysconf_customization())
+            .unwrap();
+
+        (
+            sysconf.user.as_ref().unwrap().password.clone(),
+            sysconf.wifi.as_ref().unwrap().password.clone(),
+        )
+    }
+
+    #[test]
+    fn secrets_in_keyring() {
+        let store = MockKeyring(Some(Default::default()));
+        let config = config();
+
+        let json = config.to_json(&store);
+        assert!(!json.contains("user-secret"));
+        assert!(!json.contains("wifi-secret"));
+        assert_eq!(store.0.as_ref().unwrap().borrow().len(), 2);
+
+        let mut loaded: GuiConfiguration = serde_json::from_str(&json).unwrap();
+        loaded.load_secrets(&store);
+        assert_eq!(
+            passwords(&loaded),
+            ("user-secret".to_string(), "wifi-secret".to_string())
+        );
+
+        // Fallback to plaintext without keyring
+        let json = config.to_json(&MockKeyring::default());
+        assert!(json.contains("user-secret"));
+        assert!(json.contains("wifi-secret"));
+
+        let mut loaded: GuiConfiguration = serde_json::from_str(&json).unwrap();
+        loaded.load_secrets(&MockKeyring::default());
+        assert_eq!(
+            passwords(&loaded),
+            ("user-secret".to_string(), "wifi-secret".to_string())
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     time::{Duration, Instant},
 };
 
@@ -35,13 +35,114 @@ pub(crate) struct BBImagerCommon {
     pub(crate) window_icon_handle: widget::image::Handle,
 
     pub(crate) img_handle_cache: helpers::ImageHandleCache,
+    /// SHA256 of extracted remote images, computed while flashing
+    pub(crate) image_sha256: HashMap<url::Url, [u8; 32]>,
 
     pub(crate) scroll_id: widget::Id,
 }
 
 impl BBImagerCommon {
+    pub(crate) fn image_sha256(&self, img: &helpers::BoardImage) -> Option<String> {
+        self.image_sha256
+            .get(img.remote_url()?)
+            .map(const_hex::encode)
+    }
+
+    pub(crate) fn new(
+        app_config: persistance::GuiConfiguration,
+        downloader: bb_downloader::Downloader,
+    ) -> Self {
+        let boards = helpers::Boards::new();
+
+        let img_handle_cache = helpers::ImageHandleCache::from_iter(
+            boards
+                .devices()
+                .filter_map(|(_, dev)| dev.icon.clone())
+                .filter_map(|icon| {
+                    let path = downloader.check_cache_from_url(icon.clone())?;
+                    Some((icon, path))
+                }),
+        );
+
+        Self {
+            app_config,
+            downloader,
+            timezones: widget::combo_box::State::new(
+                constants::TIMEZONES.iter().map(|x| x.to_string()).collect(),
+            ),
+            keymaps: widget::combo_box::State::new(
+                constants::KEYMAP_LAYOUTS
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect(),
+            ),
+            boards,
+            board_svg_handle: widget::svg::Handle::from_memory(constants::BOARD_ICON),
+            downloading_svg_handle: widget::svg::Handle::from_memory(constants::DOWNLOADING_ICON),
+            arrow_forward_svg_handle: widget::svg::Handle::from_memory(
+                constants::ARROW_FORWARD_IOS_ICON,
+            ),
+            format_svg_handle: widget::svg::Handle::from_memory(constants::FORMAT_ICON),
+            file_add_svg_handle: widget::svg::Handle::from_memory(constants::FILE_ADD_ICON),
+            arrow_back_svg_handle: widget::svg::Handle::from_memory(constants::ARROW_BACK_ICON),
+            usb_svg_handle: widget::svg::Handle::from_memory(constants::USB_ICON),
+            file_save_icon: widget::svg::Handle::from_memory(constants::FILE_SAVE_ICON),
+            info_svg_handle: widget::svg::Handle::from_memory(constants::INFO_ICON),
+            window_icon_handle: widget::image::Handle::from_bytes(constants::WINDOW_ICON),
+            copy_svg_handle: widget::svg::Handle::from_memory(constants::COPY_ICON),
+
+            img_handle_cache,
+            image_sha256: HashMap::new(),
+
+            scroll_id: widget::Id::unique(),
+        }
+    }
+
+    pub(crate) fn save_app_config(&self) -> Task<BBImagerMessage> {
+        let config = self.app_config.clone();
+        Task::future(async move {
+            if let Err(e) = config.save().await {
+                tracing::error!("Failed to save config: {e}");
+            }
+            BBImagerMessage::Null
+        })
+    }
+
+    /// Remove old files from cache, if not pruned recently.
+    pub(crate) fn prune_cache_task(&mut self) -> Task<BBImagerMessage> {
+        let now = std::time::SystemTime::now();
+        if !self.app_config.cache_prune_due(now) {
+            return Task::none();
+        }
+
+        self.app_config.update_last_cache_prune(now);
+
+        let downloader = self.downloader.clone();
+        let max_age = self.app_config.cache_max_age();
+        let size_unit = self.app_config.size_unit();
+        let prune_task = Task::future(async move {
+            match downloader.prune_older_than(max_age).await {
+                Ok(x) => tracing::info!("Pruned cache. Freed {}", size_unit.format(x)),
+                Err(e) => tracing::error!("Failed to prune cache: {e}"),
+            }
+            BBImagerMessage::Null
+        });
+
+        Task::batch([self.save_app_config(), prune_task])
+    }
+
+    /// Fetch remote configs. Skipped in offline mode.
+    pub(crate) fn refresh_config_task(&self) -> Task<BBImagerMessage> {
+        if self.app_config.local_config_only() {
+            tracing::info!("Offline mode. Only using embedded config");
+            return Task::none();
+        }
+
+        helpers::refresh_config_task(self.downloader.clone(), &self.boards)
+    }
+
     pub(crate) fn updater_task(&self) -> Task<BBImagerMessage> {
-        if cfg!(feature = "updater") {
+        if cfg!(feature = "updater") && self.app_config.update_check() {
             let downloader = self.downloader.clone();
             Task::perform(
                 async move { updater::check_update(downloader).await },
@@ -66,22 +167,25 @@ impl BBImagerCommon {
         &self,
         iter: impl IntoIterator<Item = url::Url>,
     ) -> Task<BBImagerMessage> {
-        let tasks = iter.into_iter().map(|icon| {
-            let downloader = self.downloader.clone();
-            let icon_clone = icon.clone();
-            let icon_clone2 = icon.clone();
-            Task::perform(
-                async move { downloader.download_no_cache(icon_clone, None).await },
-                move |p| match p {
-                    Ok(p) => BBImagerMessage::ResolveImage(icon_clone2, p),
-                    Err(_) => {
-                        tracing::warn!("Failed to fetch image {}", icon);
-                        BBImagerMessage::Null
-                    }
-                },
-            )
-        });
-        Task::batch(tasks)
+        // Only use cached images in offline mode
+        if self.app_config.local_config_only() {
+            let tasks = iter.into_iter().filter_map(|icon| {
+                let p = self.downloader.check_cache_from_url(icon.clone())?;
+                Some(Task::done(BBImagerMessage::ResolveImage(icon, p)))
+            });
+            return Task::batch(tasks);
+        }
+
+        let downloads = self
+            .downloader
+            .download_many(iter, constants::ICON_DOWNLOAD_CONCURRENCY);
+        Task::run(downloads, |(icon, p)| match p {
+            Ok(p) => BBImagerMessage::ResolveImage(icon, p),
+            Err(e) => {
+                tracing::warn!("Failed to fetch image {}: {e}", icon);
+                BBImagerMessage::Null
+            }
+        })
     }
 
     pub(crate) fn fetch_board_images(&self) -> Task<BBImagerMessage> {
@@ -162,6 +266,28 @@ impl BBImagerCommon {
     }
 }
 
+/// Steps of the wizard. All of them are shown together in expert mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum ExpertStep {
+    Board,
+    Os,
+    Destination,
+    Customize,
+    Review,
+}
+
+impl ExpertStep {
+    pub(crate) const fn label(self) -> &'static str {
+        match self {
+            Self::Board => "Device",
+            Self::Os => "Operating System",
+            Self::Destination => "Storage",
+            Self::Customize => "Customization",
+            Self::Review => "Review",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct ChooseBoardState {
     pub(crate) common: BBImagerCommon,
@@ -205,6 +331,8 @@ pub(crate) struct ChooseOsState {
     pub(crate) selected_board: usize,
     pub(crate) pos: Vec<usize>,
     pub(crate) selected_image: Option<(OsImageId, helpers::BoardImage)>,
+    /// Inputs for image from URL. [None] unless "Flash from URL" is selected.
+    pub(crate) url_form: Option<helpers::UrlImageForm>,
 }
 
 impl ChooseOsState {
@@ -219,6 +347,22 @@ impl ChooseOsState {
         self.common.boards.device(self.selected_board)
     }
 
+    /// Remember name and description of selected local image.
+    pub(crate) fn save_local_image_info(&mut self) -> Task<BBImagerMessage> {
+        let Some((path, info)) = self
+            .selected_image
+            .as_ref()
+            .and_then(|(_, img)| img.local_info())
+        else {
+            return Task::none();
+        };
+
+        self.common
+            .app_config
+            .update_local_image_info(path.to_path_buf(), info);
+        self.common.save_app_config()
+    }
+
     pub(crate) fn images(&self) -> Option<impl Iterator<Item = OsImageItem<'_>>> {
         let iter = self
             .common
@@ -228,23 +372,19 @@ impl ChooseOsState {
                 let mut idx = self.pos.clone();
                 idx.push(id);
 
-                OsImageItem::remote(
-                    idx,
-                    x.icon(),
-                    x.name(),
-                    matches!(
-                        x,
-                        config::OsListItem::SubList(_) | config::OsListItem::RemoteSubList(_)
-                    ),
-                )
+                OsImageItem::remote(idx, x)
             });
 
         let extra = match self.flasher() {
             config::Flasher::SdCard => vec![
                 OsImageItem::format(self.pos.clone(), "Format SD Card"),
                 OsImageItem::local(self.pos.clone()),
+                OsImageItem::url(self.pos.clone()),
+            ],
+            _ => vec![
+                OsImageItem::local(self.pos.clone()),
+                OsImageItem::url(self.pos.clone()),
             ],
-            _ => vec![OsImageItem::local(self.pos.clone())],
         };
 
         Some(iter.chain(extra))
@@ -317,6 +457,7 @@ impl From<CustomizeState> for ChooseOsState {
             selected_board: value.selected_board,
             pos: Vec::new(),
             selected_image: Some(value.selected_image),
+            url_form: None,
         }
     }
 }
@@ -328,6 +469,7 @@ impl From<ChooseDestState> for ChooseOsState {
             selected_board: value.selected_board,
             pos: Vec::new(),
             selected_image: Some(value.selected_image),
+            url_form: None,
         }
     }
 }
@@ -340,6 +482,10 @@ pub(crate) struct ChooseDestState {
     pub(crate) selected_dest: Option<helpers::Destination>,
     pub(crate) destinations: Vec<helpers::Destination>,
     pub(crate) filter_destination: bool,
+    /// Reused instead of customization from app config. Set when flashing another destination.
+    pub(crate) customization: Option<helpers::FlashingCustomization>,
+    /// Selected destination which was removed before flashing started.
+    pub(crate) removed_dest: Option<helpers::Destination>,
 }
 
 impl ChooseDestState {
@@ -383,6 +529,23 @@ impl From<CustomizeState> for ChooseDestState {
             selected_dest: Some(value.selected_dest),
             destinations: Vec::new(),
             filter_destination: true,
+            customization: None,
+            removed_dest: None,
+        }
+    }
+}
+
+impl From<FlashingState> for ChooseDestState {
+    fn from(value: FlashingState) -> Self {
+        Self {
+            common: value.common,
+            selected_board: value.selected_board,
+            selected_image: value.request.selected_image,
+            selected_dest: None,
+            destinations: Vec::new(),
+            filter_destination: true,
+            customization: Some(value.request.customization),
+            removed_dest: Some(value.request.selected_dest),
         }
     }
 }
@@ -410,13 +573,7 @@ impl CustomizeState {
     }
 
     pub(crate) fn save_app_config(&self) -> Task<BBImagerMessage> {
-        let config = self.app_config().clone();
-        Task::future(async move {
-            if let Err(e) = config.save().await {
-                tracing::error!("Failed to save config: {e}");
-            }
-            BBImagerMessage::Null
-        })
+        self.common.save_app_config()
     }
 
     pub(crate) fn selected_board(&self) -> &str {
@@ -429,7 +586,11 @@ impl CustomizeState {
 
     pub(crate) fn selected_destination(&self) -> String {
         match self.selected_dest.size() {
-            Some(x) => format!("{} ({})", self.selected_dest, helpers::pretty_bytes(x)),
+            Some(x) => format!(
+                "{} ({})",
+                self.selected_dest,
+                self.app_config().size_unit().format(x)
+            ),
             None => self.selected_dest.to_string(),
         }
     }
@@ -438,6 +599,41 @@ impl CustomizeState {
         self.selected_dest.is_download_action()
     }
 
+    /// Warning shown when the image is larger, or much smaller than the selected SD Card. Does
+    /// not prevent flashing, although flashing fails if the image is larger.
+    pub(crate) fn size_warning(&self) -> Option<String> {
+        let image_size = self.selected_image.1.extract_size()?;
+        let dest_size = self.selected_dest.size()?;
+        let ratio = self.app_config().small_image_ratio();
+        let size_unit = self.app_config().size_unit();
+
+        if image_size > dest_size {
+            return Some(format!(
+                "The image ({}) is larger than the selected destination ({}). Select a larger \
+                SD Card.",
+                size_unit.format(image_size),
+                size_unit.format(dest_size)
+            ));
+        }
+
+        if !helpers::is_image_much_smaller(image_size, dest_size, ratio) {
+            return None;
+        }
+
+        Some(format!(
+            "The image ({}) is much smaller than the selected destination ({}). Make sure the \
+            correct destination is selected. Most images expand the root filesystem to use the \
+            whole SD Card on first boot.",
+            size_unit.format(image_size),
+            size_unit.format(dest_size)
+        ))
+    }
+
+    /// SHA256 of the extracted image, if it was computed in an earlier flashing attempt.
+    pub(crate) fn image_sha256(&self) -> Option<String> {
+        self.common.image_sha256(&self.selected_image.1)
+    }
+
     pub(crate) fn modifications(&self) -> Vec<&'static str> {
         match &self.customization {
             helpers::FlashingCustomization::LinuxSdSysconfig(x) => {
@@ -471,6 +667,20 @@ impl CustomizeState {
                     ans.push("• USB DHCP enabled");
                 }
 
+                if x.expand_rootfs == Some(true) {
+                    ans.push("• Root filesystem expansion enabled");
+                }
+
+                if x.format_before_flash {
+                    ans.push("• Partition table cleared before flashing");
+                }
+
+                match x.verify {
+                    Some(persistance::SdVerify::Sha256) => ans.push("• Verification enabled"),
+                    Some(persistance::SdVerify::Crc32) => ans.push("• Fast verification enabled"),
+                    None => {}
+                }
+
                 ans
             }
             helpers::FlashingCustomization::Bcf(x) => {
@@ -493,6 +703,7 @@ pub(crate) struct FlashingState {
     pub(crate) progress: bb_flasher::DownloadFlashingStatus,
     pub(crate) start_timestamp: Option<Instant>,
     pub(crate) is_download: bool,
+    pub(crate) request: FlashingRequest,
 }
 
 impl FlashingState {
@@ -500,6 +711,11 @@ impl FlashingState {
         self.common.boards.device(self.selected_board)
     }
 
+    /// SHA256 of the extracted image. [None] until the complete image has been read.
+    pub(crate) fn image_sha256(&self) -> Option<String> {
+        self.common.image_sha256(&self.request.selected_image.1)
+    }
+
     pub(crate) fn time_remaining(&self) -> Option<Duration> {
         const THRESHOLD: f32 = 0.02;
 
@@ -522,14 +738,13 @@ impl FlashingState {
 
     pub(crate) fn progress_update(&mut self, u: bb_flasher::DownloadFlashingStatus) {
         // Required for better time estimate.
-        match u {
+        if matches!(
+            u,
             bb_flasher::DownloadFlashingStatus::DownloadingProgress(_)
-            | bb_flasher::DownloadFlashingStatus::FlashingProgress(_) => {
-                if self.start_timestamp.is_none() {
-                    self.start_timestamp = Some(Instant::now())
-                }
-            }
-            _ => {}
+                | bb_flasher::DownloadFlashingStatus::FlashingProgress(_)
+        ) && self.start_timestamp.is_none()
+        {
+            self.start_timestamp = Some(Instant::now())
         }
 
         self.progress = u;
@@ -541,6 +756,10 @@ pub(crate) struct FlashingFinishState {
     pub(crate) common: BBImagerCommon,
     pub(crate) selected_board: usize,
     pub(crate) is_download: bool,
+    /// Kept around to allow flashing another destination.
+    pub(crate) request: FlashingRequest,
+    /// Downloaded image is present in cache, so flashing again will not download it.
+    pub(crate) image_cached: bool,
 }
 
 impl FlashingFinishState {
@@ -555,14 +774,173 @@ impl From<FlashingState> for FlashingFinishState {
             common: value.common,
             selected_board: value.selected_board,
             is_download: value.is_download,
+            request: value.request,
+            image_cached: false,
         }
     }
 }
 
 pub(crate) struct FlashingFailState {
     pub(crate) common: BBImagerCommon,
+    pub(crate) selected_board: usize,
     pub(crate) err: String,
     pub(crate) logs: widget::text_editor::Content,
+    pub(crate) origin: FailureOrigin,
+    pub(crate) request: FlashingRequest,
+    /// Link to open a new issue prefilled with diagnostics.
+    pub(crate) report_url: url::Url,
+}
+
+impl FlashingFailState {
+    pub(crate) fn new(state: FlashingState, err: String, logs: &str) -> Self {
+        let os = format!("{} ({})", std::env::consts::OS, std::env::consts::ARCH);
+        let board = &state.common.boards.device(state.selected_board).name;
+        let image = state.request.selected_image.1.to_string();
+        let dest = state.request.selected_dest.to_string();
+        let report_url = issue_report_url(
+            &[
+                ("App Version", constants::APP_RELEASE),
+                ("OS", &os),
+                ("Board", board),
+                ("Image", &image),
+                ("Destination", &dest),
+                ("Error", &err),
+            ],
+            logs,
+            &state.request.customization.secrets(),
+        );
+
+        Self {
+            origin: FailureOrigin::new(&state.progress, state.is_download),
+            common: state.common,
+            selected_board: state.selected_board,
+            err,
+            logs: widget::text_editor::Content::with_text(log_excerpt(logs)),
+            request: state.request,
+            report_url,
+        }
+    }
+}
+
+/// Link to open a new issue in the issue tracker, with `details` and tail of `logs` in the
+/// description. All occurences of `secrets` are redacted.
+fn issue_report_url(details: &[(&str, &str)], logs: &str, secrets: &[&str]) -> url::Url {
+    let redact = |x: &str| {
+        secrets
+            .iter()
+            .filter(|s| !s.is_empty())
+            .fold(x.to_string(), |acc, s| acc.replace(s, "[REDACTED]"))
+    };
+
+    let logs = redact(logs);
+    let logs = match logs.rmatch_indices('\n').nth(constants::REPORT_LOG_LINES) {
+        Some((idx, _)) => &logs[idx + 1..],
+        None => &logs,
+    };
+    let start = logs.len().saturating_sub(constants::REPORT_LOG_MAX_LEN);
+    let logs = &logs[(start..).find(|x| logs.is_char_boundary(*x)).unwrap()..];
+
+    let details: String = details
+        .iter()
+        .map(|(k, v)| format!("- {k}: {}\n", redact(v)))
+        .collect();
+    let body = format!(
+        "## Describe the problem\n\n<!-- What were you trying to do? What happened? -->\n\n\
+        ## Diagnostics\n\n{details}\n## Logs\n\n```\n{logs}\n```\n"
+    );
+
+    url::Url::parse_with_params(
+        constants::ISSUE_TRACKER_URL,
+        [
+            ("issue[title]", "Flashing failed"),
+            ("issue[description]", body.as_str()),
+        ],
+    )
+    .expect("Invalid issue tracker URL")
+}
+
+/// Go back to review page to retry with the same choices.
+impl From<FlashingFailState> for CustomizeState {
+    fn from(value: FlashingFailState) -> Self {
+        Self {
+            common: value.common,
+            selected_board: value.selected_board,
+            selected_image: value.request.selected_image,
+            selected_dest: value.request.selected_dest,
+            customization: value.request.customization,
+        }
+    }
+}
+
+/// Choices used for flashing. Kept around to allow retrying on failure.
+#[derive(Debug, Clone)]
+pub(crate) struct FlashingRequest {
+    pub(crate) selected_image: (OsImageId, helpers::BoardImage),
+    pub(crate) selected_dest: helpers::Destination,
+    pub(crate) customization: helpers::FlashingCustomization,
+}
+
+/// Stage of the flashing process which failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FailureOrigin {
+    /// Downloading the image failed. Nothing was written to the destination.
+    Download,
+    /// Writing to the destination failed.
+    Flash,
+}
+
+impl FailureOrigin {
+    /// Determine origin from the last progress reported before failure.
+    pub(crate) const fn new(
+        progress: &bb_flasher::DownloadFlashingStatus,
+        is_download: bool,
+    ) -> Self {
+        // Save to file only downloads
+        if is_download
+            || matches!(
+                progress,
+                bb_flasher::DownloadFlashingStatus::DownloadingProgress(_)
+            )
+        {
+            Self::Download
+        } else {
+            Self::Flash
+        }
+    }
+
+    pub(crate) const fn title(self) -> &'static str {
+        match self {
+            Self::Download => "Download failed",
+            Self::Flash => "Flashing failed",
+        }
+    }
+
+    pub(crate) const fn hint(self) -> &'static str {
+        match self {
+            Self::Download => {
+                "Check your internet connection and try again. Nothing was written to the destination."
+            }
+            Self::Flash => {
+                "Check that the destination is still connected and not write protected. The downloaded image is reused on retry."
+            }
+        }
+    }
+
+    pub(crate) const fn retry_label(self) -> &'static str {
+        match self {
+            Self::Download => "RETRY DOWNLOAD",
+            Self::Flash => "RETRY FLASH",
+        }
+    }
+}
+
+/// Last [LOG_EXCERPT_LINES](constants::LOG_EXCERPT_LINES) lines of logs. Complete logs are
+/// available in the log file.
+fn log_excerpt(logs: &str) -> &str {
+    match logs.rmatch_indices('\n').nth(constants::LOG_EXCERPT_LINES) {
+        Some((idx, _)) => &logs[idx + 1..],
+        None => logs,
+    }
 }
 
 // State for Pages that can be opened from any of the normal pages but are not part of normal flow.
@@ -649,6 +1027,11 @@ pub(crate) struct OverlayState {
     pub(crate) log_path: String,
     pub(crate) license: widget::text_editor::Content,
     pub(crate) cache_dir: String,
+    /// Tail of log file, filtered by `log_level`.
+    pub(crate) logs: widget::text_editor::Content,
+    pub(crate) log_level: helpers::LogLevel,
+    /// Text currently in `logs`, so unchanged logs are not reloaded.
+    pub(crate) log_text: String,
 }
 
 impl OverlayState {
@@ -661,11 +1044,30 @@ impl OverlayState {
             .to_string_lossy()
             .to_string();
 
-        Self {
+        let mut state = Self {
             page,
             log_path,
             license,
             cache_dir,
+            logs: widget::text_editor::Content::new(),
+            log_level: Default::default(),
+            log_text: String::new(),
+        };
+        state.reload_logs();
+
+        state
+    }
+
+    /// Reload log viewer from log file. Contents are only replaced on change, to preserve scroll
+    /// position and selection.
+    pub(crate) fn reload_logs(&mut self) {
+        let logs = std::fs::read_to_string(helpers::log_file_path()).unwrap_or_default();
+        let logs = helpers::filter_logs(&logs, self.log_level);
+        let text = log_excerpt(&logs);
+
+        if text != self.log_text {
+            self.logs = widget::text_editor::Content::with_text(text);
+            self.log_text = text.to_string();
         }
     }
 
@@ -677,3 +1079,103 @@ impl OverlayState {
         self.page.common_mut()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bb_flasher::DownloadFlashingStatus;
+
+    use super::*;
+
+    #[test]
+    fn failure_origin() {
+        let cases = [
+            (
+                DownloadFlashingStatus::Preparing,
+                false,
+                FailureOrigin::Flash,
+            ),
+            (
+                DownloadFlashingStatus::DownloadingProgress(0.5),
+                false,
+                FailureOrigin::Download,
+            ),
+            (
+                DownloadFlashingStatus::FlashingProgress(0.5),
+                false,
+                FailureOrigin::Flash,
+            ),
+            (
+                DownloadFlashingStatus::Verifying,
+                false,
+                FailureOrigin::Flash,
+            ),
+            (
+                DownloadFlashingStatus::Customizing,
+                false,
+                FailureOrigin::Flash,
+            ),
+            // Save to file
+            (
+                DownloadFlashingStatus::FlashingProgress(0.5),
+                true,
+                FailureOrigin::Download,
+            ),
+        ];
+
+        for (progress, is_download, origin) in cases {
+            assert_eq!(FailureOrigin::new(&progress, is_download), origin);
+        }
+
+        assert_ne!(
+            FailureOrigin::Download.retry_label(),
+            FailureOrigin::Flash.retry_label()
+        );
+    }
+
+    #[test]
+    fn issue_report_url() {
+        let logs: String = (0..constants::REPORT_LOG_LINES + 10)
+            .map(|x| format!("line {x}: wifi hunter2\n"))
+            .collect();
+
+        let url = super::issue_report_url(
+            &[
+                ("App Version", "1.2.3"),
+                ("Board", "BeagleY-AI"),
+                ("Error", "Failed to connect to hunter2"),
+            ],
+            &logs,
+            &["hunter2", ""],
+        );
+        assert!(url.as_str().starts_with(constants::ISSUE_TRACKER_URL));
+
+        let (_, body) = url
+            .query_pairs()
+            .find(|(k, _)| k == "issue[description]")
+            .unwrap();
+        assert!(body.contains("- App Version: 1.2.3\n"));
+        assert!(body.contains("- Board: BeagleY-AI\n"));
+        assert!(body.contains("- Error: Failed to connect to [REDACTED]\n"));
+        assert!(body.contains("line 49: wifi [REDACTED]"));
+        assert!(!body.contains("line 9:"));
+        assert!(!body.contains("hunter2"));
+        assert!(!url.as_str().contains("hunter2"));
+
+        // Long logs are truncated
+        let logs = "x".repeat(constants::REPORT_LOG_MAX_LEN * 2);
+        let url = super::issue_report_url(&[], &logs, &[]);
+        assert!(url.as_str().len() < constants::REPORT_LOG_MAX_LEN + 500);
+    }
+
+    #[test]
+    fn log_excerpt() {
+        let logs: String = (0..constants::LOG_EXCERPT_LINES + 10)
+            .map(|x| format!("line {x}\n"))
+            .collect();
+
+        let excerpt = super::log_excerpt(&logs);
+        assert_eq!(excerpt.lines().count(), constants::LOG_EXCERPT_LINES);
+        assert!(excerpt.starts_with("line 10\n"));
+        assert_eq!(super::log_excerpt("line 0\n"), "line 0\n");
+    }
+}
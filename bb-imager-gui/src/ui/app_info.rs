@@ -1,12 +1,14 @@
 use iced::{Element, widget};
 
 use crate::{
+    helpers::LogLevel,
     message::BBImagerMessage,
     state::OverlayState,
     ui::helpers::{VIEW_COL_PADDING, element_with_label, page_type3, selectable_text},
 };
 
 const INP_BOX_WIDTH: u32 = 420;
+const LOG_VIEWER_HEIGHT: u32 = 240;
 
 pub(crate) fn view<'a>(state: &'a OverlayState) -> Element<'a, BBImagerMessage> {
     page_type3(
@@ -39,6 +41,50 @@ fn review_view<'a>(state: &'a OverlayState) -> Element<'a, BBImagerMessage> {
                 .on_input(|_| BBImagerMessage::Null)
                 .into()
         ),
+        element_with_label(
+            "Log Level",
+            widget::pick_list(
+                LogLevel::ALL,
+                Some(state.log_level),
+                BBImagerMessage::LogLevel
+            )
+            .into()
+        ),
+        widget::container(
+            widget::text_editor(&state.logs)
+                .on_action(BBImagerMessage::LogViewerEvent)
+                .height(LOG_VIEWER_HEIGHT)
+        )
+        .padding(iced::Padding::ZERO.right(16)),
+        widget::rule::horizontal(2),
+        widget::container(
+            widget::toggler(state.common().app_config.expert_mode())
+                .label("Expert mode (show all steps on a single screen)")
+                .on_toggle(BBImagerMessage::ExpertMode),
+        )
+        .padding(iced::Padding::ZERO.horizontal(16))
+        .width(iced::Fill),
+        widget::container(
+            widget::toggler(state.common().app_config.update_check())
+                .label("Check for updates on startup")
+                .on_toggle(BBImagerMessage::UpdateCheck),
+        )
+        .padding(iced::Padding::ZERO.horizontal(16))
+        .width(iced::Fill),
+        widget::container(
+            widget::toggler(state.common().app_config.local_config_only())
+                .label("Offline mode (only use embedded config, applies on restart)")
+                .on_toggle(BBImagerMessage::LocalConfigOnly),
+        )
+        .padding(iced::Padding::ZERO.horizontal(16))
+        .width(iced::Fill),
+        widget::container(
+            widget::toggler(state.common().app_config.secrets_in_keyring())
+                .label("Store passwords in system keyring instead of config file")
+                .on_toggle(BBImagerMessage::SecretsInKeyring),
+        )
+        .padding(iced::Padding::ZERO.horizontal(16))
+        .width(iced::Fill),
         widget::rule::horizontal(2),
         widget::container(selectable_text(&state.license)).padding(iced::Padding::ZERO.right(16))
     ]
@@ -25,7 +25,7 @@ pub(crate) fn view<'a>(state: &'a ChooseBoardState) -> Element<'a, BBImagerMessa
     )
 }
 
-fn board_list_pane<'a>(state: &'a ChooseBoardState) -> Element<'a, BBImagerMessage> {
+pub(crate) fn board_list_pane<'a>(state: &'a ChooseBoardState) -> Element<'a, BBImagerMessage> {
     let items = state
         .devices()
         .map(|(id, dev)| {
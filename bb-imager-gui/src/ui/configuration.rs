@@ -23,16 +23,20 @@ pub(crate) fn view<'a>(state: &'a crate::state::CustomizeState) -> Element<'a, B
             widget::button("BACK")
                 .on_press(BBImagerMessage::Back)
                 .style(widget::button::secondary),
-            widget::button("NEXT").on_press_maybe(if state.customization.validate() {
-                Some(BBImagerMessage::Next)
-            } else {
-                None
-            }),
+            widget::button("NEXT").on_press_maybe(
+                state
+                    .customization
+                    .validate()
+                    .is_ok()
+                    .then_some(BBImagerMessage::Next),
+            ),
         ],
     )
 }
 
-fn customization_pane<'a>(state: &'a crate::state::CustomizeState) -> Element<'a, BBImagerMessage> {
+pub(crate) fn customization_pane<'a>(
+    state: &'a crate::state::CustomizeState,
+) -> Element<'a, BBImagerMessage> {
     match &state.customization {
         FlashingCustomization::LinuxSdSysconfig(inner) => linux_sd_card(state, inner),
         FlashingCustomization::Bcf(inner) => bcf(inner),
@@ -75,8 +79,34 @@ fn linux_sd_card<'a>(
     state: &'a crate::state::CustomizeState,
     config: &'a persistance::SdSysconfCustomization,
 ) -> Element<'a, BBImagerMessage> {
+    let errors = state.customization.validate().err().unwrap_or_default();
+    let has_error = |f: fn(helpers::CustomizationError) -> bool| errors.iter().any(|x| f(*x));
+
     let mut col = widget::column([]);
 
+    col = col.extend([
+        widget::row![
+            widget::button("USE MY COMPUTER'S SETTINGS")
+                .on_press(BBImagerMessage::HostFlashingConfig)
+                .style(widget::button::secondary),
+            widget::button("IMPORT FROM RASPBERRY PI IMAGER")
+                .on_press(BBImagerMessage::ImportRpiImager)
+                .style(widget::button::secondary),
+        ]
+        .spacing(8)
+        .into(),
+        widget::rule::horizontal(2).into(),
+    ]);
+
+    if !errors.is_empty() {
+        col = col.extend(
+            errors
+                .iter()
+                .map(|x| text(format!("• {x}")).style(text::danger).into()),
+        );
+        col = col.push(widget::rule::horizontal(2));
+    }
+
     // Username and Password
     col = col.push(
         widget::toggler(config.user.is_some())
@@ -101,7 +131,7 @@ fn linux_sd_card<'a>(
                             .update_user(Some(usr.clone().update_username(inp))),
                     )
                 },
-                !usr.validate_username(),
+                has_error(helpers::CustomizationError::is_user_error),
             )
             .into(),
             input_with_label(
@@ -115,7 +145,7 @@ fn linux_sd_card<'a>(
                             .update_user(Some(usr.clone().update_password(inp))),
                     )
                 },
-                false,
+                has_error(|x| x == helpers::CustomizationError::EmptyUserPassword),
             )
             .into(),
         ])
@@ -147,7 +177,7 @@ fn linux_sd_card<'a>(
                             .update_wifi(Some(wifi.clone().update_ssid(inp))),
                     )
                 },
-                false,
+                has_error(helpers::CustomizationError::is_wifi_ssid_error),
             )
             .into(),
             input_with_label(
@@ -161,9 +191,19 @@ fn linux_sd_card<'a>(
                             .update_wifi(Some(wifi.clone().update_password(inp))),
                     )
                 },
-                false,
+                has_error(|x| x == helpers::CustomizationError::InvalidWifiPassword),
             )
             .into(),
+            widget::checkbox(wifi.hidden)
+                .label("Hidden network")
+                .on_toggle(|x| {
+                    BBImagerMessage::UpdateFlashConfig(FlashingCustomization::LinuxSdSysconfig(
+                        config
+                            .clone()
+                            .update_wifi(Some(wifi.clone().update_hidden(x))),
+                    ))
+                })
+                .into(),
         ])
     };
 
@@ -297,6 +337,65 @@ fn linux_sd_card<'a>(
             }),
     );
 
+    col = col.push(widget::rule::horizontal(2));
+
+    // Expand rootfs
+    col = col.push(
+        widget::toggler(config.expand_rootfs == Some(true))
+            .label("Expand root filesystem on first boot")
+            .on_toggle(|x| {
+                BBImagerMessage::UpdateFlashConfig(FlashingCustomization::LinuxSdSysconfig(
+                    config.clone().update_expand_rootfs(Some(x)),
+                ))
+            }),
+    );
+
+    col = col.push(widget::rule::horizontal(2));
+
+    // Format
+    col = col.push(
+        widget::toggler(config.format_before_flash)
+            .label("Clear partition table before flashing")
+            .on_toggle(|x| {
+                BBImagerMessage::UpdateFlashConfig(FlashingCustomization::LinuxSdSysconfig(
+                    config.clone().update_format_before_flash(x),
+                ))
+            }),
+    );
+
+    col = col.push(widget::rule::horizontal(2));
+
+    // Verification
+    col = col.push(
+        widget::toggler(config.verify.is_some())
+            .label("Verify after flashing")
+            .on_toggle(|x| {
+                let v = if x { Some(Default::default()) } else { None };
+                BBImagerMessage::UpdateFlashConfig(FlashingCustomization::LinuxSdSysconfig(
+                    config.clone().update_verify(v),
+                ))
+            }),
+    );
+    if config.verify.is_some() {
+        col = col.push(
+            widget::container(
+                widget::toggler(config.verify == Some(persistance::SdVerify::Crc32))
+                    .label("Fast verification (CRC32, only detects accidental corruption)")
+                    .on_toggle(|x| {
+                        let v = if x {
+                            persistance::SdVerify::Crc32
+                        } else {
+                            persistance::SdVerify::Sha256
+                        };
+                        BBImagerMessage::UpdateFlashConfig(FlashingCustomization::LinuxSdSysconfig(
+                            config.clone().update_verify(Some(v)),
+                        ))
+                    }),
+            )
+            .padding(iced::Padding::ZERO.horizontal(16)),
+        );
+    }
+
     widget::scrollable(col.spacing(16).padding(VIEW_COL_PADDING))
         .id(state.common.scroll_id.clone())
         .into()
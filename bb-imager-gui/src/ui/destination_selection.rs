@@ -1,3 +1,4 @@
+use bb_helper::size::SizeUnit;
 use iced::{
     Element,
     widget::{self, button, text},
@@ -30,7 +31,7 @@ pub(crate) fn view<'a>(state: &'a ChooseDestState) -> Element<'a, BBImagerMessag
     )
 }
 
-fn dest_list_pane<'a>(state: &'a ChooseDestState) -> Element<'a, BBImagerMessage> {
+pub(crate) fn dest_list_pane<'a>(state: &'a ChooseDestState) -> Element<'a, BBImagerMessage> {
     let items = state
         .destinations()
         .map(|dest| {
@@ -67,9 +68,19 @@ fn dest_list_pane<'a>(state: &'a ChooseDestState) -> Element<'a, BBImagerMessage
         widget::column(
             [
                 widget::container(
-                    widget::toggler(!state.filter_destination)
-                        .label("Show all destinations")
-                        .on_toggle(|x| BBImagerMessage::DestinationFilter(!x)),
+                    widget::column![
+                        widget::toggler(!state.filter_destination)
+                            .label("Show all destinations")
+                            .on_toggle(|x| BBImagerMessage::DestinationFilter(!x)),
+                        widget::toggler(state.common.app_config.size_unit() == SizeUnit::Decimal)
+                            .label("Show sizes in GB (as printed on SD Cards)")
+                            .on_toggle(|x| BBImagerMessage::SizeUnit(if x {
+                                SizeUnit::Decimal
+                            } else {
+                                SizeUnit::Binary
+                            })),
+                    ]
+                    .spacing(8),
                 )
                 .padding(16)
                 .into(),
@@ -102,7 +113,7 @@ fn dest_view_pane<'a>(state: &'a crate::state::ChooseDestState) -> Element<'a, B
             ];
 
             let col = col.extend(
-                dest.details()
+                dest.details(state.common.app_config.size_unit())
                     .into_iter()
                     .map(|(k, v)| detail_entry(k, v))
                     .map(Into::into),
@@ -121,6 +132,18 @@ fn dest_view_pane<'a>(state: &'a crate::state::ChooseDestState) -> Element<'a, B
                     .font(constants::FONT_BOLD)
             ];
 
+            let col = match &state.removed_dest {
+                Some(x) => col.push(
+                    text(format!(
+                        "{x} is no longer connected. Please select the destination again."
+                    ))
+                    .width(iced::Fill)
+                    .align_x(iced::Center)
+                    .style(widget::text::danger),
+                ),
+                None => col,
+            };
+
             let col = match state.instruction() {
                 Some(x) => col.extend([
                     widget::rule::horizontal(2).into(),
@@ -0,0 +1,124 @@
+//! Single screen layout for users who flash repeatedly. Uses the same states as the step by step
+//! wizard, but shows all choices together so that any earlier choice can be changed directly.
+
+use iced::{
+    Element,
+    widget::{self, button, text},
+};
+
+use crate::{
+    BBImager, constants,
+    message::BBImagerMessage,
+    state::{CustomizeState, ExpertStep},
+    ui::helpers::{VIEW_COL_PADDING, card_btn_style, page_type1},
+};
+
+pub(crate) fn view(state: &BBImager) -> Element<'_, BBImagerMessage> {
+    let current = state.expert_step().expect("Not a wizard page");
+
+    let pane = match state {
+        BBImager::ChooseBoard(x) => super::board_selection::board_list_pane(x),
+        BBImager::ChooseOs(x) => match &x.url_form {
+            Some(form) => widget::column![
+                super::image_selection::url_form_view(x, form),
+                super::image_selection::os_list_pane(x)
+            ]
+            .into(),
+            None => super::image_selection::os_list_pane(x),
+        },
+        BBImager::ChooseDest(x) => super::destination_selection::dest_list_pane(x),
+        BBImager::Customize(x) => super::configuration::customization_pane(x),
+        BBImager::Review(x) => super::review::review_view(x),
+        _ => panic!("Unexpected page"),
+    };
+
+    page_type1(
+        state.common(),
+        pane,
+        summary_pane(state, current),
+        buttons(state),
+    )
+}
+
+fn summary_pane(state: &BBImager, current: ExpertStep) -> Element<'_, BBImagerMessage> {
+    let items = summary(state).into_iter().map(|(step, val)| {
+        let is_selected = step == current;
+
+        let col = widget::column![
+            text(step.label()).font(constants::FONT_BOLD).size(18),
+            text(val.unwrap_or_else(|| "Not selected".to_string())),
+        ]
+        .spacing(4)
+        .padding(8)
+        .width(iced::Fill);
+
+        button(col)
+            // Later steps can only be reached by making a choice in the current one
+            .on_press_maybe((step <= current).then_some(BBImagerMessage::ExpertGoto(step)))
+            .style(move |theme, status| card_btn_style(theme, status, is_selected))
+            .into()
+    });
+
+    widget::scrollable(widget::column(items).spacing(8).padding(VIEW_COL_PADDING)).into()
+}
+
+fn summary(state: &BBImager) -> [(ExpertStep, Option<String>); 4] {
+    let (board, os, dest, customization) = match state {
+        BBImager::ChooseBoard(x) => (x.selected_board().map(|d| d.name.clone()), None, None, None),
+        BBImager::ChooseOs(x) => (
+            Some(x.selected_board().name.clone()),
+            x.selected_image().map(|(_, img)| img.to_string()),
+            None,
+            None,
+        ),
+        BBImager::ChooseDest(x) => (
+            Some(x.selected_board().name.clone()),
+            Some(x.selected_image.1.to_string()),
+            x.selected_dest.as_ref().map(ToString::to_string),
+            None,
+        ),
+        BBImager::Customize(x) | BBImager::Review(x) => (
+            Some(x.selected_board().to_string()),
+            Some(x.selected_image()),
+            Some(x.selected_destination()),
+            Some(match x.modifications().len() {
+                0 => "None".to_string(),
+                n => format!("{n} modification(s)"),
+            }),
+        ),
+        _ => panic!("Unexpected page"),
+    };
+
+    [
+        (ExpertStep::Board, board),
+        (ExpertStep::Os, os),
+        (ExpertStep::Destination, dest),
+        (ExpertStep::Customize, customization),
+    ]
+}
+
+fn buttons(state: &BBImager) -> Vec<widget::Button<'_, BBImagerMessage>> {
+    match state {
+        BBImager::Customize(x) => vec![
+            button("RESET")
+                .style(widget::button::danger)
+                .on_press(BBImagerMessage::ResetFlashingConfig),
+            button(flash_label(x)).on_press_maybe(
+                x.customization
+                    .validate()
+                    .is_ok()
+                    .then_some(BBImagerMessage::ExpertFlash),
+            ),
+        ],
+        BBImager::Review(x) => vec![button(flash_label(x)).on_press(BBImagerMessage::ExpertFlash)],
+        _ => vec![button("WRITE")],
+    }
+}
+
+fn flash_label(state: &CustomizeState) -> &'static str {
+    if state.is_download() {
+        "DOWNLOAD"
+    } else {
+        "WRITE"
+    }
+}
@@ -27,6 +27,7 @@ pub(crate) fn progress_view(state: &FlashingState) -> Element<'_, BBImagerMessag
         bb_flasher::DownloadFlashingStatus::FlashingProgress(x) => (x, "Flashing Image ..."),
         bb_flasher::DownloadFlashingStatus::Verifying => (0.99, "Verifying ..."),
         bb_flasher::DownloadFlashingStatus::Customizing => (0.99, "Customizing ..."),
+        bb_flasher::DownloadFlashingStatus::UpToDate => (1.0, "Already up to date"),
     };
 
     let progress = ProgressCircle::new(prog, 10.0, constants::TONGUE_ORANGE);
@@ -38,6 +39,14 @@ pub(crate) fn progress_view(state: &FlashingState) -> Element<'_, BBImagerMessag
             crate::helpers::pretty_duration(x),
         ));
     }
+    if state.request.selected_image.1.remote_url().is_some() {
+        col = col.push(detail_entry(
+            "Image SHA256",
+            state
+                .image_sha256()
+                .unwrap_or_else(|| "Computing ...".to_string()),
+        ));
+    }
 
     col.align_x(iced::Center).padding(VIEW_COL_PADDING).into()
 }
@@ -13,21 +13,26 @@ pub(crate) fn view(state: &FlashingFinishState) -> Element<'_, BBImagerMessage>
     page_type1(
         &state.common,
         info_view(state),
-        progress_view(),
+        progress_view(state),
         [button("Restart")
             .style(widget::button::danger)
             .on_press(BBImagerMessage::Restart)],
     )
 }
 
-pub(crate) fn progress_view() -> Element<'static, BBImagerMessage> {
-    widget::column![
+pub(crate) fn progress_view(state: &FlashingFinishState) -> Element<'static, BBImagerMessage> {
+    let mut col = widget::column![
         CircleBar::new("Cancelled", 10.0, constants::DANGER),
         widget::text("Flashing Cancelled by the user")
-    ]
-    .align_x(iced::Center)
-    .padding(VIEW_COL_PADDING)
-    .into()
+    ];
+    if state.image_cached {
+        col = col.push(
+            widget::text("Image is ready in cache. Flashing again will not download it again.")
+                .style(widget::text::success),
+        );
+    }
+
+    col.align_x(iced::Center).padding(VIEW_COL_PADDING).into()
 }
 
 pub(crate) fn info_view(state: &FlashingFinishState) -> Element<'_, BBImagerMessage> {
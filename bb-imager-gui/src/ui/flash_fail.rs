@@ -14,17 +14,30 @@ pub(crate) fn view(state: &FlashingFailState) -> Element<'_, BBImagerMessage> {
         &state.common,
         info_view(state),
         progress_view(state),
-        [button("Restart")
-            .style(widget::button::danger)
-            .on_press(BBImagerMessage::Restart)],
+        [
+            button("Restart")
+                .style(widget::button::secondary)
+                .on_press(BBImagerMessage::Restart),
+            button("REPORT PROBLEM")
+                .style(widget::button::secondary)
+                .on_press(BBImagerMessage::OpenUrl(state.report_url.clone())),
+            button(state.origin.retry_label())
+                .style(widget::button::danger)
+                .on_press(BBImagerMessage::FlashRetry),
+        ],
     )
 }
 
 pub(crate) fn progress_view(state: &FlashingFailState) -> Element<'_, BBImagerMessage> {
     widget::column![
         CircleBar::new("Failed", 10.0, constants::DANGER),
-        widget::text(&state.err)
+        widget::text(state.origin.title())
+            .size(20)
+            .font(constants::FONT_BOLD),
+        widget::text(&state.err),
+        widget::text(state.origin.hint()).style(widget::text::secondary)
     ]
+    .spacing(8)
     .align_x(iced::Center)
     .padding(VIEW_COL_PADDING)
     .into()
@@ -14,9 +14,14 @@ pub(crate) fn view(state: &FlashingFinishState) -> Element<'_, BBImagerMessage>
         &state.common,
         info_view(state),
         progress_view(state),
-        [button("Restart")
-            .style(widget::button::primary)
-            .on_press(BBImagerMessage::Restart)],
+        [
+            button("Restart")
+                .style(widget::button::secondary)
+                .on_press(BBImagerMessage::Restart),
+            button("FLASH ANOTHER")
+                .style(widget::button::primary)
+                .on_press(BBImagerMessage::FlashAnother),
+        ],
     )
 }
 
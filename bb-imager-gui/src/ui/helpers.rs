@@ -30,6 +30,10 @@ pub(crate) fn card_btn_style(
             .rounded(5);
     }
 
+    if matches!(status, widget::button::Status::Disabled) {
+        style.text_color = style.text_color.scale_alpha(0.5);
+    }
+
     style
 }
 
@@ -52,17 +56,10 @@ pub(crate) fn page_type1<'a>(
     col2: Element<'a, BBImagerMessage>,
     btns: impl IntoIterator<Item = widget::Button<'a, BBImagerMessage>>,
 ) -> Element<'a, BBImagerMessage> {
-    let row2 = widget::row(
-        [
-            info_btn(common.info_svg_handle.clone()).into(),
-            widget::space::horizontal().into(),
-        ]
-        .into_iter()
-        .chain(btns.into_iter().map(Into::into)),
-    )
-    .align_y(iced::Center)
-    .width(iced::Length::Fill)
-    .spacing(24);
+    let row2 = widget::row(info_items(common).chain(btns.into_iter().map(Into::into)))
+        .align_y(iced::Center)
+        .width(iced::Length::Fill)
+        .spacing(24);
 
     let col2 = widget::column![
         card_box(col2)
@@ -96,17 +93,10 @@ pub(crate) fn page_type2<'a>(
     row1: Element<'a, BBImagerMessage>,
     btns: impl IntoIterator<Item = widget::Button<'a, BBImagerMessage>>,
 ) -> Element<'a, BBImagerMessage> {
-    let row2 = widget::row(
-        [
-            info_btn(common.info_svg_handle.clone()).into(),
-            widget::space::horizontal().into(),
-        ]
-        .into_iter()
-        .chain(btns.into_iter().map(Into::into)),
-    )
-    .align_y(iced::Center)
-    .width(iced::Length::Fill)
-    .spacing(24);
+    let row2 = widget::row(info_items(common).chain(btns.into_iter().map(Into::into)))
+        .align_y(iced::Center)
+        .width(iced::Length::Fill)
+        .spacing(24);
 
     widget::column![card_box(row1).height(iced::Fill).width(iced::Fill), row2]
         .padding(24)
@@ -407,6 +397,21 @@ fn card_box<'a>(
     })
 }
 
+/// Info button, followed by offline mode indicator (if enabled) and space before page buttons.
+fn info_items<'a>(
+    common: &crate::BBImagerCommon,
+) -> impl Iterator<Item = Element<'a, BBImagerMessage>> {
+    let offline = common.app_config.local_config_only().then(|| {
+        widget::text("Offline mode")
+            .style(widget::text::secondary)
+            .into()
+    });
+
+    std::iter::once(info_btn(common.info_svg_handle.clone()).into())
+        .chain(offline)
+        .chain([widget::space::horizontal().into()])
+}
+
 fn info_btn(handle: widget::svg::Handle) -> widget::Button<'static, BBImagerMessage> {
     widget::button(widget::svg(handle))
         .on_press(BBImagerMessage::AppInfo)
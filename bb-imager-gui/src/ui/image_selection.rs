@@ -5,7 +5,9 @@ use iced::{
 
 use crate::{
     constants,
+    helpers::UrlImageForm,
     message::BBImagerMessage,
+    persistance::LocalImageInfo,
     ui::helpers::{
         self, LIST_COL_PADDING, VIEW_COL_PADDING, card_btn_style, detail_entry, page_type1,
         svg_icon_style,
@@ -29,7 +31,9 @@ pub(crate) fn view<'a>(state: &'a crate::state::ChooseOsState) -> Element<'a, BB
     )
 }
 
-fn os_list_pane<'a>(state: &'a crate::state::ChooseOsState) -> Element<'a, BBImagerMessage> {
+pub(crate) fn os_list_pane<'a>(
+    state: &'a crate::state::ChooseOsState,
+) -> Element<'a, BBImagerMessage> {
     match state.images() {
         Some(imgs) => {
             let items = imgs
@@ -38,7 +42,9 @@ fn os_list_pane<'a>(state: &'a crate::state::ChooseOsState) -> Element<'a, BBIma
                         .selected_image
                         .as_ref()
                         .map(|(x, _)| *x == img.id)
-                        .unwrap_or(false);
+                        .unwrap_or(false)
+                        || (state.url_form.is_some()
+                            && matches!(img.id, crate::helpers::OsImageId::Url(_)));
 
                     let icon: Element<BBImagerMessage> = match img.id {
                         crate::helpers::OsImageId::Format(_) => {
@@ -55,6 +61,13 @@ fn os_list_pane<'a>(state: &'a crate::state::ChooseOsState) -> Element<'a, BBIma
                                 .style(svg_icon_style)
                                 .into()
                         }
+                        crate::helpers::OsImageId::Url(_) => {
+                            widget::svg(state.downloading_svg().clone())
+                                .height(ICON_WIDTH)
+                                .width(ICON_WIDTH)
+                                .style(svg_icon_style)
+                                .into()
+                        }
                         crate::helpers::OsImageId::Remote(_) => {
                             match state
                                 .image_handle_cache()
@@ -70,8 +83,19 @@ fn os_list_pane<'a>(state: &'a crate::state::ChooseOsState) -> Element<'a, BBIma
                         }
                     };
 
-                    let row =
-                        widget::row![icon, text(img.label).size(18).width(iced::Length::Fill)];
+                    let label: Element<BBImagerMessage> = match img.update_required {
+                        Some(x) => widget::column![
+                            text(img.label).size(18),
+                            text(format!("Update required (v{x} or newer)"))
+                                .size(14)
+                                .style(widget::text::danger)
+                        ]
+                        .width(iced::Length::Fill)
+                        .into(),
+                        None => text(img.label).size(18).width(iced::Length::Fill).into(),
+                    };
+
+                    let row = widget::row![icon, label];
                     let row = if img.is_sublist {
                         row.push(
                             widget::svg(state.arrow_forward_svg().clone())
@@ -88,7 +112,12 @@ fn os_list_pane<'a>(state: &'a crate::state::ChooseOsState) -> Element<'a, BBIma
                             .padding(8)
                             .align_y(iced::alignment::Vertical::Center),
                     )
-                    .on_press(BBImagerMessage::SelectOs(img.id))
+                    // Images requiring a newer app version cannot be selected
+                    .on_press_maybe(
+                        img.update_required
+                            .is_none()
+                            .then_some(BBImagerMessage::SelectOs(img.id)),
+                    )
                     .style(move |theme, status| card_btn_style(theme, status, is_selected))
                 })
                 .map(Into::into);
@@ -128,7 +157,100 @@ fn os_list_pane<'a>(state: &'a crate::state::ChooseOsState) -> Element<'a, BBIma
     }
 }
 
+fn release_notes_view<'a>(
+    notes: Option<&'a str>,
+    changelog_url: Option<&'a url::Url>,
+) -> Element<'a, BBImagerMessage> {
+    let mut col = widget::column![
+        widget::rule::horizontal(2),
+        text("Release Notes").size(18).font(constants::FONT_BOLD)
+    ];
+
+    if let Some(x) = notes {
+        col = col.push(text(x));
+    }
+
+    if let Some(x) = changelog_url {
+        col = col.push(widget::center(
+            widget::button("VIEW CHANGELOG")
+                .on_press(BBImagerMessage::OpenUrl(x.clone()))
+                .style(widget::button::secondary),
+        ));
+    }
+
+    col.spacing(8).into()
+}
+
+fn local_info_view<'a>(info: LocalImageInfo) -> Element<'a, BBImagerMessage> {
+    let non_empty = |x: String| if x.is_empty() { None } else { Some(x) };
+
+    let name = widget::text_input("Name", info.name.as_deref().unwrap_or_default()).on_input({
+        let info = info.clone();
+        move |x| BBImagerMessage::UpdateLocalImageInfo(info.clone().update_name(non_empty(x)))
+    });
+    let description = widget::text_input(
+        "Description",
+        info.description.as_deref().unwrap_or_default(),
+    )
+    .on_input(move |x| {
+        BBImagerMessage::UpdateLocalImageInfo(info.clone().update_description(non_empty(x)))
+    });
+
+    widget::column![widget::rule::horizontal(2), name, description]
+        .spacing(8)
+        .into()
+}
+
+/// Inputs for "Flash from URL". The image can only be used once inputs are valid.
+pub(crate) fn url_form_view<'a>(
+    state: &'a crate::state::ChooseOsState,
+    form: &'a UrlImageForm,
+) -> Element<'a, BBImagerMessage> {
+    let res = form.parse(state.flasher());
+
+    let url = widget::text_input("https://example.com/image.img.xz", &form.url).on_input({
+        let form = form.clone();
+        move |x| BBImagerMessage::UpdateUrlImageForm(form.clone().update_url(x))
+    });
+    let paste = widget::button("PASTE")
+        .on_press(BBImagerMessage::PasteImageUrl)
+        .style(widget::button::secondary);
+    let sha256 = widget::text_input("SHA256 (optional)", &form.sha256).on_input({
+        let form = form.clone();
+        move |x| BBImagerMessage::UpdateUrlImageForm(form.clone().update_sha256(x))
+    });
+
+    let mut col = widget::column![
+        text("Flash from URL")
+            .size(24)
+            .align_x(iced::alignment::Alignment::Center)
+            .width(iced::Length::Fill),
+        widget::row![url, paste].spacing(8),
+        sha256,
+    ];
+
+    // Do not complain before the user has typed anything
+    if let Err(e) = &res
+        && !form.url.is_empty()
+    {
+        col = col.push(text(e.clone()).style(widget::text::danger));
+    }
+
+    let select = res
+        .is_ok()
+        .then(|| BBImagerMessage::SelectUrlOs(state.pos.clone()));
+
+    col.push(widget::button("USE IMAGE").on_press_maybe(select))
+        .spacing(16)
+        .padding(VIEW_COL_PADDING)
+        .into()
+}
+
 fn os_view_pane<'a>(state: &'a crate::state::ChooseOsState) -> Element<'a, BBImagerMessage> {
+    if let Some(form) = &state.url_form {
+        return url_form_view(state, form);
+    }
+
     match state.selected_image() {
         Some((_, img)) => {
             let icon = match img.icon() {
@@ -140,6 +262,10 @@ fn os_view_pane<'a>(state: &'a crate::state::ChooseOsState) -> Element<'a, BBIma
                             .into(),
                     }
                 }
+                crate::helpers::BoardImageIcon::Url => widget::svg(state.downloading_svg().clone())
+                    .height(100)
+                    .width(iced::Length::Fill)
+                    .into(),
                 crate::helpers::BoardImageIcon::Local => widget::svg(state.file_add_svg().clone())
                     .height(100)
                     .width(iced::Length::Fill)
@@ -179,6 +305,12 @@ fn os_view_pane<'a>(state: &'a crate::state::ChooseOsState) -> Element<'a, BBIma
                 None => col,
             };
 
+            // Add release notes if present
+            let col = match img.release_notes() {
+                (None, None) => col,
+                (notes, url) => col.push(release_notes_view(notes, url)),
+            };
+
             let col = col.extend(
                 img.details()
                     .iter()
@@ -186,6 +318,12 @@ fn os_view_pane<'a>(state: &'a crate::state::ChooseOsState) -> Element<'a, BBIma
                     .map(Into::into),
             );
 
+            // Allow naming local images
+            let col = match img.local_info() {
+                Some((_, info)) => col.push(local_info_view(info)),
+                None => col,
+            };
+
             widget::scrollable(col.spacing(16).padding(VIEW_COL_PADDING))
                 .id(state.common.scroll_id.clone())
                 .into()
@@ -203,3 +341,58 @@ fn os_view_pane<'a>(state: &'a crate::state::ChooseOsState) -> Element<'a, BBIma
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{helpers, persistance::GuiConfiguration, state};
+
+    #[test]
+    fn release_notes_view() {
+        let image: bb_config::config::OsImage = serde_json::from_str(
+            r#"{
+                "name": "Image",
+                "description": "Image",
+                "icon": "https://example.com/icon.png",
+                "url": "https://example.com/image.img.xz",
+                "image_download_size": null,
+                "image_download_sha256": "0000000000000000000000000000000000000000000000000000000000000000",
+                "extract_size": 1024,
+                "release_date": "2024-01-01",
+                "bmap": null,
+                "info_text": null,
+                "release_notes": "- Update kernel",
+                "changelog_url": "https://example.com/changelog"
+            }"#,
+        )
+        .unwrap();
+
+        let downloader = bb_downloader::Downloader::new(
+            std::env::temp_dir().join("bb-imager-gui-release-notes-test"),
+        )
+        .unwrap();
+        let img = helpers::BoardImage::remote(
+            image,
+            bb_config::config::Flasher::SdCard,
+            downloader.clone(),
+            Default::default(),
+        );
+        assert_eq!(
+            img.release_notes(),
+            (
+                Some("- Update kernel"),
+                Some(&url::Url::parse("https://example.com/changelog").unwrap())
+            )
+        );
+
+        let state = state::ChooseOsState {
+            common: state::BBImagerCommon::new(GuiConfiguration::default(), downloader),
+            selected_board: 0,
+            pos: Vec::new(),
+            selected_image: Some((helpers::OsImageId::Url(Vec::new()), img)),
+            url_form: None,
+        };
+
+        let _ = super::view(&state);
+        let _ = super::os_view_pane(&state);
+    }
+}
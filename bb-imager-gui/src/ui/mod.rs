@@ -4,6 +4,7 @@ mod app_info;
 mod board_selection;
 mod configuration;
 mod destination_selection;
+mod expert;
 mod flash;
 mod flash_cancel;
 mod flash_fail;
@@ -13,6 +14,10 @@ mod image_selection;
 mod review;
 
 pub(crate) fn view(state: &BBImager) -> iced::Element<'_, BBImagerMessage> {
+    if state.common().app_config.expert_mode() && state.expert_step().is_some() {
+        return expert::view(state);
+    }
+
     match state {
         BBImager::ChooseBoard(inner) => board_selection::view(inner),
         BBImager::ChooseOs(inner) => image_selection::view(inner),
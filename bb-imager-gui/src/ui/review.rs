@@ -31,7 +31,23 @@ pub(crate) fn view<'a>(state: &'a CustomizeState) -> Element<'a, BBImagerMessage
     )
 }
 
-fn review_view<'a>(state: &'a CustomizeState) -> Element<'a, BBImagerMessage> {
+pub(crate) fn review_view<'a>(state: &'a CustomizeState) -> Element<'a, BBImagerMessage> {
+    let mut summary = widget::grid![
+        text("Device"),
+        text(state.selected_board()),
+        text("Operating System"),
+        text(state.selected_image()),
+        text("Storage"),
+        text(state.selected_destination())
+    ]
+    .height(iced::Length::Shrink)
+    .spacing(8)
+    .columns(2);
+
+    if let Some(x) = state.image_sha256() {
+        summary = summary.extend([text("Image SHA256").into(), text(x).into()]);
+    }
+
     let mut col = widget::column![
         text("Write Image")
             .font(constants::FONT_BOLD)
@@ -41,19 +57,13 @@ fn review_view<'a>(state: &'a CustomizeState) -> Element<'a, BBImagerMessage> {
         text("Summary")
             .font(constants::FONT_BOLD)
             .size(HEADING_SIZE),
-        widget::grid![
-            text("Device"),
-            text(state.selected_board()),
-            text("Operating System"),
-            text(state.selected_image()),
-            text("Storage"),
-            text(state.selected_destination())
-        ]
-        .height(iced::Length::Shrink)
-        .spacing(8)
-        .columns(2),
+        summary,
     ];
 
+    if let Some(x) = state.size_warning() {
+        col = col.push(text(x).style(widget::text::warning));
+    }
+
     let modifications = state.modifications();
     if !modifications.is_empty() {
         col = col.extend([